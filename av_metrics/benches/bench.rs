@@ -3,11 +3,14 @@ extern crate av_metrics;
 extern crate criterion;
 
 use av_metrics::video::ciede::{calculate_frame_ciede, calculate_frame_ciede_nosimd};
-use av_metrics::video::decode::convert_chroma_data;
+use av_metrics::video::decode::{convert_chroma_data, Decoder, VideoDetails};
 use av_metrics::video::psnr::calculate_frame_psnr;
-use av_metrics::video::psnr_hvs::calculate_frame_psnr_hvs;
-use av_metrics::video::ssim::{calculate_frame_msssim, calculate_frame_ssim};
-use av_metrics::video::Frame;
+use av_metrics::video::psnr_hvs::{calculate_frame_psnr_hvs, calculate_frame_psnr_hvs_nosimd};
+use av_metrics::video::ssim::{
+    calculate_frame_msssim, calculate_frame_ssim, calculate_frame_ssim_luma,
+    calculate_plane_ssim_u32, calculate_video_msssim, calculate_video_ssim,
+};
+use av_metrics::video::{convert_bit_depth, Frame};
 use av_metrics::video::{ChromaSamplePosition, ChromaSampling, Pixel};
 use criterion::Criterion;
 use std::fs::File;
@@ -64,6 +67,37 @@ fn map_y4m_color_space(color_space: y4m::Colorspace) -> (ChromaSampling, ChromaS
     }
 }
 
+/// Returns the same 8-bit frame a fixed number of times, to benchmark
+/// the processing of whole videos without the cost of decoding.
+struct RepeatDecoder {
+    frame: Frame<u8>,
+    remaining: usize,
+}
+
+impl Decoder for RepeatDecoder {
+    fn read_video_frame<T: Pixel>(&mut self) -> Option<Frame<T>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        Some(convert_bit_depth(&self.frame, 8, 8))
+    }
+
+    fn get_bit_depth(&self) -> usize {
+        8
+    }
+
+    fn get_video_details(&self) -> VideoDetails {
+        VideoDetails {
+            width: self.frame.planes[0].cfg.width,
+            height: self.frame.planes[0].cfg.height,
+            bit_depth: 8,
+            chroma_sampling: ChromaSampling::Cs420,
+            ..Default::default()
+        }
+    }
+}
+
 pub fn psnr_benchmark(c: &mut Criterion) {
     let frame1 = get_video_frame::<u8>(&format!(
         "{}/../testfiles/yuv420p8_input.y4m",
@@ -96,6 +130,22 @@ pub fn psnrhvs_benchmark(c: &mut Criterion) {
     });
 }
 
+pub fn psnrhvs_nosimd_benchmark(c: &mut Criterion) {
+    let frame1 = get_video_frame::<u8>(&format!(
+        "{}/../testfiles/yuv420p8_input.y4m",
+        env!("CARGO_MANIFEST_DIR")
+    ));
+    let frame2 = get_video_frame::<u8>(&format!(
+        "{}/../testfiles/yuv420p8_output.y4m",
+        env!("CARGO_MANIFEST_DIR")
+    ));
+    c.bench_function("PSNR-HVS yuv420p8 nosimd", |b| {
+        b.iter(|| {
+            calculate_frame_psnr_hvs_nosimd(&frame1, &frame2, 8, ChromaSampling::Cs420).unwrap();
+        })
+    });
+}
+
 pub fn ssim_benchmark(c: &mut Criterion) {
     let frame1 = get_video_frame::<u8>(&format!(
         "{}/../testfiles/yuv420p8_input.y4m",
@@ -128,6 +178,79 @@ pub fn msssim_benchmark(c: &mut Criterion) {
     });
 }
 
+pub fn ssim_video_benchmark(c: &mut Criterion) {
+    let frame1 = get_video_frame::<u8>(&format!(
+        "{}/../testfiles/yuv420p8_input.y4m",
+        env!("CARGO_MANIFEST_DIR")
+    ));
+    let frame2 = get_video_frame::<u8>(&format!(
+        "{}/../testfiles/yuv420p8_output.y4m",
+        env!("CARGO_MANIFEST_DIR")
+    ));
+    let decoder = |frame: &Frame<u8>| RepeatDecoder {
+        frame: frame.clone(),
+        remaining: 30,
+    };
+    c.bench_function("SSIM yuv420p8 30 frames", |b| {
+        b.iter(|| {
+            calculate_video_ssim(&mut decoder(&frame1), &mut decoder(&frame2), None, |_| ())
+                .unwrap();
+        })
+    });
+    c.bench_function("MSSSIM yuv420p8 30 frames", |b| {
+        b.iter(|| {
+            calculate_video_msssim(&mut decoder(&frame1), &mut decoder(&frame2), None, |_| ())
+                .unwrap();
+        })
+    });
+}
+
+/// Repeats the luma plane of `frame` to fill a 4K plane, packed as `u32`.
+fn tile_luma_4k(frame: &Frame<u8>) -> Vec<u32> {
+    let plane = &frame.planes[0];
+    (0..2160)
+        .flat_map(|y| {
+            let row = &plane.data_origin()[(y % plane.cfg.height) * plane.cfg.stride..];
+            (0..3840).map(move |x| row[x % plane.cfg.width] as u32)
+        })
+        .collect()
+}
+
+pub fn ssim_u32_4k_benchmark(c: &mut Criterion) {
+    let plane1 = tile_luma_4k(&get_video_frame::<u8>(&format!(
+        "{}/../testfiles/yuv420p8_input.y4m",
+        env!("CARGO_MANIFEST_DIR")
+    )));
+    let plane2 = tile_luma_4k(&get_video_frame::<u8>(&format!(
+        "{}/../testfiles/yuv420p8_output.y4m",
+        env!("CARGO_MANIFEST_DIR")
+    )));
+    let to_frame = |plane: &[u32]| {
+        let mut frame = Frame::<u8>::new_with_padding(3840, 2160, ChromaSampling::Cs420, 0);
+        for (row, samples) in frame.planes[0].rows_iter_mut().zip(plane.chunks(3840)) {
+            for (pix, &sample) in row.iter_mut().zip(samples) {
+                *pix = sample as u8;
+            }
+        }
+        frame
+    };
+    let (frame1, frame2) = (to_frame(&plane1), to_frame(&plane2));
+
+    let mut group = c.benchmark_group("SSIM 4K luma");
+    group.sample_size(10);
+    group.bench_function("frame", |b| {
+        b.iter(|| {
+            calculate_frame_ssim_luma(&frame1, &frame2, 8, ChromaSampling::Cs420).unwrap();
+        })
+    });
+    group.bench_function("u32 plane", |b| {
+        b.iter(|| {
+            calculate_plane_ssim_u32(&plane1, &plane2, 3840, 2160, 8).unwrap();
+        })
+    });
+    group.finish();
+}
+
 pub fn ciede2000_nosimd_benchmark(c: &mut Criterion) {
     let frame1 = get_video_frame::<u8>(&format!(
         "{}/../testfiles/yuv420p8_input.y4m",
@@ -260,8 +383,11 @@ criterion_group!(
     benches,
     psnr_benchmark,
     psnrhvs_benchmark,
+    psnrhvs_nosimd_benchmark,
     ssim_benchmark,
     msssim_benchmark,
+    ssim_video_benchmark,
+    ssim_u32_4k_benchmark,
     ciede2000_nosimd_benchmark,
     ciede2000_simd_benchmark,
     psnr_10bit_benchmark,
@@ -0,0 +1,149 @@
+//! Benchmarks of the inner loops of SSIM, MSSSIM and PSNR-HVS at common resolutions.
+//!
+//! Requires the `bench` feature: `cargo bench -p av-metrics --features bench`.
+
+#[macro_use]
+extern crate criterion;
+
+use av_metrics::video::bench::{fdct8x8, plane_msssim, plane_ssim};
+use av_metrics::video::{Pixel, Plane};
+use criterion::{BenchmarkId, Criterion};
+use std::fs::File;
+use y4m::Decoder as Y4MDec;
+
+const RESOLUTIONS: [(&str, usize, usize); 3] = [
+    ("720p", 1280, 720),
+    ("1080p", 1920, 1080),
+    ("4K", 3840, 2160),
+];
+
+/// Reads the luma plane of the first frame of a test file, along with its
+/// dimensions and bit depth.
+fn read_luma(name: &str) -> (Vec<u32>, usize, usize, usize) {
+    let path = format!("{}/../testfiles/{}", env!("CARGO_MANIFEST_DIR"), name);
+    let mut file = File::open(path).unwrap();
+    let mut dec = Y4MDec::new(&mut file).unwrap();
+    let (width, height) = (dec.get_width(), dec.get_height());
+    let bit_depth = dec.get_bit_depth();
+    let bytes = dec.get_bytes_per_sample();
+    let frame = dec.read_frame().unwrap();
+    let luma = frame
+        .get_y_plane()
+        .chunks(bytes)
+        .map(|sample| match sample {
+            [lo, hi] => u16::from_le_bytes([*lo, *hi]) as u32,
+            _ => sample[0] as u32,
+        })
+        .collect();
+    (luma, width, height, bit_depth)
+}
+
+/// Repeats the luma plane of a test file to fill a plane of the given size.
+fn tile_luma(name: &str, width: usize, height: usize) -> (Vec<u32>, usize) {
+    let (luma, src_width, src_height, bit_depth) = read_luma(name);
+    let plane = (0..height)
+        .flat_map(|y| {
+            let row = &luma[(y % src_height) * src_width..][..src_width];
+            (0..width).map(move |x| row[x % src_width])
+        })
+        .collect();
+    (plane, bit_depth)
+}
+
+fn to_plane<T: Pixel>(samples: &[u32], width: usize, height: usize) -> Plane<T> {
+    let mut plane = Plane::new(width, height, 0, 0, 0, 0);
+    for (row, samples) in plane.rows_iter_mut().zip(samples.chunks(width)) {
+        for (pix, &sample) in row.iter_mut().zip(samples) {
+            *pix = T::cast_from(sample as u16);
+        }
+    }
+    plane
+}
+
+/// Yields the tiled input and output planes for each resolution and bit depth.
+fn inputs() -> impl Iterator<Item = (String, Vec<u32>, Vec<u32>, usize, usize, usize)> {
+    [8, 10].into_iter().flat_map(|depth| {
+        RESOLUTIONS.into_iter().map(move |(name, width, height)| {
+            let (plane1, bit_depth) =
+                tile_luma(&format!("yuv420p{}_input.y4m", depth), width, height);
+            let (plane2, _) = tile_luma(&format!("yuv420p{}_output.y4m", depth), width, height);
+            (
+                format!("{} {}-bit", name, depth),
+                plane1,
+                plane2,
+                width,
+                height,
+                bit_depth,
+            )
+        })
+    })
+}
+
+pub fn plane_ssim_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("SSIM plane");
+    group.sample_size(10);
+    for (id, plane1, plane2, width, height, bit_depth) in inputs() {
+        group.bench_function(BenchmarkId::from_parameter(id), |b| {
+            b.iter(|| plane_ssim(&plane1, &plane2, width, height, bit_depth))
+        });
+    }
+    group.finish();
+}
+
+pub fn plane_msssim_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("MSSSIM plane");
+    group.sample_size(10);
+    for (id, plane1, plane2, width, height, bit_depth) in inputs() {
+        if bit_depth > 8 {
+            let plane1 = to_plane::<u16>(&plane1, width, height);
+            let plane2 = to_plane::<u16>(&plane2, width, height);
+            group.bench_function(BenchmarkId::from_parameter(id), |b| {
+                b.iter(|| plane_msssim(&plane1, &plane2, bit_depth))
+            });
+        } else {
+            let plane1 = to_plane::<u8>(&plane1, width, height);
+            let plane2 = to_plane::<u8>(&plane2, width, height);
+            group.bench_function(BenchmarkId::from_parameter(id), |b| {
+                b.iter(|| plane_msssim(&plane1, &plane2, bit_depth))
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Transforms every 8x8 block of each plane, the same number of blocks as PSNR-HVS.
+pub fn fdct8x8_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("PSNR-HVS fdct8x8");
+    group.sample_size(10);
+    for (id, plane, _, width, height, _) in inputs() {
+        let blocks: Vec<[i32; 64]> = (0..height / 8)
+            .flat_map(|by| (0..width / 8).map(move |bx| (bx, by)))
+            .map(|(bx, by)| {
+                let mut block = [0; 64];
+                for (i, sample) in block.iter_mut().enumerate() {
+                    *sample = plane[(by * 8 + i / 8) * width + bx * 8 + i % 8] as i32;
+                }
+                block
+            })
+            .collect();
+        for simd in [false, true] {
+            let name = format!("{} {}", id, if simd { "simd" } else { "nosimd" });
+            group.bench_function(BenchmarkId::from_parameter(name), |b| {
+                b.iter_batched_ref(
+                    || blocks.clone(),
+                    |blocks| fdct8x8(blocks, simd),
+                    criterion::BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    plane_ssim_benchmark,
+    plane_msssim_benchmark,
+    fdct8x8_benchmark
+);
+criterion_main!(benches);
@@ -39,6 +39,12 @@ pub enum MetricsError {
         #[doc(hidden)]
         reason: &'static str,
     },
+    /// Indicates the properties of two input videos, e.g. their resolutions, do not match.
+    #[error("Input videos must have matching formats: mismatched {}", .fields.join(", "))]
+    VideoDetailsMismatch {
+        #[doc(hidden)]
+        fields: Vec<&'static str>,
+    },
     /// Indicates the impossibility to process the two videos.
     #[error("Could not process the two videos: {reason}")]
     VideoError {
@@ -57,4 +63,40 @@ pub enum MetricsError {
         #[doc(hidden)]
         reason: String,
     },
+    /// Indicates that no frames were compared, e.g. because one of the inputs
+    /// contains no readable frames or the frame limit was zero.
+    #[error("No frames were compared")]
+    NoFramesCompared,
+    /// Indicates that the calculation was cancelled, see
+    /// [`CancellableDecoder`](crate::video::decode::CancellableDecoder).
+    #[error("The calculation was cancelled")]
+    Cancelled,
+    /// Indicates that an input ended in the middle of a frame, e.g. because the
+    /// file was truncated.
+    #[error("Input ended in the middle of a frame")]
+    UnexpectedEof,
+    /// Indicates that an input could not be opened or decoded.
+    ///
+    /// The underlying error, e.g. an [`std::io::Error`] for a missing file,
+    /// is returned by [`std::error::Error::source`].
+    #[error("Could not decode input: {reason}")]
+    DecodeError {
+        #[doc(hidden)]
+        reason: &'static str,
+        #[doc(hidden)]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+impl MetricsError {
+    /// Wraps an error of a decoder, keeping it as the source of the returned error.
+    pub fn decode_error<E>(reason: &'static str, source: E) -> Self
+    where
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        MetricsError::DecodeError {
+            reason,
+            source: source.into(),
+        }
+    }
 }
@@ -0,0 +1,62 @@
+//! Entry points into the hot loops of the metrics, for the criterion benchmarks.
+//!
+//! These skip the input validation and the conversion of the frames, so that the
+//! benchmarks measure the loops themselves. They are only available with the
+//! `bench` feature and are not part of the stable API.
+
+use crate::video::psnr_hvs::dct::get_fdct8x8_fn;
+use crate::video::ssim::{
+    build_plane_kernel, calculate_plane_msssim, calculate_plane_ssim_internal, LineBuffers,
+    PlaneBuffers, SsimConfig,
+};
+use crate::video::Pixel;
+use v_frame::plane::Plane;
+
+/// Runs the SSIM convolution over two tightly packed planes and returns the
+/// mean SSIM of their windows.
+pub fn plane_ssim(
+    plane1: &[u32],
+    plane2: &[u32],
+    width: usize,
+    height: usize,
+    bit_depth: usize,
+) -> f64 {
+    let kernel = build_plane_kernel(width, height);
+    let (ssim, _) = calculate_plane_ssim_internal(
+        plane1,
+        plane2,
+        width,
+        height,
+        (1 << bit_depth) - 1,
+        &kernel,
+        &kernel,
+        None,
+        SsimConfig::default(),
+        None,
+        &mut LineBuffers::default(),
+    );
+    ssim
+}
+
+/// Calculates the unconverted MSSSIM score of two planes.
+pub fn plane_msssim<T: Pixel>(plane1: &Plane<T>, plane2: &Plane<T>, bit_depth: usize) -> f64 {
+    calculate_plane_msssim(
+        plane1,
+        plane2,
+        bit_depth,
+        None,
+        &mut PlaneBuffers::default(),
+    )
+}
+
+/// Transforms each block in place with the 8x8 forward DCT of PSNR-HVS.
+///
+/// `simd` selects the fastest version supported by the CPU, as in
+/// [`calculate_frame_psnr_hvs`](crate::video::psnr_hvs::calculate_frame_psnr_hvs).
+pub fn fdct8x8(blocks: &mut [[i32; 64]], simd: bool) {
+    let fdct = get_fdct8x8_fn(simd);
+    for block in blocks {
+        // SAFETY: `get_fdct8x8_fn` only returns versions supported by the CPU.
+        unsafe { fdct(block) };
+    }
+}
@@ -4,13 +4,15 @@
 //!
 //! CIEDE2000 implementation adapted from
 //! [Kyle Siefring's](https://github.com/KyleSiefring/dump_ciede2000).
+//!
+//! Only 8, 10 and 12 bit input is supported. The chroma planes may be subsampled
+//! by at most 2 in each direction, so 4:1:1 input is not supported.
 
 use crate::video::decode::Decoder;
 use crate::video::pixel::{CastFromPrimitive, Pixel};
-use crate::video::VideoMetric;
+use crate::video::{check_pixel_width, FrameStatistics, FrameTypeMetrics, VideoMetric};
 use crate::MetricsError;
 use std::f64;
-use std::mem::size_of;
 
 mod rgbtolab;
 use rgbtolab::*;
@@ -35,6 +37,46 @@ pub fn calculate_video_ciede<D: Decoder, F: Fn(usize) + Send>(
     Ciede2000::default().process_video(decoder1, decoder2, frame_limit, progress_callback)
 }
 
+/// Calculate the CIEDE2000 metric between two video clips. Higher is better.
+///
+/// The frames of each type are also aggregated separately. The type of each frame
+/// is taken from `decoder2`, which is usually the encoded video, or from `decoder1`
+/// if `decoder2` does not know it.
+#[inline]
+pub fn calculate_video_ciede_by_frame_type<D: Decoder, F: Fn(usize) + Send>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<FrameTypeMetrics<f64>, Box<dyn Error>> {
+    Ciede2000::default().process_video_by_frame_type(
+        decoder1,
+        decoder2,
+        frame_limit,
+        progress_callback,
+    )
+}
+
+/// Calculate the CIEDE2000 metric between two video clips. Higher is better.
+///
+/// The mean, variance and standard deviation of the per-frame scores are also
+/// calculated, which show how stable the quality is over time.
+#[inline]
+pub fn calculate_video_ciede_with_statistics<D: Decoder, F: Fn(usize) + Send>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<FrameStatistics<f64>, Box<dyn Error>> {
+    Ciede2000::default().process_video_with_statistics(
+        decoder1,
+        decoder2,
+        frame_limit,
+        progress_callback,
+        |m| m,
+    )
+}
+
 /// Calculate the CIEDE2000 metric between two video clips. Higher is better.
 ///
 /// This version disables SIMD. It is intended to only be used
@@ -91,13 +133,14 @@ impl Default for Ciede2000 {
     }
 }
 
-use rayon::prelude::*;
+use crate::video::parallel::prelude::*;
 use v_frame::frame::Frame;
 use v_frame::prelude::ChromaSampling;
 
 impl VideoMetric for Ciede2000 {
     type FrameResult = f64;
     type VideoResult = f64;
+    type Context = ();
 
     fn process_frame<T: Pixel>(
         &self,
@@ -106,15 +149,38 @@ impl VideoMetric for Ciede2000 {
         bit_depth: usize,
         chroma_sampling: ChromaSampling,
     ) -> Result<Self::FrameResult, Box<dyn Error>> {
-        if (size_of::<T>() == 1 && bit_depth > 8) || (size_of::<T>() == 2 && bit_depth <= 8) {
-            return Err(Box::new(MetricsError::InputMismatch {
-                reason: "Bit depths does not match pixel width",
+        check_pixel_width::<T>(bit_depth)?;
+
+        frame1.can_compare(frame2)?;
+
+        if chroma_sampling == ChromaSampling::Cs400 {
+            return Err(Box::new(MetricsError::UnsupportedInput {
+                reason: "CIEDE2000 requires chroma planes",
             }));
         }
 
-        frame1.can_compare(frame2)?;
+        // The chroma planes are upsampled by repeating each sample at most twice
+        if frame1.planes[1].cfg.xdec > 1 || frame1.planes[1].cfg.ydec > 1 {
+            return Err(Box::new(MetricsError::UnsupportedInput {
+                reason: "CIEDE2000 does not support chroma subsampled by more than 2",
+            }));
+        }
+
+        if !matches!(bit_depth, 8 | 10 | 12) {
+            return Err(Box::new(MetricsError::UnsupportedInput {
+                reason: "CIEDE2000 only supports 8, 10 and 12 bit input",
+            }));
+        }
 
-        let dec = chroma_sampling.get_decimation().unwrap_or((1, 1));
+        // Identical frames have no color difference, which is clamped to the maximum score
+        if frame1.planes[0].cfg.width > 0
+            && frame1.planes[0].cfg.height > 0
+            && frame1.is_identical(frame2)
+        {
+            return Ok(100.);
+        }
+
+        let dec = (frame1.planes[1].cfg.xdec, frame1.planes[1].cfg.ydec);
         let y_width = frame1.planes[0].cfg.width;
         let y_height = frame1.planes[0].cfg.height;
         let c_width = frame1.planes[1].cfg.width;
@@ -122,27 +188,22 @@ impl VideoMetric for Ciede2000 {
         // let mut delta_e_vec: Vec<f32> = vec![0.0; y_width * y_height];
 
         let delta_e_per_line = (0..y_height).into_par_iter().map(|i| {
-            let y_start = i * y_width;
-            let y_end = y_start + y_width;
-            let c_start = (i >> dec.1) * c_width;
-            let c_end = c_start + c_width;
-
-            let y_range = y_start..y_end;
-            let c_range = c_start..c_end;
+            let y_row = i as isize;
+            let c_row = (i >> dec.1) as isize;
 
-            let mut delta_e_vec = vec![0.0; y_end - y_start];
+            let mut delta_e_vec = vec![0.0; y_width];
 
             unsafe {
                 delta_e_row_fn(
                     FrameRow {
-                        y: &frame1.planes[0].data[y_range.clone()],
-                        u: &frame1.planes[1].data[c_range.clone()],
-                        v: &frame1.planes[2].data[c_range.clone()],
+                        y: &frame1.planes[0].row(y_row)[..y_width],
+                        u: &frame1.planes[1].row(c_row)[..c_width],
+                        v: &frame1.planes[2].row(c_row)[..c_width],
                     },
                     FrameRow {
-                        y: &frame2.planes[0].data[y_range],
-                        u: &frame2.planes[1].data[c_range.clone()],
-                        v: &frame2.planes[2].data[c_range],
+                        y: &frame2.planes[0].row(y_row)[..y_width],
+                        u: &frame2.planes[1].row(c_row)[..c_width],
+                        v: &frame2.planes[2].row(c_row)[..c_width],
                     },
                     &mut delta_e_vec[..],
                 );
@@ -151,8 +212,9 @@ impl VideoMetric for Ciede2000 {
             delta_e_vec.iter().map(|x| *x as f64).sum::<f64>()
         });
 
-        let score =
-            45. - 20. * (delta_e_per_line.sum::<f64>() / ((y_width * y_height) as f64)).log10();
+        // Sum sequentially so the result does not depend on how rayon splits the work.
+        let delta_e_sum = delta_e_per_line.collect::<Vec<_>>().iter().sum::<f64>();
+        let score = 45. - 20. * (delta_e_sum / ((y_width * y_height) as f64)).log10();
         Ok(score.min(100.))
     }
 
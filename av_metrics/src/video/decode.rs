@@ -2,8 +2,17 @@
 //! Prebuilt decoders are included in the `av-metrics-decoders` crate.
 
 use crate::video::pixel::Pixel;
-use crate::video::{ChromaSamplePosition, ChromaSampling};
+use crate::video::{
+    convert_bit_depth, crop_frame, deinterlace_frame, resize_frame, ChromaSamplePosition,
+    ChromaSampling, Deinterlace, FieldOrder, Rect, ResizeFilter, TransferCharacteristics,
+};
+use crate::MetricsError;
+use std::any::Any;
 use std::cmp;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use v_frame::frame::Frame;
 use v_frame::pixel::CastFromPrimitive;
 use v_frame::plane::Plane;
@@ -36,10 +45,996 @@ pub trait Decoder: Send {
     fn get_bit_depth(&self) -> usize;
     /// Get the Video Details
     fn get_video_details(&self) -> VideoDetails;
+    /// Get the total number of frames in the video, if it is known.
+    ///
+    /// This is only a hint, e.g. for progress reporting, and may not be exact.
+    /// Returns `None` by default.
+    fn total_frames(&self) -> Option<usize> {
+        None
+    }
+    /// Get the coding type of the frame which was returned by the last call
+    /// to `read_video_frame`.
+    ///
+    /// Returns [`FrameType::Unknown`] by default, e.g. for uncompressed input.
+    fn last_frame_type(&self) -> FrameType {
+        FrameType::Unknown
+    }
+    /// Returns `true` if the decoder supports random access to its frames, e.g. to
+    /// offer scrubbing through the video.
+    ///
+    /// This depends on the input as well as the decoder, e.g. files can usually be
+    /// seeked in, but pipes cannot. Returns `false` by default. The adapters in this
+    /// module return `false` as well, as they do not provide seeking themselves.
+    fn can_seek(&self) -> bool {
+        false
+    }
+    /// Rewinds the decoder to the first frame, so that the video can be read again,
+    /// e.g. to calculate another metric in a second pass.
+    ///
+    /// Returns [`MetricsError::UnsupportedInput`] by default, as well as for inputs
+    /// which cannot be read again, such as pipes. The adapters in this module rewind
+    /// the decoder they wrap.
+    fn reset(&mut self) -> Result<(), MetricsError> {
+        Err(MetricsError::UnsupportedInput {
+            reason: "Decoder cannot be rewound",
+        })
+    }
+    /// Returns the error which stopped the video early, if the last call to
+    /// `read_video_frame` returned `None` because a frame could not be read, rather
+    /// than at the end of the video.
+    ///
+    /// The metrics check this once a decoder returns no more frames, and return the
+    /// error instead of a result, e.g. [`MetricsError::UnexpectedEof`] for a truncated
+    /// file. Returns `None` by default. The adapters in this module return the error
+    /// of the decoder they wrap.
+    fn take_error(&mut self) -> Option<MetricsError> {
+        None
+    }
+    /// Returns `true` if the calculation which reads from this decoder should stop.
+    ///
+    /// This is checked before each frame is read. The metric then returns
+    /// [`MetricsError::Cancelled`] instead of a result. Returns `false` by default,
+    /// see [`CancellableDecoder`] for a decoder which can be cancelled from another thread.
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+    /// Returns an iterator over the remaining frames of the video, which calls
+    /// `read_video_frame` for each frame.
+    ///
+    /// This allows processing frames with the standard iterator adapters, e.g.
+    /// `decoder1.frames::<u8>().zip(decoder2.frames())` to compare two videos.
+    fn frames<T: Pixel>(&mut self) -> Frames<'_, Self, T>
+    where
+        Self: Sized,
+    {
+        Frames {
+            decoder: self,
+            pixel: PhantomData,
+        }
+    }
+}
+
+/// An iterator over the frames of a decoder, see [`Decoder::frames`].
+///
+/// The iterator ends when `read_video_frame` returns `None`, which is the case at
+/// the end of the video, but also if a frame could not be decoded, see
+/// [`Decoder::take_error`].
+pub struct Frames<'a, D: Decoder, T: Pixel> {
+    decoder: &'a mut D,
+    pixel: PhantomData<T>,
+}
+
+impl<'a, D: Decoder, T: Pixel> Frames<'a, D, T> {
+    /// Returns the decoder, e.g. to query the type of the last frame.
+    pub fn decoder(&self) -> &D {
+        self.decoder
+    }
+}
+
+impl<'a, D: Decoder, T: Pixel> Iterator for Frames<'a, D, T> {
+    type Item = Frame<T>;
+
+    fn next(&mut self) -> Option<Frame<T>> {
+        self.decoder.read_video_frame()
+    }
+}
+
+/// The coding type of a frame, as reported by the decoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FrameType {
+    /// An intra frame, which does not reference other frames.
+    I,
+    /// A frame which is predicted from earlier frames.
+    P,
+    /// A frame which is predicted from earlier and later frames.
+    B,
+    /// The decoder does not know the type, e.g. for uncompressed input.
+    #[default]
+    Unknown,
+}
+
+/// A decoder adapter which only yields every `step`-th frame of the inner decoder,
+/// starting with the first one.
+///
+/// The frames in between are still decoded, so two inputs being compared stay in sync,
+/// but they are not returned. Running a video metric on two `FrameStepDecoder`s
+/// therefore gives a faster approximation of the score over the full videos.
+pub struct FrameStepDecoder<D: Decoder> {
+    inner: D,
+    step: usize,
+    started: bool,
+}
+
+impl<D: Decoder> FrameStepDecoder<D> {
+    /// Wraps `decoder` so that only every `step`-th frame is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `step` is zero.
+    pub fn new(decoder: D, step: usize) -> Self {
+        assert!(step > 0, "Frame step must be greater than zero");
+        FrameStepDecoder {
+            inner: decoder,
+            step,
+            started: false,
+        }
+    }
+
+    /// Returns the wrapped decoder.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D: Decoder> Decoder for FrameStepDecoder<D> {
+    fn read_video_frame<T: Pixel>(&mut self) -> Option<Frame<T>> {
+        if self.started {
+            for _ in 1..self.step {
+                self.inner.read_video_frame::<T>()?;
+            }
+        }
+        self.started = true;
+        self.inner.read_video_frame()
+    }
+
+    fn get_bit_depth(&self) -> usize {
+        self.inner.get_bit_depth()
+    }
+
+    fn get_video_details(&self) -> VideoDetails {
+        let details = self.inner.get_video_details();
+        let step = self.step as u64;
+        VideoDetails {
+            time_base: Rational::new(details.time_base.num * step, details.time_base.den),
+            frame_rate: details
+                .frame_rate
+                .map(|rate| Rational::new(rate.num, rate.den * step)),
+            ..details
+        }
+    }
+
+    fn total_frames(&self) -> Option<usize> {
+        self.inner
+            .total_frames()
+            .map(|frames| frames.div_ceil(self.step))
+    }
+
+    fn last_frame_type(&self) -> FrameType {
+        self.inner.last_frame_type()
+    }
+
+    fn reset(&mut self) -> Result<(), MetricsError> {
+        self.inner.reset()?;
+        self.started = false;
+        Ok(())
+    }
+
+    fn take_error(&mut self) -> Option<MetricsError> {
+        self.inner.take_error()
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
+}
+
+/// A decoder adapter which discards the first frames of the inner decoder,
+/// e.g. a black intro, or the first frames of an encode whose quality is unstable.
+///
+/// The skipped frames are decoded and dropped when the first frame is read.
+/// Wrapping both inputs being compared with the same number of frames keeps them
+/// in sync. Unlike the `frame_limit` of the metrics, which leaves out the frames
+/// at the end of the videos, this leaves out the frames at the start.
+pub struct SkipFramesDecoder<D: Decoder> {
+    inner: D,
+    skip_frames: usize,
+    skipped: bool,
+}
+
+impl<D: Decoder> SkipFramesDecoder<D> {
+    /// Wraps `decoder` so that its first `skip_frames` frames are discarded.
+    pub fn new(decoder: D, skip_frames: usize) -> Self {
+        SkipFramesDecoder {
+            inner: decoder,
+            skip_frames,
+            skipped: false,
+        }
+    }
+
+    /// Returns the wrapped decoder.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D: Decoder> Decoder for SkipFramesDecoder<D> {
+    fn read_video_frame<T: Pixel>(&mut self) -> Option<Frame<T>> {
+        if !self.skipped {
+            for _ in 0..self.skip_frames {
+                self.inner.read_video_frame::<T>()?;
+            }
+            self.skipped = true;
+        }
+        self.inner.read_video_frame()
+    }
+
+    fn get_bit_depth(&self) -> usize {
+        self.inner.get_bit_depth()
+    }
+
+    fn get_video_details(&self) -> VideoDetails {
+        self.inner.get_video_details()
+    }
+
+    fn total_frames(&self) -> Option<usize> {
+        self.inner
+            .total_frames()
+            .map(|frames| frames.saturating_sub(self.skip_frames))
+    }
+
+    fn last_frame_type(&self) -> FrameType {
+        self.inner.last_frame_type()
+    }
+
+    fn reset(&mut self) -> Result<(), MetricsError> {
+        self.inner.reset()?;
+        self.skipped = false;
+        Ok(())
+    }
+
+    fn take_error(&mut self) -> Option<MetricsError> {
+        self.inner.take_error()
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
+}
+
+/// A decoder adapter which only returns the frames of the inner decoder within a
+/// time range, e.g. to score a single scene given by its timecodes.
+///
+/// The times are converted to frame numbers with [`VideoDetails::time_to_frame`].
+/// The frames before the start are skipped like with [`SkipFramesDecoder`], and
+/// reading stops at the frame shown at the end of the range.
+pub struct TimeRangeDecoder<D: Decoder> {
+    inner: SkipFramesDecoder<D>,
+    frame_count: Option<usize>,
+    frames_read: usize,
+}
+
+impl<D: Decoder> TimeRangeDecoder<D> {
+    /// Wraps `decoder` so that only the frames from `start` up to `end` seconds are
+    /// returned, or up to the end of the video if `end` is `None`.
+    ///
+    /// Returns an error if the input has no frame rate, or if the range is invalid
+    /// or shorter than a frame.
+    pub fn new(decoder: D, start: f64, end: Option<f64>) -> Result<Self, MetricsError> {
+        let details = decoder.get_video_details();
+        let start_frame = details.time_to_frame(start)?;
+        let end_frame = end.map(|end| details.time_to_frame(end)).transpose()?;
+        if end_frame.is_some_and(|end_frame| end_frame <= start_frame) {
+            return Err(MetricsError::UnsupportedInput {
+                reason: "The time range does not contain any frames",
+            });
+        }
+        Ok(TimeRangeDecoder {
+            inner: SkipFramesDecoder::new(decoder, start_frame),
+            frame_count: end_frame.map(|end_frame| end_frame - start_frame),
+            frames_read: 0,
+        })
+    }
+
+    /// Returns the wrapped decoder.
+    pub fn into_inner(self) -> D {
+        self.inner.into_inner()
+    }
+}
+
+impl<D: Decoder> Decoder for TimeRangeDecoder<D> {
+    fn read_video_frame<T: Pixel>(&mut self) -> Option<Frame<T>> {
+        if self
+            .frame_count
+            .is_some_and(|frame_count| self.frames_read >= frame_count)
+        {
+            return None;
+        }
+        let frame = self.inner.read_video_frame()?;
+        self.frames_read += 1;
+        Some(frame)
+    }
+
+    fn get_bit_depth(&self) -> usize {
+        self.inner.get_bit_depth()
+    }
+
+    fn get_video_details(&self) -> VideoDetails {
+        self.inner.get_video_details()
+    }
+
+    fn total_frames(&self) -> Option<usize> {
+        let frames = self.inner.total_frames()?;
+        Some(
+            self.frame_count
+                .map_or(frames, |count| cmp::min(frames, count)),
+        )
+    }
+
+    fn last_frame_type(&self) -> FrameType {
+        self.inner.last_frame_type()
+    }
+
+    fn reset(&mut self) -> Result<(), MetricsError> {
+        self.inner.reset()?;
+        self.frames_read = 0;
+        Ok(())
+    }
+
+    fn take_error(&mut self) -> Option<MetricsError> {
+        self.inner.take_error()
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
+}
+
+/// A decoder adapter which crops every frame of the inner decoder to a region.
+///
+/// See [`crop_frame`] for the constraints on the region. If a frame cannot be
+/// cropped, e.g. because the inner decoder returned a smaller frame than its
+/// details state, decoding stops and the error is returned by [`Decoder::take_error`].
+pub struct CropDecoder<D: Decoder> {
+    inner: D,
+    rect: Rect,
+    /// The error which stopped the last call to `read_video_frame`
+    error: Option<MetricsError>,
+}
+
+impl<D: Decoder> CropDecoder<D> {
+    /// Wraps `decoder` so that all frames are cropped to `rect`.
+    ///
+    /// Returns an error if `rect` does not fit the video.
+    pub fn new(decoder: D, rect: Rect) -> Result<Self, MetricsError> {
+        let details = decoder.get_video_details();
+        let (xdec, ydec) = details.chroma_sampling.get_decimation().unwrap_or((0, 0));
+        rect.validate(details.width, details.height, xdec, ydec)?;
+        Ok(CropDecoder {
+            inner: decoder,
+            rect,
+            error: None,
+        })
+    }
+
+    /// Returns the wrapped decoder.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D: Decoder> Decoder for CropDecoder<D> {
+    fn read_video_frame<T: Pixel>(&mut self) -> Option<Frame<T>> {
+        let frame = self.inner.read_video_frame()?;
+        crop_frame(&frame, self.rect)
+            .map_err(|error| self.error = Some(error))
+            .ok()
+    }
+
+    fn get_bit_depth(&self) -> usize {
+        self.inner.get_bit_depth()
+    }
+
+    fn get_video_details(&self) -> VideoDetails {
+        VideoDetails {
+            width: self.rect.width,
+            height: self.rect.height,
+            luma_padding: 0,
+            ..self.inner.get_video_details()
+        }
+    }
+
+    fn total_frames(&self) -> Option<usize> {
+        self.inner.total_frames()
+    }
+
+    fn last_frame_type(&self) -> FrameType {
+        self.inner.last_frame_type()
+    }
+
+    fn reset(&mut self) -> Result<(), MetricsError> {
+        self.error = None;
+        self.inner.reset()
+    }
+
+    fn take_error(&mut self) -> Option<MetricsError> {
+        self.error.take().or_else(|| self.inner.take_error())
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
+}
+
+/// A decoder adapter which resizes every frame of the inner decoder.
+///
+/// This allows comparing videos with different resolutions, usually by resizing the
+/// distorted video to the resolution of the reference. See [`resize_frame`] for how
+/// this affects the scores.
+pub struct ResizeDecoder<D: Decoder> {
+    inner: D,
+    width: usize,
+    height: usize,
+    filter: ResizeFilter,
+    /// The error which stopped the last call to `read_video_frame`
+    error: Option<MetricsError>,
+}
+
+impl<D: Decoder> ResizeDecoder<D> {
+    /// Wraps `decoder` so that all frames are resized to `width` x `height`.
+    ///
+    /// Returns an error if the size is empty.
+    pub fn new(
+        decoder: D,
+        width: usize,
+        height: usize,
+        filter: ResizeFilter,
+    ) -> Result<Self, MetricsError> {
+        if width == 0 || height == 0 {
+            return Err(MetricsError::UnsupportedInput {
+                reason: "Target size of a resize is empty",
+            });
+        }
+        Ok(ResizeDecoder {
+            inner: decoder,
+            width,
+            height,
+            filter,
+            error: None,
+        })
+    }
+
+    /// Returns the wrapped decoder.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D: Decoder> Decoder for ResizeDecoder<D> {
+    fn read_video_frame<T: Pixel>(&mut self) -> Option<Frame<T>> {
+        let bit_depth = self.inner.get_bit_depth();
+        let frame = self.inner.read_video_frame()?;
+        resize_frame(&frame, self.width, self.height, bit_depth, self.filter)
+            .map_err(|error| self.error = Some(error))
+            .ok()
+    }
+
+    fn get_bit_depth(&self) -> usize {
+        self.inner.get_bit_depth()
+    }
+
+    fn get_video_details(&self) -> VideoDetails {
+        let details = self.inner.get_video_details();
+        VideoDetails {
+            width: self.width,
+            height: self.height,
+            // The video is still displayed with the same aspect ratio
+            sample_aspect_ratio: details.sample_aspect_ratio.map(|sar| {
+                Rational::reduced(
+                    sar.num * (details.width * self.height) as u64,
+                    sar.den * (details.height * self.width) as u64,
+                )
+            }),
+            luma_padding: 0,
+            ..details
+        }
+    }
+
+    fn total_frames(&self) -> Option<usize> {
+        self.inner.total_frames()
+    }
+
+    fn last_frame_type(&self) -> FrameType {
+        self.inner.last_frame_type()
+    }
+
+    fn reset(&mut self) -> Result<(), MetricsError> {
+        self.error = None;
+        self.inner.reset()
+    }
+
+    fn take_error(&mut self) -> Option<MetricsError> {
+        self.error.take().or_else(|| self.inner.take_error())
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
+}
+
+/// A decoder adapter which converts every frame of the inner decoder to another bit depth.
+///
+/// This allows comparing videos with different bit depths, e.g. an 8-bit source
+/// with a 10-bit encode. See [`convert_bit_depth`] for how the samples are scaled.
+pub struct BitDepthConvertDecoder<D: Decoder> {
+    inner: D,
+    bit_depth: usize,
+}
+
+impl<D: Decoder> BitDepthConvertDecoder<D> {
+    /// Wraps `decoder` so that all frames are converted to `bit_depth`.
+    ///
+    /// Returns an error if `bit_depth` is not between 8 and 16.
+    pub fn new(decoder: D, bit_depth: usize) -> Result<Self, MetricsError> {
+        if !(8..=16).contains(&bit_depth) {
+            return Err(MetricsError::UnsupportedInput {
+                reason: "Bit depth must be between 8 and 16",
+            });
+        }
+        Ok(BitDepthConvertDecoder {
+            inner: decoder,
+            bit_depth,
+        })
+    }
+
+    /// Returns the wrapped decoder.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D: Decoder> Decoder for BitDepthConvertDecoder<D> {
+    fn read_video_frame<T: Pixel>(&mut self) -> Option<Frame<T>> {
+        let inner_bit_depth = self.inner.get_bit_depth();
+        if inner_bit_depth > 8 {
+            self.inner
+                .read_video_frame::<u16>()
+                .map(|frame| convert_bit_depth(&frame, inner_bit_depth, self.bit_depth))
+        } else {
+            self.inner
+                .read_video_frame::<u8>()
+                .map(|frame| convert_bit_depth(&frame, inner_bit_depth, self.bit_depth))
+        }
+    }
+
+    fn get_bit_depth(&self) -> usize {
+        self.bit_depth
+    }
+
+    fn get_video_details(&self) -> VideoDetails {
+        VideoDetails {
+            bit_depth: self.bit_depth,
+            luma_padding: 0,
+            ..self.inner.get_video_details()
+        }
+    }
+
+    fn total_frames(&self) -> Option<usize> {
+        self.inner.total_frames()
+    }
+
+    fn last_frame_type(&self) -> FrameType {
+        self.inner.last_frame_type()
+    }
+
+    fn reset(&mut self) -> Result<(), MetricsError> {
+        self.inner.reset()
+    }
+
+    fn take_error(&mut self) -> Option<MetricsError> {
+        self.inner.take_error()
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
+}
+
+/// A decoder adapter which deinterlaces every frame of the inner decoder.
+///
+/// Each interlaced frame is turned into two progressive frames, one per field, in the
+/// order given by the [`FieldOrder`]. The frame rate and the number of frames are
+/// doubled accordingly. See [`deinterlace_frame`] for the available modes.
+pub struct DeinterlaceDecoder<D: Decoder> {
+    inner: D,
+    mode: Deinterlace,
+    field_order: FieldOrder,
+    /// The second field of the last interlaced frame, if it was not returned yet.
+    pending: Option<Box<dyn Any + Send>>,
+    /// The error which stopped the last call to `read_video_frame`
+    error: Option<MetricsError>,
+}
+
+impl<D: Decoder> DeinterlaceDecoder<D> {
+    /// Wraps `decoder` so that all frames are deinterlaced.
+    ///
+    /// Returns an error if any plane of the video has an odd number of lines.
+    pub fn new(
+        decoder: D,
+        mode: Deinterlace,
+        field_order: FieldOrder,
+    ) -> Result<Self, MetricsError> {
+        let details = decoder.get_video_details();
+        let (_, chroma_height) = details
+            .chroma_sampling
+            .get_chroma_dimensions(details.width, details.height);
+        if details.height & 1 != 0 || chroma_height & 1 != 0 {
+            return Err(MetricsError::UnsupportedInput {
+                reason: "Interlaced planes must have an even number of lines",
+            });
+        }
+        Ok(DeinterlaceDecoder {
+            inner: decoder,
+            mode,
+            field_order,
+            pending: None,
+            error: None,
+        })
+    }
+
+    /// Returns the order in which the fields are returned.
+    pub fn field_order(&self) -> FieldOrder {
+        self.field_order
+    }
+
+    /// Returns the wrapped decoder.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D: Decoder> Decoder for DeinterlaceDecoder<D> {
+    fn read_video_frame<T: Pixel>(&mut self) -> Option<Frame<T>> {
+        if let Some(pending) = self.pending.take() {
+            // The pixel type does not change between calls in practice. If it does,
+            // the second field would be lost, so stop instead of skipping it.
+            return match pending.downcast::<Frame<T>>() {
+                Ok(field) => Some(*field),
+                Err(_) => {
+                    self.error = Some(MetricsError::UnsupportedInput {
+                        reason: "Pixel type changed between the fields of a frame",
+                    });
+                    None
+                }
+            };
+        }
+        let frame = self.inner.read_video_frame::<T>()?;
+        let [first, second] = deinterlace_frame(&frame, self.mode, self.field_order)
+            .map_err(|error| self.error = Some(error))
+            .ok()?;
+        self.pending = Some(Box::new(second));
+        Some(first)
+    }
+
+    fn get_bit_depth(&self) -> usize {
+        self.inner.get_bit_depth()
+    }
+
+    fn get_video_details(&self) -> VideoDetails {
+        let details = self.inner.get_video_details();
+        let height = match self.mode {
+            Deinterlace::SeparateFields => details.height / 2,
+            Deinterlace::Bob => details.height,
+        };
+        VideoDetails {
+            height,
+            time_base: Rational::new(details.time_base.num, details.time_base.den * 2),
+            frame_rate: details
+                .frame_rate
+                .map(|rate| Rational::new(rate.num * 2, rate.den)),
+            field_order: None,
+            // Each field only has every other line, so its pixels are twice as tall
+            sample_aspect_ratio: match self.mode {
+                Deinterlace::SeparateFields => details
+                    .sample_aspect_ratio
+                    .map(|sar| Rational::reduced(sar.num, sar.den * 2)),
+                Deinterlace::Bob => details.sample_aspect_ratio,
+            },
+            luma_padding: 0,
+            ..details
+        }
+    }
+
+    fn total_frames(&self) -> Option<usize> {
+        self.inner.total_frames().map(|frames| frames * 2)
+    }
+
+    fn last_frame_type(&self) -> FrameType {
+        self.inner.last_frame_type()
+    }
+
+    fn reset(&mut self) -> Result<(), MetricsError> {
+        self.inner.reset()?;
+        self.pending = None;
+        self.error = None;
+        Ok(())
+    }
+
+    fn take_error(&mut self) -> Option<MetricsError> {
+        self.error.take().or_else(|| self.inner.take_error())
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
+}
+
+/// A decoder adapter which allows cancelling a running calculation from another thread.
+///
+/// The calculation stops before the next frame is read once `true` is stored in the
+/// token, and the metric returns [`MetricsError::Cancelled`]. The same token can be
+/// shared by both decoders, and by the decoders of several calculations.
+pub struct CancellableDecoder<D: Decoder> {
+    inner: D,
+    token: Arc<AtomicBool>,
+}
+
+impl<D: Decoder> CancellableDecoder<D> {
+    /// Wraps `decoder` so that the calculation is cancelled when `token` is set.
+    pub fn new(decoder: D, token: Arc<AtomicBool>) -> Self {
+        CancellableDecoder {
+            inner: decoder,
+            token,
+        }
+    }
+
+    /// Returns the wrapped decoder.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D: Decoder> Decoder for CancellableDecoder<D> {
+    fn read_video_frame<T: Pixel>(&mut self) -> Option<Frame<T>> {
+        self.inner.read_video_frame()
+    }
+
+    fn get_bit_depth(&self) -> usize {
+        self.inner.get_bit_depth()
+    }
+
+    fn get_video_details(&self) -> VideoDetails {
+        self.inner.get_video_details()
+    }
+
+    fn total_frames(&self) -> Option<usize> {
+        self.inner.total_frames()
+    }
+
+    fn last_frame_type(&self) -> FrameType {
+        self.inner.last_frame_type()
+    }
+
+    fn reset(&mut self) -> Result<(), MetricsError> {
+        self.inner.reset()
+    }
+
+    fn take_error(&mut self) -> Option<MetricsError> {
+        self.inner.take_error()
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.token.load(Ordering::Relaxed) || self.inner.is_cancelled()
+    }
+}
+
+/// A destination for the frames passed through a [`TeeDecoder`].
+///
+/// Implement this to inspect the frames in a callback, or use [`Y4mWriter`] to
+/// write them to a file.
+pub trait FrameSink: Send {
+    /// Receives the next frame, along with the details of the video it belongs to.
+    fn write_frame<T: Pixel>(&mut self, frame: &Frame<T>, details: &VideoDetails)
+        -> io::Result<()>;
+}
+
+/// A [`FrameSink`] which writes the frames as a y4m video.
+///
+/// The header is written along with the first frame. Samples of videos with a bit
+/// depth above 8 are written as 16-bit little endian values.
+///
+/// Only the formats which y4m readers understand can be written: 8, 10 and 12 bits
+/// for 4:2:0, 4:2:2 and 4:4:4 video, and 8 and 12 bits for monochrome video. Other
+/// formats fail with an error wrapping [`MetricsError::UnsupportedInput`].
+pub struct Y4mWriter<W: Write + Send> {
+    writer: W,
+    header_written: bool,
+}
+
+impl<W: Write + Send> Y4mWriter<W> {
+    /// Creates a writer which writes the video to `writer`.
+    pub fn new(writer: W) -> Self {
+        Y4mWriter {
+            writer,
+            header_written: false,
+        }
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + Send> FrameSink for Y4mWriter<W> {
+    fn write_frame<T: Pixel>(
+        &mut self,
+        frame: &Frame<T>,
+        details: &VideoDetails,
+    ) -> io::Result<()> {
+        let planes = if details.chroma_sampling == ChromaSampling::Cs400 {
+            1
+        } else {
+            3
+        };
+        if !self.header_written {
+            let colorspace = match (details.chroma_sampling, details.bit_depth) {
+                // Only 8-bit 4:2:0 video can signal the chroma sample position
+                (ChromaSampling::Cs420, 8) => match details.chroma_sample_position {
+                    ChromaSamplePosition::Colocated => "420",
+                    ChromaSamplePosition::Vertical => "420mpeg2",
+                    ChromaSamplePosition::Interpolated => "420paldv",
+                    ChromaSamplePosition::Bilateral | ChromaSamplePosition::Unknown => "420jpeg",
+                },
+                (ChromaSampling::Cs420, 10) => "420p10",
+                (ChromaSampling::Cs420, 12) => "420p12",
+                (ChromaSampling::Cs422, 8) => "422",
+                (ChromaSampling::Cs422, 10) => "422p10",
+                (ChromaSampling::Cs422, 12) => "422p12",
+                (ChromaSampling::Cs444, 8) => "444",
+                (ChromaSampling::Cs444, 10) => "444p10",
+                (ChromaSampling::Cs444, 12) => "444p12",
+                (ChromaSampling::Cs400, 8) => "mono",
+                (ChromaSampling::Cs400, 12) => "mono12",
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        MetricsError::UnsupportedInput {
+                            reason: "Bit depth and chroma sampling cannot be written as y4m",
+                        },
+                    ))
+                }
+            };
+            // A ratio of 0:0 marks the frame rate or pixel aspect as unknown
+            let frame_rate = details.frame_rate.unwrap_or(Rational { num: 0, den: 0 });
+            let pixel_aspect = details
+                .sample_aspect_ratio
+                .unwrap_or(Rational { num: 0, den: 0 });
+            let interlacing = match details.field_order {
+                None => 'p',
+                Some(FieldOrder::TopFieldFirst) => 't',
+                Some(FieldOrder::BottomFieldFirst) => 'b',
+            };
+            writeln!(
+                self.writer,
+                "YUV4MPEG2 W{} H{} F{}:{} I{} A{}:{} C{}",
+                frame.planes[0].cfg.width,
+                frame.planes[0].cfg.height,
+                frame_rate.num,
+                frame_rate.den,
+                interlacing,
+                pixel_aspect.num,
+                pixel_aspect.den,
+                colorspace
+            )?;
+            self.header_written = true;
+        }
+
+        let mut data = b"FRAME\n".to_vec();
+        for plane in &frame.planes[..planes] {
+            for row in plane.rows_iter() {
+                for &pix in &row[..plane.cfg.width] {
+                    if details.bit_depth > 8 {
+                        data.extend_from_slice(&u16::cast_from(pix).to_le_bytes());
+                    } else {
+                        data.push(u8::cast_from(pix));
+                    }
+                }
+            }
+        }
+        self.writer.write_all(&data)
+    }
+}
+
+/// A decoder adapter which passes each decoded frame to a [`FrameSink`], e.g. to
+/// dump the exact frames which produced a bad score with a [`Y4mWriter`].
+///
+/// The frames and details seen by the metric are not changed. If the sink returns
+/// an error, no further frames are passed to it, and the error is returned by
+/// [`TeeDecoder::finish`]. Frames read again after a [`Decoder::reset`] are passed
+/// to the sink again.
+pub struct TeeDecoder<D: Decoder, S: FrameSink> {
+    inner: D,
+    sink: S,
+    error: Option<io::Error>,
+}
+
+impl<D: Decoder, S: FrameSink> TeeDecoder<D, S> {
+    /// Wraps `decoder` so that its frames are passed to `sink`.
+    pub fn new(decoder: D, sink: S) -> Self {
+        TeeDecoder {
+            inner: decoder,
+            sink,
+            error: None,
+        }
+    }
+
+    /// Returns the wrapped decoder.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    /// Returns the sink, or the first error it returned.
+    pub fn finish(self) -> io::Result<S> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.sink),
+        }
+    }
+}
+
+impl<D: Decoder, S: FrameSink> Decoder for TeeDecoder<D, S> {
+    fn read_video_frame<T: Pixel>(&mut self) -> Option<Frame<T>> {
+        let frame = self.inner.read_video_frame()?;
+        if self.error.is_none() {
+            let details = self.inner.get_video_details();
+            self.error = self.sink.write_frame(&frame, &details).err();
+        }
+        Some(frame)
+    }
+
+    fn get_bit_depth(&self) -> usize {
+        self.inner.get_bit_depth()
+    }
+
+    fn get_video_details(&self) -> VideoDetails {
+        self.inner.get_video_details()
+    }
+
+    fn total_frames(&self) -> Option<usize> {
+        self.inner.total_frames()
+    }
+
+    fn last_frame_type(&self) -> FrameType {
+        self.inner.last_frame_type()
+    }
+
+    fn reset(&mut self) -> Result<(), MetricsError> {
+        self.inner.reset()
+    }
+
+    fn take_error(&mut self) -> Option<MetricsError> {
+        self.inner.take_error()
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
 }
 
 /// A Structure containing Video Details as per Plane's Config
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VideoDetails {
     /// Width in pixels.
     pub width: usize,
@@ -53,6 +1048,30 @@ pub struct VideoDetails {
     pub chroma_sample_position: ChromaSamplePosition,
     /// Add Time base of the Video.
     pub time_base: Rational,
+    /// Frame rate of the video in frames per second, if the input stores it.
+    ///
+    /// The metrics do not use this, but it is needed to convert frame numbers to
+    /// timestamps when reporting results.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub frame_rate: Option<Rational>,
+    /// Transfer characteristics of the Video, if the input stores them.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub transfer_characteristics: TransferCharacteristics,
+    /// The order of the fields if the input marks the video as interlaced, or `None`
+    /// for progressive video or if the input does not say.
+    ///
+    /// The metrics treat every frame as progressive. Wrap the decoder in a
+    /// [`DeinterlaceDecoder`] to compare interlaced video field by field.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub field_order: Option<FieldOrder>,
+    /// The shape of a pixel as the ratio of its width to its height, if the input
+    /// stores it, e.g. `4:3` for anamorphic video which is displayed wider than its
+    /// size in pixels.
+    ///
+    /// The metrics do not use this, but it is needed to display the video or to
+    /// report its display size, see [`VideoDetails::display_aspect_ratio`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sample_aspect_ratio: Option<Rational>,
     /// Padding Constant
     pub luma_padding: usize,
 }
@@ -66,13 +1085,156 @@ impl Default for VideoDetails {
             chroma_sampling: ChromaSampling::Cs420,
             chroma_sample_position: ChromaSamplePosition::Unknown,
             time_base: Rational { num: 30, den: 1 },
+            frame_rate: None,
+            transfer_characteristics: TransferCharacteristics::Unknown,
+            field_order: None,
+            sample_aspect_ratio: None,
             luma_padding: 0,
         }
     }
 }
 
+impl VideoDetails {
+    /// Creates the details of a video with the given dimensions and format.
+    ///
+    /// The remaining fields have the same values as in [`VideoDetails::default`],
+    /// and can be set with the `with_*` methods.
+    pub fn new(
+        width: usize,
+        height: usize,
+        bit_depth: usize,
+        chroma_sampling: ChromaSampling,
+    ) -> Self {
+        VideoDetails {
+            width,
+            height,
+            bit_depth,
+            chroma_sampling,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the width in pixels.
+    pub const fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the height in pixels.
+    pub const fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the bit depth of the video.
+    pub const fn bit_depth(&self) -> usize {
+        self.bit_depth
+    }
+
+    /// Returns the chroma sampling of the video.
+    pub const fn chroma_sampling(&self) -> ChromaSampling {
+        self.chroma_sampling
+    }
+
+    /// Sets the width and height in pixels.
+    pub const fn with_size(mut self, width: usize, height: usize) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    /// Sets the bit depth of the video.
+    pub const fn with_bit_depth(mut self, bit_depth: usize) -> Self {
+        self.bit_depth = bit_depth;
+        self
+    }
+
+    /// Sets the chroma sampling of the video.
+    pub const fn with_chroma_sampling(mut self, chroma_sampling: ChromaSampling) -> Self {
+        self.chroma_sampling = chroma_sampling;
+        self
+    }
+
+    /// Sets the chroma sample position of the video.
+    pub const fn with_chroma_sample_position(
+        mut self,
+        chroma_sample_position: ChromaSamplePosition,
+    ) -> Self {
+        self.chroma_sample_position = chroma_sample_position;
+        self
+    }
+
+    /// Sets the time base of the video.
+    pub const fn with_time_base(mut self, time_base: Rational) -> Self {
+        self.time_base = time_base;
+        self
+    }
+
+    /// Sets the frame rate of the video in frames per second.
+    pub const fn with_frame_rate(mut self, frame_rate: Rational) -> Self {
+        self.frame_rate = Some(frame_rate);
+        self
+    }
+
+    /// Sets the transfer characteristics of the video.
+    pub const fn with_transfer_characteristics(
+        mut self,
+        transfer_characteristics: TransferCharacteristics,
+    ) -> Self {
+        self.transfer_characteristics = transfer_characteristics;
+        self
+    }
+
+    /// Marks the video as interlaced, with the fields in the given order.
+    pub const fn with_field_order(mut self, field_order: FieldOrder) -> Self {
+        self.field_order = Some(field_order);
+        self
+    }
+
+    /// Sets the ratio of the width to the height of a pixel.
+    pub const fn with_sample_aspect_ratio(mut self, sample_aspect_ratio: Rational) -> Self {
+        self.sample_aspect_ratio = Some(sample_aspect_ratio);
+        self
+    }
+
+    /// Returns the ratio of the width to the height of the displayed video in lowest
+    /// terms, e.g. `16:9`, or `None` if the sample aspect ratio is unknown.
+    pub fn display_aspect_ratio(&self) -> Option<Rational> {
+        let sar = self.sample_aspect_ratio?;
+        Some(Rational::reduced(
+            self.width as u64 * sar.num,
+            self.height as u64 * sar.den,
+        ))
+    }
+
+    /// Sets the luma padding of the video.
+    pub const fn with_luma_padding(mut self, luma_padding: usize) -> Self {
+        self.luma_padding = luma_padding;
+        self
+    }
+
+    /// Converts a time in seconds to the number of the frame shown at that time,
+    /// rounded to the nearest frame.
+    ///
+    /// Returns an error if the frame rate is unknown, or if `seconds` is negative
+    /// or not finite.
+    pub fn time_to_frame(&self, seconds: f64) -> Result<usize, MetricsError> {
+        let frame_rate = self
+            .frame_rate
+            .filter(|rate| rate.num > 0 && rate.den > 0)
+            .ok_or(MetricsError::UnsupportedInput {
+                reason: "The frame rate of the input is unknown",
+            })?;
+        if !(seconds.is_finite() && seconds >= 0.0) {
+            return Err(MetricsError::UnsupportedInput {
+                reason: "Time must be a non-negative number of seconds",
+            });
+        }
+        Ok((seconds * frame_rate.as_f64()).round() as usize)
+    }
+}
+
 /// A rational number.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 pub struct Rational {
     /// Numerator.
@@ -97,6 +1259,17 @@ impl Rational {
     pub fn as_f64(self) -> f64 {
         self.num as f64 / self.den as f64
     }
+    /// Creates a rational number in lowest terms, keeping `0:0` for unknown ratios.
+    fn reduced(num: u64, den: u64) -> Self {
+        let (mut a, mut b) = (num, den);
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        match a {
+            0 => Rational::new(num, den),
+            gcd => Rational::new(num / gcd, den / gcd),
+        }
+    }
 }
 
 /// The algorithms (as ported from daala-tools) expect a colocated or bilaterally located chroma
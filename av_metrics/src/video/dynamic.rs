@@ -0,0 +1,491 @@
+//! Metrics which can be selected at runtime.
+//!
+//! The functions in the metric modules are generic over the decoder and pixel type,
+//! so they cannot be stored as trait objects. [`DynMetric`] wraps each metric
+//! with monomorphized entry points, so that a list of metrics can be built from
+//! a configuration, e.g. with [`from_name`], and processed in a loop.
+//!
+//! For the metrics which are calculated per plane, the [`Metric`] enum is a simpler
+//! alternative: it can be parsed from a string, e.g. a command line argument, and
+//! passed to [`calculate_video_metric`] or [`calculate_frame_metric`].
+//!
+//! Each decoder can only be read once, so a new pair of decoders is needed for each
+//! metric. [`calculate_video_metrics`] takes care of this and collects the results
+//! into a [`MetricResults`]. To compare many pairs of videos, [`calculate_batch`]
+//! runs independent jobs in parallel.
+
+use crate::video::decode::{Decoder, VideoDetails};
+use crate::video::parallel::prelude::*;
+use crate::video::{ciede, psnr, psnr_hvs, ssim, ChromaSampling, Frame, Pixel, PlanarMetrics};
+use crate::MetricsError;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The result of a metric selected at runtime.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MetricResult {
+    /// A metric which is calculated for each plane, e.g. PSNR.
+    Planar(PlanarMetrics),
+    /// A metric which is calculated for the whole frame, e.g. CIEDE2000.
+    Single(f64),
+}
+
+/// An object-safe interface to a metric, for use in `Box<dyn DynMetric<D>>`.
+///
+/// `D` is the decoder type used to read videos.
+pub trait DynMetric<D: Decoder>: Send + Sync {
+    /// The name of the metric, as accepted by [`from_name`].
+    fn name(&self) -> &'static str;
+
+    /// Calculates the metric between two videos.
+    ///
+    /// Optionally, `frame_limit` can be set to only compare the first
+    /// `frame_limit` frames in each video.
+    fn calculate_video(
+        &self,
+        decoder1: &mut D,
+        decoder2: &mut D,
+        frame_limit: Option<usize>,
+        progress_callback: &(dyn Fn(usize) + Sync),
+    ) -> Result<MetricResult, Box<dyn Error>>;
+
+    /// Calculates the metric between two video frames with a bit depth of 8.
+    fn calculate_frame_u8(
+        &self,
+        frame1: &Frame<u8>,
+        frame2: &Frame<u8>,
+        bit_depth: usize,
+        chroma_sampling: ChromaSampling,
+    ) -> Result<MetricResult, Box<dyn Error>>;
+
+    /// Calculates the metric between two video frames with a bit depth above 8.
+    fn calculate_frame_u16(
+        &self,
+        frame1: &Frame<u16>,
+        frame2: &Frame<u16>,
+        bit_depth: usize,
+        chroma_sampling: ChromaSampling,
+    ) -> Result<MetricResult, Box<dyn Error>>;
+}
+
+/// Returns the metric with the given name, or `None` if there is no such metric.
+///
+/// The supported names are `psnr`, `apsnr`, `psnrhvs`, `ssim`, `msssim`
+/// and `ciede2000`.
+pub fn from_name<D: Decoder>(name: &str) -> Option<Box<dyn DynMetric<D>>> {
+    Some(match name {
+        "psnr" => Box::new(Psnr),
+        "apsnr" => Box::new(Apsnr),
+        "psnrhvs" => Box::new(PsnrHvs),
+        "ssim" => Box::new(Ssim),
+        "msssim" => Box::new(MsSsim),
+        "ciede2000" => Box::new(Ciede2000),
+        _ => return None,
+    })
+}
+
+/// A metric which is calculated for each plane, for [`calculate_video_metric`].
+///
+/// It can be parsed from the names of the per-plane metrics accepted by [`from_name`],
+/// e.g. `"psnrhvs".parse::<Metric>()`, and is displayed as that name. CIEDE2000 is not
+/// calculated per plane, so `"ciede2000"` is rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Metric {
+    /// See [`psnr::calculate_video_psnr`]
+    Psnr,
+    /// See [`psnr::calculate_video_apsnr`]
+    Apsnr,
+    /// See [`psnr_hvs::calculate_video_psnr_hvs`]
+    PsnrHvs,
+    /// See [`ssim::calculate_video_ssim`]
+    Ssim,
+    /// See [`ssim::calculate_video_msssim`]
+    MsSsim,
+}
+
+impl Metric {
+    /// All metrics, in the order of their declaration.
+    pub const ALL: [Metric; 5] = [
+        Metric::Psnr,
+        Metric::Apsnr,
+        Metric::PsnrHvs,
+        Metric::Ssim,
+        Metric::MsSsim,
+    ];
+
+    /// The name of the metric, as accepted by [`from_name`].
+    pub const fn name(self) -> &'static str {
+        match self {
+            Metric::Psnr => "psnr",
+            Metric::Apsnr => "apsnr",
+            Metric::PsnrHvs => "psnrhvs",
+            Metric::Ssim => "ssim",
+            Metric::MsSsim => "msssim",
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Metric {
+    type Err = MetricsError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Metric::ALL
+            .into_iter()
+            .find(|metric| metric.name() == name)
+            .ok_or(MetricsError::UnsupportedInput {
+                reason: "Unknown metric name",
+            })
+    }
+}
+
+/// Calculates the given metric between two videos. Higher is better.
+///
+/// This is the same as calling the function of the metric, e.g.
+/// [`ssim::calculate_video_ssim`] for [`Metric::Ssim`].
+///
+/// Optionally, `frame_limit` can be set to only compare the first
+/// `frame_limit` frames in each video.
+pub fn calculate_video_metric<D: Decoder, F: Fn(usize) + Send>(
+    metric: Metric,
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    match metric {
+        Metric::Psnr => {
+            psnr::calculate_video_psnr(decoder1, decoder2, frame_limit, progress_callback)
+        }
+        Metric::Apsnr => {
+            psnr::calculate_video_apsnr(decoder1, decoder2, frame_limit, progress_callback)
+        }
+        Metric::PsnrHvs => {
+            psnr_hvs::calculate_video_psnr_hvs(decoder1, decoder2, frame_limit, progress_callback)
+        }
+        Metric::Ssim => {
+            ssim::calculate_video_ssim(decoder1, decoder2, frame_limit, progress_callback)
+        }
+        Metric::MsSsim => {
+            ssim::calculate_video_msssim(decoder1, decoder2, frame_limit, progress_callback)
+        }
+    }
+}
+
+/// Calculates the given metric between two frames. Higher is better.
+///
+/// This is the same as calling the function of the metric, e.g.
+/// [`ssim::calculate_frame_ssim`] for [`Metric::Ssim`]. For a single frame,
+/// APSNR is the same as PSNR.
+pub fn calculate_frame_metric<T: Pixel>(
+    metric: Metric,
+    frame1: &Frame<T>,
+    frame2: &Frame<T>,
+    bit_depth: usize,
+    chroma_sampling: ChromaSampling,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    match metric {
+        Metric::Psnr | Metric::Apsnr => {
+            psnr::calculate_frame_psnr(frame1, frame2, bit_depth, chroma_sampling)
+        }
+        Metric::PsnrHvs => {
+            psnr_hvs::calculate_frame_psnr_hvs(frame1, frame2, bit_depth, chroma_sampling)
+        }
+        Metric::Ssim => ssim::calculate_frame_ssim(frame1, frame2, bit_depth, chroma_sampling),
+        Metric::MsSsim => ssim::calculate_frame_msssim(frame1, frame2, bit_depth, chroma_sampling),
+    }
+}
+
+/// The results of several metrics between two videos, with one field per metric.
+///
+/// Metrics which were not calculated are `None` and are left out when serializing.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MetricResults {
+    /// The number of frames which were compared.
+    pub frames: usize,
+    /// The details of the reference video.
+    pub video_details: VideoDetails,
+    /// See [`psnr::calculate_video_psnr`]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub psnr: Option<PlanarMetrics>,
+    /// See [`psnr::calculate_video_apsnr`]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub apsnr: Option<PlanarMetrics>,
+    /// See [`psnr_hvs::calculate_video_psnr_hvs`]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub psnr_hvs: Option<PlanarMetrics>,
+    /// See [`ssim::calculate_video_ssim`]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub ssim: Option<PlanarMetrics>,
+    /// See [`ssim::calculate_video_msssim`]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub msssim: Option<PlanarMetrics>,
+    /// See [`ciede::calculate_video_ciede`]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub ciede2000: Option<f64>,
+}
+
+impl MetricResults {
+    /// Stores the result of the metric with the given name in its field.
+    fn set(&mut self, name: &str, result: MetricResult) -> Result<(), MetricsError> {
+        match (name, result) {
+            ("psnr", MetricResult::Planar(result)) => self.psnr = Some(result),
+            ("apsnr", MetricResult::Planar(result)) => self.apsnr = Some(result),
+            ("psnrhvs", MetricResult::Planar(result)) => self.psnr_hvs = Some(result),
+            ("ssim", MetricResult::Planar(result)) => self.ssim = Some(result),
+            ("msssim", MetricResult::Planar(result)) => self.msssim = Some(result),
+            ("ciede2000", MetricResult::Single(result)) => self.ciede2000 = Some(result),
+            _ => {
+                return Err(MetricsError::UnsupportedInput {
+                    reason: "Metric has no field in MetricResults",
+                })
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Calculates several metrics between two videos.
+///
+/// Each decoder can only be read once, so `open_decoders` is called to open a new
+/// pair of decoders for each metric. The progress callback is called with
+/// the progress of the metric which is currently being calculated.
+///
+/// Optionally, `frame_limit` can be set to only compare the first
+/// `frame_limit` frames in each video.
+pub fn calculate_video_metrics<D, O, F>(
+    metrics: &[Box<dyn DynMetric<D>>],
+    mut open_decoders: O,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<MetricResults, Box<dyn Error>>
+where
+    D: Decoder,
+    O: FnMut() -> Result<(D, D), Box<dyn Error>>,
+    F: Fn(usize) + Sync,
+{
+    let mut results = MetricResults::default();
+    for metric in metrics.iter() {
+        let (mut decoder1, mut decoder2) = open_decoders()?;
+        results.video_details = decoder1.get_video_details();
+        let frames = AtomicUsize::new(0);
+        let result =
+            metric.calculate_video(&mut decoder1, &mut decoder2, frame_limit, &|frameno| {
+                // The end of decoding is signaled with `usize::MAX`
+                if frameno != usize::MAX {
+                    frames.fetch_max(frameno, Ordering::Relaxed);
+                }
+                progress_callback(frameno)
+            })?;
+        results.frames = frames.into_inner();
+        results.set(metric.name(), result)?;
+    }
+    Ok(results)
+}
+
+/// A pair of videos to compare with a metric, for [`calculate_batch`].
+///
+/// `S` identifies a video, e.g. a path, and is passed to the function which
+/// opens the decoders.
+pub struct BatchJob<S, D: Decoder> {
+    /// The reference video.
+    pub reference: S,
+    /// The distorted video, e.g. an encode of the reference.
+    pub distorted: S,
+    /// The metric to calculate.
+    pub metric: Box<dyn DynMetric<D>>,
+}
+
+/// Calculates the metrics of many pairs of videos in parallel.
+///
+/// Each job opens its own decoders with `open_decoder` and is processed
+/// independently, so the work is distributed by job rather than by frame.
+/// At most `max_jobs` jobs run at the same time, which bounds the number of
+/// open files and decoded frames kept in memory. The frames of each job are
+/// processed on the same threads, so `max_jobs` also limits the number of
+/// threads used. Without the `parallel` feature, the jobs run one after another.
+///
+/// The results are returned in the order of `jobs`. A job which fails does not
+/// stop the others, and its error is returned in its place. The progress callback
+/// is called with the index of a job and its progress, which is `usize::MAX` once
+/// the job has finished decoding.
+///
+/// Optionally, `frame_limit` can be set to only compare the first
+/// `frame_limit` frames in each video.
+pub fn calculate_batch<S, D, O, F>(
+    jobs: &[BatchJob<S, D>],
+    open_decoder: O,
+    max_jobs: usize,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<Vec<Result<MetricResult, MetricsError>>, MetricsError>
+where
+    S: Sync,
+    D: Decoder,
+    O: Fn(&S) -> Result<D, Box<dyn Error>> + Sync,
+    F: Fn(usize, usize) + Sync,
+{
+    if max_jobs == 0 {
+        return Err(MetricsError::UnsupportedInput {
+            reason: "At least one job must be allowed to run",
+        });
+    }
+    let run = |index: usize, job: &BatchJob<S, D>| -> Result<MetricResult, Box<dyn Error>> {
+        let mut decoder1 = open_decoder(&job.reference)?;
+        let mut decoder2 = open_decoder(&job.distorted)?;
+        job.metric
+            .calculate_video(&mut decoder1, &mut decoder2, frame_limit, &|frameno| {
+                progress_callback(index, frameno)
+            })
+    };
+    let run_all = || {
+        jobs.par_iter()
+            .enumerate()
+            .map(|(index, job)| {
+                // `Box<dyn Error>` cannot be sent between threads
+                run(index, job).map_err(|e| match e.downcast::<MetricsError>() {
+                    Ok(e) => *e,
+                    Err(e) => MetricsError::ProcessError {
+                        reason: e.to_string(),
+                    },
+                })
+            })
+            .collect()
+    };
+    #[cfg(feature = "parallel")]
+    let results = rayon::ThreadPoolBuilder::new()
+        .num_threads(max_jobs)
+        .build()
+        .map_err(|e| MetricsError::ProcessError {
+            reason: e.to_string(),
+        })?
+        .install(run_all);
+    #[cfg(not(feature = "parallel"))]
+    let results = run_all();
+    Ok(results)
+}
+
+macro_rules! dyn_metric {
+    ($(#[$attr:meta])* $metric:ident, $name:literal, $result:ident, $video_fn:path, $frame_fn:path) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy, Default)]
+        pub struct $metric;
+
+        impl<D: Decoder> DynMetric<D> for $metric {
+            fn name(&self) -> &'static str {
+                $name
+            }
+
+            fn calculate_video(
+                &self,
+                decoder1: &mut D,
+                decoder2: &mut D,
+                frame_limit: Option<usize>,
+                progress_callback: &(dyn Fn(usize) + Sync),
+            ) -> Result<MetricResult, Box<dyn Error>> {
+                $video_fn(decoder1, decoder2, frame_limit, progress_callback)
+                    .map(MetricResult::$result)
+            }
+
+            fn calculate_frame_u8(
+                &self,
+                frame1: &Frame<u8>,
+                frame2: &Frame<u8>,
+                bit_depth: usize,
+                chroma_sampling: ChromaSampling,
+            ) -> Result<MetricResult, Box<dyn Error>> {
+                $frame_fn(frame1, frame2, bit_depth, chroma_sampling).map(MetricResult::$result)
+            }
+
+            fn calculate_frame_u16(
+                &self,
+                frame1: &Frame<u16>,
+                frame2: &Frame<u16>,
+                bit_depth: usize,
+                chroma_sampling: ChromaSampling,
+            ) -> Result<MetricResult, Box<dyn Error>> {
+                $frame_fn(frame1, frame2, bit_depth, chroma_sampling).map(MetricResult::$result)
+            }
+        }
+    };
+}
+
+dyn_metric!(
+    /// See [`psnr::calculate_video_psnr`]
+    Psnr,
+    "psnr",
+    Planar,
+    psnr::calculate_video_psnr,
+    psnr::calculate_frame_psnr
+);
+dyn_metric!(
+    /// See [`psnr::calculate_video_apsnr`]
+    ///
+    /// For a single frame, APSNR is the same as PSNR.
+    Apsnr,
+    "apsnr",
+    Planar,
+    psnr::calculate_video_apsnr,
+    psnr::calculate_frame_psnr
+);
+dyn_metric!(
+    /// See [`psnr_hvs::calculate_video_psnr_hvs`]
+    PsnrHvs,
+    "psnrhvs",
+    Planar,
+    psnr_hvs::calculate_video_psnr_hvs,
+    psnr_hvs::calculate_frame_psnr_hvs
+);
+dyn_metric!(
+    /// See [`ssim::calculate_video_ssim`]
+    Ssim,
+    "ssim",
+    Planar,
+    ssim::calculate_video_ssim,
+    ssim::calculate_frame_ssim
+);
+dyn_metric!(
+    /// See [`ssim::calculate_video_msssim`]
+    MsSsim,
+    "msssim",
+    Planar,
+    ssim::calculate_video_msssim,
+    ssim::calculate_frame_msssim
+);
+dyn_metric!(
+    /// See [`ciede::calculate_video_ciede`]
+    Ciede2000,
+    "ciede2000",
+    Single,
+    ciede::calculate_video_ciede,
+    ciede::calculate_frame_ciede
+);
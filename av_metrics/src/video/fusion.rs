@@ -0,0 +1,129 @@
+//! Weighted combinations of several metrics into a single score.
+//!
+//! Models like VMAF fuse several elementary features with a trained model. No model
+//! is shipped here: [`FusionMetric`] calculates the configured metrics for each
+//! frame and combines their `avg` scores linearly with weights chosen by the caller,
+//! e.g. fitted to subjective scores. This makes it possible to build custom quality
+//! models on top of the existing metrics.
+
+use crate::video::decode::Decoder;
+use crate::video::dynamic::{calculate_frame_metric, Metric};
+use crate::video::pixel::Pixel;
+use crate::video::VideoMetric;
+use std::error::Error;
+use v_frame::frame::Frame;
+use v_frame::prelude::ChromaSampling;
+
+/// A metric and its weight in a [`FusionMetric`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FusionFeature {
+    /// The metric to calculate for each frame.
+    pub metric: Metric,
+    /// The factor of the `avg` score of the metric in the fused score.
+    pub weight: f64,
+}
+
+/// A linear combination of the per-frame scores of several metrics.
+///
+/// The fused score of a frame is `bias + sum(weight * score)`, where `score` is the
+/// `avg` score of each metric on the same scale as returned by its function, e.g.
+/// decibels for PSNR. The score of a video is the mean of the fused frame scores.
+///
+/// All metrics are calculated in a single pass over the videos.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FusionMetric {
+    /// The metrics to combine.
+    pub features: Vec<FusionFeature>,
+    /// A constant which is added to the score of each frame.
+    pub bias: f64,
+}
+
+/// The result of a [`FusionMetric`] over the frames of a video.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FusionResult {
+    /// The mean of the fused frame scores.
+    pub score: f64,
+    /// The fused score of each frame, in decode order.
+    pub frames: Vec<f64>,
+}
+
+impl FusionMetric {
+    /// Creates a fusion of the given metrics, each with its weight, and no bias.
+    pub fn new<I: IntoIterator<Item = (Metric, f64)>>(features: I) -> Self {
+        FusionMetric {
+            features: features
+                .into_iter()
+                .map(|(metric, weight)| FusionFeature { metric, weight })
+                .collect(),
+            bias: 0.0,
+        }
+    }
+
+    /// Sets the constant which is added to the score of each frame.
+    pub fn with_bias(mut self, bias: f64) -> Self {
+        self.bias = bias;
+        self
+    }
+
+    /// Calculates the fused score between two videos.
+    ///
+    /// Optionally, `frame_limit` can be set to only compare the first
+    /// `frame_limit` frames in each video.
+    pub fn calculate_video<D: Decoder, F: Fn(usize) + Send>(
+        &self,
+        decoder1: &mut D,
+        decoder2: &mut D,
+        frame_limit: Option<usize>,
+        progress_callback: F,
+    ) -> Result<FusionResult, Box<dyn Error>> {
+        Fusion(self).process_video(decoder1, decoder2, frame_limit, progress_callback)
+    }
+
+    /// Calculates the fused score between two frames.
+    pub fn calculate_frame<T: Pixel>(
+        &self,
+        frame1: &Frame<T>,
+        frame2: &Frame<T>,
+        bit_depth: usize,
+        chroma_sampling: ChromaSampling,
+    ) -> Result<f64, Box<dyn Error>> {
+        self.features.iter().try_fold(self.bias, |score, feature| {
+            let result =
+                calculate_frame_metric(feature.metric, frame1, frame2, bit_depth, chroma_sampling)?;
+            Ok(score + feature.weight * result.avg)
+        })
+    }
+}
+
+/// Processes the frames of a video with a borrowed [`FusionMetric`].
+struct Fusion<'a>(&'a FusionMetric);
+
+impl<'a> VideoMetric for Fusion<'a> {
+    type FrameResult = f64;
+    type VideoResult = FusionResult;
+    type Context = ();
+
+    fn process_frame<T: Pixel>(
+        &self,
+        frame1: &Frame<T>,
+        frame2: &Frame<T>,
+        bit_depth: usize,
+        chroma_sampling: ChromaSampling,
+    ) -> Result<Self::FrameResult, Box<dyn Error>> {
+        self.0
+            .calculate_frame(frame1, frame2, bit_depth, chroma_sampling)
+    }
+
+    fn aggregate_frame_results(
+        &self,
+        metrics: &[Self::FrameResult],
+    ) -> Result<Self::VideoResult, Box<dyn Error>> {
+        Ok(FusionResult {
+            score: metrics.iter().sum::<f64>() / metrics.len() as f64,
+            frames: metrics.to_vec(),
+        })
+    }
+}
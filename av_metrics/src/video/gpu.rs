@@ -0,0 +1,255 @@
+//! Filtering of the SSIM windows on the GPU, see
+//! [`Accumulation::Gpu`](crate::video::ssim::Accumulation::Gpu).
+//!
+//! The device is requested on first use and shared by all calculations. If there is
+//! no usable GPU, or a plane does not fit into its buffers, the functions in this
+//! module return `None` and the windows are filtered on the CPU instead.
+
+use crate::video::ssim::EdgeHandling;
+use std::sync::{mpsc, Mutex, OnceLock};
+use wgpu::util::DeviceExt;
+
+/// The side length of the compute workgroups, see `ssim.wgsl`.
+const WORKGROUP_SIZE: u32 = 16;
+
+struct GpuContext {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    horizontal: wgpu::ComputePipeline,
+    vertical: wgpu::ComputePipeline,
+    /// Held while a plane is filtered, as the error scopes of the device are
+    /// shared between threads.
+    busy: Mutex<()>,
+}
+
+impl GpuContext {
+    /// Returns the shared context, or `None` if no GPU is available.
+    fn get() -> Option<&'static GpuContext> {
+        static CONTEXT: OnceLock<Option<GpuContext>> = OnceLock::new();
+        CONTEXT.get_or_init(GpuContext::new).as_ref()
+    }
+
+    fn new() -> Option<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            ..Default::default()
+        }))?;
+        // The default limits only allow buffers of 256 MiB, which is not enough
+        // for the moments of a 4K plane.
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("av-metrics"),
+                required_features: wgpu::Features::empty(),
+                required_limits: adapter.limits(),
+                memory_hints: wgpu::MemoryHints::Performance,
+            },
+            None,
+        ))
+        .ok()?;
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("ssim"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("ssim.wgsl").into()),
+        });
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: None,
+                module: &module,
+                entry_point,
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        let horizontal = pipeline("horizontal");
+        let vertical = pipeline("vertical");
+
+        Some(GpuContext {
+            device,
+            queue,
+            horizontal,
+            vertical,
+            busy: Mutex::new(()),
+        })
+    }
+
+    /// Returns whether a plane of the given size fits into the buffers of the device.
+    fn fits(&self, width: usize, height: usize) -> bool {
+        let limits = self.device.limits();
+        let moments_size = (width * height * 6 * 4) as u64;
+        let workgroups = |len: usize| len.div_ceil(WORKGROUP_SIZE as usize) as u64;
+        moments_size <= limits.max_buffer_size
+            && moments_size <= limits.max_storage_buffer_binding_size as u64
+            && workgroups(width) <= limits.max_compute_workgroups_per_dimension as u64
+            && workgroups(height) <= limits.max_compute_workgroups_per_dimension as u64
+    }
+}
+
+/// Returns whether a GPU is available for filtering the windows.
+pub(crate) fn is_available() -> bool {
+    GpuContext::get().is_some()
+}
+
+/// Filters the SSIM windows of two tightly packed planes on the GPU.
+///
+/// Returns the moments `[mux, muy, x2, xy, y2, w]` of each window in row-major order,
+/// the same as `filter_windows` in the `ssim` module, but calculated in `f32`.
+/// Returns `None` if no GPU is available, or if the planes are too large or empty.
+pub(crate) fn filter_windows(
+    plane1: &[u32],
+    plane2: &[u32],
+    width: usize,
+    height: usize,
+    vert_kernel: &[i64],
+    horiz_kernel: &[i64],
+    edge: EdgeHandling,
+) -> Option<Vec<[f32; 6]>> {
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let context = GpuContext::get()?;
+    if !context.fits(width, height) {
+        return None;
+    }
+    let _busy = context.busy.lock().ok()?;
+    let device = &context.device;
+
+    let edge = match edge {
+        EdgeHandling::Shrink => 0,
+        EdgeHandling::Replicate => 1,
+        EdgeHandling::Mirror => 2,
+        EdgeHandling::Zero => 3,
+    };
+    let params = [
+        width as u32,
+        height as u32,
+        edge,
+        horiz_kernel.len() as u32,
+        vert_kernel.len() as u32,
+        0,
+        0,
+        0,
+    ];
+    let kernels: Vec<f32> = horiz_kernel
+        .iter()
+        .chain(vert_kernel)
+        .map(|&weight| weight as f32)
+        .collect();
+    let moments_size = (width * height * 6 * 4) as u64;
+
+    // Errors, e.g. running out of memory, leave the calculation to the CPU
+    // instead of reaching the default error handler, which panics.
+    device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+    let init_buffer = |label, contents: &[u8], usage| {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents,
+            usage,
+        })
+    };
+    let buffer = |label, size, usage| {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage,
+            mapped_at_creation: false,
+        })
+    };
+    let params = init_buffer(
+        "params",
+        bytemuck::cast_slice(&params),
+        wgpu::BufferUsages::UNIFORM,
+    );
+    let kernels = init_buffer(
+        "kernels",
+        bytemuck::cast_slice(&kernels),
+        wgpu::BufferUsages::STORAGE,
+    );
+    let plane1 = init_buffer(
+        "plane1",
+        bytemuck::cast_slice(plane1),
+        wgpu::BufferUsages::STORAGE,
+    );
+    let plane2 = init_buffer(
+        "plane2",
+        bytemuck::cast_slice(plane2),
+        wgpu::BufferUsages::STORAGE,
+    );
+    let rows = buffer("rows", moments_size, wgpu::BufferUsages::STORAGE);
+    let windows = buffer(
+        "windows",
+        moments_size,
+        wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+    );
+    let readback = buffer(
+        "readback",
+        moments_size,
+        wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+    );
+
+    // Each pass only binds the buffers it uses, as the layouts are derived from the shader
+    let bind_group = |pipeline: &wgpu::ComputePipeline, buffers: &[(u32, &wgpu::Buffer)]| {
+        let entries: Vec<_> = buffers
+            .iter()
+            .map(|&(binding, buffer)| wgpu::BindGroupEntry {
+                binding,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect();
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &entries,
+        })
+    };
+    let horizontal = bind_group(
+        &context.horizontal,
+        &[
+            (0, &params),
+            (1, &kernels),
+            (2, &plane1),
+            (3, &plane2),
+            (4, &rows),
+        ],
+    );
+    let vertical = bind_group(
+        &context.vertical,
+        &[(0, &params), (1, &kernels), (4, &rows), (5, &windows)],
+    );
+
+    let mut encoder = device.create_command_encoder(&Default::default());
+    {
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+        let workgroups = |len: usize| len.div_ceil(WORKGROUP_SIZE as usize) as u32;
+        for (pipeline, bind_group) in [
+            (&context.horizontal, &horizontal),
+            (&context.vertical, &vertical),
+        ] {
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(workgroups(width), workgroups(height), 1);
+        }
+    }
+    encoder.copy_buffer_to_buffer(&windows, 0, &readback, 0, moments_size);
+    context.queue.submit([encoder.finish()]);
+
+    let validation_error = pollster::block_on(device.pop_error_scope());
+    let memory_error = pollster::block_on(device.pop_error_scope());
+    if validation_error.is_some() || memory_error.is_some() {
+        return None;
+    }
+
+    let slice = readback.slice(..);
+    let (sender, receiver) = mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::Maintain::Wait);
+    receiver.recv().ok()?.ok()?;
+    let windows = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+    readback.unmap();
+    Some(windows)
+}
@@ -1,15 +1,31 @@
 //! Contains metrics related to video/image quality.
 
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
 pub mod ciede;
 pub mod decode;
+pub mod dynamic;
+pub mod fusion;
+#[cfg(feature = "gpu")]
+mod gpu;
+mod parallel;
 mod pixel;
 pub mod psnr;
 pub mod psnr_hvs;
 pub mod ssim;
+pub mod temporal;
+pub mod verdict;
 
 use crate::MetricsError;
 use decode::*;
+use std::any::Any;
+use std::cmp;
 use std::error::Error;
+use std::io::{self, Write};
+#[cfg(feature = "parallel")]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 pub use pixel::*;
 pub use v_frame::frame::Frame;
@@ -17,6 +33,8 @@ pub use v_frame::plane::Plane;
 
 trait FrameCompare {
     fn can_compare(&self, other: &Self) -> Result<(), MetricsError>;
+    /// Returns whether the visible pixels of all planes are equal.
+    fn is_identical(&self, other: &Self) -> bool;
 }
 
 impl<T: Pixel> FrameCompare for Frame<T> {
@@ -27,10 +45,73 @@ impl<T: Pixel> FrameCompare for Frame<T> {
 
         Ok(())
     }
+
+    fn is_identical(&self, other: &Self) -> bool {
+        self.planes
+            .iter()
+            .zip(other.planes.iter())
+            .all(|(plane1, plane2)| plane1.is_identical(plane2))
+    }
+}
+
+/// Accessors for the layout of the planes of a [`Frame`].
+///
+/// These read the configuration of the planes, so custom processing
+/// does not have to reach into `frame.planes[i].cfg` itself.
+pub trait FrameLayout {
+    /// Returns the width and height of the luma plane, in pixels.
+    fn luma_dimensions(&self) -> (usize, usize);
+    /// Returns the width and height of each chroma plane, in pixels.
+    ///
+    /// Both are zero for monochrome frames.
+    fn chroma_dimensions(&self) -> (usize, usize);
+    /// Returns the horizontal and vertical decimation of the chroma planes,
+    /// e.g. `(1, 1)` for 4:2:0 video.
+    fn chroma_decimation(&self) -> (usize, usize);
+    /// Reconstructs the chroma sampling from the decimation of the chroma planes.
+    ///
+    /// Returns `None` for subsampling which [`ChromaSampling`] cannot describe,
+    /// e.g. 4:1:1 or 4:4:0 video.
+    fn chroma_sampling(&self) -> Option<ChromaSampling>;
+}
+
+impl<T: Pixel> FrameLayout for Frame<T> {
+    fn luma_dimensions(&self) -> (usize, usize) {
+        (self.planes[0].cfg.width, self.planes[0].cfg.height)
+    }
+
+    fn chroma_dimensions(&self) -> (usize, usize) {
+        (self.planes[1].cfg.width, self.planes[1].cfg.height)
+    }
+
+    fn chroma_decimation(&self) -> (usize, usize) {
+        (self.planes[1].cfg.xdec, self.planes[1].cfg.ydec)
+    }
+
+    fn chroma_sampling(&self) -> Option<ChromaSampling> {
+        let (luma_width, luma_height) = self.luma_dimensions();
+        let (chroma_width, chroma_height) = self.chroma_dimensions();
+        if (chroma_width == 0 || chroma_height == 0) && luma_width != 0 && luma_height != 0 {
+            return Some(ChromaSampling::Cs400);
+        }
+        match self.chroma_decimation() {
+            (1, 1) => Some(ChromaSampling::Cs420),
+            (1, 0) => Some(ChromaSampling::Cs422),
+            (0, 0) => Some(ChromaSampling::Cs444),
+            _ => None,
+        }
+    }
 }
 
 pub(crate) trait PlaneCompare {
     fn can_compare(&self, other: &Self) -> Result<(), MetricsError>;
+    /// Returns whether the visible pixels of both planes are equal.
+    ///
+    /// Metrics use this to skip the computation for identical planes.
+    fn is_identical(&self, other: &Self) -> bool;
+    /// Returns the value of all visible pixels if they are equal,
+    /// or `None` if they differ or the plane is empty.
+    fn constant_sample(&self) -> Option<u32>;
 }
 
 impl<T: Pixel> PlaneCompare for Plane<T> {
@@ -42,6 +123,23 @@ impl<T: Pixel> PlaneCompare for Plane<T> {
         }
         Ok(())
     }
+
+    fn is_identical(&self, other: &Self) -> bool {
+        let width = self.cfg.width;
+        self.cfg == other.cfg
+            && self
+                .rows_iter()
+                .zip(other.rows_iter())
+                .all(|(row1, row2)| row1[..width] == row2[..width])
+    }
+
+    fn constant_sample(&self) -> Option<u32> {
+        let width = self.cfg.width;
+        let first = *self.rows_iter().next()?[..width].first()?;
+        self.rows_iter()
+            .all(|row| row[..width].iter().all(|&pix| pix == first))
+            .then(|| u32::cast_from(first))
+    }
 }
 
 pub use v_frame::pixel::ChromaSampling;
@@ -52,6 +150,10 @@ pub(crate) trait ChromaWeight {
 
 impl ChromaWeight for ChromaSampling {
     /// The relative impact of chroma planes compared to luma
+    ///
+    /// Monochrome video has no chroma planes, so its `avg` score equals the luma score.
+    /// `ChromaSampling` has no variants for 4:1:1 and 4:4:0 video, see
+    /// [`ChromaWeights::from_decimation`] for those.
     fn get_chroma_weight(self) -> f64 {
         match self {
             ChromaSampling::Cs420 => 0.25,
@@ -62,8 +164,183 @@ impl ChromaWeight for ChromaSampling {
     }
 }
 
+/// Weights of the chroma planes relative to the luma plane, which are used
+/// to compute the `avg` score of [`PlanarMetrics`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChromaWeights {
+    /// Weight of the U/Cb plane.
+    pub u: f64,
+    /// Weight of the V/Cr plane.
+    pub v: f64,
+}
+
+impl ChromaWeights {
+    /// Uses the same weight for both chroma planes.
+    pub const fn uniform(weight: f64) -> Self {
+        ChromaWeights {
+            u: weight,
+            v: weight,
+        }
+    }
+
+    /// Uses `weight` for both chroma planes if it is `Some`, or the default weights
+    /// of `chroma_sampling` otherwise.
+    ///
+    /// This allows an optional weight, e.g. from a command line argument, to override
+    /// the weights which are derived from the chroma subsampling.
+    pub fn from_override(weight: Option<f64>, chroma_sampling: ChromaSampling) -> Self {
+        weight.map_or(chroma_sampling.into(), ChromaWeights::uniform)
+    }
+
+    /// Uses the relative size of chroma planes which are subsampled by `xdec`
+    /// and `ydec`, i.e. `1 / 2^(xdec + ydec)`.
+    ///
+    /// This gives the default weights for any subsampling, including formats which
+    /// [`ChromaSampling`] cannot describe, e.g. `(2, 0)` for 4:1:1 and `(0, 1)`
+    /// for 4:4:0 video.
+    pub fn from_decimation(xdec: usize, ydec: usize) -> Self {
+        ChromaWeights::uniform(1.0 / (1u64 << (xdec + ydec)) as f64)
+    }
+
+    /// The default weights for the chroma planes of `frame`, based on their actual
+    /// decimation rather than on `chroma_sampling`.
+    ///
+    /// This keeps the weights right for planes which [`ChromaSampling`] cannot
+    /// describe, e.g. 4:1:1 planes in a frame which is passed as `Cs444`.
+    pub(crate) fn for_frame<T: Pixel>(frame: &Frame<T>, chroma_sampling: ChromaSampling) -> Self {
+        let (chroma_width, chroma_height) = frame.chroma_dimensions();
+        if chroma_sampling == ChromaSampling::Cs400 || chroma_width == 0 || chroma_height == 0 {
+            return ChromaWeights::uniform(0.0);
+        }
+        let (xdec, ydec) = frame.chroma_decimation();
+        ChromaWeights::from_decimation(xdec, ydec)
+    }
+
+    /// Sums up the plane scores, weighted relative to the luma plane.
+    ///
+    /// Planes with a weight of zero are skipped, so their score may be `NaN`,
+    /// e.g. for monochrome video.
+    pub(crate) fn weighted_sum(&self, y: f64, u: f64, v: f64) -> f64 {
+        let mut sum = y;
+        if self.u != 0.0 {
+            sum += self.u * u;
+        }
+        if self.v != 0.0 {
+            sum += self.v * v;
+        }
+        sum
+    }
+
+    /// The sum of all plane weights.
+    pub(crate) fn total(&self) -> f64 {
+        1.0 + self.u + self.v
+    }
+}
+
+impl From<ChromaSampling> for ChromaWeights {
+    /// The default weights, based on the size of the chroma planes.
+    fn from(chroma_sampling: ChromaSampling) -> Self {
+        ChromaWeights::uniform(chroma_sampling.get_chroma_weight())
+    }
+}
+
+/// A set of planes to compare, e.g. `PlaneSelection::Y | PlaneSelection::U`.
+///
+/// Planes which are not selected are skipped entirely. Their scores are `NaN`,
+/// and they are left out of the `avg` score.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PlaneSelection(u8);
+
+impl PlaneSelection {
+    /// The Y plane.
+    pub const Y: Self = PlaneSelection(1 << 0);
+    /// The U/Cb plane.
+    pub const U: Self = PlaneSelection(1 << 1);
+    /// The V/Cr plane.
+    pub const V: Self = PlaneSelection(1 << 2);
+    /// Both chroma planes.
+    pub const CHROMA: Self = PlaneSelection(Self::U.0 | Self::V.0);
+    /// All three planes.
+    pub const ALL: Self = PlaneSelection(Self::Y.0 | Self::CHROMA.0);
+
+    /// Returns whether all planes in `other` are selected.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns whether no plane is selected.
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns whether the plane with the given index is selected.
+    pub(crate) const fn has_plane(self, index: usize) -> bool {
+        self.0 & (1 << index) != 0
+    }
+
+    pub(crate) fn validate(self) -> Result<(), MetricsError> {
+        if self.is_empty() {
+            return Err(MetricsError::UnsupportedInput {
+                reason: "No planes selected",
+            });
+        }
+        Ok(())
+    }
+
+    /// Sums up the scores of the selected planes, weighted relative to the luma plane.
+    ///
+    /// With all planes selected, this is the same as [`ChromaWeights::weighted_sum`].
+    pub(crate) fn weighted_sum(self, cweight: ChromaWeights, y: f64, u: f64, v: f64) -> f64 {
+        let mut sum = 0.0;
+        for (index, weight, score) in [(0, 1.0, y), (1, cweight.u, u), (2, cweight.v, v)] {
+            if self.has_plane(index) && weight != 0.0 {
+                sum += weight * score;
+            }
+        }
+        sum
+    }
+
+    /// The sum of the weights of the selected planes.
+    pub(crate) fn total(self, cweight: ChromaWeights) -> f64 {
+        [(0, 1.0), (1, cweight.u), (2, cweight.v)]
+            .into_iter()
+            .filter(|&(index, _)| self.has_plane(index))
+            .map(|(_, weight)| weight)
+            .sum()
+    }
+}
+
+impl Default for PlaneSelection {
+    fn default() -> Self {
+        PlaneSelection::ALL
+    }
+}
+
+impl std::ops::BitOr for PlaneSelection {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        PlaneSelection(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitAnd for PlaneSelection {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        PlaneSelection(self.0 & rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for PlaneSelection {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
 /// Sample position for subsampled chroma
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ChromaSamplePosition {
     /// The source video transfer function is not signaled. This crate will assume
     /// no transformation needs to be done on this data, but there is a risk of metric
@@ -81,10 +358,27 @@ pub enum ChromaSamplePosition {
     Interpolated,
 }
 
+/// Transfer function which maps the sample values to the displayed luminance
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TransferCharacteristics {
+    /// The transfer function is not signaled.
+    #[default]
+    Unknown,
+    /// Standard dynamic range, displayed as specified by BT.1886.
+    Sdr,
+    /// Perceptual quantizer as specified by SMPTE ST 2084 and BT.2100.
+    Pq,
+    /// Hybrid log-gamma as specified by ARIB STD-B67 and BT.2100.
+    Hlg,
+}
+
 /// Certain metrics return a value per plane. This struct contains the output
 /// for those metrics per plane, as well as a weighted average of the planes.
+///
+/// For monochrome (4:0:0) video, `u` and `v` are `NaN` and `avg` is equal to `y`.
 #[derive(Debug, Default, Clone, Copy, PartialEq)]
-#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PlanarMetrics {
     /// Metric value for the Y plane.
     pub y: f64,
@@ -96,9 +390,1181 @@ pub struct PlanarMetrics {
     pub avg: f64,
 }
 
+impl PlanarMetrics {
+    /// Returns the fields in the order `[y, u, v, avg]`, e.g. to pass them over a C ABI.
+    pub const fn to_array(&self) -> [f64; 4] {
+        [self.y, self.u, self.v, self.avg]
+    }
+
+    /// Creates the metrics from an array of the fields in the order `[y, u, v, avg]`,
+    /// the inverse of [`PlanarMetrics::to_array`].
+    pub const fn from_array([y, u, v, avg]: [f64; 4]) -> Self {
+        PlanarMetrics { y, u, v, avg }
+    }
+
+    /// Returns the difference `self - other` of each field.
+    ///
+    /// Fields which are `NaN` in either result are `NaN` in the difference as well.
+    pub fn difference(&self, other: &Self) -> Self {
+        self.zip_with(*other, |a, b| a - b)
+    }
+
+    /// Returns whether each field differs from the field of `other` by at most `tolerance`.
+    ///
+    /// Fields which are `NaN` in both results, e.g. the chroma scores of monochrome video,
+    /// are considered equal, as are infinite scores with the same sign.
+    pub fn approx_eq(&self, other: &Self, tolerance: f64) -> bool {
+        self.check_approx_eq(other, tolerance).is_ok()
+    }
+
+    /// Same as [`PlanarMetrics::approx_eq`], but returns a message which lists
+    /// the differing fields, e.g. for the assertions of a regression test.
+    pub fn check_approx_eq(&self, other: &Self, tolerance: f64) -> Result<(), String> {
+        let mismatches: Vec<String> = [
+            ("y", self.y, other.y),
+            ("u", self.u, other.u),
+            ("v", self.v, other.v),
+            ("avg", self.avg, other.avg),
+        ]
+        .into_iter()
+        .filter(|&(_, a, b)| {
+            if a.is_nan() || b.is_nan() {
+                !(a.is_nan() && b.is_nan())
+            } else {
+                // Equal infinities have a difference of `NaN`, which is not greater
+                (a - b).abs() > tolerance
+            }
+        })
+        .map(|(name, a, b)| format!("{name}: {a} vs {b} (difference {})", a - b))
+        .collect();
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Metrics differ by more than {tolerance}: {}",
+                mismatches.join(", ")
+            ))
+        }
+    }
+}
+
+/// Writes per-frame scores as CSV with the columns `frame`, `y`, `u`, `v` and `avg`.
+///
+/// `frames` contains the score of each frame, e.g. as returned by
+/// [`ssim::calculate_frame_ssim`], and the `frame` column is the index into it.
+/// `NaN` scores, such as the chroma scores of monochrome video, are written as `NaN`
+/// and infinite scores as `inf`.
+pub fn write_frames_csv<W: Write>(frames: &[PlanarMetrics], mut writer: W) -> io::Result<()> {
+    writeln!(writer, "frame,y,u,v,avg")?;
+    for (i, score) in frames.iter().enumerate() {
+        writeln!(
+            writer,
+            "{},{},{},{},{}",
+            i, score.y, score.u, score.v, score.avg
+        )?;
+    }
+    writer.flush()
+}
+
+/// The result of a metric over the frames of one type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameTypeResult<R> {
+    /// The type of the frames.
+    pub frame_type: FrameType,
+    /// The number of frames of this type.
+    pub frames: usize,
+    /// The metric over the frames of this type.
+    pub result: R,
+}
+
+/// The result of a metric over all frames of a video, along with subtotals
+/// for each type of frame.
+///
+/// This shows how the quality differs between e.g. intra and predicted frames.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameTypeMetrics<R> {
+    /// The metric over all frames.
+    pub all: R,
+    /// The metric over the frames of each type, in the order I, P, B, unknown.
+    /// Types without any frames are left out.
+    pub by_type: Vec<FrameTypeResult<R>>,
+}
+
+impl<R> FrameTypeMetrics<R> {
+    /// Converts each result with `f`.
+    pub(crate) fn map<S, F: Fn(R) -> S>(self, f: F) -> FrameTypeMetrics<S> {
+        FrameTypeMetrics {
+            all: f(self.all),
+            by_type: self
+                .by_type
+                .into_iter()
+                .map(|subtotal| FrameTypeResult {
+                    frame_type: subtotal.frame_type,
+                    frames: subtotal.frames,
+                    result: f(subtotal.result),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The result of a metric over a video, along with statistics of the per-frame scores.
+///
+/// The standard deviation shows how stable the quality is over time: a high value
+/// flags encodes with inconsistent quality, even if their overall score looks fine.
+/// Scores which are infinite, e.g. the SSIM of identical frames, make the
+/// statistics infinite or `NaN`.
+///
+/// As a higher score means a better quality for all metrics, `min` is the score of
+/// the worst frame and `max` the score of the best one. The frames are counted in
+/// decode order from 0, separately for each plane, and frames whose score is `NaN`
+/// are left out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameStatistics<R: FrameScore> {
+    /// The metric over all frames, the same as returned by the regular functions.
+    pub all: R,
+    /// The mean of the per-frame scores.
+    pub mean: R,
+    /// The population variance of the per-frame scores.
+    pub variance: R,
+    /// The standard deviation of the per-frame scores.
+    pub stddev: R,
+    /// The lowest per-frame score.
+    pub min: R,
+    /// The frame with the lowest score.
+    pub min_frame: R::FrameIndex,
+    /// The highest per-frame score.
+    pub max: R,
+    /// The frame with the highest score.
+    pub max_frame: R::FrameIndex,
+}
+
+/// The index of a frame for each plane of a [`PlanarMetrics`], e.g. the worst frame
+/// of each plane in [`FrameStatistics`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlanarFrameIndices {
+    /// Frame index for the Y plane.
+    pub y: usize,
+    /// Frame index for the U/Cb plane.
+    pub u: usize,
+    /// Frame index for the V/Cr plane.
+    pub v: usize,
+    /// Frame index for the weighted average of the three planes.
+    pub avg: usize,
+}
+
+mod private {
+    /// Keeps [`FrameScore`](super::FrameScore) from being implemented outside of
+    /// this crate, so methods can be added to it without breaking changes.
+    pub trait Sealed {}
+
+    impl Sealed for f64 {}
+    impl Sealed for super::PlanarMetrics {}
+}
+
+/// A score which [`FrameStatistics`] can be calculated for.
+///
+/// This trait is sealed. It is implemented for `f64` and [`PlanarMetrics`], the
+/// scores returned by the metrics, and cannot be implemented for other types.
+pub trait FrameScore: private::Sealed + Copy + Default {
+    /// The index of a frame for each value of the score.
+    type FrameIndex: Copy + Default;
+
+    /// Combines each value of `self` with the corresponding value of `other`.
+    fn zip_with<F: Fn(f64, f64) -> f64>(self, other: Self, f: F) -> Self;
+
+    /// Returns the values of `frames` which `replaces` prefers over all others,
+    /// along with the index of their frame.
+    fn select_frames<F: Fn(f64, f64) -> bool>(
+        frames: &[Self],
+        replaces: F,
+    ) -> (Self, Self::FrameIndex);
+}
+
+/// Returns the value of `values` which `replaces` prefers over all others, along
+/// with its index. Values which are `NaN` are skipped.
+fn select_value<I, F>(values: I, replaces: F) -> (f64, usize)
+where
+    I: Iterator<Item = f64>,
+    F: Fn(f64, f64) -> bool,
+{
+    values
+        .enumerate()
+        .filter(|(_, value)| !value.is_nan())
+        .fold(
+            None,
+            |selected: Option<(usize, f64)>, (i, value)| match selected {
+                Some((_, best)) if !replaces(value, best) => selected,
+                _ => Some((i, value)),
+            },
+        )
+        .map_or((f64::NAN, 0), |(i, value)| (value, i))
+}
+
+impl FrameScore for f64 {
+    type FrameIndex = usize;
+
+    fn zip_with<F: Fn(f64, f64) -> f64>(self, other: Self, f: F) -> Self {
+        f(self, other)
+    }
+
+    fn select_frames<F: Fn(f64, f64) -> bool>(frames: &[Self], replaces: F) -> (Self, usize) {
+        select_value(frames.iter().copied(), replaces)
+    }
+}
+
+impl FrameScore for PlanarMetrics {
+    type FrameIndex = PlanarFrameIndices;
+
+    fn zip_with<F: Fn(f64, f64) -> f64>(self, other: Self, f: F) -> Self {
+        PlanarMetrics {
+            y: f(self.y, other.y),
+            u: f(self.u, other.u),
+            v: f(self.v, other.v),
+            avg: f(self.avg, other.avg),
+        }
+    }
+
+    fn select_frames<F: Fn(f64, f64) -> bool>(
+        frames: &[Self],
+        replaces: F,
+    ) -> (Self, PlanarFrameIndices) {
+        let select =
+            |field: fn(&PlanarMetrics) -> f64| select_value(frames.iter().map(field), &replaces);
+        let (y, y_frame) = select(|m| m.y);
+        let (u, u_frame) = select(|m| m.u);
+        let (v, v_frame) = select(|m| m.v);
+        let (avg, avg_frame) = select(|m| m.avg);
+        (
+            PlanarMetrics { y, u, v, avg },
+            PlanarFrameIndices {
+                y: y_frame,
+                u: u_frame,
+                v: v_frame,
+                avg: avg_frame,
+            },
+        )
+    }
+}
+
+/// Calculates the statistics of the per-frame scores `frames`.
+fn frame_statistics<R: FrameScore>(all: R, frames: &[R]) -> FrameStatistics<R> {
+    let n = frames.len() as f64;
+    let mean = frames
+        .iter()
+        .fold(R::default(), |acc, &score| {
+            acc.zip_with(score, |a, b| a + b)
+        })
+        .zip_with(R::default(), |sum, _| sum / n);
+    let variance = frames
+        .iter()
+        .fold(R::default(), |acc, &score| {
+            acc.zip_with(
+                score.zip_with(mean, |x, mean| (x - mean).powi(2)),
+                |a, b| a + b,
+            )
+        })
+        .zip_with(R::default(), |sum, _| sum / n);
+    let (min, min_frame) = R::select_frames(frames, |value, min| value < min);
+    let (max, max_frame) = R::select_frames(frames, |value, max| value > max);
+    FrameStatistics {
+        all,
+        mean,
+        variance,
+        stddev: variance.zip_with(R::default(), |variance, _| variance.sqrt()),
+        min,
+        min_frame,
+        max,
+        max_frame,
+    }
+}
+
+/// A rectangular region of a frame, given in luma samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    /// Horizontal offset of the left edge.
+    pub x: usize,
+    /// Vertical offset of the top edge.
+    pub y: usize,
+    /// Width of the region.
+    pub width: usize,
+    /// Height of the region.
+    pub height: usize,
+}
+
+impl Rect {
+    /// Checks that the region lies within a frame of the given size, and that
+    /// its origin is aligned to the chroma subsampling.
+    pub(crate) fn validate(
+        &self,
+        width: usize,
+        height: usize,
+        xdec: usize,
+        ydec: usize,
+    ) -> Result<(), MetricsError> {
+        if self.width == 0 || self.height == 0 {
+            return Err(MetricsError::UnsupportedInput {
+                reason: "Crop region is empty",
+            });
+        }
+        if self.x + self.width > width || self.y + self.height > height {
+            return Err(MetricsError::UnsupportedInput {
+                reason: "Crop region exceeds the frame dimensions",
+            });
+        }
+        if self.x & ((1 << xdec) - 1) != 0 || self.y & ((1 << ydec) - 1) != 0 {
+            return Err(MetricsError::UnsupportedInput {
+                reason: "Crop region is not aligned to the chroma subsampling",
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Copies the region `rect` out of `frame`.
+///
+/// The chroma planes are cropped to the matching subsampled region, so the
+/// origin of `rect` must be aligned to the chroma subsampling, e.g. even
+/// for 4:2:0 video. The result can be passed to any of the frame metrics,
+/// which allows excluding letterboxing or other regions from the comparison.
+pub fn crop_frame<T: Pixel>(frame: &Frame<T>, rect: Rect) -> Result<Frame<T>, MetricsError> {
+    let luma = &frame.planes[0].cfg;
+    let chroma = &frame.planes[1].cfg;
+    rect.validate(luma.width, luma.height, chroma.xdec, chroma.ydec)?;
+
+    Ok(Frame {
+        planes: [
+            crop_plane(&frame.planes[0], rect),
+            crop_plane(&frame.planes[1], rect),
+            crop_plane(&frame.planes[2], rect),
+        ],
+    })
+}
+
+fn crop_plane<T: Pixel>(plane: &Plane<T>, rect: Rect) -> Plane<T> {
+    let (xdec, ydec) = (plane.cfg.xdec, plane.cfg.ydec);
+    let x = rect.x >> xdec;
+    let y = rect.y >> ydec;
+    // The chroma planes of monochrome frames are empty, so nothing is left of them
+    let width = cmp::min(
+        (rect.width + (1 << xdec) - 1) >> xdec,
+        plane.cfg.width.saturating_sub(x),
+    );
+    let height = cmp::min(
+        (rect.height + (1 << ydec) - 1) >> ydec,
+        plane.cfg.height.saturating_sub(y),
+    );
+    if width == 0 || height == 0 {
+        return Plane::new(0, 0, xdec, ydec, 0, 0);
+    }
+
+    // The metrics expect the rows of a plane to be tightly packed.
+    let data: Vec<T> = (y..(y + height))
+        .flat_map(|row| plane.row(row as isize)[x..(x + width)].iter().copied())
+        .collect();
+    let mut cropped = Plane::from_slice(&data, width);
+    cropped.cfg.xdec = xdec;
+    cropped.cfg.ydec = ydec;
+    cropped
+}
+
+/// Splits both frames into tiles of `tile_width` by `tile_height` luma samples and
+/// scores each pair of tiles with `metric`.
+///
+/// Returns the rows of tiles from top to bottom, each from left to right.
+/// The tiles at the right and bottom edges are smaller if the frame size is
+/// not a multiple of the tile size.
+pub(crate) fn calculate_frame_tiled<T: Pixel>(
+    frame1: &Frame<T>,
+    frame2: &Frame<T>,
+    tile_width: usize,
+    tile_height: usize,
+    metric: impl Fn(&Frame<T>, &Frame<T>) -> Result<PlanarMetrics, Box<dyn Error>>,
+) -> Result<Vec<Vec<PlanarMetrics>>, Box<dyn Error>> {
+    frame1.can_compare(frame2)?;
+    let luma = &frame1.planes[0].cfg;
+    let chroma = &frame1.planes[1].cfg;
+    if tile_width == 0 || tile_height == 0 {
+        return Err(Box::new(MetricsError::UnsupportedInput {
+            reason: "Tile size must not be zero",
+        }));
+    }
+    if tile_width & ((1 << chroma.xdec) - 1) != 0 || tile_height & ((1 << chroma.ydec) - 1) != 0 {
+        return Err(Box::new(MetricsError::UnsupportedInput {
+            reason: "Tile size must be a multiple of the chroma subsampling",
+        }));
+    }
+
+    (0..luma.height)
+        .step_by(tile_height)
+        .map(|y| {
+            (0..luma.width)
+                .step_by(tile_width)
+                .map(|x| {
+                    let rect = Rect {
+                        x,
+                        y,
+                        width: cmp::min(tile_width, luma.width - x),
+                        height: cmp::min(tile_height, luma.height - y),
+                    };
+                    metric(&crop_frame(frame1, rect)?, &crop_frame(frame2, rect)?)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The filter used by [`resize_frame`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResizeFilter {
+    /// Linear interpolation between the nearest samples.
+    Bilinear,
+    /// A windowed sinc filter with three lobes, which keeps more detail than
+    /// bilinear filtering at the cost of some ringing.
+    #[default]
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    /// The distance from the center at which the filter becomes zero.
+    fn support(self) -> f64 {
+        match self {
+            ResizeFilter::Bilinear => 1.0,
+            ResizeFilter::Lanczos3 => 3.0,
+        }
+    }
+
+    fn weight(self, x: f64) -> f64 {
+        let x = x.abs();
+        match self {
+            ResizeFilter::Bilinear => (1.0 - x).max(0.0),
+            ResizeFilter::Lanczos3 if x < f64::EPSILON => 1.0,
+            ResizeFilter::Lanczos3 if x < 3.0 => {
+                let px = std::f64::consts::PI * x;
+                3.0 * px.sin() * (px / 3.0).sin() / (px * px)
+            }
+            ResizeFilter::Lanczos3 => 0.0,
+        }
+    }
+}
+
+/// Resizes a frame to `width` x `height` luma samples.
+///
+/// This allows comparing videos with different resolutions, e.g. a 720p encode
+/// with its 1080p source. The chroma planes are resized to the matching subsampled
+/// size, and the samples are clamped to the range of `bit_depth`. The returned frame
+/// has no padding.
+///
+/// Note that the metrics of a resized video do not only measure the encode, but
+/// also the loss of detail from scaling, which depends on the filter. They are
+/// therefore not comparable to the metrics of videos at the reference resolution.
+pub fn resize_frame<T: Pixel>(
+    frame: &Frame<T>,
+    width: usize,
+    height: usize,
+    bit_depth: usize,
+    filter: ResizeFilter,
+) -> Result<Frame<T>, MetricsError> {
+    if width == 0 || height == 0 {
+        return Err(MetricsError::UnsupportedInput {
+            reason: "Target size of a resize is empty",
+        });
+    }
+    if bit_depth == 0 || bit_depth > 16 {
+        return Err(MetricsError::UnsupportedInput {
+            reason: "Bit depth must be between 1 and 16",
+        });
+    }
+    let max = (1u32 << bit_depth) - 1;
+    let resize = |plane: &Plane<T>| {
+        let (xdec, ydec) = (plane.cfg.xdec, plane.cfg.ydec);
+        // Monochrome video has empty chroma planes
+        if plane.cfg.width == 0 || plane.cfg.height == 0 {
+            return Plane::new(0, 0, xdec, ydec, 0, 0);
+        }
+        let width = (width + (1 << xdec) - 1) >> xdec;
+        let height = (height + (1 << ydec) - 1) >> ydec;
+        resize_plane(plane, width, height, max, filter)
+    };
+    Ok(Frame {
+        planes: [
+            resize(&frame.planes[0]),
+            resize(&frame.planes[1]),
+            resize(&frame.planes[2]),
+        ],
+    })
+}
+
+fn resize_plane<T: Pixel>(
+    plane: &Plane<T>,
+    width: usize,
+    height: usize,
+    max: u32,
+    filter: ResizeFilter,
+) -> Plane<T> {
+    let (src_width, src_height) = (plane.cfg.width, plane.cfg.height);
+    let horiz_taps = resize_taps(src_width, width, filter);
+    let vert_taps = resize_taps(src_height, height, filter);
+
+    // Filter the rows first, then the columns
+    let mut rows = Vec::with_capacity(src_height * width);
+    for row in plane.rows_iter() {
+        rows.extend(horiz_taps.iter().map(|taps| {
+            taps.iter()
+                .map(|&(x, weight)| u32::cast_from(row[x]) as f64 * weight)
+                .sum::<f64>()
+        }));
+    }
+    let mut data = Vec::with_capacity(width * height);
+    for taps in &vert_taps {
+        data.extend((0..width).map(|x| {
+            let value = taps
+                .iter()
+                .map(|&(y, weight)| rows[y * width + x] * weight)
+                .sum::<f64>();
+            T::cast_from((value.round().max(0.0) as u32).min(max))
+        }));
+    }
+    let mut resized = Plane::from_slice(&data, width);
+    resized.cfg.xdec = plane.cfg.xdec;
+    resized.cfg.ydec = plane.cfg.ydec;
+    resized
+}
+
+/// Returns the source samples and their weights for each of `dst_len` samples
+/// resized from `src_len` samples.
+fn resize_taps(src_len: usize, dst_len: usize, filter: ResizeFilter) -> Vec<Vec<(usize, f64)>> {
+    let scale = src_len as f64 / dst_len as f64;
+    // When downscaling, the filter is widened to cover all source samples
+    let filter_scale = scale.max(1.0);
+    let support = filter.support() * filter_scale;
+    (0..dst_len)
+        .map(|i| {
+            // The centers of the samples are aligned, not their edges
+            let center = (i as f64 + 0.5) * scale - 0.5;
+            let first = (center - support).ceil() as isize;
+            let last = (center + support).floor() as isize;
+            let mut taps: Vec<(usize, f64)> = (first..=last)
+                .map(|j| {
+                    let weight = filter.weight((j as f64 - center) / filter_scale);
+                    // Samples outside of the plane repeat the edge
+                    (j.clamp(0, src_len as isize - 1) as usize, weight)
+                })
+                .filter(|&(_, weight)| weight != 0.0)
+                .collect();
+            let total: f64 = taps.iter().map(|&(_, weight)| weight).sum();
+            for (_, weight) in taps.iter_mut() {
+                *weight /= total;
+            }
+            taps
+        })
+        .collect()
+}
+
+/// Upsamples the chroma planes of a frame to the luma resolution, which turns it
+/// into a 4:4:4 frame.
+///
+/// Each chroma sample is interpolated bilinearly from the nearest samples of the
+/// subsampled plane, taking their position relative to the luma samples into account,
+/// see [`upsample_chroma_plane`]. Frames which are already 4:4:4 are copied as they are.
+/// Monochrome frames have no chroma planes, so an error is returned for them.
+/// The returned frame has no padding.
+pub fn upsample_chroma<T: Pixel>(
+    frame: &Frame<T>,
+    chroma_sample_position: ChromaSamplePosition,
+) -> Result<Frame<T>, MetricsError> {
+    let luma = &frame.planes[0].cfg;
+    if frame.planes[1].cfg.width == 0 || frame.planes[1].cfg.height == 0 {
+        return Err(MetricsError::UnsupportedInput {
+            reason: "Monochrome video has no chroma planes",
+        });
+    }
+    let (width, height) = (luma.width, luma.height);
+    let full_frame = Rect {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    };
+    Ok(Frame {
+        planes: [
+            // Copies the luma plane without its padding
+            crop_plane(&frame.planes[0], full_frame),
+            upsample_chroma_plane(&frame.planes[1], width, height, chroma_sample_position),
+            upsample_chroma_plane(&frame.planes[2], width, height, chroma_sample_position),
+        ],
+    })
+}
+
+/// Upsamples a subsampled chroma plane to `width` x `height` samples, usually the
+/// size of the luma plane.
+///
+/// The samples are interpolated bilinearly. Where the chroma samples are located
+/// matters for the result: co-sited samples are aligned with the first luma sample,
+/// e.g. horizontally for MPEG-2 style 4:2:0 ([`ChromaSamplePosition::Vertical`]),
+/// while other samples are centered between the luma samples they cover, e.g.
+/// for JPEG ([`ChromaSamplePosition::Bilateral`]). An unknown position is treated
+/// as centered. The returned plane has no padding.
+pub fn upsample_chroma_plane<T: Pixel>(
+    plane: &Plane<T>,
+    width: usize,
+    height: usize,
+    chroma_sample_position: ChromaSamplePosition,
+) -> Plane<T> {
+    let (src_width, src_height) = (plane.cfg.width, plane.cfg.height);
+    if width == 0 || height == 0 || src_width == 0 || src_height == 0 {
+        return Plane::new(0, 0, 0, 0, 0, 0);
+    }
+    let (cosited_x, cosited_y) = match chroma_sample_position {
+        ChromaSamplePosition::Colocated => (true, true),
+        ChromaSamplePosition::Vertical => (true, false),
+        ChromaSamplePosition::Bilateral
+        | ChromaSamplePosition::Interpolated
+        | ChromaSamplePosition::Unknown => (false, false),
+    };
+    let horiz_taps = upsample_taps(src_width, width, plane.cfg.xdec, cosited_x);
+    let vert_taps = upsample_taps(src_height, height, plane.cfg.ydec, cosited_y);
+
+    let sample = |x: usize, y: usize| u32::cast_from(plane.row(y as isize)[x]) as f64;
+    let mut data = Vec::with_capacity(width * height);
+    for &(y0, y1, fy) in &vert_taps {
+        data.extend(horiz_taps.iter().map(|&(x0, x1, fx)| {
+            let top = sample(x0, y0) * (1.0 - fx) + sample(x1, y0) * fx;
+            let bottom = sample(x0, y1) * (1.0 - fx) + sample(x1, y1) * fx;
+            T::cast_from((top * (1.0 - fy) + bottom * fy).round() as u32)
+        }));
+    }
+    Plane::from_slice(&data, width)
+}
+
+/// Returns the two neighbouring source samples and the weight of the second one
+/// for each of `dst_len` samples upsampled from `src_len` samples, which are
+/// subsampled by `dec`.
+fn upsample_taps(
+    src_len: usize,
+    dst_len: usize,
+    dec: usize,
+    cosited: bool,
+) -> Vec<(usize, usize, f64)> {
+    let scale = (1 << dec) as f64;
+    (0..dst_len)
+        .map(|i| {
+            // The position of the output sample in units of source samples
+            let pos = if cosited {
+                i as f64 / scale
+            } else {
+                (i as f64 + 0.5) / scale - 0.5
+            };
+            // Samples outside of the plane repeat the edge
+            let pos = pos.clamp(0.0, (src_len - 1) as f64);
+            let first = pos.floor() as usize;
+            let second = cmp::min(first + 1, src_len - 1);
+            (first, second, pos - first as f64)
+        })
+        .collect()
+}
+
+/// The order in which the two fields of an interlaced frame were captured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FieldOrder {
+    /// The top field, which holds the even lines counting from zero, was captured first.
+    /// This is the usual order for HD content.
+    #[default]
+    TopFieldFirst,
+    /// The bottom field, which holds the odd lines, was captured first.
+    /// This is the usual order for DV content.
+    BottomFieldFirst,
+}
+
+/// How the fields of an interlaced frame are turned into progressive frames.
+///
+/// Both modes turn each interlaced frame into two progressive frames, one per
+/// field, so the result has twice the frame rate of the input. Comparing interlaced
+/// frames directly treats the combing between fields as detail, which makes
+/// especially SSIM misleading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Deinterlace {
+    /// Each field becomes a frame of half the height, without any interpolation.
+    /// This compares the videos field by field.
+    SeparateFields,
+    /// Each field becomes a full height frame, with the missing lines interpolated
+    /// from the lines above and below them ("bob" deinterlacing).
+    Bob,
+}
+
+/// Splits an interlaced frame into two progressive frames, in the order in which
+/// the fields were captured.
+///
+/// Every plane must have an even number of lines, e.g. the height of 4:2:0 video
+/// must be a multiple of 4. See [`Deinterlace`] for the available modes.
+/// The returned frames have no padding.
+pub fn deinterlace_frame<T: Pixel>(
+    frame: &Frame<T>,
+    mode: Deinterlace,
+    field_order: FieldOrder,
+) -> Result<[Frame<T>; 2], MetricsError> {
+    if frame.planes.iter().any(|plane| plane.cfg.height & 1 != 0) {
+        return Err(MetricsError::UnsupportedInput {
+            reason: "Interlaced planes must have an even number of lines",
+        });
+    }
+
+    let field = |parity| Frame {
+        planes: [
+            field_plane(&frame.planes[0], parity, mode),
+            field_plane(&frame.planes[1], parity, mode),
+            field_plane(&frame.planes[2], parity, mode),
+        ],
+    };
+    Ok(match field_order {
+        FieldOrder::TopFieldFirst => [field(0), field(1)],
+        FieldOrder::BottomFieldFirst => [field(1), field(0)],
+    })
+}
+
+/// Extracts the field with the lines of the given parity from a plane.
+fn field_plane<T: Pixel>(plane: &Plane<T>, parity: usize, mode: Deinterlace) -> Plane<T> {
+    let (xdec, ydec) = (plane.cfg.xdec, plane.cfg.ydec);
+    let (width, height) = (plane.cfg.width, plane.cfg.height);
+    if width == 0 || height == 0 {
+        return Plane::new(0, 0, xdec, ydec, 0, 0);
+    }
+
+    let row = |y: usize| &plane.row(y as isize)[..width];
+    // The metrics expect the rows of a plane to be tightly packed.
+    let data: Vec<T> = match mode {
+        Deinterlace::SeparateFields => (parity..height)
+            .step_by(2)
+            .flat_map(|y| row(y).iter().copied())
+            .collect(),
+        Deinterlace::Bob => (0..height)
+            .flat_map(|y| {
+                // Lines of the other field are averaged from their neighbours,
+                // or copied from the only neighbour at the edges.
+                let (above, below) = if y & 1 == parity {
+                    (y, y)
+                } else {
+                    let above = if y > 0 { y - 1 } else { y + 1 };
+                    let below = if y + 1 < height { y + 1 } else { y - 1 };
+                    (above, below)
+                };
+                row(above)
+                    .iter()
+                    .zip(row(below))
+                    .map(|(&a, &b)| T::cast_from((u32::cast_from(a) + u32::cast_from(b) + 1) >> 1))
+            })
+            .collect(),
+    };
+    let mut field = Plane::from_slice(&data, width);
+    field.cfg.xdec = xdec;
+    field.cfg.ydec = ydec;
+    field
+}
+
+/// Converts the samples of a frame from one bit depth to another.
+///
+/// Increasing the bit depth shifts the samples to the left, so that e.g. the
+/// 8-bit value 255 becomes 1020 at 10 bits. Decreasing the bit depth rounds the
+/// samples to the nearest value of the target bit depth.
+/// The returned frame has no padding.
+pub fn convert_bit_depth<S: Pixel, T: Pixel>(
+    frame: &Frame<S>,
+    from_bit_depth: usize,
+    to_bit_depth: usize,
+) -> Frame<T> {
+    Frame {
+        planes: [
+            convert_plane(&frame.planes[0], from_bit_depth, to_bit_depth),
+            convert_plane(&frame.planes[1], from_bit_depth, to_bit_depth),
+            convert_plane(&frame.planes[2], from_bit_depth, to_bit_depth),
+        ],
+    }
+}
+
+fn convert_plane<S: Pixel, T: Pixel>(plane: &Plane<S>, from: usize, to: usize) -> Plane<T> {
+    let (xdec, ydec) = (plane.cfg.xdec, plane.cfg.ydec);
+    if plane.cfg.width == 0 || plane.cfg.height == 0 {
+        return Plane::new(0, 0, xdec, ydec, 0, 0);
+    }
+
+    let max = (1u32 << to) - 1;
+    let data: Vec<T> = plane
+        .rows_iter()
+        .flatten()
+        .map(|&sample| {
+            let sample = u32::cast_from(sample);
+            T::cast_from(if to >= from {
+                sample << (to - from)
+            } else {
+                let shift = from - to;
+                ((sample + (1 << (shift - 1))) >> shift).min(max)
+            })
+        })
+        .collect();
+    let mut converted = Plane::from_slice(&data, plane.cfg.width);
+    converted.cfg.xdec = xdec;
+    converted.cfg.ydec = ydec;
+    converted
+}
+
+/// Creates a plane from a buffer of samples, e.g. from an external decoder.
+///
+/// The buffer holds `height` rows of `width` samples, with the start of each
+/// row `stride` samples after the start of the previous one. An error is returned
+/// if the buffer is shorter than `stride * height` samples. `xdec` and `ydec` are
+/// the subsampling of the plane, e.g. 1 and 1 for the chroma planes of 4:2:0 video.
+pub fn plane_from_slice<T: Pixel>(
+    data: &[T],
+    width: usize,
+    height: usize,
+    stride: usize,
+    xdec: usize,
+    ydec: usize,
+) -> Result<Plane<T>, MetricsError> {
+    if stride < width {
+        return Err(MetricsError::UnsupportedInput {
+            reason: "Plane stride is smaller than its width",
+        });
+    }
+    if data.len() < stride * height {
+        return Err(MetricsError::UnsupportedInput {
+            reason: "Plane data is shorter than stride * height",
+        });
+    }
+    if width == 0 || height == 0 {
+        return Ok(Plane::new(0, 0, xdec, ydec, 0, 0));
+    }
+
+    // The metrics expect the rows of a plane to be tightly packed.
+    let data: Vec<T> = data
+        .chunks(stride)
+        .take(height)
+        .flat_map(|row| row[..width].iter().copied())
+        .collect();
+    let mut plane = Plane::from_slice(&data, width);
+    plane.cfg.xdec = xdec;
+    plane.cfg.ydec = ydec;
+    Ok(plane)
+}
+
+/// Creates a frame from its luma and chroma planes, in plane order (Y, U, V).
+///
+/// The chroma planes must have the same size and subsampling, and their size
+/// must match the size of the luma plane after subsampling. For monochrome video,
+/// the chroma planes are empty.
+pub fn frame_from_planes<T: Pixel>(planes: [Plane<T>; 3]) -> Result<Frame<T>, MetricsError> {
+    let [luma, u, v] = &planes;
+    if u.cfg != v.cfg {
+        return Err(MetricsError::InputMismatch {
+            reason: "Chroma planes do not match",
+        });
+    }
+    let (xdec, ydec) = (u.cfg.xdec, u.cfg.ydec);
+    let monochrome = u.cfg.width == 0 && u.cfg.height == 0;
+    if !monochrome
+        && (u.cfg.width != (luma.cfg.width + (1 << xdec) - 1) >> xdec
+            || u.cfg.height != (luma.cfg.height + (1 << ydec) - 1) >> ydec)
+    {
+        return Err(MetricsError::InputMismatch {
+            reason: "Chroma plane size does not match the luma plane",
+        });
+    }
+    Ok(Frame { planes })
+}
+
+/// Checks that the pixel type `T` matches `bit_depth`, i.e. `u8` for a bit depth
+/// of up to 8 and `u16` above that.
+pub(crate) fn check_pixel_width<T: Pixel>(bit_depth: usize) -> Result<(), MetricsError> {
+    let bytes = std::mem::size_of::<T>();
+    if (bytes == 1 && bit_depth > 8) || (bytes == 2 && bit_depth <= 8) {
+        return Err(MetricsError::InputMismatch {
+            reason: "Bit depth does not match pixel width",
+        });
+    }
+    Ok(())
+}
+
+/// Creates a frame from a buffer of planar YUV samples, e.g. one frame of a raw
+/// `.yuv` file.
+///
+/// The Y, U and V planes are stored one after another without padding, with the
+/// chroma planes subsampled according to `chroma_sampling`. Samples take one byte
+/// for a bit depth of up to 8, and two little-endian bytes above that, which also
+/// has to match the pixel type `T`. An error is returned if the length of `data`
+/// does not match this geometry exactly.
+pub fn frame_from_yuv_bytes<T: Pixel>(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    bit_depth: usize,
+    chroma_sampling: ChromaSampling,
+) -> Result<Frame<T>, MetricsError> {
+    if bit_depth == 0 || bit_depth > 16 {
+        return Err(MetricsError::UnsupportedInput {
+            reason: "Bit depth must be between 1 and 16",
+        });
+    }
+    check_pixel_width::<T>(bit_depth)?;
+    let bytes_per_sample = if bit_depth > 8 { 2 } else { 1 };
+    let (xdec, ydec) = chroma_sampling.get_decimation().unwrap_or((0, 0));
+    let (chroma_width, chroma_height) = chroma_sampling.get_chroma_dimensions(width, height);
+    let luma_len = width * height * bytes_per_sample;
+    let chroma_len = chroma_width * chroma_height * bytes_per_sample;
+    if data.len() != luma_len + 2 * chroma_len {
+        return Err(MetricsError::InputMismatch {
+            reason: "Buffer length does not match the frame geometry",
+        });
+    }
+
+    let plane = |data: &[u8], width, height, xdec, ydec| {
+        let samples: Vec<T> = if bytes_per_sample == 1 {
+            data.iter().map(|&sample| T::cast_from(sample)).collect()
+        } else {
+            data.chunks_exact(2)
+                .map(|sample| T::cast_from(u16::from_le_bytes([sample[0], sample[1]])))
+                .collect()
+        };
+        plane_from_slice(&samples, width, height, width, xdec, ydec)
+    };
+    let (luma, chroma) = data.split_at(luma_len);
+    let (u, v) = chroma.split_at(chroma_len);
+    frame_from_planes([
+        plane(luma, width, height, 0, 0)?,
+        plane(u, chroma_width, chroma_height, xdec, ydec)?,
+        plane(v, chroma_width, chroma_height, xdec, ydec)?,
+    ])
+}
+
+/// The byte order of packed 4:2:2 YUV, which stores two horizontally adjacent
+/// pixels in four bytes that share one pair of chroma samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackedFormat {
+    /// `U Y0 V Y1`, also known as UYNV or Y422.
+    Uyvy,
+    /// `Y0 U Y1 V`, also known as YUY2.
+    Yuyv,
+}
+
+/// Creates a frame from a buffer of packed 8-bit 4:2:2 YUV samples, e.g. as
+/// captured from a video device.
+///
+/// The samples are de-interleaved into a planar frame with [`ChromaSampling::Cs422`].
+/// The rows are stored one after another without padding, each taking `2 * width`
+/// bytes. An error is returned if `width` is odd, if `T` is not an 8-bit pixel type,
+/// or if the length of `data` does not match the frame geometry exactly.
+pub fn frame_from_packed_yuv<T: Pixel>(
+    data: &[u8],
+    format: PackedFormat,
+    width: usize,
+    height: usize,
+) -> Result<Frame<T>, MetricsError> {
+    check_pixel_width::<T>(8)?;
+    if !width.is_multiple_of(2) {
+        return Err(MetricsError::UnsupportedInput {
+            reason: "Packed 4:2:2 input must have an even width",
+        });
+    }
+    if data.len() != width * height * 2 {
+        return Err(MetricsError::InputMismatch {
+            reason: "Buffer length does not match the frame geometry",
+        });
+    }
+
+    let chroma_width = width / 2;
+    let mut luma = Vec::with_capacity(width * height);
+    let mut u = Vec::with_capacity(chroma_width * height);
+    let mut v = Vec::with_capacity(chroma_width * height);
+    for pair in data.chunks_exact(4) {
+        let (y0, cb, y1, cr) = match format {
+            PackedFormat::Uyvy => (pair[1], pair[0], pair[3], pair[2]),
+            PackedFormat::Yuyv => (pair[0], pair[1], pair[2], pair[3]),
+        };
+        luma.extend([T::cast_from(y0), T::cast_from(y1)]);
+        u.push(T::cast_from(cb));
+        v.push(T::cast_from(cr));
+    }
+    frame_from_planes([
+        plane_from_slice(&luma, width, height, width, 0, 0)?,
+        plane_from_slice(&u, chroma_width, height, chroma_width, 1, 0)?,
+        plane_from_slice(&v, chroma_width, height, chroma_width, 1, 0)?,
+    ])
+}
+
+/// Returns whether the visible pixels of two frames are equal.
+///
+/// Unlike comparing the planes with `==`, this ignores the padding and stride of
+/// the planes, so frames from different decoders with the same content are equal.
+pub fn frames_identical<T: Pixel>(frame1: &Frame<T>, frame2: &Frame<T>) -> bool {
+    frame1.is_identical(frame2)
+}
+
+/// Calculates a hash of the visible pixels of a frame, e.g. to find repeated
+/// frames and reuse their scores.
+///
+/// See [`plane_hash`] for the properties of the hash.
+pub fn frame_hash<T: Pixel>(frame: &Frame<T>) -> u64 {
+    frame.planes.iter().fold(FNV_OFFSET_BASIS, |hash, plane| {
+        (hash ^ plane_hash(plane)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Calculates a hash of the visible pixels of a plane.
+///
+/// Padding is left out, so planes with the same size and content have the same hash
+/// regardless of their stride. The hash is the same across platforms and runs, but
+/// it is not cryptographic: different planes have the same hash with a very small
+/// probability, which [`frames_identical`] can rule out for a cache hit.
+pub fn plane_hash<T: Pixel>(plane: &Plane<T>) -> u64 {
+    // FNV-1a over whole samples, followed by a finalizer which mixes the
+    // high bits back into the low ones.
+    let width = plane.cfg.width;
+    let mut hash = [width, plane.cfg.height, plane.cfg.xdec, plane.cfg.ydec]
+        .iter()
+        .fold(FNV_OFFSET_BASIS, |hash, &value| {
+            (hash ^ value as u64).wrapping_mul(FNV_PRIME)
+        });
+    for row in plane.rows_iter() {
+        for &pix in &row[..width] {
+            hash = (hash ^ u32::cast_from(pix) as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^ (hash >> 33)
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// The scores returned by [`calculate_yuv_metrics`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct YuvMetrics {
+    /// See [`psnr::calculate_frame_psnr`]
+    pub psnr: PlanarMetrics,
+    /// See [`ssim::calculate_frame_ssim`]
+    pub ssim: PlanarMetrics,
+}
+
+/// Calculates the PSNR and SSIM between two frames stored as planar YUV bytes.
+///
+/// This is the simplest way to compare two images, e.g. in tests or scripts,
+/// as it requires neither a decoder nor building frames. See
+/// [`frame_from_yuv_bytes`] for the expected layout of the buffers.
+pub fn calculate_yuv_metrics(
+    data1: &[u8],
+    data2: &[u8],
+    width: usize,
+    height: usize,
+    bit_depth: usize,
+    chroma_sampling: ChromaSampling,
+) -> Result<YuvMetrics, Box<dyn Error>> {
+    fn calculate<T: Pixel>(
+        data1: &[u8],
+        data2: &[u8],
+        width: usize,
+        height: usize,
+        bit_depth: usize,
+        chroma_sampling: ChromaSampling,
+    ) -> Result<YuvMetrics, Box<dyn Error>> {
+        let frame1 = frame_from_yuv_bytes::<T>(data1, width, height, bit_depth, chroma_sampling)?;
+        let frame2 = frame_from_yuv_bytes::<T>(data2, width, height, bit_depth, chroma_sampling)?;
+        Ok(YuvMetrics {
+            psnr: psnr::calculate_frame_psnr(&frame1, &frame2, bit_depth, chroma_sampling)?,
+            ssim: ssim::calculate_frame_ssim(&frame1, &frame2, bit_depth, chroma_sampling)?,
+        })
+    }
+
+    if bit_depth > 8 {
+        calculate::<u16>(data1, data2, width, height, bit_depth, chroma_sampling)
+    } else {
+        calculate::<u8>(data1, data2, width, height, bit_depth, chroma_sampling)
+    }
+}
+
+/// Checks that two decoders output videos which can be compared, so that a
+/// mismatch is found before any frames are decoded.
+pub(crate) fn check_video_details<D: Decoder>(
+    decoder1: &D,
+    decoder2: &D,
+) -> Result<(), MetricsError> {
+    let (details1, details2) = (decoder1.get_video_details(), decoder2.get_video_details());
+    let mut fields = Vec::new();
+    if details1.width != details2.width {
+        fields.push("width");
+    }
+    if details1.height != details2.height {
+        fields.push("height");
+    }
+    if decoder1.get_bit_depth() != decoder2.get_bit_depth() {
+        fields.push("bit depth");
+    }
+    if details1.chroma_sampling != details2.chroma_sampling {
+        fields.push("chroma sampling");
+    }
+    if !fields.is_empty() {
+        return Err(MetricsError::VideoDetailsMismatch { fields });
+    }
+    Ok(())
+}
+
+/// Per-frame results, together with the type of each frame.
+type TypedFrameResults<R> = Vec<(FrameType, R)>;
+
+/// The closure which receives each pair of decoded frames of pixel type `T`.
+type PreprocessFn<T> = dyn Fn(&mut Frame<T>, &mut Frame<T>) -> bool + Send + Sync;
+
+/// A closure which transforms or leaves out the pairs of frames of two videos
+/// before they are compared, as set with e.g.
+/// [`SsimOptions::preprocess`](ssim::SsimOptions::preprocess).
+///
+/// The pixel type of the closure is only known when the video is processed, so
+/// it is checked against the bit depth then.
+#[derive(Clone)]
+pub(crate) struct Preprocess(Arc<dyn Any + Send + Sync>);
+
+impl Preprocess {
+    pub(crate) fn new<T: Pixel, F>(preprocess: F) -> Self
+    where
+        F: Fn(&mut Frame<T>, &mut Frame<T>) -> bool + Send + Sync + 'static,
+    {
+        let preprocess: Box<PreprocessFn<T>> = Box::new(preprocess);
+        Preprocess(Arc::new(preprocess))
+    }
+
+    /// Returns the closure if it takes frames of pixel type `T`.
+    fn get<T: Pixel>(&self) -> Result<&PreprocessFn<T>, MetricsError> {
+        self.0
+            .downcast_ref::<Box<PreprocessFn<T>>>()
+            .map(|preprocess| &**preprocess)
+            .ok_or(MetricsError::UnsupportedInput {
+                reason: "Preprocess pixel type does not match the bit depth",
+            })
+    }
+}
+
+impl std::fmt::Debug for Preprocess {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Preprocess")
+    }
+}
+
 trait VideoMetric: Send + Sync {
-    type FrameResult: Send + Sync;
+    type FrameResult: Send + Sync + Clone;
     type VideoResult: Send + Sync;
+    /// Scratch buffers which are reused across the frames of a video,
+    /// so that they are not allocated again for every frame.
+    type Context: Default + Send;
+
+    /// The closure to pass each pair of decoded frames to before comparing them.
+    fn preprocess(&self) -> Option<&Preprocess> {
+        None
+    }
 
     /// Generic method for internal use that processes multiple frames from a video
     /// into an aggregate metric.
@@ -112,18 +1578,91 @@ trait VideoMetric: Send + Sync {
         frame_limit: Option<usize>,
         progress_callback: F,
     ) -> Result<Self::VideoResult, Box<dyn Error>> {
-        if decoder1.get_bit_depth() != decoder2.get_bit_depth() {
-            return Err(Box::new(MetricsError::InputMismatch {
-                reason: "Bit depths do not match",
-            }));
-        }
-        if decoder1.get_video_details().chroma_sampling
-            != decoder2.get_video_details().chroma_sampling
-        {
-            return Err(Box::new(MetricsError::InputMismatch {
-                reason: "Chroma samplings do not match",
-            }));
+        let frames =
+            self.process_video_frames(decoder1, decoder2, frame_limit, progress_callback)?;
+        let results: Vec<_> = frames.into_iter().map(|(_, result)| result).collect();
+        self.aggregate_frame_results(&results)
+    }
+
+    /// Same as `process_video`, but also aggregates the frames of each type separately.
+    ///
+    /// The type of each frame is taken from `decoder2`, which is usually the encoded
+    /// video, or from `decoder1` if `decoder2` does not know it.
+    fn process_video_by_frame_type<D: Decoder, F: Fn(usize) + Send>(
+        &mut self,
+        decoder1: &mut D,
+        decoder2: &mut D,
+        frame_limit: Option<usize>,
+        progress_callback: F,
+    ) -> Result<FrameTypeMetrics<Self::VideoResult>, Box<dyn Error>> {
+        let frames =
+            self.process_video_frames(decoder1, decoder2, frame_limit, progress_callback)?;
+        let results: Vec<_> = frames.iter().map(|(_, result)| result.clone()).collect();
+        let all = self.aggregate_frame_results(&results)?;
+
+        let mut by_type = Vec::new();
+        for frame_type in [FrameType::I, FrameType::P, FrameType::B, FrameType::Unknown] {
+            let results: Vec<_> = frames
+                .iter()
+                .filter(|(t, _)| *t == frame_type)
+                .map(|(_, result)| result.clone())
+                .collect();
+            if !results.is_empty() {
+                by_type.push(FrameTypeResult {
+                    frame_type,
+                    frames: results.len(),
+                    result: self.aggregate_frame_results(&results)?,
+                });
+            }
         }
+        Ok(FrameTypeMetrics { all, by_type })
+    }
+
+    /// Same as `process_video`, but also calculates statistics of the per-frame
+    /// scores, which are taken from `score` like the overall result.
+    ///
+    /// The score of a single frame is the aggregate of only that frame.
+    fn process_video_with_statistics<D, F, R, S>(
+        &mut self,
+        decoder1: &mut D,
+        decoder2: &mut D,
+        frame_limit: Option<usize>,
+        progress_callback: F,
+        score: S,
+    ) -> Result<FrameStatistics<R>, Box<dyn Error>>
+    where
+        D: Decoder,
+        F: Fn(usize) + Send,
+        R: FrameScore,
+        S: Fn(Self::VideoResult) -> R,
+    {
+        let frames =
+            self.process_video_frames(decoder1, decoder2, frame_limit, progress_callback)?;
+        let results: Vec<_> = frames.into_iter().map(|(_, result)| result).collect();
+        let all = score(self.aggregate_frame_results(&results)?);
+        let scores = results
+            .iter()
+            .map(|result| {
+                Ok(score(
+                    self.aggregate_frame_results(std::slice::from_ref(result))?,
+                ))
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        Ok(frame_statistics(all, &scores))
+    }
+
+    /// Processes the frames of two videos, and returns the result and type
+    /// of each frame in decode order.
+    ///
+    /// Returns an error if no frames were compared.
+    fn process_video_frames<D: Decoder, F: Fn(usize) + Send>(
+        &mut self,
+        decoder1: &mut D,
+        decoder2: &mut D,
+        frame_limit: Option<usize>,
+        progress_callback: F,
+    ) -> Result<TypedFrameResults<Self::FrameResult>, Box<dyn Error>> {
+        check_video_details(decoder1, decoder2)?;
 
         if decoder1.get_bit_depth() > 8 {
             self.process_video_mt::<D, u16, F>(decoder1, decoder2, frame_limit, progress_callback)
@@ -140,19 +1679,33 @@ trait VideoMetric: Send + Sync {
         chroma_sampling: ChromaSampling,
     ) -> Result<Self::FrameResult, Box<dyn Error>>;
 
+    /// Same as `process_frame`, but reuses the buffers in `context`.
+    fn process_frame_with_context<T: Pixel>(
+        &self,
+        _context: &mut Self::Context,
+        frame1: &Frame<T>,
+        frame2: &Frame<T>,
+        bit_depth: usize,
+        chroma_sampling: ChromaSampling,
+    ) -> Result<Self::FrameResult, Box<dyn Error>> {
+        self.process_frame(frame1, frame2, bit_depth, chroma_sampling)
+    }
+
     fn aggregate_frame_results(
         &self,
         metrics: &[Self::FrameResult],
     ) -> Result<Self::VideoResult, Box<dyn Error>>;
 
+    #[cfg(feature = "parallel")]
     fn process_video_mt<D: Decoder, P: Pixel, F: Fn(usize) + Send>(
         &mut self,
         decoder1: &mut D,
         decoder2: &mut D,
         frame_limit: Option<usize>,
         progress_callback: F,
-    ) -> Result<Self::VideoResult, Box<dyn Error>> {
-        let num_threads = (rayon::current_num_threads() - 1).max(1);
+    ) -> Result<TypedFrameResults<Self::FrameResult>, Box<dyn Error>> {
+        let num_threads = (parallel::current_num_threads() - 1).max(1);
+        let preprocess = self.preprocess().map(Preprocess::get::<P>).transpose()?;
 
         let mut out = Vec::new();
 
@@ -163,17 +1716,31 @@ trait VideoMetric: Send + Sync {
             let send_result = s.spawn(move |_| {
                 let mut decoded = 0;
                 while frame_limit.map(|limit| limit > decoded).unwrap_or(true) {
+                    if decoder1.is_cancelled() || decoder2.is_cancelled() {
+                        return Err(MetricsError::Cancelled);
+                    }
                     decoded += 1;
                     let frame1 = decoder1.read_video_frame::<P>();
                     let frame2 = decoder2.read_video_frame::<P>();
-                    if let (Some(frame1), Some(frame2)) = (frame1, frame2) {
+                    if let (Some(mut frame1), Some(mut frame2)) = (frame1, frame2) {
                         progress_callback(decoded);
-                        if let Err(e) = send.send((frame1, frame2)) {
-                            let (frame1, frame2) = e.into_inner();
-                            return Err(format!(
-                                "Error sending\n\nframe1: {frame1:?}\n\nframe2: {frame2:?}"
-                            ));
+                        if !preprocess.is_none_or(|f| f(&mut frame1, &mut frame2)) {
+                            continue;
+                        }
+                        let frame_type = match decoder2.last_frame_type() {
+                            FrameType::Unknown => decoder1.last_frame_type(),
+                            frame_type => frame_type,
+                        };
+                        if let Err(e) = send.send((decoded, frame_type, frame1, frame2)) {
+                            let (_, _, frame1, frame2) = e.into_inner();
+                            return Err(MetricsError::SendError {
+                                reason: format!(
+                                    "Error sending\n\nframe1: {frame1:?}\n\nframe2: {frame2:?}"
+                                ),
+                            });
                         }
+                    } else if let Some(error) = decoder1.take_error().or(decoder2.take_error()) {
+                        return Err(error);
                     } else {
                         break;
                     }
@@ -183,67 +1750,86 @@ trait VideoMetric: Send + Sync {
                 Ok(())
             });
 
-            use rayon::prelude::*;
-            let mut metrics = Vec::with_capacity(frame_limit.unwrap_or(0));
-            let mut process_error = Ok(());
-            loop {
-                let working_set: Vec<_> = (0..num_threads)
-                    .into_par_iter()
-                    .filter_map(|_w| {
-                        recv.recv()
-                            .map(|(f1, f2)| {
-                                self.process_frame(
+            // Each worker processes the next decoded frame as soon as it is done with
+            // the previous one, reusing its context for all frames it processes.
+            // Only the workers hold receivers, so that the decoder stops once they
+            // have all stopped, e.g. after an error.
+            let receivers: Vec<_> = (0..num_threads).map(|_| recv.clone()).collect();
+            drop(recv);
+            let failed = AtomicBool::new(false);
+            let mut worker_results: Vec<Result<Vec<_>, String>> =
+                (0..num_threads).map(|_| Ok(Vec::new())).collect();
+            let this = &*self;
+            parallel::scope(|s| {
+                for (recv, worker_result) in receivers.into_iter().zip(&mut worker_results) {
+                    let failed = &failed;
+                    s.spawn(move |_| {
+                        let mut context = Self::Context::default();
+                        *worker_result = recv
+                            .into_iter()
+                            .take_while(|_| !failed.load(Ordering::Relaxed))
+                            .map(|(frameno, frame_type, f1, f2)| {
+                                this.process_frame_with_context(
+                                    &mut context,
                                     &f1,
                                     &f2,
                                     vid_info.bit_depth,
                                     vid_info.chroma_sampling,
                                 )
+                                .map(|result| (frameno, frame_type, result))
                                 .map_err(|e| {
+                                    failed.store(true, Ordering::Relaxed);
                                     format!("\n\n{e} on\n\nframe1: {f1:?}\n\nand\n\nframe2: {f2:?}")
                                 })
                             })
-                            .ok()
-                    })
-                    .collect();
-                let work_set: Vec<_> = working_set
-                    .into_iter()
-                    .filter_map(|v| v.map_err(|e| process_error = Err(e)).ok())
-                    .collect();
-                if work_set.is_empty() || process_error.is_err() {
-                    break;
-                } else {
-                    metrics.extend(work_set);
+                            .collect();
+                    });
+                }
+            });
+            let mut metrics = Vec::with_capacity(frame_limit.unwrap_or(0));
+            let mut process_error = Ok(());
+            for worker_result in worker_results {
+                match worker_result {
+                    Ok(results) => metrics.extend(results),
+                    Err(e) => process_error = Err(e),
                 }
             }
 
-            out = metrics;
+            // Frames may finish processing out of order. Restore decode order so that
+            // the aggregation is performed in a fixed order and the floating point
+            // results are reproducible across runs.
+            metrics.sort_unstable_by_key(|(frameno, _, _)| *frameno);
+            out = metrics
+                .into_iter()
+                .map(|(_, frame_type, result)| (frame_type, result))
+                .collect();
 
             (
-                send_result
-                    .join()
-                    .unwrap_or_else(|_| Err("Failed joining the sender thread".to_owned())),
+                send_result.join().unwrap_or_else(|_| {
+                    Err(MetricsError::SendError {
+                        reason: "Failed joining the sender thread".to_owned(),
+                    })
+                }),
                 process_error,
             )
         });
 
         match scope_result {
             Ok((send_error, process_error)) => {
-                if let Err(error) = send_error {
-                    return Err(MetricsError::SendError { reason: error }.into());
-                }
-
+                // Checked first, as a failed worker also stops the sender thread
                 if let Err(error) = process_error {
                     return Err(MetricsError::ProcessError { reason: error }.into());
                 }
 
+                // Also covers cancellation, which stops the sender thread
+                send_error?;
+
+                // Aggregating zero frames would divide by zero
                 if out.is_empty() {
-                    return Err(MetricsError::UnsupportedInput {
-                        reason: "No readable frames found in one or more input files",
-                    }
-                    .into());
+                    return Err(MetricsError::NoFramesCompared.into());
                 }
 
-                self.aggregate_frame_results(&out)
+                Ok(out)
             }
             Err(e) => Err(MetricsError::VideoError {
                 reason: format!("\n\nError {e:?} processing the two videos"),
@@ -251,4 +1837,63 @@ trait VideoMetric: Send + Sync {
             .into()),
         }
     }
+
+    /// Same as the parallel version, but decodes and processes the frames one
+    /// after another on the current thread.
+    #[cfg(not(feature = "parallel"))]
+    fn process_video_mt<D: Decoder, P: Pixel, F: Fn(usize) + Send>(
+        &mut self,
+        decoder1: &mut D,
+        decoder2: &mut D,
+        frame_limit: Option<usize>,
+        progress_callback: F,
+    ) -> Result<TypedFrameResults<Self::FrameResult>, Box<dyn Error>> {
+        let vid_info = decoder1.get_video_details();
+        let preprocess = self.preprocess().map(Preprocess::get::<P>).transpose()?;
+        let mut context = Self::Context::default();
+        let mut out = Vec::with_capacity(frame_limit.unwrap_or(0));
+        let mut decoded = 0;
+        while frame_limit.map(|limit| limit > decoded).unwrap_or(true) {
+            if decoder1.is_cancelled() || decoder2.is_cancelled() {
+                return Err(MetricsError::Cancelled.into());
+            }
+            decoded += 1;
+            let frame1 = decoder1.read_video_frame::<P>();
+            let frame2 = decoder2.read_video_frame::<P>();
+            let (Some(mut f1), Some(mut f2)) = (frame1, frame2) else {
+                if let Some(error) = decoder1.take_error().or(decoder2.take_error()) {
+                    return Err(error.into());
+                }
+                break;
+            };
+            progress_callback(decoded);
+            if !preprocess.is_none_or(|f| f(&mut f1, &mut f2)) {
+                continue;
+            }
+            let frame_type = match decoder2.last_frame_type() {
+                FrameType::Unknown => decoder1.last_frame_type(),
+                frame_type => frame_type,
+            };
+            let result = self
+                .process_frame_with_context(
+                    &mut context,
+                    &f1,
+                    &f2,
+                    vid_info.bit_depth,
+                    vid_info.chroma_sampling,
+                )
+                .map_err(|e| MetricsError::ProcessError {
+                    reason: format!("\n\n{e} on\n\nframe1: {f1:?}\n\nand\n\nframe2: {f2:?}"),
+                })?;
+            out.push((frame_type, result));
+        }
+        // Mark the end of the decoding process
+        progress_callback(usize::MAX);
+
+        // Aggregating zero frames would divide by zero
+        if out.is_empty() {
+            return Err(MetricsError::NoFramesCompared.into());
+        }
+        Ok(out)
+    }
 }
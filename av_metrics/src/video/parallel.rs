@@ -0,0 +1,50 @@
+//! Scheduling of the work on multiple threads.
+//!
+//! With the `parallel` feature, this re-exports the parts of rayon used by the metrics.
+//! Without it, the same functions run everything on the current thread, so that the
+//! crate can be built for targets without threads, e.g. `wasm32-unknown-unknown`.
+//! The results are the same either way.
+
+#[cfg(feature = "parallel")]
+pub(crate) use rayon::{current_num_threads, prelude, scope};
+
+#[cfg(not(feature = "parallel"))]
+pub(crate) use self::sequential::*;
+
+#[cfg(not(feature = "parallel"))]
+mod sequential {
+    /// Runs the closures passed to [`Scope::spawn`] immediately.
+    pub(crate) struct Scope;
+
+    impl Scope {
+        pub(crate) fn spawn<F: FnOnce(&Scope)>(&self, f: F) {
+            f(self)
+        }
+    }
+
+    pub(crate) fn scope<R, F: FnOnce(&Scope) -> R>(f: F) -> R {
+        f(&Scope)
+    }
+
+    pub(crate) mod prelude {
+        /// Sequential stand-in for `rayon::iter::IntoParallelIterator`.
+        pub(crate) trait IntoParallelIterator: IntoIterator + Sized {
+            fn into_par_iter(self) -> Self::IntoIter {
+                self.into_iter()
+            }
+        }
+
+        impl<I: IntoIterator> IntoParallelIterator for I {}
+
+        /// Sequential stand-in for `rayon::iter::IntoParallelRefIterator`.
+        pub(crate) trait ParallelSlice<T> {
+            fn par_iter(&self) -> std::slice::Iter<'_, T>;
+        }
+
+        impl<T> ParallelSlice<T> for [T] {
+            fn par_iter(&self) -> std::slice::Iter<'_, T> {
+                self.iter()
+            }
+        }
+    }
+}
@@ -5,20 +5,28 @@
 //! See https://en.wikipedia.org/wiki/Peak_signal-to-noise_ratio for more details.
 
 use crate::video::decode::Decoder;
+use crate::video::parallel;
 use crate::video::pixel::CastFromPrimitive;
 use crate::video::pixel::Pixel;
-use crate::video::{PlanarMetrics, VideoMetric};
+use crate::video::{
+    calculate_frame_tiled, FrameStatistics, FrameTypeMetrics, PlanarMetrics, PlaneSelection,
+    Preprocess, TransferCharacteristics, VideoMetric,
+};
 use crate::MetricsError;
 use std::error::Error;
-use std::mem::size_of;
 use v_frame::frame::Frame;
 use v_frame::plane::Plane;
 use v_frame::prelude::ChromaSampling;
 
-use super::FrameCompare;
+use super::{check_pixel_width, FrameCompare, PlaneCompare};
 
 /// Calculates the PSNR for two videos. Higher is better.
 ///
+/// The PSNR is calculated from the mean squared error over all frames. As the
+/// frames of a video have the same size, this is the harmonic mean of the PSNR of
+/// each frame, which some video analyzers report as harmonic PSNR, unless the PSNR
+/// of a frame is capped.
+///
 /// PSNR is capped at 100 in order to avoid skewed statistics
 /// from e.g. all black frames, which would
 /// otherwise show a PSNR of infinity.
@@ -29,7 +37,8 @@ pub fn calculate_video_psnr<D: Decoder, F: Fn(usize) + Send>(
     frame_limit: Option<usize>,
     progress_callback: F,
 ) -> Result<PlanarMetrics, Box<dyn Error>> {
-    let metrics = Psnr.process_video(decoder1, decoder2, frame_limit, progress_callback)?;
+    let metrics =
+        Psnr::default().process_video(decoder1, decoder2, frame_limit, progress_callback)?;
     Ok(metrics.psnr)
 }
 
@@ -45,10 +54,120 @@ pub fn calculate_video_apsnr<D: Decoder, F: Fn(usize) + Send>(
     frame_limit: Option<usize>,
     progress_callback: F,
 ) -> Result<PlanarMetrics, Box<dyn Error>> {
-    let metrics = Psnr.process_video(decoder1, decoder2, frame_limit, progress_callback)?;
+    let metrics =
+        Psnr::default().process_video(decoder1, decoder2, frame_limit, progress_callback)?;
     Ok(metrics.apsnr)
 }
 
+/// Calculates the PSNR for two videos. Higher is better.
+///
+/// The frames of each type are also aggregated separately. The type of each frame
+/// is taken from `decoder2`, which is usually the encoded video, or from `decoder1`
+/// if `decoder2` does not know it.
+///
+/// PSNR is capped at 100 in order to avoid skewed statistics
+/// from e.g. all black frames, which would
+/// otherwise show a PSNR of infinity.
+#[inline]
+pub fn calculate_video_psnr_by_frame_type<D: Decoder, F: Fn(usize) + Send>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<FrameTypeMetrics<PlanarMetrics>, Box<dyn Error>> {
+    let metrics = Psnr::default().process_video_by_frame_type(
+        decoder1,
+        decoder2,
+        frame_limit,
+        progress_callback,
+    )?;
+    Ok(metrics.map(|metrics| metrics.psnr))
+}
+
+/// Calculates the PSNR for two videos. Higher is better.
+///
+/// The mean, variance and standard deviation of the per-frame scores are also
+/// calculated, which show how stable the quality is over time.
+/// The mean of the per-frame PSNR is the APSNR.
+///
+/// PSNR is capped at 100 in order to avoid skewed statistics
+/// from e.g. all black frames, which would
+/// otherwise show a PSNR of infinity.
+#[inline]
+pub fn calculate_video_psnr_with_statistics<D: Decoder, F: Fn(usize) + Send>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<FrameStatistics<PlanarMetrics>, Box<dyn Error>> {
+    Psnr::default().process_video_with_statistics(
+        decoder1,
+        decoder2,
+        frame_limit,
+        progress_callback,
+        |metrics| metrics.psnr,
+    )
+}
+
+/// Calculates the PSNR of the luma plane of two HDR videos in a perceptually uniform
+/// space. Higher is better.
+///
+/// The code values are converted to the displayed luminance with the transfer function
+/// from the [`VideoDetails`](crate::video::decode::VideoDetails) of `decoder1`, and then
+/// encoded with the PQ curve of SMPTE ST 2084 before measuring the error. Equal steps
+/// of PQ are about equally visible from dark to bright areas, which is not true for
+/// the code values of HLG or SDR video, so the scores are comparable between videos
+/// with different transfer functions. HLG is displayed with a peak luminance of
+/// 1000 cd/m², SDR with a peak luminance of 100 cd/m². The samples are assumed to use
+/// the limited range, and the peak signal is the PQ value of 10000 cd/m².
+///
+/// Only the luma plane is measured. The `u` and `v` fields of the result are `NaN`,
+/// and `avg` is equal to `y`.
+///
+/// Returns an error if the transfer function is unknown, which is always the case for
+/// y4m input. Use [`calculate_video_psnr_hdr_with_transfer`] to specify it instead.
+///
+/// PSNR is capped at 100 in order to avoid skewed statistics
+/// from e.g. all black frames, which would
+/// otherwise show a PSNR of infinity.
+#[inline]
+pub fn calculate_video_psnr_hdr<D: Decoder, F: Fn(usize) + Send>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    let transfer = decoder1.get_video_details().transfer_characteristics;
+    calculate_video_psnr_hdr_with_transfer(
+        decoder1,
+        decoder2,
+        frame_limit,
+        transfer,
+        progress_callback,
+    )
+}
+
+/// Calculates the PSNR of the luma plane of two HDR videos in a perceptually uniform
+/// space, using the given transfer function for both videos. Higher is better.
+///
+/// See [`calculate_video_psnr_hdr`] for details.
+#[inline]
+pub fn calculate_video_psnr_hdr_with_transfer<D: Decoder, F: Fn(usize) + Send>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    transfer: TransferCharacteristics,
+    progress_callback: F,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    let bit_depth = decoder1.get_video_details().bit_depth;
+    HdrPsnr::new(transfer, bit_depth)?.process_video(
+        decoder1,
+        decoder2,
+        frame_limit,
+        progress_callback,
+    )
+}
+
 /// Calculates the PSNR for two video frames. Higher is better.
 ///
 /// PSNR is capped at 100 in order to avoid skewed statistics
@@ -61,12 +180,144 @@ pub fn calculate_frame_psnr<T: Pixel>(
     bit_depth: usize,
     chroma_sampling: ChromaSampling,
 ) -> Result<PlanarMetrics, Box<dyn Error>> {
-    let metrics = Psnr.process_frame(frame1, frame2, bit_depth, chroma_sampling)?;
-    Ok(PlanarMetrics {
-        y: calculate_psnr(metrics[0]),
-        u: calculate_psnr(metrics[1]),
-        v: calculate_psnr(metrics[2]),
-        avg: calculate_summed_psnr(&metrics),
+    PsnrOptions::new().calculate_frame(frame1, frame2, bit_depth, chroma_sampling)
+}
+
+/// Options for calculating PSNR and APSNR, for when the defaults of
+/// [`calculate_video_psnr`], [`calculate_video_apsnr`] and [`calculate_frame_psnr`]
+/// do not fit.
+///
+/// The options are set with the builder methods, e.g.
+/// `PsnrOptions::new().planes(PlaneSelection::Y).calculate_video(...)`. Invalid
+/// options are reported by the `calculate_*` methods.
+#[derive(Debug, Clone, Default)]
+pub struct PsnrOptions {
+    planes: PlaneSelection,
+    preprocess: Option<Preprocess>,
+}
+
+impl PsnrOptions {
+    /// The default options, which give the same scores as [`calculate_video_psnr`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compute the score for the selected planes only.
+    ///
+    /// Planes which are not selected are skipped entirely. Their fields in the result
+    /// are `NaN`, and they are left out of the `avg` score.
+    pub fn planes(mut self, planes: PlaneSelection) -> Self {
+        self.planes = planes;
+        self
+    }
+
+    /// Pass each pair of decoded frames to `preprocess` before comparing them.
+    ///
+    /// See [`SsimOptions::preprocess`](crate::video::ssim::SsimOptions::preprocess) for how the closure is called.
+    pub fn preprocess<T: Pixel, P>(mut self, preprocess: P) -> Self
+    where
+        P: Fn(&mut Frame<T>, &mut Frame<T>) -> bool + Send + Sync + 'static,
+    {
+        self.preprocess = Some(Preprocess::new(preprocess));
+        self
+    }
+
+    /// Calculates the PSNR for two videos. Higher is better.
+    ///
+    /// See [`calculate_video_psnr`] for how the frames are aggregated.
+    #[inline]
+    pub fn calculate_video<D: Decoder, F: Fn(usize) + Send>(
+        &self,
+        decoder1: &mut D,
+        decoder2: &mut D,
+        frame_limit: Option<usize>,
+        progress_callback: F,
+    ) -> Result<PlanarMetrics, Box<dyn Error>> {
+        let metrics =
+            self.metric()?
+                .process_video(decoder1, decoder2, frame_limit, progress_callback)?;
+        Ok(metrics.psnr)
+    }
+
+    /// Calculates the APSNR for two videos. Higher is better.
+    #[inline]
+    pub fn calculate_video_apsnr<D: Decoder, F: Fn(usize) + Send>(
+        &self,
+        decoder1: &mut D,
+        decoder2: &mut D,
+        frame_limit: Option<usize>,
+        progress_callback: F,
+    ) -> Result<PlanarMetrics, Box<dyn Error>> {
+        let metrics =
+            self.metric()?
+                .process_video(decoder1, decoder2, frame_limit, progress_callback)?;
+        Ok(metrics.apsnr)
+    }
+
+    /// Calculates the PSNR for two video frames. Higher is better.
+    #[inline]
+    pub fn calculate_frame<T: Pixel>(
+        &self,
+        frame1: &Frame<T>,
+        frame2: &Frame<T>,
+        bit_depth: usize,
+        chroma_sampling: ChromaSampling,
+    ) -> Result<PlanarMetrics, Box<dyn Error>> {
+        let metric = self.metric()?;
+        let metrics = metric.process_frame(frame1, frame2, bit_depth, chroma_sampling)?;
+        Ok(PlanarMetrics {
+            y: calculate_psnr(metrics[0]),
+            u: calculate_psnr(metrics[1]),
+            v: calculate_psnr(metrics[2]),
+            avg: calculate_summed_psnr(&metric.selected(&metrics)),
+        })
+    }
+
+    fn metric(&self) -> Result<Psnr, MetricsError> {
+        self.planes.validate()?;
+        Ok(Psnr {
+            planes: self.planes,
+            preprocess: self.preprocess.clone(),
+        })
+    }
+}
+
+/// Calculates the PSNR between the alpha planes of two frames. Higher is better.
+///
+/// A [`Frame`] only holds the three color planes, so the alpha planes of images
+/// with transparency are passed separately, e.g. as returned by the PNG loader of
+/// `av-metrics-decoders`. Any other single plane can be compared the same way.
+/// The frame and video metrics ignore alpha, and no video decoder returns it yet,
+/// so this only covers still images.
+/// The score is capped at 100 like the scores of [`calculate_frame_psnr`].
+pub fn calculate_alpha_psnr<T: Pixel>(
+    alpha1: &Plane<T>,
+    alpha2: &Plane<T>,
+    bit_depth: usize,
+) -> Result<f64, Box<dyn Error>> {
+    check_pixel_width::<T>(bit_depth)?;
+    alpha1.can_compare(alpha2)?;
+    Ok(calculate_psnr(calculate_plane_psnr_metrics(
+        alpha1, alpha2, bit_depth,
+    )))
+}
+
+/// Calculates the PSNR of each tile of two video frames. Higher is better.
+///
+/// The frames are split into tiles of `tile_width` by `tile_height` luma samples,
+/// which must be a multiple of the chroma subsampling. The result holds the rows of
+/// tiles from top to bottom, each from left to right. The tiles at the right and
+/// bottom edges are smaller if the frame size is not a multiple of the tile size.
+pub fn calculate_frame_psnr_tiled<T: Pixel>(
+    frame1: &Frame<T>,
+    frame2: &Frame<T>,
+    bit_depth: usize,
+    chroma_sampling: ChromaSampling,
+    tile_width: usize,
+    tile_height: usize,
+) -> Result<Vec<Vec<PlanarMetrics>>, Box<dyn Error>> {
+    calculate_frame_tiled(frame1, frame2, tile_width, tile_height, |tile1, tile2| {
+        calculate_frame_psnr(tile1, tile2, bit_depth, chroma_sampling)
     })
 }
 
@@ -76,11 +327,32 @@ struct PsnrResults {
     apsnr: PlanarMetrics,
 }
 
-struct Psnr;
+#[derive(Default)]
+struct Psnr {
+    /// The planes to compute the score for.
+    planes: PlaneSelection,
+    /// The closure to pass each pair of decoded frames to before comparing them.
+    preprocess: Option<Preprocess>,
+}
+
+impl Psnr {
+    /// Returns the metrics of the selected planes of a frame.
+    fn selected(&self, metrics: &[PsnrMetrics; 3]) -> Vec<PsnrMetrics> {
+        (0..3)
+            .filter(|&index| self.planes.has_plane(index))
+            .map(|index| metrics[index])
+            .collect()
+    }
+}
 
 impl VideoMetric for Psnr {
     type FrameResult = [PsnrMetrics; 3];
     type VideoResult = PsnrResults;
+    type Context = ();
+
+    fn preprocess(&self) -> Option<&Preprocess> {
+        self.preprocess.as_ref()
+    }
 
     fn process_frame<T: Pixel>(
         &self,
@@ -89,11 +361,7 @@ impl VideoMetric for Psnr {
         bit_depth: usize,
         _chroma_sampling: ChromaSampling,
     ) -> Result<Self::FrameResult, Box<dyn Error>> {
-        if (size_of::<T>() == 1 && bit_depth > 8) || (size_of::<T>() == 2 && bit_depth <= 8) {
-            return Err(Box::new(MetricsError::InputMismatch {
-                reason: "Bit depths does not match pixel width",
-            }));
-        }
+        check_pixel_width::<T>(bit_depth)?;
 
         frame1.can_compare(frame2)?;
 
@@ -101,16 +369,18 @@ impl VideoMetric for Psnr {
         let mut u = Default::default();
         let mut v = Default::default();
 
-        rayon::scope(|s| {
-            s.spawn(|_| {
-                y = calculate_plane_psnr_metrics(&frame1.planes[0], &frame2.planes[0], bit_depth)
-            });
-            s.spawn(|_| {
-                u = calculate_plane_psnr_metrics(&frame1.planes[1], &frame2.planes[1], bit_depth)
-            });
-            s.spawn(|_| {
-                v = calculate_plane_psnr_metrics(&frame1.planes[2], &frame2.planes[2], bit_depth)
-            });
+        parallel::scope(|s| {
+            for (index, result) in [&mut y, &mut u, &mut v].into_iter().enumerate() {
+                if self.planes.has_plane(index) {
+                    s.spawn(move |_| {
+                        *result = calculate_plane_psnr_metrics(
+                            &frame1.planes[index],
+                            &frame2.planes[index],
+                            bit_depth,
+                        )
+                    });
+                }
+            }
         });
 
         Ok([y, u, v])
@@ -124,7 +394,12 @@ impl VideoMetric for Psnr {
             y: calculate_summed_psnr(&metrics.iter().map(|m| m[0]).collect::<Vec<_>>()),
             u: calculate_summed_psnr(&metrics.iter().map(|m| m[1]).collect::<Vec<_>>()),
             v: calculate_summed_psnr(&metrics.iter().map(|m| m[2]).collect::<Vec<_>>()),
-            avg: calculate_summed_psnr(&metrics.iter().flatten().copied().collect::<Vec<_>>()),
+            avg: calculate_summed_psnr(
+                &metrics
+                    .iter()
+                    .flat_map(|m| self.selected(m))
+                    .collect::<Vec<_>>(),
+            ),
         };
         let apsnr = PlanarMetrics {
             y: metrics.iter().map(|m| calculate_psnr(m[0])).sum::<f64>() / metrics.len() as f64,
@@ -132,7 +407,7 @@ impl VideoMetric for Psnr {
             v: metrics.iter().map(|m| calculate_psnr(m[2])).sum::<f64>() / metrics.len() as f64,
             avg: metrics
                 .iter()
-                .map(|m| calculate_summed_psnr(m))
+                .map(|m| calculate_summed_psnr(&self.selected(m)))
                 .sum::<f64>()
                 / metrics.len() as f64,
         };
@@ -140,6 +415,86 @@ impl VideoMetric for Psnr {
     }
 }
 
+struct HdrPsnr {
+    /// Maps each code value to its PQ value, scaled to the range of the code values.
+    ///
+    /// This is the same for every frame, so it is built once per video.
+    table: Vec<f64>,
+}
+
+impl HdrPsnr {
+    fn new(transfer: TransferCharacteristics, bit_depth: usize) -> Result<Self, MetricsError> {
+        if transfer == TransferCharacteristics::Unknown {
+            return Err(MetricsError::UnsupportedInput {
+                reason: "The transfer characteristics of the video are unknown",
+            });
+        }
+        if bit_depth == 0 || bit_depth > 16 {
+            return Err(MetricsError::UnsupportedInput {
+                reason: "Bit depth must be between 1 and 16",
+            });
+        }
+        let max = ((1 << bit_depth) - 1) as f64;
+        let scale = (1 << bit_depth) as f64 / 256.0;
+        let table = (0..1 << bit_depth)
+            .map(|code| {
+                let signal = ((code as f64 - 16.0 * scale) / (219.0 * scale)).clamp(0.0, 1.0);
+                max * to_pq_signal(transfer, signal)
+            })
+            .collect();
+        Ok(HdrPsnr { table })
+    }
+}
+
+impl VideoMetric for HdrPsnr {
+    type FrameResult = PsnrMetrics;
+    type VideoResult = PlanarMetrics;
+    type Context = ();
+
+    fn process_frame<T: Pixel>(
+        &self,
+        frame1: &Frame<T>,
+        frame2: &Frame<T>,
+        bit_depth: usize,
+        _chroma_sampling: ChromaSampling,
+    ) -> Result<Self::FrameResult, Box<dyn Error>> {
+        check_pixel_width::<T>(bit_depth)?;
+
+        frame1.can_compare(frame2)?;
+
+        debug_assert_eq!(self.table.len(), 1 << bit_depth);
+        let table = &self.table;
+        let lookup = |sample: &T| table[(u32::cast_from(*sample) as usize).min(table.len() - 1)];
+        let plane1 = &frame1.planes[0];
+        let plane2 = &frame2.planes[0];
+        let sq_err = plane1
+            .rows_iter()
+            .flatten()
+            .zip(plane2.rows_iter().flatten())
+            .map(|(a, b)| lookup(a) - lookup(b))
+            .map(|err| err * err)
+            .sum::<f64>();
+        Ok(PsnrMetrics {
+            sq_err,
+            n_pixels: plane1.cfg.width * plane1.cfg.height,
+            sample_max: (1 << bit_depth) - 1,
+        })
+    }
+
+    fn aggregate_frame_results(
+        &self,
+        metrics: &[Self::FrameResult],
+    ) -> Result<Self::VideoResult, Box<dyn Error>> {
+        let y = calculate_summed_psnr(metrics);
+        Ok(PlanarMetrics {
+            y,
+            u: f64::NAN,
+            v: f64::NAN,
+            avg: y,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy, Default)]
 struct PsnrMetrics {
     sq_err: f64,
@@ -176,20 +531,59 @@ fn calculate_plane_psnr_metrics<T: Pixel>(
 }
 
 fn calculate_psnr(metrics: PsnrMetrics) -> f64 {
-    if metrics.sq_err <= std::f64::EPSILON {
+    // Empty planes, i.e. the chroma planes of monochrome video, have no score
+    if metrics.n_pixels == 0 {
+        return f64::NAN;
+    }
+    if metrics.sq_err <= f64::EPSILON {
         return 100.0;
     }
     10.0 * ((metrics.sample_max.pow(2) as f64).log10() + (metrics.n_pixels as f64).log10()
         - metrics.sq_err.log10())
 }
 
+/// Converts a nonlinear luma signal in the range [0, 1] to the PQ signal
+/// with the same displayed luminance.
+fn to_pq_signal(transfer: TransferCharacteristics, signal: f64) -> f64 {
+    let luminance = match transfer {
+        TransferCharacteristics::Pq => return signal,
+        TransferCharacteristics::Hlg => 1000.0 * hlg_inverse_oetf(signal).powf(1.2),
+        TransferCharacteristics::Sdr => 100.0 * signal.powf(2.4),
+        TransferCharacteristics::Unknown => unreachable!("checked by HdrPsnr::new"),
+    };
+    pq_inverse_eotf(luminance / 10000.0)
+}
+
+/// The inverse of the PQ EOTF of SMPTE ST 2084, for a luminance relative to 10000 cd/m².
+fn pq_inverse_eotf(luminance: f64) -> f64 {
+    const M1: f64 = 2610.0 / 16384.0;
+    const M2: f64 = 2523.0 / 4096.0 * 128.0;
+    const C1: f64 = 3424.0 / 4096.0;
+    const C2: f64 = 2413.0 / 4096.0 * 32.0;
+    const C3: f64 = 2392.0 / 4096.0 * 32.0;
+    let y = luminance.max(0.0).powf(M1);
+    ((C1 + C2 * y) / (1.0 + C3 * y)).powf(M2)
+}
+
+/// The inverse of the HLG OETF of BT.2100, which returns the relative scene light.
+fn hlg_inverse_oetf(signal: f64) -> f64 {
+    const A: f64 = 0.178_832_77;
+    const B: f64 = 1.0 - 4.0 * A;
+    const C: f64 = 0.559_910_73;
+    if signal <= 0.5 {
+        signal * signal / 3.0
+    } else {
+        (((signal - C) / A).exp() + B) / 12.0
+    }
+}
+
 /// Calculate the squared error for a `Plane` by comparing the original (uncompressed)
 /// to the compressed version.
 fn calculate_plane_total_squared_error<T: Pixel>(plane1: &Plane<T>, plane2: &Plane<T>) -> f64 {
     plane1
-        .data
-        .iter()
-        .zip(plane2.data.iter())
+        .rows_iter()
+        .flatten()
+        .zip(plane2.rows_iter().flatten())
         .map(|(a, b)| (i32::cast_from(*a) - i32::cast_from(*b)).unsigned_abs() as u64)
         .map(|err| err * err)
         .sum::<u64>() as f64
@@ -0,0 +1,261 @@
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+use self::avx2::*;
+
+const DCT_STRIDE: usize = 8;
+
+pub(crate) type FdctFn = unsafe fn(&mut [i32; 64]);
+
+/// Returns the fastest 8x8 forward DCT supported by the CPU.
+///
+/// All versions produce bit-identical output.
+pub(crate) fn get_fdct8x8_fn(simd: bool) -> FdctFn {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("avx2") && simd {
+            return od_bin_fdct8x8_avx2;
+        }
+    }
+    od_bin_fdct8x8_scalar
+}
+
+unsafe fn od_bin_fdct8x8_scalar(data: &mut [i32; 64]) {
+    od_bin_fdct8x8(data);
+}
+
+// Based on daala's version. It is different from the 8x8 DCT we use during encoding.
+fn od_bin_fdct8x8(data: &mut [i32]) {
+    assert!(data.len() >= 64);
+    let mut z = [0; 64];
+    for i in 0..8 {
+        od_bin_fdct8(&mut z[(DCT_STRIDE * i)..], &data[i..]);
+    }
+    for i in 0..8 {
+        od_bin_fdct8(&mut data[(DCT_STRIDE * i)..], &z[i..]);
+    }
+}
+#[allow(clippy::identity_op)]
+fn od_bin_fdct8(y: &mut [i32], x: &[i32]) {
+    assert!(y.len() >= 8);
+    assert!(x.len() > 7 * DCT_STRIDE);
+    let mut t = [0; 8];
+    let mut th = [0; 8];
+    // Initial permutation
+    t[0] = x[0];
+    t[4] = x[1 * DCT_STRIDE];
+    t[2] = x[2 * DCT_STRIDE];
+    t[6] = x[3 * DCT_STRIDE];
+    t[7] = x[4 * DCT_STRIDE];
+    t[3] = x[5 * DCT_STRIDE];
+    t[5] = x[6 * DCT_STRIDE];
+    t[1] = x[7 * DCT_STRIDE];
+    // +1/-1 butterflies
+    t[1] = t[0] - t[1];
+    th[1] = od_dct_rshift(t[1], 1);
+    t[0] -= th[1];
+    t[4] += t[5];
+    th[4] = od_dct_rshift(t[4], 1);
+    t[5] -= th[4];
+    t[3] = t[2] - t[3];
+    t[2] -= od_dct_rshift(t[3], 1);
+    t[6] += t[7];
+    th[6] = od_dct_rshift(t[6], 1);
+    t[7] = th[6] - t[7];
+    // + Embedded 4-point type-II DCT
+    t[0] += th[6];
+    t[6] = t[0] - t[6];
+    t[2] = th[4] - t[2];
+    t[4] = t[2] - t[4];
+    // |-+ Embedded 2-point type-II DCT
+    t[0] -= (t[4] * 13573 + 16384) >> 15;
+    t[4] += (t[0] * 11585 + 8192) >> 14;
+    t[0] -= (t[4] * 13573 + 16384) >> 15;
+    // |-+ Embedded 2-point type-IV DST
+    t[6] -= (t[2] * 21895 + 16384) >> 15;
+    t[2] += (t[6] * 15137 + 8192) >> 14;
+    t[6] -= (t[2] * 21895 + 16384) >> 15;
+    // + Embedded 4-point type-IV DST
+    t[3] += (t[5] * 19195 + 16384) >> 15;
+    t[5] += (t[3] * 11585 + 8192) >> 14;
+    t[3] -= (t[5] * 7489 + 4096) >> 13;
+    t[7] = od_dct_rshift(t[5], 1) - t[7];
+    t[5] -= t[7];
+    t[3] = th[1] - t[3];
+    t[1] -= t[3];
+    t[7] += (t[1] * 3227 + 16384) >> 15;
+    t[1] -= (t[7] * 6393 + 16384) >> 15;
+    t[7] += (t[1] * 3227 + 16384) >> 15;
+    t[5] += (t[3] * 2485 + 4096) >> 13;
+    t[3] -= (t[5] * 18205 + 16384) >> 15;
+    t[5] += (t[3] * 2485 + 4096) >> 13;
+    y[0] = t[0];
+    y[1] = t[1];
+    y[2] = t[2];
+    y[3] = t[3];
+    y[4] = t[4];
+    y[5] = t[5];
+    y[6] = t[6];
+    y[7] = t[7];
+}
+
+/// This is the strength reduced version of `a / (1 << b)`, i.e. a right shift
+/// which rounds towards zero instead of towards negative infinity.
+#[inline(always)]
+fn od_dct_rshift(a: i32, b: u32) -> i32 {
+    debug_assert!(b < 32);
+
+    // Negative values are biased by `(1 << b) - 1` before shifting. For `b == 0`
+    // the bias would need a shift by 32, which `checked_shr` turns into 0.
+    let bias = ((a >> 31) as u32).checked_shr(32 - b).unwrap_or(0);
+    (bias as i32 + a) >> b
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod avx2 {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    /// Computes `(a * mul + round) >> SHIFT` in each lane.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn mul_round_shift<const SHIFT: i32>(a: __m256i, mul: i32, round: i32) -> __m256i {
+        _mm256_srai_epi32::<SHIFT>(_mm256_add_epi32(
+            _mm256_mullo_epi32(a, _mm256_set1_epi32(mul)),
+            _mm256_set1_epi32(round),
+        ))
+    }
+
+    /// Vector version of `od_dct_rshift(a, 1)`.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn rshift1(a: __m256i) -> __m256i {
+        _mm256_srai_epi32::<1>(_mm256_add_epi32(_mm256_srli_epi32::<31>(a), a))
+    }
+
+    /// Transposes an 8x8 matrix of 32-bit values, stored as one row per vector.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn transpose8x8(r: &mut [__m256i; 8]) {
+        let a0 = _mm256_unpacklo_epi32(r[0], r[1]);
+        let a1 = _mm256_unpackhi_epi32(r[0], r[1]);
+        let a2 = _mm256_unpacklo_epi32(r[2], r[3]);
+        let a3 = _mm256_unpackhi_epi32(r[2], r[3]);
+        let a4 = _mm256_unpacklo_epi32(r[4], r[5]);
+        let a5 = _mm256_unpackhi_epi32(r[4], r[5]);
+        let a6 = _mm256_unpacklo_epi32(r[6], r[7]);
+        let a7 = _mm256_unpackhi_epi32(r[6], r[7]);
+
+        let b0 = _mm256_unpacklo_epi64(a0, a2);
+        let b1 = _mm256_unpackhi_epi64(a0, a2);
+        let b2 = _mm256_unpacklo_epi64(a1, a3);
+        let b3 = _mm256_unpackhi_epi64(a1, a3);
+        let b4 = _mm256_unpacklo_epi64(a4, a6);
+        let b5 = _mm256_unpackhi_epi64(a4, a6);
+        let b6 = _mm256_unpacklo_epi64(a5, a7);
+        let b7 = _mm256_unpackhi_epi64(a5, a7);
+
+        r[0] = _mm256_permute2x128_si256::<0x20>(b0, b4);
+        r[1] = _mm256_permute2x128_si256::<0x20>(b1, b5);
+        r[2] = _mm256_permute2x128_si256::<0x20>(b2, b6);
+        r[3] = _mm256_permute2x128_si256::<0x20>(b3, b7);
+        r[4] = _mm256_permute2x128_si256::<0x31>(b0, b4);
+        r[5] = _mm256_permute2x128_si256::<0x31>(b1, b5);
+        r[6] = _mm256_permute2x128_si256::<0x31>(b2, b6);
+        r[7] = _mm256_permute2x128_si256::<0x31>(b3, b7);
+    }
+
+    /// Runs `od_bin_fdct8` on the 8 columns held in the lanes of `x` at once.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn od_bin_fdct8_avx2(x: &mut [__m256i; 8]) {
+        use self::{_mm256_add_epi32 as add, _mm256_sub_epi32 as sub};
+
+        // Initial permutation
+        let mut t0 = x[0];
+        let mut t4 = x[1];
+        let mut t2 = x[2];
+        let mut t6 = x[3];
+        let mut t7 = x[4];
+        let mut t3 = x[5];
+        let mut t5 = x[6];
+        let mut t1 = x[7];
+        // +1/-1 butterflies
+        t1 = sub(t0, t1);
+        let th1 = rshift1(t1);
+        t0 = sub(t0, th1);
+        t4 = add(t4, t5);
+        let th4 = rshift1(t4);
+        t5 = sub(t5, th4);
+        t3 = sub(t2, t3);
+        t2 = sub(t2, rshift1(t3));
+        t6 = add(t6, t7);
+        let th6 = rshift1(t6);
+        t7 = sub(th6, t7);
+        // + Embedded 4-point type-II DCT
+        t0 = add(t0, th6);
+        t6 = sub(t0, t6);
+        t2 = sub(th4, t2);
+        t4 = sub(t2, t4);
+        // |-+ Embedded 2-point type-II DCT
+        t0 = sub(t0, mul_round_shift::<15>(t4, 13573, 16384));
+        t4 = add(t4, mul_round_shift::<14>(t0, 11585, 8192));
+        t0 = sub(t0, mul_round_shift::<15>(t4, 13573, 16384));
+        // |-+ Embedded 2-point type-IV DST
+        t6 = sub(t6, mul_round_shift::<15>(t2, 21895, 16384));
+        t2 = add(t2, mul_round_shift::<14>(t6, 15137, 8192));
+        t6 = sub(t6, mul_round_shift::<15>(t2, 21895, 16384));
+        // + Embedded 4-point type-IV DST
+        t3 = add(t3, mul_round_shift::<15>(t5, 19195, 16384));
+        t5 = add(t5, mul_round_shift::<14>(t3, 11585, 8192));
+        t3 = sub(t3, mul_round_shift::<13>(t5, 7489, 4096));
+        t7 = sub(rshift1(t5), t7);
+        t5 = sub(t5, t7);
+        t3 = sub(th1, t3);
+        t1 = sub(t1, t3);
+        t7 = add(t7, mul_round_shift::<15>(t1, 3227, 16384));
+        t1 = sub(t1, mul_round_shift::<15>(t7, 6393, 16384));
+        t7 = add(t7, mul_round_shift::<15>(t1, 3227, 16384));
+        t5 = add(t5, mul_round_shift::<13>(t3, 2485, 4096));
+        t3 = sub(t3, mul_round_shift::<15>(t5, 18205, 16384));
+        t5 = add(t5, mul_round_shift::<13>(t3, 2485, 4096));
+        *x = [t0, t1, t2, t3, t4, t5, t6, t7];
+    }
+
+    /// AVX2 version of `od_bin_fdct8x8`. The output is identical.
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn od_bin_fdct8x8_avx2(data: &mut [i32; 64]) {
+        let mut rows = [_mm256_setzero_si256(); 8];
+        for (i, row) in rows.iter_mut().enumerate() {
+            *row = _mm256_loadu_si256(data.as_ptr().add(i * 8) as *const __m256i);
+        }
+        // Each lane holds one column, so both passes transform all columns at
+        // once. The transposes make the second pass work on the rows.
+        od_bin_fdct8_avx2(&mut rows);
+        transpose8x8(&mut rows);
+        od_bin_fdct8_avx2(&mut rows);
+        transpose8x8(&mut rows);
+        for (i, row) in rows.iter().enumerate() {
+            _mm256_storeu_si256(data.as_mut_ptr().add(i * 8) as *mut __m256i, *row);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::od_dct_rshift;
+
+    #[test]
+    fn od_dct_rshift_rounds_towards_zero() {
+        let values = (-1000..=1000).chain([i32::MIN, i32::MIN + 1, i32::MAX - 1, i32::MAX]);
+        for a in values {
+            for b in 0..=3 {
+                assert_eq!(od_dct_rshift(a, b), a / (1 << b), "a = {}, b = {}", a, b);
+            }
+            for b in 4..32 {
+                let expected = (a as i64 / (1i64 << b)) as i32;
+                assert_eq!(od_dct_rshift(a, b), expected, "a = {}, b = {}", a, b);
+            }
+        }
+    }
+}
@@ -0,0 +1,972 @@
+//! Peak Signal-to-Noise Ratio metric accounting for the Human Visual System.
+//!
+//! Humans perceive larger differences from certain factors of an image compared
+//! to other factors. This metric attempts to take the human perception factor
+//! into account.
+//!
+//! The metric is computed over 8x8 blocks placed every 7 pixels. When a plane
+//! dimension is not covered exactly by this grid, the last block is shifted to end
+//! at the plane boundary, so that edge pixels are always taken into account.
+//!
+//! By default, errors which are masked by the contrast of the surrounding block are
+//! discounted, as in PSNR-HVS-M. [`PsnrHvsOptions::masking`] can compute plain
+//! PSNR-HVS without the masking instead.
+//!
+//! Input with a bit depth of up to 14 bits is supported.
+//!
+//! See https://en.wikipedia.org/wiki/Peak_signal-to-noise_ratio for more details.
+
+use crate::video::decode::Decoder;
+use crate::video::parallel;
+use crate::video::parallel::prelude::*;
+use crate::video::pixel::CastFromPrimitive;
+use crate::video::pixel::Pixel;
+use crate::video::{
+    ChromaWeights, FrameStatistics, FrameTypeMetrics, PlanarMetrics, PlaneSelection, Preprocess,
+    VideoMetric,
+};
+use crate::MetricsError;
+use std::error::Error;
+use v_frame::frame::Frame;
+use v_frame::plane::Plane;
+use v_frame::prelude::ChromaSampling;
+
+use self::dct::{get_fdct8x8_fn, FdctFn};
+use super::{check_pixel_width, FrameCompare, PlaneCompare};
+
+pub(crate) mod dct;
+
+/// Calculates the PSNR-HVS score between two videos. Higher is better.
+#[inline]
+pub fn calculate_video_psnr_hvs<D: Decoder, F: Fn(usize) + Send>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    PsnrHvsOptions::new().calculate_video(decoder1, decoder2, frame_limit, progress_callback)
+}
+
+/// Calculates the PSNR-HVS score between two videos. Higher is better.
+///
+/// The frames of each type are also aggregated separately. The type of each frame
+/// is taken from `decoder2`, which is usually the encoded video, or from `decoder1`
+/// if `decoder2` does not know it.
+#[inline]
+pub fn calculate_video_psnr_hvs_by_frame_type<D: Decoder, F: Fn(usize) + Send>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<FrameTypeMetrics<PlanarMetrics>, Box<dyn Error>> {
+    PsnrHvs {
+        cweight: None,
+        use_simd: true,
+        planes: PlaneSelection::ALL,
+        masking: true,
+        preprocess: None,
+    }
+    .process_video_by_frame_type(decoder1, decoder2, frame_limit, progress_callback)
+}
+
+/// Calculates the PSNR-HVS score between two videos. Higher is better.
+///
+/// The mean, variance and standard deviation of the per-frame scores are also
+/// calculated, which show how stable the quality is over time.
+#[inline]
+pub fn calculate_video_psnr_hvs_with_statistics<D: Decoder, F: Fn(usize) + Send>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<FrameStatistics<PlanarMetrics>, Box<dyn Error>> {
+    PsnrHvs {
+        cweight: None,
+        use_simd: true,
+        planes: PlaneSelection::ALL,
+        masking: true,
+        preprocess: None,
+    }
+    .process_video_with_statistics(decoder1, decoder2, frame_limit, progress_callback, |m| m)
+}
+
+/// Calculates the PSNR-HVS score between two videos. Higher is better.
+///
+/// This version disables SIMD. It is intended to only be used
+/// by tests and benchmarks.
+#[inline]
+#[doc(hidden)]
+pub fn calculate_video_psnr_hvs_nosimd<D: Decoder, F: Fn(usize) + Send>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    PsnrHvsOptions {
+        use_simd: false,
+        ..Default::default()
+    }
+    .calculate_video(decoder1, decoder2, frame_limit, progress_callback)
+}
+
+/// Calculates the PSNR-HVS score between two video frames. Higher is better.
+#[inline]
+pub fn calculate_frame_psnr_hvs<T: Pixel>(
+    frame1: &Frame<T>,
+    frame2: &Frame<T>,
+    bit_depth: usize,
+    chroma_sampling: ChromaSampling,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    PsnrHvsOptions::new().calculate_frame(frame1, frame2, bit_depth, chroma_sampling)
+}
+
+/// Options for calculating PSNR-HVS, for when the defaults of
+/// [`calculate_video_psnr_hvs`] and [`calculate_frame_psnr_hvs`] do not fit.
+///
+/// The options are set with the builder methods, e.g.
+/// `PsnrHvsOptions::new().chroma_weights(weights).calculate_video(...)`.
+#[derive(Debug, Clone)]
+pub struct PsnrHvsOptions {
+    cweight: Option<ChromaWeights>,
+    planes: PlaneSelection,
+    masking: bool,
+    use_simd: bool,
+    preprocess: Option<Preprocess>,
+}
+
+impl Default for PsnrHvsOptions {
+    fn default() -> Self {
+        PsnrHvsOptions {
+            cweight: None,
+            planes: PlaneSelection::ALL,
+            masking: true,
+            use_simd: true,
+            preprocess: None,
+        }
+    }
+}
+
+impl PsnrHvsOptions {
+    /// The default options, which give the same scores as [`calculate_video_psnr_hvs`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use custom weights for the chroma planes in the `avg` score, instead of
+    /// weighting them by their size relative to the luma plane.
+    pub fn chroma_weights(mut self, cweight: ChromaWeights) -> Self {
+        self.cweight = Some(cweight);
+        self
+    }
+
+    /// Compute the score for the selected planes only.
+    ///
+    /// Planes which are not selected are skipped entirely. Their fields in the result
+    /// are `NaN`, and they are left out of the `avg` score.
+    pub fn planes(mut self, planes: PlaneSelection) -> Self {
+        self.planes = planes;
+        self
+    }
+
+    /// Whether to apply the contrast masking of PSNR-HVS-M, which is the default.
+    ///
+    /// Without the masking, all differences between the DCT coefficients count
+    /// fully, which results in lower scores.
+    pub fn masking(mut self, masking: bool) -> Self {
+        self.masking = masking;
+        self
+    }
+
+    /// Pass each pair of decoded frames to `preprocess` before comparing them.
+    ///
+    /// See [`SsimOptions::preprocess`](crate::video::ssim::SsimOptions::preprocess) for how the closure is called.
+    pub fn preprocess<T: Pixel, P>(mut self, preprocess: P) -> Self
+    where
+        P: Fn(&mut Frame<T>, &mut Frame<T>) -> bool + Send + Sync + 'static,
+    {
+        self.preprocess = Some(Preprocess::new(preprocess));
+        self
+    }
+
+    /// Calculates the PSNR-HVS score between two videos. Higher is better.
+    #[inline]
+    pub fn calculate_video<D: Decoder, F: Fn(usize) + Send>(
+        &self,
+        decoder1: &mut D,
+        decoder2: &mut D,
+        frame_limit: Option<usize>,
+        progress_callback: F,
+    ) -> Result<PlanarMetrics, Box<dyn Error>> {
+        self.metric()?
+            .process_video(decoder1, decoder2, frame_limit, progress_callback)
+    }
+
+    /// Calculates the PSNR-HVS score between two video frames. Higher is better.
+    #[inline]
+    pub fn calculate_frame<T: Pixel>(
+        &self,
+        frame1: &Frame<T>,
+        frame2: &Frame<T>,
+        bit_depth: usize,
+        chroma_sampling: ChromaSampling,
+    ) -> Result<PlanarMetrics, Box<dyn Error>> {
+        let result = self
+            .metric()?
+            .process_frame(frame1, frame2, bit_depth, chroma_sampling)?;
+        Ok(PlanarMetrics {
+            y: log10_convert(result.y, 1.0),
+            u: log10_convert(result.u, 1.0),
+            v: log10_convert(result.v, 1.0),
+            avg: log10_convert(result.avg, 1.0),
+        })
+    }
+
+    fn metric(&self) -> Result<PsnrHvs, MetricsError> {
+        self.planes.validate()?;
+        Ok(PsnrHvs {
+            cweight: self.cweight,
+            use_simd: self.use_simd,
+            planes: self.planes,
+            masking: self.masking,
+            preprocess: self.preprocess.clone(),
+        })
+    }
+}
+
+/// Calculates the PSNR-HVS score between two video frames. Higher is better.
+///
+/// This version disables SIMD. It is intended to only be used
+/// by tests and benchmarks.
+#[inline]
+#[doc(hidden)]
+pub fn calculate_frame_psnr_hvs_nosimd<T: Pixel>(
+    frame1: &Frame<T>,
+    frame2: &Frame<T>,
+    bit_depth: usize,
+    chroma_sampling: ChromaSampling,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    PsnrHvsOptions {
+        use_simd: false,
+        ..Default::default()
+    }
+    .calculate_frame(frame1, frame2, bit_depth, chroma_sampling)
+}
+
+/// A map of the PSNR-HVS error of each 8x8 block in a plane.
+///
+/// Blocks are laid out in row-major order on a grid of `width` by `height` blocks.
+/// Each value is the mean weighted squared error of the block, normalized to the
+/// sample range, so the mean of all blocks is the unconverted score of the plane.
+/// Lower values mean less visible distortion.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PsnrHvsMap {
+    /// Number of blocks per row.
+    pub width: usize,
+    /// Number of blocks per column.
+    pub height: usize,
+    /// Error of each block.
+    pub data: Vec<f64>,
+}
+
+/// Calculates the per-block PSNR-HVS error maps between two video frames,
+/// in plane order (Y, U, V).
+///
+/// This is useful to visualize where quality is lost in a frame.
+/// Blocks are 8x8 pixels and overlap by one pixel. If a plane dimension is not
+/// covered exactly by this grid, the last block in that dimension is shifted
+/// so that it ends at the plane boundary.
+pub fn calculate_frame_psnr_hvs_map<T: Pixel>(
+    frame1: &Frame<T>,
+    frame2: &Frame<T>,
+    bit_depth: usize,
+    _chroma_sampling: ChromaSampling,
+) -> Result<[PsnrHvsMap; 3], Box<dyn Error>> {
+    check_bit_depth::<T>(bit_depth)?;
+
+    frame1.can_compare(frame2)?;
+
+    let mut maps: [PsnrHvsMap; 3] = Default::default();
+    let [y, u, v] = &mut maps;
+
+    parallel::scope(|s| {
+        s.spawn(|_| {
+            calculate_plane_psnr_hvs_internal(
+                &frame1.planes[0],
+                &frame2.planes[0],
+                0,
+                bit_depth,
+                true,
+                true,
+                None,
+                Some(y),
+            );
+        });
+        s.spawn(|_| {
+            calculate_plane_psnr_hvs_internal(
+                &frame1.planes[1],
+                &frame2.planes[1],
+                1,
+                bit_depth,
+                true,
+                true,
+                None,
+                Some(u),
+            );
+        });
+        s.spawn(|_| {
+            calculate_plane_psnr_hvs_internal(
+                &frame1.planes[2],
+                &frame2.planes[2],
+                2,
+                bit_depth,
+                true,
+                true,
+                None,
+                Some(v),
+            );
+        });
+    });
+
+    Ok(maps)
+}
+
+/// Describes how much of a plane contributed to its PSNR-HVS score.
+///
+/// The score is computed on 8x8 blocks, so planes which are smaller than a
+/// block in either dimension are not compared at all. Their score is `NaN`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PsnrHvsCoverage {
+    /// Number of 8x8 blocks which were compared.
+    pub blocks: usize,
+    /// Number of pixels which are part of at least one compared block.
+    pub covered_pixels: usize,
+    /// Number of pixels in the plane.
+    pub total_pixels: usize,
+}
+
+/// Calculates the PSNR-HVS score between two video frames, along with the
+/// coverage of each plane in plane order (Y, U, V). Higher is better.
+///
+/// The score is the same as the one returned by [`calculate_frame_psnr_hvs`].
+/// The coverage can be used to check how much of a small frame was compared.
+pub fn calculate_frame_psnr_hvs_with_coverage<T: Pixel>(
+    frame1: &Frame<T>,
+    frame2: &Frame<T>,
+    bit_depth: usize,
+    chroma_sampling: ChromaSampling,
+) -> Result<(PlanarMetrics, [PsnrHvsCoverage; 3]), Box<dyn Error>> {
+    check_bit_depth::<T>(bit_depth)?;
+
+    frame1.can_compare(frame2)?;
+
+    let mut results = [(0.0, PsnrHvsCoverage::default()); 3];
+    let [y, u, v] = &mut results;
+
+    parallel::scope(|s| {
+        s.spawn(|_| {
+            *y = calculate_plane_psnr_hvs_internal(
+                &frame1.planes[0],
+                &frame2.planes[0],
+                0,
+                bit_depth,
+                true,
+                true,
+                None,
+                None,
+            );
+        });
+        s.spawn(|_| {
+            *u = calculate_plane_psnr_hvs_internal(
+                &frame1.planes[1],
+                &frame2.planes[1],
+                1,
+                bit_depth,
+                true,
+                true,
+                None,
+                None,
+            );
+        });
+        s.spawn(|_| {
+            *v = calculate_plane_psnr_hvs_internal(
+                &frame1.planes[2],
+                &frame2.planes[2],
+                2,
+                bit_depth,
+                true,
+                true,
+                None,
+                None,
+            );
+        });
+    });
+
+    let [(y, y_coverage), (u, u_coverage), (v, v_coverage)] = results;
+    let cweight = ChromaWeights::for_frame(frame1, chroma_sampling);
+    let metrics = PlanarMetrics {
+        y: log10_convert(y, 1.0),
+        u: log10_convert(u, 1.0),
+        v: log10_convert(v, 1.0),
+        avg: log10_convert(cweight.weighted_sum(y, u, v), cweight.total()),
+    };
+    Ok((metrics, [y_coverage, u_coverage, v_coverage]))
+}
+
+/// Calculates the PSNR-HVS score between a reference frame and each of several
+/// distorted frames, in the order of `distorted`. Higher is better.
+///
+/// The DCTs of the reference frame are calculated once and shared by all comparisons,
+/// which makes this faster than calling [`calculate_frame_psnr_hvs`] for each
+/// distorted frame, e.g. when sweeping the parameters of an image codec.
+/// The scores are the same.
+pub fn calculate_frame_psnr_hvs_many<'a, T: Pixel, I: IntoIterator<Item = &'a Frame<T>>>(
+    reference: &Frame<T>,
+    distorted: I,
+    bit_depth: usize,
+    chroma_sampling: ChromaSampling,
+) -> Result<Vec<PlanarMetrics>, Box<dyn Error>> {
+    check_bit_depth::<T>(bit_depth)?;
+
+    // Monochrome video has no chroma planes to compare
+    let plane_count = if chroma_sampling == ChromaSampling::Cs400 {
+        1
+    } else {
+        3
+    };
+    let references: Vec<Vec<BlockAnalysis>> = reference.planes[..plane_count]
+        .par_iter()
+        .enumerate()
+        .map(|(plane_idx, plane)| analyze_reference_plane(plane, plane_idx, true))
+        .collect();
+    let cweight = ChromaWeights::for_frame(reference, chroma_sampling);
+
+    let mut results = Vec::new();
+    for frame in distorted {
+        reference.can_compare(frame)?;
+        let mut scores = [f64::NAN; 3];
+        parallel::scope(|s| {
+            for (plane_idx, (blocks, score)) in references.iter().zip(scores.iter_mut()).enumerate()
+            {
+                let plane1 = &reference.planes[plane_idx];
+                let plane2 = &frame.planes[plane_idx];
+                s.spawn(move |_| {
+                    *score = if plane1.cfg.width >= 8
+                        && plane1.cfg.height >= 8
+                        && plane1.is_identical(plane2)
+                    {
+                        0.0
+                    } else {
+                        calculate_plane_psnr_hvs_internal(
+                            plane1,
+                            plane2,
+                            plane_idx,
+                            bit_depth,
+                            true,
+                            true,
+                            Some(blocks),
+                            None,
+                        )
+                        .0
+                    };
+                });
+            }
+        });
+        let [y, u, v] = scores;
+        results.push(PlanarMetrics {
+            y: log10_convert(y, 1.0),
+            u: log10_convert(u, 1.0),
+            v: log10_convert(v, 1.0),
+            avg: log10_convert(cweight.weighted_sum(y, u, v), cweight.total()),
+        });
+    }
+    Ok(results)
+}
+
+struct PsnrHvs {
+    /// Weights of the chroma planes in `avg`. Derived from the decimation of the
+    /// chroma planes of each frame if `None`.
+    pub cweight: Option<ChromaWeights>,
+    pub use_simd: bool,
+    /// The planes to compute the score for.
+    pub planes: PlaneSelection,
+    /// Whether to apply the contrast masking of PSNR-HVS-M.
+    pub masking: bool,
+    /// The closure to pass each pair of decoded frames to before comparing them.
+    pub preprocess: Option<Preprocess>,
+}
+
+impl VideoMetric for PsnrHvs {
+    type FrameResult = PlanarMetrics;
+    type VideoResult = PlanarMetrics;
+    type Context = ();
+
+    fn preprocess(&self) -> Option<&Preprocess> {
+        self.preprocess.as_ref()
+    }
+
+    /// Returns the errors of the planes, without the logarithmic conversion.
+    /// `avg` is already weighted, as the default weights depend on the decimation
+    /// of the planes.
+    fn process_frame<T: Pixel>(
+        &self,
+        frame1: &Frame<T>,
+        frame2: &Frame<T>,
+        bit_depth: usize,
+        chroma_sampling: ChromaSampling,
+    ) -> Result<Self::FrameResult, Box<dyn Error>> {
+        check_bit_depth::<T>(bit_depth)?;
+
+        frame1.can_compare(frame2)?;
+
+        let mut y = f64::NAN;
+        let mut u = f64::NAN;
+        let mut v = f64::NAN;
+
+        parallel::scope(|s| {
+            if self.planes.has_plane(0) {
+                s.spawn(|_| {
+                    y = calculate_plane_psnr_hvs(
+                        &frame1.planes[0],
+                        &frame2.planes[0],
+                        0,
+                        bit_depth,
+                        self.use_simd,
+                        self.masking,
+                    )
+                });
+            }
+
+            // Monochrome video has no chroma planes to compare
+            if chroma_sampling == ChromaSampling::Cs400 {
+                return;
+            }
+
+            if self.planes.has_plane(1) {
+                s.spawn(|_| {
+                    u = calculate_plane_psnr_hvs(
+                        &frame1.planes[1],
+                        &frame2.planes[1],
+                        1,
+                        bit_depth,
+                        self.use_simd,
+                        self.masking,
+                    )
+                });
+            }
+            if self.planes.has_plane(2) {
+                s.spawn(|_| {
+                    v = calculate_plane_psnr_hvs(
+                        &frame1.planes[2],
+                        &frame2.planes[2],
+                        2,
+                        bit_depth,
+                        self.use_simd,
+                        self.masking,
+                    )
+                });
+            }
+        });
+
+        let cweight = self
+            .cweight
+            .unwrap_or_else(|| ChromaWeights::for_frame(frame1, chroma_sampling));
+        Ok(PlanarMetrics {
+            y,
+            u,
+            v,
+            // The same weighting as the `avg` of `calculate_frame_psnr_hvs`
+            avg: self.planes.weighted_sum(cweight, y, u, v) * self.planes.total(cweight),
+        })
+    }
+
+    fn aggregate_frame_results(
+        &self,
+        metrics: &[Self::FrameResult],
+    ) -> Result<Self::VideoResult, Box<dyn Error>> {
+        let sum_y = metrics.iter().map(|m| m.y).sum::<f64>();
+        let sum_u = metrics.iter().map(|m| m.u).sum::<f64>();
+        let sum_v = metrics.iter().map(|m| m.v).sum::<f64>();
+        let sum_avg = metrics.iter().map(|m| m.avg).sum::<f64>();
+        Ok(PlanarMetrics {
+            y: log10_convert(sum_y, 1. / metrics.len() as f64),
+            u: log10_convert(sum_u, 1. / metrics.len() as f64),
+            v: log10_convert(sum_v, 1. / metrics.len() as f64),
+            avg: log10_convert(sum_avg, 1. / metrics.len() as f64),
+        })
+    }
+}
+
+// Normalized inverse quantization matrix for 8x8 DCT at the point of transparency.
+// This is not the JPEG based matrix from the paper,
+// this one gives a slightly higher MOS agreement.
+#[rustfmt::skip]
+const CSF_Y: [[f64; 8]; 8] = [
+    [1.6193873005, 2.2901594831, 2.08509755623, 1.48366094411, 1.00227514334, 0.678296995242, 0.466224900598, 0.3265091542],
+    [2.2901594831, 1.94321815382, 2.04793073064, 1.68731108984, 1.2305666963, 0.868920337363, 0.61280991668, 0.436405793551],
+    [2.08509755623, 2.04793073064, 1.34329019223, 1.09205635862, 0.875748795257, 0.670882927016, 0.501731932449, 0.372504254596],
+    [1.48366094411, 1.68731108984, 1.09205635862, 0.772819797575, 0.605636379554, 0.48309405692, 0.380429446972, 0.295774038565],
+    [1.00227514334, 1.2305666963, 0.875748795257, 0.605636379554, 0.448996256676, 0.352889268808, 0.283006984131, 0.226951348204],
+    [0.678296995242, 0.868920337363, 0.670882927016, 0.48309405692, 0.352889268808, 0.27032073436, 0.215017739696, 0.17408067321],
+    [0.466224900598, 0.61280991668, 0.501731932449, 0.380429446972, 0.283006984131, 0.215017739696, 0.168869545842, 0.136153931001],
+    [0.3265091542, 0.436405793551, 0.372504254596, 0.295774038565, 0.226951348204, 0.17408067321, 0.136153931001, 0.109083846276]
+];
+
+#[rustfmt::skip]
+const CSF_CB420: [[f64; 8]; 8] = [
+    [1.91113096927, 2.46074210438, 1.18284184739, 1.14982565193, 1.05017074788, 0.898018824055, 0.74725392039, 0.615105596242],
+    [2.46074210438, 1.58529308355, 1.21363250036, 1.38190029285, 1.33100189972, 1.17428548929, 0.996404342439, 0.830890433625],
+    [1.18284184739, 1.21363250036, 0.978712413627, 1.02624506078, 1.03145147362, 0.960060382087, 0.849823426169, 0.731221236837],
+    [1.14982565193, 1.38190029285, 1.02624506078, 0.861317501629, 0.801821139099, 0.751437590932, 0.685398513368, 0.608694761374],
+    [1.05017074788, 1.33100189972, 1.03145147362, 0.801821139099, 0.676555426187, 0.605503172737, 0.55002013668, 0.495804539034],
+    [0.898018824055, 1.17428548929, 0.960060382087, 0.751437590932, 0.605503172737, 0.514674450957, 0.454353482512, 0.407050308965],
+    [0.74725392039, 0.996404342439, 0.849823426169, 0.685398513368, 0.55002013668, 0.454353482512, 0.389234902883, 0.342353999733],
+    [0.615105596242, 0.830890433625, 0.731221236837, 0.608694761374, 0.495804539034, 0.407050308965, 0.342353999733, 0.295530605237]
+];
+
+#[rustfmt::skip]
+const CSF_CR420: [[f64; 8]; 8] = [
+    [2.03871978502, 2.62502345193, 1.26180942886, 1.11019789803, 1.01397751469, 0.867069376285, 0.721500455585, 0.593906509971],
+    [2.62502345193, 1.69112867013, 1.17180569821, 1.3342742857, 1.28513006198, 1.13381474809, 0.962064122248, 0.802254508198],
+    [1.26180942886, 1.17180569821, 0.944981930573, 0.990876405848, 0.995903384143, 0.926972725286, 0.820534991409, 0.706020324706],
+    [1.11019789803, 1.3342742857, 0.990876405848, 0.831632933426, 0.77418706195, 0.725539939514, 0.661776842059, 0.587716619023],
+    [1.01397751469, 1.28513006198, 0.995903384143, 0.77418706195, 0.653238524286, 0.584635025748, 0.531064164893, 0.478717061273],
+    [0.867069376285, 1.13381474809, 0.926972725286, 0.725539939514, 0.584635025748, 0.496936637883, 0.438694579826, 0.393021669543],
+    [0.721500455585, 0.962064122248, 0.820534991409, 0.661776842059, 0.531064164893, 0.438694579826, 0.375820256136, 0.330555063063],
+    [0.593906509971, 0.802254508198, 0.706020324706, 0.587716619023, 0.478717061273, 0.393021669543, 0.330555063063, 0.285345396658]
+];
+
+/// The largest bit depth for which the DCT cannot overflow its 32-bit intermediates.
+const MAX_BIT_DEPTH: usize = 14;
+
+fn check_bit_depth<T: Pixel>(bit_depth: usize) -> Result<(), Box<dyn Error>> {
+    check_pixel_width::<T>(bit_depth)?;
+    if bit_depth > MAX_BIT_DEPTH {
+        return Err(Box::new(MetricsError::UnsupportedInput {
+            reason: "PSNR-HVS only supports input up to 14 bits",
+        }));
+    }
+    Ok(())
+}
+
+/// Returns the CSF to use for the given plane, which is subsampled by `xdec`
+/// and `ydec` relative to the luma plane.
+///
+/// The chroma tables were derived for 4:2:0 content, where the chroma planes have
+/// half the resolution of the luma plane in both directions. Each DCT coefficient
+/// of a chroma plane with a different subsampling covers a different spatial frequency
+/// than the same coefficient in a 4:2:0 chroma plane, so the table is resampled
+/// accordingly. Along a direction in which chroma is not subsampled, a block covers
+/// half the area of a 4:2:0 block, so coefficient `j` is looked up at `2 * j`.
+/// Along a direction subsampled by 4, e.g. for 4:1:1 content, it is looked up at
+/// `j / 2`. Frequencies beyond the table, i.e. the upper half of the coefficients of
+/// 4:4:4 content, use the weights of the highest coefficient.
+fn get_csf(plane_idx: usize, xdec: usize, ydec: usize) -> [[f64; 8]; 8] {
+    let csf_420 = match plane_idx {
+        0 => return CSF_Y,
+        1 => &CSF_CB420,
+        2 => &CSF_CR420,
+        _ => unreachable!(),
+    };
+    let x_scale = 2.0 / (1 << xdec) as f64;
+    let y_scale = 2.0 / (1 << ydec) as f64;
+
+    let mut csf = [[0.0; 8]; 8];
+    for i in 0..8 {
+        for j in 0..8 {
+            csf[i][j] = interpolate_csf(csf_420, i as f64 * y_scale, j as f64 * x_scale);
+        }
+    }
+    csf
+}
+
+/// Bilinearly interpolates `csf` at the fractional coefficient position `(i, j)`.
+fn interpolate_csf(csf: &[[f64; 8]; 8], i: f64, j: f64) -> f64 {
+    let (i, j) = (i.min(7.0), j.min(7.0));
+    let (i0, j0) = (i.floor() as usize, j.floor() as usize);
+    let (i1, j1) = ((i0 + 1).min(7), (j0 + 1).min(7));
+    let (fi, fj) = (i - i0 as f64, j - j0 as f64);
+    let top = csf[i0][j0] * (1.0 - fj) + csf[i0][j1] * fj;
+    let bottom = csf[i1][j0] * (1.0 - fj) + csf[i1][j1] * fj;
+    top * (1.0 - fi) + bottom * fi
+}
+
+fn calculate_plane_psnr_hvs<T: Pixel>(
+    plane1: &Plane<T>,
+    plane2: &Plane<T>,
+    plane_idx: usize,
+    bit_depth: usize,
+    simd: bool,
+    masking: bool,
+) -> f64 {
+    // Identical planes have no error, so the DCTs can be skipped. Planes without
+    // a single block keep going through the regular path, which scores them as `NaN`.
+    if plane1.cfg.width >= 8 && plane1.cfg.height >= 8 && plane1.is_identical(plane2) {
+        return 0.0;
+    }
+    calculate_plane_psnr_hvs_internal(
+        plane1, plane2, plane_idx, bit_depth, simd, masking, None, None,
+    )
+    .0
+}
+
+/// Returns the masking weight of each DCT coefficient for the given CSF.
+fn get_mask(csf: &[[f64; 8]; 8]) -> [[f64; 8]; 8] {
+    // In the PSNR-HVS-M paper[1] the authors describe the construction of
+    // their masking table as "we have used the quantization table for the
+    // color component Y of JPEG [6] that has been also obtained on the
+    // basis of CSF. Note that the values in quantization table JPEG have
+    // been normalized and then squared." Their CSF matrix (from PSNR-HVS)
+    // was also constructed from the JPEG matrices. I can not find any obvious
+    // scheme of normalizing to produce their table, but if I multiply their
+    // CSF by 0.38857 and square the result I get their masking table.
+    // I have no idea where this constant comes from, but deviating from it
+    // too greatly hurts MOS agreement.
+    //
+    // [1] Nikolay Ponomarenko, Flavia Silvestri, Karen Egiazarian, Marco Carli,
+    //     Jaakko Astola, Vladimir Lukin, "On between-coefficient contrast masking
+    //     of DCT basis functions", CD-ROM Proceedings of the Third
+    //     International Workshop on Video Processing and Quality Metrics for Consumer
+    //     Electronics VPQM-07, Scottsdale, Arizona, USA, 25-26 January, 2007, 4 p.
+    const CSF_MULTIPLIER: f64 = 0.3885746225901003;
+    let mut mask = [[0.0; 8]; 8];
+    for x in 0..8 {
+        for y in 0..8 {
+            mask[x][y] = (csf[x][y] * CSF_MULTIPLIER).powi(2);
+        }
+    }
+    mask
+}
+
+/// The DCT coefficients of an 8x8 block, along with how strongly the contents
+/// of the block mask errors.
+#[derive(Clone, Copy)]
+struct BlockAnalysis {
+    dct: [i32; 64],
+    mask: f64,
+}
+
+/// Transforms the 8x8 block with its top left corner at `(x, y)`.
+fn analyze_block<T: Pixel>(
+    data: &[T],
+    stride: usize,
+    x: usize,
+    y: usize,
+    mask: &[[f64; 8]; 8],
+    fdct8x8: FdctFn,
+) -> BlockAnalysis {
+    let mut p = [0i16; 8 * 8];
+    let mut means = [0.0; 4];
+    let mut vars = [0.0; 4];
+    let mut gmean = 0.0;
+    let mut gvar = 0.0;
+    let mut block_mask = 0.0;
+
+    for i in 0..8 {
+        for j in 0..8 {
+            p[i * 8 + j] = i16::cast_from(data[(y + i) * stride + x + j]);
+
+            let sub = ((i & 12) >> 2) + ((j & 12) >> 1);
+            gmean += p[i * 8 + j] as f64;
+            means[sub] += p[i * 8 + j] as f64;
+        }
+    }
+    gmean /= 64.0;
+    for i in 0..4 {
+        means[i] /= 16.0;
+    }
+
+    for i in 0..8 {
+        for j in 0..8 {
+            let sub = ((i & 12) >> 2) + ((j & 12) >> 1);
+            gvar += (p[i * 8 + j] as f64 - gmean) * (p[i * 8 + j] as f64 - gmean);
+            vars[sub] += (p[i * 8 + j] as f64 - means[sub]) * (p[i * 8 + j] as f64 - means[sub]);
+        }
+    }
+    gvar *= 64.0 / 63.0;
+    for i in 0..4 {
+        vars[i] *= 16.0 / 15.0;
+    }
+    if gvar > 0.0 {
+        gvar = vars.iter().sum::<f64>() / gvar;
+    }
+
+    let mut dct = [0i32; 8 * 8];
+    p.iter().copied().enumerate().for_each(|(i, v)| {
+        dct[i] = v as i32;
+    });
+    unsafe {
+        fdct8x8(&mut dct);
+    }
+    for i in 0..8 {
+        for j in (i == 0) as usize..8 {
+            block_mask += dct[i * 8 + j].pow(2) as f64 * mask[i][j];
+        }
+    }
+    BlockAnalysis {
+        dct,
+        mask: (block_mask * gvar).sqrt() / 32.0,
+    }
+}
+
+/// Transforms every block of a reference plane, for comparing several planes
+/// against it with [`calculate_plane_psnr_hvs_internal`].
+fn analyze_reference_plane<T: Pixel>(
+    plane: &Plane<T>,
+    plane_idx: usize,
+    simd: bool,
+) -> Vec<BlockAnalysis> {
+    let mask = get_mask(&get_csf(plane_idx, plane.cfg.xdec, plane.cfg.ydec));
+    let fdct8x8 = get_fdct8x8_fn(simd);
+    let data = plane.data_origin();
+    let x_offsets = block_offsets(plane.cfg.width);
+    let y_offsets = block_offsets(plane.cfg.height);
+    y_offsets
+        .iter()
+        .flat_map(|&y| x_offsets.iter().map(move |&x| (x, y)))
+        .map(|(x, y)| analyze_block(data, plane.cfg.stride, x, y, &mask, fdct8x8))
+        .collect()
+}
+
+/// Calculates the PSNR-HVS score and coverage of a plane, optionally recording
+/// the error of each block into `block_map`.
+///
+/// If `masking` is `false`, the contrast masking of PSNR-HVS-M is skipped, which
+/// gives the plain PSNR-HVS score.
+///
+/// If `reference_blocks` is given, it must hold the blocks of `plane1` returned by
+/// [`analyze_reference_plane`], which are then not transformed again.
+#[allow(clippy::too_many_arguments)]
+fn calculate_plane_psnr_hvs_internal<T: Pixel>(
+    plane1: &Plane<T>,
+    plane2: &Plane<T>,
+    plane_idx: usize,
+    bit_depth: usize,
+    simd: bool,
+    masking: bool,
+    reference_blocks: Option<&[BlockAnalysis]>,
+    mut block_map: Option<&mut PsnrHvsMap>,
+) -> (f64, PsnrHvsCoverage) {
+    let mut result = 0.0;
+    let mut pixels = 0usize;
+    let csf = &get_csf(plane_idx, plane1.cfg.xdec, plane1.cfg.ydec);
+    let mask = get_mask(csf);
+
+    let height = plane1.cfg.height;
+    let width = plane1.cfg.width;
+    let stride = plane1.cfg.stride;
+    let data1 = plane1.data_origin();
+    let data2 = plane2.data_origin();
+    assert!(data1.len() >= stride * height.saturating_sub(1) + width);
+    assert!(data2.len() >= stride * height.saturating_sub(1) + width);
+    let sample_max: usize = (1 << bit_depth) - 1;
+    let fdct8x8 = get_fdct8x8_fn(simd);
+    let x_offsets = block_offsets(width);
+    let y_offsets = block_offsets(height);
+    if let Some(map) = block_map.as_deref_mut() {
+        map.width = x_offsets.len();
+        map.height = y_offsets.len();
+        map.data = Vec::with_capacity(map.width * map.height);
+    }
+    for (row, &y) in y_offsets.iter().enumerate() {
+        for (col, &x) in x_offsets.iter().enumerate() {
+            let block1 = match reference_blocks {
+                Some(blocks) => blocks[row * x_offsets.len() + col],
+                None => analyze_block(data1, stride, x, y, &mask, fdct8x8),
+            };
+            let block2 = analyze_block(data2, stride, x, y, &mask, fdct8x8);
+            let mut block_mask = block1.mask;
+            if block2.mask > block_mask {
+                block_mask = block2.mask;
+            }
+            let mut block_result = 0.0;
+            for i in 0..8 {
+                for j in 0..8 {
+                    let mut err = (block1.dct[i * 8 + j] - block2.dct[i * 8 + j]).abs() as f64;
+                    if masking && (i != 0 || j != 0) {
+                        let err_mask = block_mask / mask[i][j];
+                        err = if err < err_mask { 0.0 } else { err - err_mask };
+                    }
+                    let weighted_err = (err * csf[i][j]).powi(2);
+                    result += weighted_err;
+                    block_result += weighted_err;
+                    pixels += 1;
+                }
+            }
+            if let Some(map) = block_map.as_deref_mut() {
+                map.data
+                    .push(block_result / 64.0 / sample_max.pow(2) as f64);
+            }
+        }
+    }
+
+    result /= pixels as f64;
+    result /= sample_max.pow(2) as f64;
+
+    // Neighbouring blocks overlap, so the covered area ends with the last block.
+    let covered = |offsets: &[usize]| offsets.last().map_or(0, |&last| last + 8);
+    let coverage = PsnrHvsCoverage {
+        blocks: x_offsets.len() * y_offsets.len(),
+        covered_pixels: covered(&x_offsets) * covered(&y_offsets),
+        total_pixels: width * height,
+    };
+    (result, coverage)
+}
+
+/// Returns the offsets of the 8x8 blocks along a plane dimension of length `len`.
+///
+/// Blocks are placed every 7 pixels, so neighbouring blocks overlap by one pixel.
+/// When the dimension is not covered exactly by this grid, one more block is placed
+/// so that it ends at the boundary, instead of dropping the residual edge pixels.
+/// That last block overlaps its neighbour by more than one pixel.
+fn block_offsets(len: usize) -> Vec<usize> {
+    const STEP: usize = 7;
+    const BLOCK_SIZE: usize = 8;
+
+    if len < BLOCK_SIZE {
+        return Vec::new();
+    }
+    let last = len - BLOCK_SIZE;
+    let mut offsets: Vec<usize> = (0..=last).step_by(STEP).collect();
+    if offsets.last() != Some(&last) {
+        offsets.push(last);
+    }
+    offsets
+}
+
+fn log10_convert(score: f64, weight: f64) -> f64 {
+    -10.0 * (weight * score).log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_csf, CSF_CB420, CSF_CR420, CSF_Y};
+
+    #[test]
+    fn csf_resampling() {
+        assert_eq!(get_csf(0, 0, 0), CSF_Y);
+        assert_eq!(get_csf(1, 1, 1), CSF_CB420);
+        assert_eq!(get_csf(2, 1, 1), CSF_CR420);
+
+        // 4:2:2 is only resampled vertically, and 4:4:4 in both directions
+        let csf_422 = get_csf(1, 1, 0);
+        let csf_444 = get_csf(1, 0, 0);
+        for i in 0..8 {
+            for j in 0..8 {
+                let i2 = (2 * i).min(7);
+                let j2 = (2 * j).min(7);
+                assert_eq!(csf_422[i][j], CSF_CB420[i2][j]);
+                assert_eq!(csf_444[i][j], CSF_CB420[i2][j2]);
+            }
+        }
+
+        // 4:1:1 is interpolated between the coefficients horizontally
+        let csf_411 = get_csf(2, 2, 0);
+        for i in 0..8 {
+            assert_eq!(csf_411[i][0], CSF_CR420[(2 * i).min(7)][0]);
+            let mid = (CSF_CR420[(2 * i).min(7)][0] + CSF_CR420[(2 * i).min(7)][1]) / 2.0;
+            assert!((csf_411[i][1] - mid).abs() < 1e-12);
+        }
+    }
+}
@@ -7,22 +7,66 @@
 //! squared error (MSE).
 //!
 //! See https://en.wikipedia.org/wiki/Structural_similarity for more details.
+//!
+//! Unless noted otherwise, the functions in this module do not return the mean
+//! SSIM in the range [0, 1], but convert it to a logarithmic scale using
+//! `-10 * log10(1 - ssim)`. The `_raw` variants return the mean SSIM unchanged,
+//! which matches the output of most other tools, e.g. scikit-image.
+//!
+//! Identical inputs score 1 before the conversion, and therefore infinity after it.
+//! Otherwise, the scores are always finite: the SSIM of each window is guaranteed to
+//! be finite by the validated stabilization constants and pixel weights, and windows
+//! which still are not are left out instead of turning the whole score into `NaN`.
+//! Negative contrast-structure scores, which occur for anti-correlated content,
+//! are clamped to zero in MSSSIM before being raised to the scale weights.
+//!
+//! Constant planes, e.g. of black frames in a fade, have no contrast or structure.
+//! When both planes are constant, their SSIM is therefore only the luminance term
+//! `(2 * a * b + c1) / (a^2 + b^2 + c1)` of their sample values `a` and `b`, with
+//! a contrast-structure score of exactly 1. This is 1 for equal planes, and
+//! decreases the further the two values are apart. As downscaling keeps the planes
+//! constant, MSSSIM only keeps the luminance term of the coarsest scale.
+//! This is calculated directly instead of by filtering the planes, except for
+//! per-pixel weights and [`EdgeHandling::Zero`], where the windows are not constant.
+//! A constant plane compared against a varying one is scored like any other plane.
+//!
+//! The SSIM of a plane is modelled on Daala's `dump_ssim`: the moments of each window are
+//! filtered as integers with a Gaussian kernel which scales with the plane height,
+//! windows shrink at the edges of the plane, and the window scores are computed as
+//! `f64` and averaged. The deliberate deviations are:
+//!
+//! - Identical planes score exactly 1, and constant planes are scored in closed form
+//!   as described above, instead of filtering their windows. This can change the
+//!   last bits of their scores, which [`SsimConfig::exact`] turns off.
+//! - Windows whose score is not finite are left out. With validated stabilization
+//!   constants, there are none, so this never changes a score.
+//! - For planes smaller than the kernel, the kernel is shortened to fit the plane.
+//! - The windows of a row are filtered in two passes, summing the vertical pass as
+//!   `i128`. Integer sums are exact, so this does not change the moments.
+//!
+//! [`SsimConfig::daala`] turns off the first point, so that only the last two apply.
+//! The scores are checked against a port of the window loop of `dump_ssim` in the
+//! tests, not against the output of `dump_ssim` itself.
 
 use crate::video::decode::Decoder;
+use crate::video::parallel;
+use crate::video::parallel::prelude::*;
 use crate::video::pixel::CastFromPrimitive;
 use crate::video::pixel::Pixel;
-use crate::video::ChromaWeight;
-use crate::video::{PlanarMetrics, VideoMetric};
+use crate::video::{
+    calculate_frame_tiled, ChromaWeights, FrameStatistics, FrameTypeMetrics, PlanarMetrics,
+    PlaneSelection, Preprocess, VideoMetric,
+};
 use crate::MetricsError;
 use std::cmp;
 use std::error::Error;
 use std::f64::consts::{E, PI};
-use std::mem::size_of;
+use std::ops::{Add, AddAssign, Mul};
 use v_frame::frame::Frame;
 use v_frame::plane::Plane;
 use v_frame::prelude::ChromaSampling;
 
-use super::FrameCompare;
+use super::{check_pixel_width, FrameCompare, PlaneCompare};
 
 /// Calculates the SSIM score between two videos. Higher is better.
 #[inline]
@@ -32,13 +76,271 @@ pub fn calculate_video_ssim<D: Decoder, F: Fn(usize) + Send>(
     frame_limit: Option<usize>,
     progress_callback: F,
 ) -> Result<PlanarMetrics, Box<dyn Error>> {
-    let cweight = Some(
-        decoder1
-            .get_video_details()
-            .chroma_sampling
-            .get_chroma_weight(),
-    );
-    Ssim { cweight }.process_video(decoder1, decoder2, frame_limit, progress_callback)
+    Ssim::default().process_video(decoder1, decoder2, frame_limit, progress_callback)
+}
+
+/// Calculates the SSIM score between the luma planes of two videos. Higher is better.
+///
+/// The chroma planes are skipped entirely, which makes this considerably faster
+/// than [`calculate_video_ssim`]. The `u` and `v` fields of the result are `NaN`,
+/// and `avg` is equal to `y`.
+#[inline]
+pub fn calculate_video_ssim_luma<D: Decoder, F: Fn(usize) + Send>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    Ssim {
+        planes: PlaneSelection::Y,
+        ..Default::default()
+    }
+    .process_video(decoder1, decoder2, frame_limit, progress_callback)
+}
+
+/// Calculates the SSIM score between two videos. Higher is better.
+///
+/// The frames of each type are also aggregated separately. The type of each frame
+/// is taken from `decoder2`, which is usually the encoded video, or from `decoder1`
+/// if `decoder2` does not know it.
+#[inline]
+pub fn calculate_video_ssim_by_frame_type<D: Decoder, F: Fn(usize) + Send>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<FrameTypeMetrics<PlanarMetrics>, Box<dyn Error>> {
+    Ssim::default().process_video_by_frame_type(decoder1, decoder2, frame_limit, progress_callback)
+}
+
+/// Calculates the SSIM score between two videos. Higher is better.
+///
+/// The mean, variance and standard deviation of the per-frame scores are also
+/// calculated, which show how stable the quality is over time.
+#[inline]
+pub fn calculate_video_ssim_with_statistics<D: Decoder, F: Fn(usize) + Send>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<FrameStatistics<PlanarMetrics>, Box<dyn Error>> {
+    Ssim::default().process_video_with_statistics(
+        decoder1,
+        decoder2,
+        frame_limit,
+        progress_callback,
+        |m| m,
+    )
+}
+
+/// Calculates the SSIM score between two RGB videos. Higher is better.
+///
+/// The planes of the decoded frames are interpreted as R, G and B instead of
+/// Y, U and V, so the result holds the R score in `y`, G in `u` and B in `v`.
+/// All three planes are weighted equally in `avg`, regardless of the chroma
+/// sampling reported by the decoder. Every plane must have the full resolution.
+///
+/// This avoids the rounding of a conversion to YUV, which changes the score,
+/// and matches how some screenshot comparison tools report SSIM.
+#[inline]
+pub fn calculate_video_ssim_rgb<D: Decoder, F: Fn(usize) + Send>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    if decoder1.get_video_details().chroma_sampling != ChromaSampling::Cs444 {
+        return Err(Box::new(MetricsError::UnsupportedInput {
+            reason: "RGB planes must not be subsampled",
+        }));
+    }
+    Ssim {
+        cweight: Some(ChromaWeights::uniform(1.0)),
+        ..Default::default()
+    }
+    .process_video(decoder1, decoder2, frame_limit, progress_callback)
+}
+
+/// Stabilization constants of the SSIM formula.
+///
+/// `c1 = (k1 * L)^2` and `c2 = (k2 * L)^2` are added to the luminance and
+/// contrast-structure terms, where `L` is the maximum sample value. They avoid
+/// instability in flat regions, and change the resulting score somewhat.
+/// The defaults of `k1 = 0.01` and `k2 = 0.03` are taken from the SSIM paper.
+///
+/// Both constants must be in the range (0, 1].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SsimConfig {
+    /// Constant for the luminance term.
+    pub k1: f64,
+    /// Constant for the contrast-structure term.
+    pub k2: f64,
+    /// How windows which extend past the edges of a plane are handled.
+    pub edge: EdgeHandling,
+    /// The type the windows are filtered in.
+    pub accumulation: Accumulation,
+    /// How the scores of the windows are combined into the score of a plane.
+    pub pooling: Pooling,
+    /// Filter the windows of identical and constant planes as well, instead of
+    /// scoring them directly. This is slower, but computes their scores the same
+    /// way as Daala's `dump_ssim`.
+    pub exact: bool,
+}
+
+impl Default for SsimConfig {
+    fn default() -> Self {
+        SsimConfig {
+            k1: 0.01,
+            k2: 0.03,
+            edge: EdgeHandling::default(),
+            accumulation: Accumulation::default(),
+            pooling: Pooling::default(),
+            exact: false,
+        }
+    }
+}
+
+/// How the SSIM windows are filtered where they extend past the edges of a plane.
+///
+/// This only affects the windows within half a kernel length of the edges, but
+/// for small frames those can make up a large part of the score. To match the
+/// scores of another tool, its window and pooling have to match as well, which
+/// differ between most tools.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum EdgeHandling {
+    /// Leave out the samples outside of the plane, shrinking the window.
+    /// This is what Daala's `dump_ssim`, which this implementation is based on, does.
+    #[default]
+    Shrink,
+    /// Repeat the samples at the edge, like MATLAB's `ssim`.
+    Replicate,
+    /// Mirror the plane at its edges, repeating the edge samples
+    /// (`d c b a | a b c d | d c b a`). This is the `reflect` mode of
+    /// `scipy.ndimage`, which scikit-image's `structural_similarity` filters with.
+    /// Note that scikit-image also leaves the windows near the edges out of the mean.
+    Mirror,
+    /// Pad the plane with zeros, like MATLAB's `filter2` with `'same'`.
+    Zero,
+}
+
+/// The type the SSIM windows of a plane are filtered in.
+///
+/// Each sample contributes six moments, which are filtered horizontally into a
+/// buffer holding as many rows as the vertical kernel is long. For large frames,
+/// this buffer takes up tens of megabytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Accumulation {
+    /// Filter the rows as `i64`, which is exact.
+    #[default]
+    Integer,
+    /// Filter the rows as `f32`, which halves the size of the row buffer.
+    /// The windows are still summed as `f64`, so this usually only changes
+    /// the scores in the fourth or fifth decimal place.
+    Float,
+    /// Filter the windows as `f32` on the GPU, which is much faster for large frames.
+    ///
+    /// This requires the `gpu` feature. Without it, or if no GPU is available,
+    /// the windows are filtered on the CPU as with [`Accumulation::Float`], see
+    /// [`Accumulation::gpu_available`].
+    ///
+    /// Unlike with [`Accumulation::Float`], both passes sum the moments as `f32`,
+    /// as WGSL has no wider type. The squared moments of 10-bit and deeper video
+    /// exceed the 24-bit mantissa of `f32`, and the variances are the small
+    /// differences of such sums, so the scores lose precision, most of all in flat
+    /// regions of high bit depth video. No bound on the difference to the scores of
+    /// [`Accumulation::Integer`] is guaranteed.
+    Gpu,
+}
+
+impl Accumulation {
+    /// Returns whether [`Accumulation::Gpu`] filters the windows on a GPU, rather
+    /// than falling back to the CPU.
+    ///
+    /// This is `false` without the `gpu` feature. Otherwise, it requests the GPU
+    /// if it was not used yet.
+    pub fn gpu_available() -> bool {
+        #[cfg(feature = "gpu")]
+        {
+            super::gpu::is_available()
+        }
+        #[cfg(not(feature = "gpu"))]
+        {
+            false
+        }
+    }
+}
+
+/// How the SSIM scores of the windows of a plane are combined.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Pooling {
+    /// The mean of all windows, as in the SSIM paper.
+    #[default]
+    Mean,
+    /// The mean of all windows, weighted by their local variance.
+    ///
+    /// Each window is weighted by the sum of the variances of both planes within the
+    /// window, plus the stabilization constant `c2`. This emphasizes textured regions,
+    /// whose distortions are more visible, over flat ones, similar to improved SSIM
+    /// variants like information content weighted SSIM.
+    /// This changes the scores, which are not comparable to those of standard SSIM.
+    Variance,
+}
+
+/// The sample a kernel tap reads, see [`EdgeHandling::tap`].
+enum Tap {
+    Sample(usize),
+    Padding,
+    Skip,
+}
+
+impl EdgeHandling {
+    /// Maps position `pos - offset` along a line of `len` samples to the sample to read.
+    #[inline]
+    fn tap(self, pos: usize, offset: usize, len: usize) -> Tap {
+        if pos >= offset && pos - offset < len {
+            return Tap::Sample(pos - offset);
+        }
+        let pos = pos as isize - offset as isize;
+        match self {
+            EdgeHandling::Shrink => Tap::Skip,
+            EdgeHandling::Replicate => Tap::Sample(pos.clamp(0, len as isize - 1) as usize),
+            EdgeHandling::Mirror => {
+                // Repeated reflections only happen for planes smaller than the kernel
+                let period = 2 * len as isize;
+                let pos = pos.rem_euclid(period);
+                Tap::Sample(if pos < len as isize {
+                    pos
+                } else {
+                    period - 1 - pos
+                } as usize)
+            }
+            EdgeHandling::Zero => Tap::Padding,
+        }
+    }
+}
+
+impl SsimConfig {
+    /// The settings closest to Daala's `dump_ssim`.
+    ///
+    /// This is the default configuration with [`SsimConfig::exact`] set, and only
+    /// differs in the scores of identical and constant planes.
+    pub fn daala() -> Self {
+        SsimConfig {
+            exact: true,
+            ..Default::default()
+        }
+    }
+
+    fn validate(&self) -> Result<(), MetricsError> {
+        // Larger constants could overflow the stabilization terms to infinity
+        let valid = |k: f64| k > 0.0 && k <= 1.0;
+        if !(valid(self.k1) && valid(self.k2)) {
+            return Err(MetricsError::UnsupportedInput {
+                reason: "SSIM constants must be in the range (0, 1]",
+            });
+        }
+        Ok(())
+    }
 }
 
 /// Calculates the SSIM score between two video frames. Higher is better.
@@ -49,225 +351,1105 @@ pub fn calculate_frame_ssim<T: Pixel>(
     bit_depth: usize,
     chroma_sampling: ChromaSampling,
 ) -> Result<PlanarMetrics, Box<dyn Error>> {
-    let processor = Ssim::default();
-    let result = processor.process_frame(frame1, frame2, bit_depth, chroma_sampling)?;
-    let cweight = chroma_sampling.get_chroma_weight();
-    Ok(PlanarMetrics {
-        y: log10_convert(result.y, 1.0),
-        u: log10_convert(result.u, 1.0),
-        v: log10_convert(result.v, 1.0),
-        avg: log10_convert(
-            result.y + cweight * (result.u + result.v),
-            1.0 + 2.0 * cweight,
-        ),
-    })
+    SsimOptions::new().calculate_frame(frame1, frame2, bit_depth, chroma_sampling)
+}
+
+/// Options for calculating SSIM, for when the defaults of [`calculate_video_ssim`]
+/// and [`calculate_frame_ssim`] do not fit.
+///
+/// The options are set with the builder methods, e.g.
+/// `SsimOptions::new().raw(true).calculate_video(...)`. Invalid options are
+/// reported by the `calculate_*` methods.
+#[derive(Debug, Clone, Default)]
+pub struct SsimOptions {
+    cweight: Option<ChromaWeights>,
+    planes: PlaneSelection,
+    raw: bool,
+    config: SsimConfig,
+    preprocess: Option<Preprocess>,
+}
+
+impl SsimOptions {
+    /// The default options, which give the same scores as [`calculate_video_ssim`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use custom weights for the chroma planes in the `avg` score, instead of
+    /// weighting them by their size relative to the luma plane.
+    pub fn chroma_weights(mut self, cweight: ChromaWeights) -> Self {
+        self.cweight = Some(cweight);
+        self
+    }
+
+    /// Compute the score for the selected planes only.
+    ///
+    /// Planes which are not selected are skipped entirely. Their fields in the result
+    /// are `NaN`, and they are left out of the `avg` score.
+    pub fn planes(mut self, planes: PlaneSelection) -> Self {
+        self.planes = planes;
+        self
+    }
+
+    /// Return the mean SSIM instead of converting it to a logarithmic scale.
+    ///
+    /// By default, the mean SSIM `s` of each plane is reported as `-10 * log10(1 - s)`,
+    /// which spreads out the scores of high quality video. With `raw` set, `s` is
+    /// reported directly, in the range [0, 1] with 1 meaning that the inputs are
+    /// identical, as most other SSIM tools do. `avg` is the weighted mean of the
+    /// planes either way, taken before the conversion.
+    pub fn raw(mut self, raw: bool) -> Self {
+        self.raw = raw;
+        self
+    }
+
+    /// Use custom stabilization constants, window edge handling, accumulation or
+    /// pooling. [`SsimConfig::default`] gives the same scores as [`calculate_video_ssim`].
+    pub fn config(mut self, config: SsimConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Pass each pair of decoded frames to `preprocess` before comparing them, e.g. to
+    /// apply a known gain correction to one of the videos.
+    ///
+    /// The frames may be modified in place, but their size and format must not change.
+    /// Returning `false` leaves the pair out, which still counts towards the
+    /// `frame_limit`. The pixel type of the closure must match the bit depth of the
+    /// videos, `u8` for up to 8 bits and `u16` otherwise, or `calculate_video` returns
+    /// an error. The closure is not used by `calculate_frame`.
+    pub fn preprocess<T: Pixel, P>(mut self, preprocess: P) -> Self
+    where
+        P: Fn(&mut Frame<T>, &mut Frame<T>) -> bool + Send + Sync + 'static,
+    {
+        self.preprocess = Some(Preprocess::new(preprocess));
+        self
+    }
+
+    /// Calculates the SSIM score between two videos. Higher is better.
+    #[inline]
+    pub fn calculate_video<D: Decoder, F: Fn(usize) + Send>(
+        &self,
+        decoder1: &mut D,
+        decoder2: &mut D,
+        frame_limit: Option<usize>,
+        progress_callback: F,
+    ) -> Result<PlanarMetrics, Box<dyn Error>> {
+        self.metric()?
+            .process_video(decoder1, decoder2, frame_limit, progress_callback)
+    }
+
+    /// Calculates the SSIM score between two video frames. Higher is better.
+    #[inline]
+    pub fn calculate_frame<T: Pixel>(
+        &self,
+        frame1: &Frame<T>,
+        frame2: &Frame<T>,
+        bit_depth: usize,
+        chroma_sampling: ChromaSampling,
+    ) -> Result<PlanarMetrics, Box<dyn Error>> {
+        let metric = self.metric()?;
+        let result = metric.process_frame(frame1, frame2, bit_depth, chroma_sampling)?;
+        Ok(PlanarMetrics {
+            y: metric.convert(result.y, 1.0),
+            u: metric.convert(result.u, 1.0),
+            v: metric.convert(result.v, 1.0),
+            avg: metric.convert(result.avg, 1.0),
+        })
+    }
+
+    fn metric(&self) -> Result<Ssim<'static>, MetricsError> {
+        self.planes.validate()?;
+        self.config.validate()?;
+        Ok(Ssim {
+            cweight: self.cweight,
+            planes: self.planes,
+            raw: self.raw,
+            config: self.config,
+            preprocess: self.preprocess.clone(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Calculates the SSIM score of each tile of two video frames. Higher is better.
+///
+/// The frames are split into tiles of `tile_width` by `tile_height` luma samples,
+/// which must be a multiple of the chroma subsampling. The result holds the rows of
+/// tiles from top to bottom, each from left to right. The tiles at the right and
+/// bottom edges are smaller if the frame size is not a multiple of the tile size.
+///
+/// Each tile is scored like a frame of its own with [`calculate_frame_ssim`], so the
+/// SSIM windows do not reach across tiles, and are sized for the tile height.
+/// Unlike [`calculate_frame_ssim_map`], this gives a coarse score per region.
+pub fn calculate_frame_ssim_tiled<T: Pixel>(
+    frame1: &Frame<T>,
+    frame2: &Frame<T>,
+    bit_depth: usize,
+    chroma_sampling: ChromaSampling,
+    tile_width: usize,
+    tile_height: usize,
+) -> Result<Vec<Vec<PlanarMetrics>>, Box<dyn Error>> {
+    calculate_frame_tiled(frame1, frame2, tile_width, tile_height, |tile1, tile2| {
+        calculate_frame_ssim(tile1, tile2, bit_depth, chroma_sampling)
+    })
+}
+
+/// Calculates the SSIM score between two RGB frames. Higher is better.
+///
+/// See [`calculate_video_ssim_rgb`] for how the planes are interpreted.
+/// Build the frames with the R, G and B planes in place of Y, U and V, e.g. with
+/// [`frame_from_planes`](crate::video::frame_from_planes).
+#[inline]
+pub fn calculate_frame_ssim_rgb<T: Pixel>(
+    frame1: &Frame<T>,
+    frame2: &Frame<T>,
+    bit_depth: usize,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    // Only the geometry matters, the planes may be padded differently
+    let geometry = |plane: &Plane<T>| {
+        let cfg = &plane.cfg;
+        (cfg.width, cfg.height, cfg.xdec, cfg.ydec)
+    };
+    let [r, g, b] = &frame1.planes;
+    if geometry(r) != geometry(g) || geometry(r) != geometry(b) {
+        return Err(Box::new(MetricsError::UnsupportedInput {
+            reason: "RGB planes must not be subsampled",
+        }));
+    }
+    let processor = Ssim::default();
+    let result = processor.process_frame(frame1, frame2, bit_depth, ChromaSampling::Cs444)?;
+    Ok(PlanarMetrics {
+        y: log10_convert(result.y, 1.0),
+        u: log10_convert(result.u, 1.0),
+        v: log10_convert(result.v, 1.0),
+        avg: log10_convert(result.y + result.u + result.v, 3.0),
+    })
+}
+
+/// Calculates the SSIM score between the luma planes of two video frames. Higher is better.
+///
+/// The chroma planes are skipped entirely, which makes this considerably faster
+/// than [`calculate_frame_ssim`]. The `u` and `v` fields of the result are `NaN`,
+/// and `avg` is equal to `y`.
+#[inline]
+pub fn calculate_frame_ssim_luma<T: Pixel>(
+    frame1: &Frame<T>,
+    frame2: &Frame<T>,
+    bit_depth: usize,
+    chroma_sampling: ChromaSampling,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    let processor = Ssim {
+        planes: PlaneSelection::Y,
+        ..Default::default()
+    };
+    let result = processor.process_frame(frame1, frame2, bit_depth, chroma_sampling)?;
+    let y = log10_convert(result.y, 1.0);
+    Ok(PlanarMetrics {
+        y,
+        u: f64::NAN,
+        v: f64::NAN,
+        avg: y,
+    })
+}
+
+/// Calculates the SSIM score between two video frames, weighting the contribution
+/// of each pixel by a per-pixel weight map. Higher is better.
+///
+/// `weights` holds one weight per luma pixel in row-major order, so it must contain
+/// exactly `width * height` values of the luma plane. This can be used e.g. with a
+/// saliency map to emphasize the regions a viewer is likely to focus on.
+/// The weights of the chroma planes are derived by averaging the luma weights
+/// covered by each chroma pixel.
+///
+/// A map where every weight is equal gives the same result as [`calculate_frame_ssim`].
+pub fn calculate_frame_ssim_weighted<T: Pixel>(
+    frame1: &Frame<T>,
+    frame2: &Frame<T>,
+    bit_depth: usize,
+    chroma_sampling: ChromaSampling,
+    weights: &[f32],
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    let luma_cfg = &frame1.planes[0].cfg;
+    if weights.len() != luma_cfg.width * luma_cfg.height {
+        return Err(Box::new(MetricsError::InputMismatch {
+            reason: "Weight map does not match luma plane dimensions",
+        }));
+    }
+    // The weighted mean is undefined otherwise
+    if !weights
+        .iter()
+        .all(|&weight| weight.is_finite() && weight >= 0.0)
+        || !weights.iter().any(|&weight| weight > 0.0)
+    {
+        return Err(Box::new(MetricsError::UnsupportedInput {
+            reason: "Weights must be finite and non-negative, and not all zero",
+        }));
+    }
+    let processor = Ssim {
+        weights: Some(weights),
+        ..Default::default()
+    };
+    let result = processor.process_frame(frame1, frame2, bit_depth, chroma_sampling)?;
+    let cweight = ChromaWeights::for_frame(frame1, chroma_sampling);
+    Ok(PlanarMetrics {
+        y: log10_convert(result.y, 1.0),
+        u: log10_convert(result.u, 1.0),
+        v: log10_convert(result.v, 1.0),
+        avg: log10_convert(
+            cweight.weighted_sum(result.y, result.u, result.v),
+            cweight.total(),
+        ),
+    })
+}
+
+/// A map of the SSIM of each pixel in a plane.
+///
+/// Pixels are laid out in row-major order, with `width` pixels per row.
+/// Each value is the SSIM of the window centered on the pixel, which is 1 for
+/// windows without any difference and lower for windows with structural differences.
+/// The score of the plane pools these values, weighting each window by how much
+/// of its kernel lies within the plane, so windows near the edges count slightly less.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SsimMap {
+    /// Number of pixels per row.
+    pub width: usize,
+    /// Number of pixels per column.
+    pub height: usize,
+    /// SSIM of each pixel.
+    pub data: Vec<f32>,
+}
+
+/// Calculates the per-pixel SSIM maps between two video frames, in plane order (Y, U, V).
+///
+/// This is useful to visualize where structural differences occur in a frame.
+/// The maps use the same windows as [`calculate_frame_ssim`].
+pub fn calculate_frame_ssim_map<T: Pixel>(
+    frame1: &Frame<T>,
+    frame2: &Frame<T>,
+    bit_depth: usize,
+    _chroma_sampling: ChromaSampling,
+) -> Result<[SsimMap; 3], Box<dyn Error>> {
+    check_pixel_width::<T>(bit_depth)?;
+
+    frame1.can_compare(frame2)?;
+
+    let sample_max = (1 << bit_depth) - 1;
+    let mut maps: [SsimMap; 3] = Default::default();
+
+    parallel::scope(|s| {
+        for ((plane1, plane2), map) in frame1
+            .planes
+            .iter()
+            .zip(frame2.planes.iter())
+            .zip(maps.iter_mut())
+        {
+            s.spawn(move |_| {
+                let kernel = build_plane_kernel(plane1.cfg.width, plane1.cfg.height);
+                let mut buffers = PlaneBuffers::default();
+                plane_to_vec(plane1, &mut buffers.plane1);
+                plane_to_vec(plane2, &mut buffers.plane2);
+                calculate_plane_ssim_internal(
+                    &buffers.plane1,
+                    &buffers.plane2,
+                    plane1.cfg.width,
+                    plane1.cfg.height,
+                    sample_max,
+                    &kernel,
+                    &kernel,
+                    None,
+                    SsimConfig::default(),
+                    Some(map),
+                    &mut buffers.lines,
+                );
+            });
+        }
+    });
+
+    Ok(maps)
+}
+
+/// Calculates the SSIM score between a reference frame and each of several distorted
+/// frames, in the order of `distorted`. Higher is better.
+///
+/// The parts of the SSIM windows which only depend on the reference frame are
+/// calculated once and shared by all comparisons, which makes this faster than
+/// calling [`calculate_frame_ssim`] for each distorted frame, e.g. when sweeping
+/// the parameters of an image codec. The scores are the same.
+pub fn calculate_frame_ssim_many<'a, T: Pixel, I: IntoIterator<Item = &'a Frame<T>>>(
+    reference: &Frame<T>,
+    distorted: I,
+    bit_depth: usize,
+    chroma_sampling: ChromaSampling,
+) -> Result<Vec<PlanarMetrics>, Box<dyn Error>> {
+    check_pixel_width::<T>(bit_depth)?;
+
+    let sample_max = (1 << bit_depth) - 1;
+    let config = SsimConfig::default();
+    // Monochrome video has no chroma planes to compare
+    let plane_count = if chroma_sampling == ChromaSampling::Cs400 {
+        1
+    } else {
+        3
+    };
+    let references: Vec<ReferencePlane> = reference.planes[..plane_count]
+        .par_iter()
+        .map(|plane| ReferencePlane::new(plane, config.edge))
+        .collect();
+    let mut buffers: [ReferenceBuffers; 3] = Default::default();
+    let cweight = ChromaWeights::for_frame(reference, chroma_sampling);
+
+    let mut results = Vec::new();
+    for frame in distorted {
+        reference.can_compare(frame)?;
+        let mut scores = [f64::NAN; 3];
+        parallel::scope(|s| {
+            for (((reference_plane, plane1), plane2), (score, buffers)) in references
+                .iter()
+                .zip(reference.planes.iter())
+                .zip(frame.planes.iter())
+                .zip(scores.iter_mut().zip(buffers.iter_mut()))
+            {
+                s.spawn(move |_| {
+                    *score = if plane1.cfg.width > 0
+                        && plane1.cfg.height > 0
+                        && plane1.is_identical(plane2)
+                    {
+                        1.0
+                    } else {
+                        calculate_plane_ssim_with_reference(
+                            reference_plane,
+                            plane2,
+                            sample_max,
+                            config,
+                            buffers,
+                        )
+                    };
+                });
+            }
+        });
+        let [y, u, v] = scores;
+        results.push(PlanarMetrics {
+            y: log10_convert(y, 1.0),
+            u: log10_convert(u, 1.0),
+            v: log10_convert(v, 1.0),
+            avg: log10_convert(cweight.weighted_sum(y, u, v) / cweight.total(), 1.0),
+        });
+    }
+    Ok(results)
+}
+
+/// Calculates the SSIM score between the alpha planes of two frames. Higher is better.
+///
+/// A [`Frame`] only holds the three color planes, so the alpha planes of images
+/// with transparency are passed separately, e.g. as returned by the PNG loader of
+/// `av-metrics-decoders`. Any other single plane can be compared the same way.
+/// The frame and video metrics ignore alpha, and no video decoder returns it yet,
+/// so this only covers still images.
+/// The score is calculated like that of the luma plane in [`calculate_frame_ssim`].
+pub fn calculate_alpha_ssim<T: Pixel>(
+    alpha1: &Plane<T>,
+    alpha2: &Plane<T>,
+    bit_depth: usize,
+) -> Result<f64, Box<dyn Error>> {
+    check_pixel_width::<T>(bit_depth)?;
+    alpha1.can_compare(alpha2)?;
+    let kernel = build_plane_kernel(alpha1.cfg.width, alpha1.cfg.height);
+    let (ssim, _) = calculate_plane_ssim(
+        alpha1,
+        alpha2,
+        (1 << bit_depth) - 1,
+        &kernel,
+        &kernel,
+        None,
+        SsimConfig::default(),
+        &mut PlaneBuffers::default(),
+    );
+    Ok(log10_convert(ssim, 1.0))
+}
+
+/// Calculates the SSIM score between two planes whose samples are already stored
+/// as `u32`. Higher is better.
+///
+/// The samples are tightly packed in row-major order, without any padding.
+/// The score is the same as that of the plane in [`calculate_frame_ssim`], but the
+/// samples are read in place instead of being copied into `u32` buffers first,
+/// which saves an allocation and a copy of each plane.
+pub fn calculate_plane_ssim_u32(
+    plane1: &[u32],
+    plane2: &[u32],
+    width: usize,
+    height: usize,
+    bit_depth: usize,
+) -> Result<f64, Box<dyn Error>> {
+    validate_u32_planes(plane1, plane2, width, height, bit_depth)?;
+    let sample_max = (1 << bit_depth) - 1;
+    let config = SsimConfig::default();
+    let (ssim, _) = if width > 0 && height > 0 && plane1 == plane2 {
+        (1.0, 1.0)
+    } else if let (Some(a), Some(b)) = (constant_sample(plane1), constant_sample(plane2)) {
+        constant_plane_ssim(a, b, sample_max, config)
+    } else {
+        let kernel = build_plane_kernel(width, height);
+        calculate_plane_ssim_internal(
+            plane1,
+            plane2,
+            width,
+            height,
+            sample_max,
+            &kernel,
+            &kernel,
+            None,
+            config,
+            None,
+            &mut LineBuffers::default(),
+        )
+    };
+    Ok(log10_convert(ssim, 1.0))
+}
+
+/// Calculates the MSSSIM score between two planes whose samples are already stored
+/// as `u32`. Higher is better.
+///
+/// See [`calculate_plane_ssim_u32`] for the layout of the planes. The score is the
+/// same as that of the plane in [`calculate_frame_msssim`].
+pub fn calculate_plane_msssim_u32(
+    plane1: &[u32],
+    plane2: &[u32],
+    width: usize,
+    height: usize,
+    bit_depth: usize,
+) -> Result<f64, Box<dyn Error>> {
+    validate_u32_planes(plane1, plane2, width, height, bit_depth)?;
+    let score = if width > 0 && height > 0 && plane1 == plane2 {
+        1.0
+    } else {
+        let mut buffers = PlaneBuffers::default();
+        let kernel = build_msssim_kernel();
+        let scales = msssim_scale_count(width, height, kernel.len());
+        let scales = match (constant_sample(plane1), constant_sample(plane2)) {
+            (Some(a), Some(b)) => constant_msssim_scales(a, b, bit_depth, scales),
+            _ => filter_msssim_scales(
+                Some((plane1, plane2)),
+                width,
+                height,
+                bit_depth,
+                scales,
+                &kernel,
+                &mut buffers,
+            ),
+        };
+        combine_msssim_scales(&scales)
+    };
+    Ok(log10_convert(score, 1.0))
+}
+
+/// Checks the planes passed to [`calculate_plane_ssim_u32`] and
+/// [`calculate_plane_msssim_u32`].
+fn validate_u32_planes(
+    plane1: &[u32],
+    plane2: &[u32],
+    width: usize,
+    height: usize,
+    bit_depth: usize,
+) -> Result<(), MetricsError> {
+    if plane1.len() != width * height || plane2.len() != width * height {
+        return Err(MetricsError::InputMismatch {
+            reason: "Plane length does not match its dimensions",
+        });
+    }
+    if !(1..=16).contains(&bit_depth) {
+        return Err(MetricsError::UnsupportedInput {
+            reason: "Bit depth must be between 1 and 16",
+        });
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Ssim<'a> {
+    /// Weights of the chroma planes in `avg`. Derived from the decimation of the
+    /// chroma planes of each frame if `None`.
+    pub cweight: Option<ChromaWeights>,
+    /// The planes to compute the score for.
+    pub planes: PlaneSelection,
+    /// Per-pixel weights of the luma plane used for pooling the SSIM values.
+    pub weights: Option<&'a [f32]>,
+    /// Output the contrast-structure component instead of the full SSIM score.
+    pub contrast_structure: bool,
+    /// Output the mean scores instead of converting them to a logarithmic scale.
+    pub raw: bool,
+    /// Stabilization constants of the SSIM formula.
+    pub config: SsimConfig,
+    /// The closure to pass each pair of decoded frames to before comparing them.
+    pub preprocess: Option<Preprocess>,
+}
+
+impl<'a> Ssim<'a> {
+    /// Picks the requested score out of the SSIM and contrast-structure scores.
+    fn select_score(&self, (ssim, cs): (f64, f64)) -> f64 {
+        if self.contrast_structure {
+            cs
+        } else {
+            ssim
+        }
+    }
+
+    /// Converts the sum of `weight` scores to the output scale.
+    fn convert(&self, score: f64, weight: f64) -> f64 {
+        if self.raw {
+            score / weight
+        } else {
+            log10_convert(score, weight)
+        }
+    }
+}
+
+impl<'a> VideoMetric for Ssim<'a> {
+    type FrameResult = PlanarMetrics;
+    type VideoResult = PlanarMetrics;
+    type Context = SsimContext;
+
+    fn preprocess(&self) -> Option<&Preprocess> {
+        self.preprocess.as_ref()
+    }
+
+    /// Returns the mean scores of the planes, without the logarithmic conversion.
+    /// `avg` is their weighted mean, as the default weights depend on the decimation
+    /// of the planes.
+    fn process_frame<T: Pixel>(
+        &self,
+        frame1: &Frame<T>,
+        frame2: &Frame<T>,
+        bit_depth: usize,
+        chroma_sampling: ChromaSampling,
+    ) -> Result<Self::FrameResult, Box<dyn Error>> {
+        self.process_frame_with_context(
+            &mut SsimContext::default(),
+            frame1,
+            frame2,
+            bit_depth,
+            chroma_sampling,
+        )
+    }
+
+    fn process_frame_with_context<T: Pixel>(
+        &self,
+        context: &mut Self::Context,
+        frame1: &Frame<T>,
+        frame2: &Frame<T>,
+        bit_depth: usize,
+        chroma_sampling: ChromaSampling,
+    ) -> Result<Self::FrameResult, Box<dyn Error>> {
+        check_pixel_width::<T>(bit_depth)?;
+
+        frame1.can_compare(frame2)?;
+
+        let sample_max = (1 << bit_depth) - 1;
+
+        let mut y = f64::NAN;
+        let mut u = f64::NAN;
+        let mut v = f64::NAN;
+        let [y_buffers, u_buffers, v_buffers] = &mut context.planes;
+
+        parallel::scope(|s| {
+            if self.planes.has_plane(0) {
+                s.spawn(|_| {
+                    let y_kernel =
+                        build_plane_kernel(frame1.planes[0].cfg.width, frame1.planes[0].cfg.height);
+                    y = self.select_score(calculate_plane_ssim(
+                        &frame1.planes[0],
+                        &frame2.planes[0],
+                        sample_max,
+                        &y_kernel,
+                        &y_kernel,
+                        self.weights,
+                        self.config,
+                        y_buffers,
+                    ))
+                });
+            }
+
+            // Monochrome video has no chroma planes to compare
+            if chroma_sampling == ChromaSampling::Cs400 {
+                return;
+            }
+
+            if self.planes.has_plane(1) {
+                s.spawn(|_| {
+                    let u_kernel =
+                        build_plane_kernel(frame1.planes[1].cfg.width, frame1.planes[1].cfg.height);
+                    let weights = self.weights.map(|weights| {
+                        downscale_weights(weights, &frame1.planes[0], &frame1.planes[1])
+                    });
+                    u = self.select_score(calculate_plane_ssim(
+                        &frame1.planes[1],
+                        &frame2.planes[1],
+                        sample_max,
+                        &u_kernel,
+                        &u_kernel,
+                        weights.as_deref(),
+                        self.config,
+                        u_buffers,
+                    ))
+                });
+            }
+
+            if self.planes.has_plane(2) {
+                s.spawn(|_| {
+                    let v_kernel =
+                        build_plane_kernel(frame1.planes[2].cfg.width, frame1.planes[2].cfg.height);
+                    let weights = self.weights.map(|weights| {
+                        downscale_weights(weights, &frame1.planes[0], &frame1.planes[2])
+                    });
+                    v = self.select_score(calculate_plane_ssim(
+                        &frame1.planes[2],
+                        &frame2.planes[2],
+                        sample_max,
+                        &v_kernel,
+                        &v_kernel,
+                        weights.as_deref(),
+                        self.config,
+                        v_buffers,
+                    ))
+                });
+            }
+        });
+
+        let cweight = self
+            .cweight
+            .unwrap_or_else(|| ChromaWeights::for_frame(frame1, chroma_sampling));
+        Ok(PlanarMetrics {
+            y,
+            u,
+            v,
+            avg: self.planes.weighted_sum(cweight, y, u, v) / self.planes.total(cweight),
+        })
+    }
+
+    fn aggregate_frame_results(
+        &self,
+        metrics: &[Self::FrameResult],
+    ) -> Result<Self::VideoResult, Box<dyn Error>> {
+        let y_sum = metrics.iter().map(|m| m.y).sum::<f64>();
+        let u_sum = metrics.iter().map(|m| m.u).sum::<f64>();
+        let v_sum = metrics.iter().map(|m| m.v).sum::<f64>();
+        let avg_sum = metrics.iter().map(|m| m.avg).sum::<f64>();
+        Ok(PlanarMetrics {
+            y: self.convert(y_sum, metrics.len() as f64),
+            u: self.convert(u_sum, metrics.len() as f64),
+            v: self.convert(v_sum, metrics.len() as f64),
+            avg: self.convert(avg_sum, metrics.len() as f64),
+        })
+    }
+}
+
+/// Calculates the contrast-structure (CS) component of the SSIM score between
+/// two videos. Higher is better.
+///
+/// This is SSIM without the luminance term, which compares the local means of
+/// the two inputs. It is the component MSSSIM uses for all but the coarsest scale.
+#[inline]
+pub fn calculate_video_cs<D: Decoder, F: Fn(usize) + Send>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    Ssim {
+        contrast_structure: true,
+        ..Default::default()
+    }
+    .process_video(decoder1, decoder2, frame_limit, progress_callback)
+}
+
+/// Calculates the contrast-structure (CS) component of the SSIM score between
+/// two video frames. Higher is better.
+///
+/// See [`calculate_video_cs`] for details.
+#[inline]
+pub fn calculate_frame_cs<T: Pixel>(
+    frame1: &Frame<T>,
+    frame2: &Frame<T>,
+    bit_depth: usize,
+    chroma_sampling: ChromaSampling,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    let processor = Ssim {
+        contrast_structure: true,
+        ..Default::default()
+    };
+    let result = processor.process_frame(frame1, frame2, bit_depth, chroma_sampling)?;
+    let cweight = ChromaWeights::for_frame(frame1, chroma_sampling);
+    Ok(PlanarMetrics {
+        y: log10_convert(result.y, 1.0),
+        u: log10_convert(result.u, 1.0),
+        v: log10_convert(result.v, 1.0),
+        avg: log10_convert(
+            cweight.weighted_sum(result.y, result.u, result.v),
+            cweight.total(),
+        ),
+    })
+}
+
+/// Calculates the MSSSIM score between two videos. Higher is better.
+///
+/// MSSSIM is a variant of SSIM computed over subsampled versions
+/// of an image. It is designed to be a more accurate metric
+/// than SSIM.
+///
+/// Up to five scales are used. Fewer scales are used for planes which are
+/// too small to be downscaled five times.
+#[inline]
+pub fn calculate_video_msssim<D: Decoder, F: Fn(usize) + Send>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    MsSsim::default().process_video(decoder1, decoder2, frame_limit, progress_callback)
+}
+
+/// Calculates the MSSSIM score between two videos. Higher is better.
+///
+/// The frames of each type are also aggregated separately. The type of each frame
+/// is taken from `decoder2`, which is usually the encoded video, or from `decoder1`
+/// if `decoder2` does not know it.
+#[inline]
+pub fn calculate_video_msssim_by_frame_type<D: Decoder, F: Fn(usize) + Send>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<FrameTypeMetrics<PlanarMetrics>, Box<dyn Error>> {
+    MsSsim::default().process_video_by_frame_type(
+        decoder1,
+        decoder2,
+        frame_limit,
+        progress_callback,
+    )
+}
+
+/// Calculates the MSSSIM score between two videos. Higher is better.
+///
+/// The mean, variance and standard deviation of the per-frame scores are also
+/// calculated, which show how stable the quality is over time.
+#[inline]
+pub fn calculate_video_msssim_with_statistics<D: Decoder, F: Fn(usize) + Send>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<FrameStatistics<PlanarMetrics>, Box<dyn Error>> {
+    MsSsim::default().process_video_with_statistics(
+        decoder1,
+        decoder2,
+        frame_limit,
+        progress_callback,
+        |m| m,
+    )
+}
+
+/// Calculates the MSSSIM score between two video frames. Higher is better.
+///
+/// MSSSIM is a variant of SSIM computed over subsampled versions
+/// of an image. It is designed to be a more accurate metric
+/// than SSIM.
+///
+/// Up to five scales are used. Fewer scales are used for planes which are
+/// too small to be downscaled five times.
+#[inline]
+pub fn calculate_frame_msssim<T: Pixel>(
+    frame1: &Frame<T>,
+    frame2: &Frame<T>,
+    bit_depth: usize,
+    chroma_sampling: ChromaSampling,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    MsSsimOptions::new().calculate_frame(frame1, frame2, bit_depth, chroma_sampling)
 }
 
-#[derive(Default)]
-struct Ssim {
-    pub cweight: Option<f64>,
+/// Options for calculating MSSSIM, for when the defaults of [`calculate_video_msssim`]
+/// and [`calculate_frame_msssim`] do not fit.
+///
+/// The options are set with the builder methods, e.g.
+/// `MsSsimOptions::new().scales(3).calculate_video(...)`. Invalid options are
+/// reported by the `calculate_*` methods.
+#[derive(Debug, Clone, Default)]
+pub struct MsSsimOptions {
+    cweight: Option<ChromaWeights>,
+    planes: PlaneSelection,
+    scales: Option<usize>,
+    preprocess: Option<Preprocess>,
 }
 
-impl VideoMetric for Ssim {
-    type FrameResult = PlanarMetrics;
-    type VideoResult = PlanarMetrics;
-
-    /// Returns the *unweighted* scores. Depending on whether we output per-frame
-    /// or per-video, these will be weighted at different points.
-    fn process_frame<T: Pixel>(
-        &self,
-        frame1: &Frame<T>,
-        frame2: &Frame<T>,
-        bit_depth: usize,
-        _chroma_sampling: ChromaSampling,
-    ) -> Result<Self::FrameResult, Box<dyn Error>> {
-        if (size_of::<T>() == 1 && bit_depth > 8) || (size_of::<T>() == 2 && bit_depth <= 8) {
-            return Err(Box::new(MetricsError::InputMismatch {
-                reason: "Bit depths does not match pixel width",
-            }));
-        }
-
-        frame1.can_compare(frame2)?;
+impl MsSsimOptions {
+    /// The default options, which give the same scores as [`calculate_video_msssim`].
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        const KERNEL_SHIFT: usize = 8;
-        const KERNEL_WEIGHT: usize = 1 << KERNEL_SHIFT;
-        let sample_max = (1 << bit_depth) - 1;
+    /// Use custom weights for the chroma planes in the `avg` score, instead of
+    /// weighting them by their size relative to the luma plane.
+    pub fn chroma_weights(mut self, cweight: ChromaWeights) -> Self {
+        self.cweight = Some(cweight);
+        self
+    }
 
-        let mut y = 0.0;
-        let mut u = 0.0;
-        let mut v = 0.0;
+    /// Compute the score for the selected planes only.
+    ///
+    /// Planes which are not selected are skipped entirely. Their fields in the result
+    /// are `NaN`, and they are left out of the `avg` score.
+    pub fn planes(mut self, planes: PlaneSelection) -> Self {
+        self.planes = planes;
+        self
+    }
 
-        rayon::scope(|s| {
-            s.spawn(|_| {
-                let y_kernel = build_gaussian_kernel(
-                    frame1.planes[0].cfg.height as f64 * 1.5 / 256.0,
-                    cmp::min(frame1.planes[0].cfg.width, frame1.planes[0].cfg.height),
-                    KERNEL_WEIGHT,
-                );
-                y = calculate_plane_ssim(
-                    &frame1.planes[0],
-                    &frame2.planes[0],
-                    sample_max,
-                    &y_kernel,
-                    &y_kernel,
-                )
-            });
+    /// Use a fixed number of scales, between 1 and 5, instead of choosing it from
+    /// the size of each plane. The scale weights are renormalized when fewer than
+    /// five scales are used.
+    pub fn scales(mut self, scales: usize) -> Self {
+        self.scales = Some(scales);
+        self
+    }
 
-            s.spawn(|_| {
-                let u_kernel = build_gaussian_kernel(
-                    frame1.planes[1].cfg.height as f64 * 1.5 / 256.0,
-                    cmp::min(frame1.planes[1].cfg.width, frame1.planes[1].cfg.height),
-                    KERNEL_WEIGHT,
-                );
-                u = calculate_plane_ssim(
-                    &frame1.planes[1],
-                    &frame2.planes[1],
-                    sample_max,
-                    &u_kernel,
-                    &u_kernel,
-                )
-            });
+    /// Pass each pair of decoded frames to `preprocess` before comparing them.
+    ///
+    /// See [`SsimOptions::preprocess`] for how the closure is called.
+    pub fn preprocess<T: Pixel, P>(mut self, preprocess: P) -> Self
+    where
+        P: Fn(&mut Frame<T>, &mut Frame<T>) -> bool + Send + Sync + 'static,
+    {
+        self.preprocess = Some(Preprocess::new(preprocess));
+        self
+    }
 
-            s.spawn(|_| {
-                let v_kernel = build_gaussian_kernel(
-                    frame1.planes[2].cfg.height as f64 * 1.5 / 256.0,
-                    cmp::min(frame1.planes[2].cfg.width, frame1.planes[2].cfg.height),
-                    KERNEL_WEIGHT,
-                );
-                v = calculate_plane_ssim(
-                    &frame1.planes[2],
-                    &frame2.planes[2],
-                    sample_max,
-                    &v_kernel,
-                    &v_kernel,
-                )
-            });
-        });
+    /// Calculates the MSSSIM score between two videos. Higher is better.
+    #[inline]
+    pub fn calculate_video<D: Decoder, F: Fn(usize) + Send>(
+        &self,
+        decoder1: &mut D,
+        decoder2: &mut D,
+        frame_limit: Option<usize>,
+        progress_callback: F,
+    ) -> Result<PlanarMetrics, Box<dyn Error>> {
+        self.metric()?
+            .process_video(decoder1, decoder2, frame_limit, progress_callback)
+    }
 
+    /// Calculates the MSSSIM score between two video frames. Higher is better.
+    #[inline]
+    pub fn calculate_frame<T: Pixel>(
+        &self,
+        frame1: &Frame<T>,
+        frame2: &Frame<T>,
+        bit_depth: usize,
+        chroma_sampling: ChromaSampling,
+    ) -> Result<PlanarMetrics, Box<dyn Error>> {
+        let result = self
+            .metric()?
+            .process_frame(frame1, frame2, bit_depth, chroma_sampling)?;
         Ok(PlanarMetrics {
-            y,
-            u,
-            v,
-            // Not used here
-            avg: 0.,
+            y: log10_convert(result.y, 1.0),
+            u: log10_convert(result.u, 1.0),
+            v: log10_convert(result.v, 1.0),
+            avg: log10_convert(result.avg, 1.0),
         })
     }
 
-    fn aggregate_frame_results(
-        &self,
-        metrics: &[Self::FrameResult],
-    ) -> Result<Self::VideoResult, Box<dyn Error>> {
-        let cweight = self.cweight.unwrap_or(1.0);
-        let y_sum = metrics.iter().map(|m| m.y).sum::<f64>();
-        let u_sum = metrics.iter().map(|m| m.u).sum::<f64>();
-        let v_sum = metrics.iter().map(|m| m.v).sum::<f64>();
-        Ok(PlanarMetrics {
-            y: log10_convert(y_sum, metrics.len() as f64),
-            u: log10_convert(u_sum, metrics.len() as f64),
-            v: log10_convert(v_sum, metrics.len() as f64),
-            avg: log10_convert(
-                y_sum + cweight * (u_sum + v_sum),
-                (1. + 2. * cweight) * metrics.len() as f64,
-            ),
+    fn metric(&self) -> Result<MsSsim, MetricsError> {
+        self.planes.validate()?;
+        validate_msssim_scales(self.scales)?;
+        Ok(MsSsim {
+            cweight: self.cweight,
+            scales: self.scales,
+            planes: self.planes,
+            preprocess: self.preprocess.clone(),
         })
     }
 }
 
-/// Calculates the MSSSIM score between two videos. Higher is better.
+/// The SSIM and contrast-structure (CS) scores of a plane at each MSSSIM scale,
+/// from the full resolution to the coarsest scale.
 ///
-/// MSSSIM is a variant of SSIM computed over subsampled versions
-/// of an image. It is designed to be a more accurate metric
-/// than SSIM.
-#[inline]
-pub fn calculate_video_msssim<D: Decoder, F: Fn(usize) + Send>(
-    decoder1: &mut D,
-    decoder2: &mut D,
-    frame_limit: Option<usize>,
-    progress_callback: F,
-) -> Result<PlanarMetrics, Box<dyn Error>> {
-    let cweight = Some(
-        decoder1
-            .get_video_details()
-            .chroma_sampling
-            .get_chroma_weight(),
-    );
-    MsSsim { cweight }.process_video(decoder1, decoder2, frame_limit, progress_callback)
+/// The scores are the mean over the plane, in the range [0, 1], without the
+/// logarithmic conversion. MSSSIM combines the CS scores of all but the coarsest
+/// scale with the SSIM score of the coarsest scale. A drop at the finer scales
+/// points to a loss of fine detail, while a drop at the coarser scales points
+/// to distorted structures.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MsSsimScales {
+    /// SSIM score at each scale.
+    pub ssim: Vec<f64>,
+    /// Contrast-structure score at each scale.
+    pub cs: Vec<f64>,
 }
 
-/// Calculates the MSSSIM score between two video frames. Higher is better.
+/// Calculates the SSIM and contrast-structure scores of two video frames at each
+/// MSSSIM scale, in plane order (Y, U, V).
 ///
-/// MSSSIM is a variant of SSIM computed over subsampled versions
-/// of an image. It is designed to be a more accurate metric
-/// than SSIM.
-#[inline]
-pub fn calculate_frame_msssim<T: Pixel>(
+/// This shows at which scale the quality diverges. The number of scales is chosen
+/// like in [`calculate_frame_msssim`]. For monochrome video, the chroma planes
+/// have no scales.
+pub fn calculate_frame_msssim_scales<T: Pixel>(
     frame1: &Frame<T>,
     frame2: &Frame<T>,
     bit_depth: usize,
     chroma_sampling: ChromaSampling,
-) -> Result<PlanarMetrics, Box<dyn Error>> {
-    let processor = MsSsim::default();
-    let result = processor.process_frame(frame1, frame2, bit_depth, chroma_sampling)?;
-    let cweight = chroma_sampling.get_chroma_weight();
-    Ok(PlanarMetrics {
-        y: log10_convert(result.y, 1.0),
-        u: log10_convert(result.u, 1.0),
-        v: log10_convert(result.v, 1.0),
-        avg: log10_convert(
-            result.y + cweight * (result.u + result.v),
-            1.0 + 2.0 * cweight,
-        ),
-    })
+) -> Result<[MsSsimScales; 3], Box<dyn Error>> {
+    check_pixel_width::<T>(bit_depth)?;
+
+    frame1.can_compare(frame2)?;
+
+    // Monochrome video has no chroma planes to compare
+    let plane_count = if chroma_sampling == ChromaSampling::Cs400 {
+        1
+    } else {
+        3
+    };
+    let mut results: [MsSsimScales; 3] = Default::default();
+    parallel::scope(|s| {
+        for ((plane1, plane2), result) in frame1
+            .planes
+            .iter()
+            .zip(frame2.planes.iter())
+            .zip(results.iter_mut())
+            .take(plane_count)
+        {
+            s.spawn(move |_| {
+                *result = calculate_plane_msssim_scales(
+                    plane1,
+                    plane2,
+                    bit_depth,
+                    None,
+                    &mut PlaneBuffers::default(),
+                );
+            });
+        }
+    });
+
+    Ok(results)
+}
+
+fn validate_msssim_scales(scales: Option<usize>) -> Result<(), MetricsError> {
+    if let Some(scales) = scales {
+        if !(1..=MS_WEIGHT.len()).contains(&scales) {
+            return Err(MetricsError::UnsupportedInput {
+                reason: "Number of MSSSIM scales must be between 1 and 5",
+            });
+        }
+    }
+    Ok(())
 }
 
 #[derive(Default)]
 struct MsSsim {
-    pub cweight: Option<f64>,
+    /// Weights of the chroma planes in `avg`. Derived from the decimation of the
+    /// chroma planes of each frame if `None`.
+    pub cweight: Option<ChromaWeights>,
+    /// Number of scales to use. Chosen from the plane size if `None`.
+    pub scales: Option<usize>,
+    /// The planes to compute the score for.
+    pub planes: PlaneSelection,
+    /// The closure to pass each pair of decoded frames to before comparing them.
+    pub preprocess: Option<Preprocess>,
 }
 
 impl VideoMetric for MsSsim {
     type FrameResult = PlanarMetrics;
     type VideoResult = PlanarMetrics;
+    type Context = SsimContext;
 
-    /// Returns the *unweighted* scores. Depending on whether we output per-frame
-    /// or per-video, these will be weighted at different points.
+    fn preprocess(&self) -> Option<&Preprocess> {
+        self.preprocess.as_ref()
+    }
+
+    /// Returns the mean scores of the planes, without the logarithmic conversion.
+    /// `avg` is their weighted mean, as the default weights depend on the decimation
+    /// of the planes.
     fn process_frame<T: Pixel>(
         &self,
         frame1: &Frame<T>,
         frame2: &Frame<T>,
         bit_depth: usize,
-        _chroma_sampling: ChromaSampling,
+        chroma_sampling: ChromaSampling,
     ) -> Result<Self::FrameResult, Box<dyn Error>> {
-        if (size_of::<T>() == 1 && bit_depth > 8) || (size_of::<T>() == 2 && bit_depth <= 8) {
-            return Err(Box::new(MetricsError::InputMismatch {
-                reason: "Bit depths does not match pixel width",
-            }));
-        }
+        self.process_frame_with_context(
+            &mut SsimContext::default(),
+            frame1,
+            frame2,
+            bit_depth,
+            chroma_sampling,
+        )
+    }
+
+    fn process_frame_with_context<T: Pixel>(
+        &self,
+        context: &mut Self::Context,
+        frame1: &Frame<T>,
+        frame2: &Frame<T>,
+        bit_depth: usize,
+        chroma_sampling: ChromaSampling,
+    ) -> Result<Self::FrameResult, Box<dyn Error>> {
+        check_pixel_width::<T>(bit_depth)?;
 
         frame1.can_compare(frame2)?;
 
-        let mut y = 0.0;
-        let mut u = 0.0;
-        let mut v = 0.0;
+        let mut y = f64::NAN;
+        let mut u = f64::NAN;
+        let mut v = f64::NAN;
+        let [y_buffers, u_buffers, v_buffers] = &mut context.planes;
 
-        rayon::scope(|s| {
-            s.spawn(|_| {
-                y = calculate_plane_msssim(&frame1.planes[0], &frame2.planes[0], bit_depth)
-            });
-            s.spawn(|_| {
-                u = calculate_plane_msssim(&frame1.planes[1], &frame2.planes[1], bit_depth)
-            });
-            s.spawn(|_| {
-                v = calculate_plane_msssim(&frame1.planes[2], &frame2.planes[2], bit_depth)
-            });
+        parallel::scope(|s| {
+            if self.planes.has_plane(0) {
+                s.spawn(|_| {
+                    y = calculate_plane_msssim(
+                        &frame1.planes[0],
+                        &frame2.planes[0],
+                        bit_depth,
+                        self.scales,
+                        y_buffers,
+                    )
+                });
+            }
+
+            // Monochrome video has no chroma planes to compare
+            if chroma_sampling == ChromaSampling::Cs400 {
+                return;
+            }
+
+            if self.planes.has_plane(1) {
+                s.spawn(|_| {
+                    u = calculate_plane_msssim(
+                        &frame1.planes[1],
+                        &frame2.planes[1],
+                        bit_depth,
+                        self.scales,
+                        u_buffers,
+                    )
+                });
+            }
+            if self.planes.has_plane(2) {
+                s.spawn(|_| {
+                    v = calculate_plane_msssim(
+                        &frame1.planes[2],
+                        &frame2.planes[2],
+                        bit_depth,
+                        self.scales,
+                        v_buffers,
+                    )
+                });
+            }
         });
 
+        let cweight = self
+            .cweight
+            .unwrap_or_else(|| ChromaWeights::for_frame(frame1, chroma_sampling));
         Ok(PlanarMetrics {
             y,
             u,
             v,
-            // Not used here
-            avg: 0.,
+            avg: self.planes.weighted_sum(cweight, y, u, v) / self.planes.total(cweight),
         })
     }
 
@@ -275,57 +1457,113 @@ impl VideoMetric for MsSsim {
         &self,
         metrics: &[Self::FrameResult],
     ) -> Result<Self::VideoResult, Box<dyn Error>> {
-        let cweight = self.cweight.unwrap();
         let y_sum = metrics.iter().map(|m| m.y).sum::<f64>();
         let u_sum = metrics.iter().map(|m| m.u).sum::<f64>();
         let v_sum = metrics.iter().map(|m| m.v).sum::<f64>();
+        let avg_sum = metrics.iter().map(|m| m.avg).sum::<f64>();
         Ok(PlanarMetrics {
             y: log10_convert(y_sum, metrics.len() as f64),
             u: log10_convert(u_sum, metrics.len() as f64),
             v: log10_convert(v_sum, metrics.len() as f64),
-            avg: log10_convert(
-                y_sum + cweight * (u_sum + v_sum),
-                (1. + 2. * cweight) * metrics.len() as f64,
-            ),
+            avg: log10_convert(avg_sum, metrics.len() as f64),
         })
     }
 }
 
-#[derive(Debug, Clone, Copy, Default)]
-struct SsimMoments {
-    mux: i64,
-    muy: i64,
-    x2: i64,
-    xy: i64,
-    y2: i64,
-    w: i64,
+/// Scratch buffers for the SSIM of a single plane.
+#[derive(Default)]
+pub(crate) struct PlaneBuffers {
+    /// Tightly packed samples of both planes, at the current scale for MSSSIM.
+    plane1: Vec<u32>,
+    plane2: Vec<u32>,
+    /// Horizontally filtered moments of the rows covered by the vertical kernel.
+    lines: LineBuffers,
+    /// Target of the next MSSSIM downscale.
+    downscaled1: Vec<u32>,
+    downscaled2: Vec<u32>,
+}
+
+/// The row buffer of [`filter_windows`] for each [`Accumulation`].
+#[derive(Default)]
+pub(crate) struct LineBuffers {
+    integer: Vec<[i64; 6]>,
+    float: Vec<[f32; 6]>,
 }
 
-const SSIM_K1: f64 = 0.01 * 0.01;
-const SSIM_K2: f64 = 0.03 * 0.03;
+/// Scratch buffers for the SSIM of a frame, with one set per plane
+/// so that the planes can be processed in parallel.
+#[derive(Default)]
+struct SsimContext {
+    planes: [PlaneBuffers; 3],
+}
 
+#[allow(clippy::too_many_arguments)]
 fn calculate_plane_ssim<T: Pixel>(
     plane1: &Plane<T>,
     plane2: &Plane<T>,
     sample_max: u64,
     vert_kernel: &[i64],
     horiz_kernel: &[i64],
-) -> f64 {
-    let vec1 = plane_to_vec(plane1);
-    let vec2 = plane_to_vec(plane2);
+    weights: Option<&[f32]>,
+    config: SsimConfig,
+    buffers: &mut PlaneBuffers,
+) -> (f64, f64) {
+    // Identical planes are a perfect match, so the convolutions can be skipped.
+    // Weighted scores keep going through the regular path, as all weights may be zero.
+    if weights.is_none()
+        && !config.exact
+        && plane1.cfg.width > 0
+        && plane1.cfg.height > 0
+        && plane1.is_identical(plane2)
+    {
+        return (1.0, 1.0);
+    }
+    if weights.is_none() && !config.exact && config.edge != EdgeHandling::Zero {
+        if let (Some(a), Some(b)) = (plane1.constant_sample(), plane2.constant_sample()) {
+            return constant_plane_ssim(a, b, sample_max, config);
+        }
+    }
+    plane_to_vec(plane1, &mut buffers.plane1);
+    plane_to_vec(plane2, &mut buffers.plane2);
     calculate_plane_ssim_internal(
-        &vec1,
-        &vec2,
+        &buffers.plane1,
+        &buffers.plane2,
         plane1.cfg.width,
         plane1.cfg.height,
         sample_max,
         vert_kernel,
         horiz_kernel,
+        weights,
+        config,
+        None,
+        &mut buffers.lines,
     )
-    .0
 }
 
-fn calculate_plane_ssim_internal(
+/// Averages the luma weights covered by each pixel of a (possibly subsampled) plane.
+fn downscale_weights<T: Pixel>(weights: &[f32], luma: &Plane<T>, plane: &Plane<T>) -> Vec<f32> {
+    let (luma_width, luma_height) = (luma.cfg.width, luma.cfg.height);
+    let (xdec, ydec) = (plane.cfg.xdec, plane.cfg.ydec);
+    let mut out = Vec::with_capacity(plane.cfg.width * plane.cfg.height);
+    for y in 0..plane.cfg.height {
+        let rows = (y << ydec)..cmp::min((y + 1) << ydec, luma_height);
+        for x in 0..plane.cfg.width {
+            let cols = (x << xdec)..cmp::min((x + 1) << xdec, luma_width);
+            let count = rows.len() * cols.len();
+            let sum = rows
+                .clone()
+                .flat_map(|row| {
+                    &weights[(row * luma_width + cols.start)..(row * luma_width + cols.end)]
+                })
+                .sum::<f32>();
+            out.push(if count > 0 { sum / count as f32 } else { 0.0 });
+        }
+    }
+    out
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn calculate_plane_ssim_internal(
     plane1: &[u32],
     plane2: &[u32],
     width: usize,
@@ -333,117 +1571,543 @@ fn calculate_plane_ssim_internal(
     sample_max: u64,
     vert_kernel: &[i64],
     horiz_kernel: &[i64],
+    weights: Option<&[f32]>,
+    config: SsimConfig,
+    mut map: Option<&mut SsimMap>,
+    lines: &mut LineBuffers,
 ) -> (f64, f64) {
+    if let Some(map) = map.as_deref_mut() {
+        map.width = width;
+        map.height = height;
+        map.data = Vec::with_capacity(width * height);
+    }
+    let mut ssim = 0.0;
+    let mut ssimw = 0.0;
+    let mut cs = 0.0;
+    let mut index = 0;
+    let mut pool = |moments: [f64; 6]| {
+        let w = moments[5];
+        let (ssim_tmp, cs_tmp) = window_ssim(moments, sample_max, config);
+        let mut pixel_weight = weights.map_or(1.0, |weights| weights[index] as f64);
+        if config.pooling == Pooling::Variance {
+            pixel_weight *= window_variance_weight(moments, sample_max, config);
+        }
+        index += 1;
+        if let Some(map) = map.as_deref_mut() {
+            map.data.push((ssim_tmp / w) as f32);
+        }
+        if !window_is_finite(ssim_tmp, cs_tmp) {
+            return;
+        }
+        cs += pixel_weight * cs_tmp;
+        ssim += pixel_weight * ssim_tmp;
+        ssimw += pixel_weight * w;
+    };
+    match config.accumulation {
+        Accumulation::Integer => filter_windows(
+            width,
+            height,
+            vert_kernel,
+            horiz_kernel,
+            config.edge,
+            |i| match i {
+                Some(i) => {
+                    let (pix1, pix2) = (plane1[i] as i64, plane2[i] as i64);
+                    [pix1, pix2, pix1 * pix1, pix1 * pix2, pix2 * pix2, 1]
+                }
+                None => [0, 0, 0, 0, 0, 1],
+            },
+            &mut lines.integer,
+            |sums| pool(sums.map(|sum| sum as f64)),
+        ),
+        Accumulation::Float | Accumulation::Gpu => {
+            #[cfg(feature = "gpu")]
+            let gpu_windows = (config.accumulation == Accumulation::Gpu)
+                .then(|| {
+                    super::gpu::filter_windows(
+                        plane1,
+                        plane2,
+                        width,
+                        height,
+                        vert_kernel,
+                        horiz_kernel,
+                        config.edge,
+                    )
+                })
+                .flatten();
+            #[cfg(not(feature = "gpu"))]
+            let gpu_windows: Option<Vec<[f32; 6]>> = None;
+            match gpu_windows {
+                Some(windows) => windows
+                    .into_iter()
+                    .for_each(|sums| pool(sums.map(f64::from))),
+                None => filter_windows(
+                    width,
+                    height,
+                    vert_kernel,
+                    horiz_kernel,
+                    config.edge,
+                    |i| match i {
+                        Some(i) => {
+                            let (pix1, pix2) = (plane1[i] as f32, plane2[i] as f32);
+                            [pix1, pix2, pix1 * pix1, pix1 * pix2, pix2 * pix2, 1.0]
+                        }
+                        None => [0.0, 0.0, 0.0, 0.0, 0.0, 1.0],
+                    },
+                    &mut lines.float,
+                    pool,
+                ),
+            }
+        }
+    }
+
+    (ssim / ssimw, cs / ssimw)
+}
+
+/// The parts of the SSIM windows of a plane which only depend on that plane,
+/// for comparing several planes against it.
+struct ReferencePlane {
+    samples: Vec<u32>,
+    width: usize,
+    height: usize,
+    kernel: Vec<i64>,
+    /// `mux`, `x2` and `w` of each window, in row-major order.
+    windows: Vec<[f64; 3]>,
+}
+
+impl ReferencePlane {
+    fn new<T: Pixel>(plane: &Plane<T>, edge: EdgeHandling) -> Self {
+        let mut samples = Vec::new();
+        plane_to_vec(plane, &mut samples);
+        let (width, height) = (plane.cfg.width, plane.cfg.height);
+        let kernel = build_plane_kernel(width, height);
+        let mut windows = Vec::with_capacity(width * height);
+        filter_windows(
+            width,
+            height,
+            &kernel,
+            &kernel,
+            edge,
+            |i| match i {
+                Some(i) => {
+                    let pix = samples[i] as i64;
+                    [pix, pix * pix, 1]
+                }
+                None => [0, 0, 1],
+            },
+            &mut Vec::new(),
+            |[mux, x2, w]| windows.push([mux as f64, x2 as f64, w as f64]),
+        );
+        ReferencePlane {
+            samples,
+            width,
+            height,
+            kernel,
+            windows,
+        }
+    }
+}
+
+/// Scratch buffers for comparing planes against a [`ReferencePlane`].
+#[derive(Default)]
+struct ReferenceBuffers {
+    plane: Vec<u32>,
+    lines: Vec<[i64; 3]>,
+}
+
+/// Calculates the SSIM between a reference plane and `plane`, filtering only
+/// the moments which depend on `plane`.
+fn calculate_plane_ssim_with_reference<T: Pixel>(
+    reference: &ReferencePlane,
+    plane: &Plane<T>,
+    sample_max: u64,
+    config: SsimConfig,
+    buffers: &mut ReferenceBuffers,
+) -> f64 {
+    plane_to_vec(plane, &mut buffers.plane);
+    let (samples1, samples2) = (&reference.samples, &buffers.plane);
+    let mut windows = reference.windows.iter();
+    let mut ssim = 0.0;
+    let mut ssimw = 0.0;
+    filter_windows(
+        reference.width,
+        reference.height,
+        &reference.kernel,
+        &reference.kernel,
+        config.edge,
+        |i| match i {
+            Some(i) => {
+                let (pix1, pix2) = (samples1[i] as i64, samples2[i] as i64);
+                [pix2, pix1 * pix2, pix2 * pix2]
+            }
+            None => [0; 3],
+        },
+        &mut buffers.lines,
+        |[muy, xy, y2]| {
+            let &[mux, x2, w] = windows.next().expect("one reference window per pixel");
+            let moments = [mux, muy as f64, x2, xy as f64, y2 as f64, w];
+            let (ssim_tmp, cs_tmp) = window_ssim(moments, sample_max, config);
+            if window_is_finite(ssim_tmp, cs_tmp) {
+                ssim += ssim_tmp;
+                ssimw += w;
+            }
+        },
+    );
+    ssim / ssimw
+}
+
+/// Filters `N` per-sample terms of a plane with both kernels, and passes the
+/// sums of each window to `window` in row-major order.
+///
+/// `terms` is called with the index of a sample in the tightly packed plane,
+/// or with `None` for the zeros padding the plane with [`EdgeHandling::Zero`].
+///
+/// Samples are at most 16 bits wide, and grow by another 8 bits after four
+/// MS-SSIM downscales. With kernel weights summing up to at most 2^10, the
+/// squared terms of a row stay below 2^59, so `i64` cannot overflow there.
+/// Applying the vertical kernel adds up to 10 more bits, which overflows `i64`
+/// for 16-bit input at the coarser MS-SSIM scales, so windows are summed as `i128`.
+/// With [`Accumulation::Float`], rows are filtered as `f32` and windows summed as `f64`.
+#[allow(clippy::too_many_arguments)]
+fn filter_windows<A: Accumulator, const N: usize>(
+    width: usize,
+    height: usize,
+    vert_kernel: &[i64],
+    horiz_kernel: &[i64],
+    edge: EdgeHandling,
+    terms: impl Fn(Option<usize>) -> [A; N],
+    lines: &mut Vec<[A; N]>,
+    mut window: impl FnMut([A::Wide; N]),
+) {
+    let vert_kernel: Vec<A::Wide> = vert_kernel
+        .iter()
+        .map(|&weight| A::from_weight(weight).widen())
+        .collect();
+    let horiz_kernel: Vec<A> = horiz_kernel
+        .iter()
+        .map(|&weight| A::from_weight(weight))
+        .collect();
     let vert_offset = vert_kernel.len() >> 1;
     let line_size = vert_kernel.len().next_power_of_two();
     let line_mask = line_size - 1;
-    let mut lines = vec![vec![SsimMoments::default(); width]; line_size];
+    // The rows are stored one after another
+    lines.clear();
+    lines.resize(line_size * width, [A::default(); N]);
     let horiz_offset = horiz_kernel.len() >> 1;
-    let mut ssim = 0.0;
-    let mut ssimw = 0.0;
-    let mut cs = 0.0;
+    // A horizontally filtered row of padding
+    let padding_weight = horiz_kernel
+        .iter()
+        .fold(A::default(), |sum, &weight| sum + weight);
+    let padding_line = terms(None).map(|term| padding_weight * term);
     for y in 0..(height + vert_offset) {
         if y < height {
-            let buf = &mut lines[y & line_mask];
-            let line1 = &plane1[(y * width)..];
-            let line2 = &plane2[(y * width)..];
+            let buf = &mut lines[(y & line_mask) * width..][..width];
             for x in 0..width {
-                let mut moments = SsimMoments::default();
-                let k_min = horiz_offset.saturating_sub(x);
-                let tmp_offset = (x + horiz_offset + 1).saturating_sub(width);
-                let k_max = horiz_kernel.len() - tmp_offset;
-                for k in k_min..k_max {
-                    let window = horiz_kernel[k];
-                    let target_x = (x + k).saturating_sub(horiz_offset);
-                    let pix1 = line1[target_x] as i64;
-                    let pix2 = line2[target_x] as i64;
-                    moments.mux += window * pix1;
-                    moments.muy += window * pix2;
-                    moments.x2 += window * pix1 * pix1;
-                    moments.xy += window * pix1 * pix2;
-                    moments.y2 += window * pix2 * pix2;
-                    moments.w += window;
+                let mut sums = [A::default(); N];
+                if x >= horiz_offset && x + horiz_kernel.len() - horiz_offset <= width {
+                    // Fast path for windows within the plane
+                    let start = y * width + x - horiz_offset;
+                    for (k, &weight) in horiz_kernel.iter().enumerate() {
+                        for (sum, term) in sums.iter_mut().zip(terms(Some(start + k))) {
+                            *sum += weight * term;
+                        }
+                    }
+                } else {
+                    for (k, &weight) in horiz_kernel.iter().enumerate() {
+                        let index = match edge.tap(x + k, horiz_offset, width) {
+                            Tap::Sample(target_x) => Some(y * width + target_x),
+                            Tap::Padding => None,
+                            Tap::Skip => continue,
+                        };
+                        for (sum, term) in sums.iter_mut().zip(terms(index)) {
+                            *sum += weight * term;
+                        }
+                    }
                 }
-                buf[x] = moments;
+                buf[x] = sums;
             }
         }
         if y >= vert_offset {
-            let k_min = vert_kernel.len().saturating_sub(y + 1);
-            let tmp_offset = (y + 1).saturating_sub(height);
-            let k_max = vert_kernel.len() - tmp_offset;
+            // Rows mapped back into the plane stay within reach of the kernel,
+            // so they are still in `lines`.
+            let out_y = y - vert_offset;
+            // The offset of each row in `lines`
+            let rows: Vec<Tap> = (0..vert_kernel.len())
+                .map(|k| match edge.tap(out_y + k, vert_offset, height) {
+                    Tap::Sample(row) => Tap::Sample((row & line_mask) * width),
+                    tap => tap,
+                })
+                .collect();
             for x in 0..width {
-                let mut moments = SsimMoments::default();
-                for k in k_min..k_max {
-                    let buf = lines[(y + 1 + k - vert_kernel.len()) & line_mask][x];
-                    let window = vert_kernel[k];
-                    moments.mux += window * buf.mux;
-                    moments.muy += window * buf.muy;
-                    moments.x2 += window * buf.x2;
-                    moments.xy += window * buf.xy;
-                    moments.y2 += window * buf.y2;
-                    moments.w += window * buf.w;
+                let mut sums = [A::Wide::default(); N];
+                for (&weight, row) in vert_kernel.iter().zip(&rows) {
+                    let line = match *row {
+                        Tap::Sample(row) => &lines[row + x],
+                        Tap::Padding => &padding_line,
+                        Tap::Skip => continue,
+                    };
+                    for (sum, &term) in sums.iter_mut().zip(line) {
+                        *sum += weight * term.widen();
+                    }
                 }
-                let w = moments.w as f64;
-                let c1 = sample_max.pow(2) as f64 * SSIM_K1 * w.powi(2);
-                let c2 = sample_max.pow(2) as f64 * SSIM_K2 * w.powi(2);
-                let mx2 = (moments.mux as f64).powi(2);
-                let mxy = moments.mux as f64 * moments.muy as f64;
-                let my2 = (moments.muy as f64).powi(2);
-                let cs_tmp = w * (c2 + 2.0 * (moments.xy as f64 * w - mxy))
-                    / (moments.x2 as f64 * w - mx2 + moments.y2 as f64 * w - my2 + c2);
-                cs += cs_tmp;
-                ssim += cs_tmp * (2.0 * mxy + c1) / (mx2 + my2 + c1);
-                ssimw += w;
+                window(sums);
             }
         }
     }
+}
 
-    (ssim / ssimw, cs / ssimw)
+/// A type [`filter_windows`] filters the rows in.
+trait Accumulator: Copy + Default + Add<Output = Self> + AddAssign + Mul<Output = Self> {
+    /// The type the windows are summed in, which needs more headroom than the rows.
+    type Wide: Copy + Default + AddAssign + Mul<Output = Self::Wide>;
+
+    fn from_weight(weight: i64) -> Self;
+    fn widen(self) -> Self::Wide;
+}
+
+impl Accumulator for i64 {
+    type Wide = i128;
+
+    #[inline]
+    fn from_weight(weight: i64) -> Self {
+        weight
+    }
+
+    #[inline]
+    fn widen(self) -> i128 {
+        self as i128
+    }
+}
+
+impl Accumulator for f32 {
+    type Wide = f64;
+
+    #[inline]
+    fn from_weight(weight: i64) -> Self {
+        weight as f32
+    }
+
+    #[inline]
+    fn widen(self) -> f64 {
+        self as f64
+    }
+}
+
+/// Calculates the SSIM and contrast-structure scores of a window from its moments
+/// `[mux, muy, x2, xy, y2, w]`, both multiplied by the window weight `w`.
+#[inline]
+fn window_ssim(moments: [f64; 6], sample_max: u64, config: SsimConfig) -> (f64, f64) {
+    let [mux, muy, x2, xy, y2, w] = moments;
+    let c1 = sample_max.pow(2) as f64 * config.k1.powi(2) * w.powi(2);
+    let c2 = sample_max.pow(2) as f64 * config.k2.powi(2) * w.powi(2);
+    let mx2 = mux.powi(2);
+    let mxy = mux * muy;
+    let my2 = muy.powi(2);
+    let cs = w * (c2 + 2.0 * (xy * w - mxy)) / (x2 * w - mx2 + y2 * w - my2 + c2);
+    let ssim = cs * (2.0 * mxy + c1) / (mx2 + my2 + c1);
+    (ssim, cs)
+}
+
+/// Calculates the weight of a window for [`Pooling::Variance`] from its moments
+/// `[mux, muy, x2, xy, y2, w]`.
+#[inline]
+fn window_variance_weight(moments: [f64; 6], sample_max: u64, config: SsimConfig) -> f64 {
+    let [mux, muy, x2, _, y2, w] = moments;
+    let c2 = (sample_max as f64 * config.k2).powi(2);
+    // Rounding can leave the variance of flat windows slightly negative
+    let variance = ((x2 * w - mux.powi(2) + y2 * w - muy.powi(2)) / w.powi(2)).max(0.0);
+    variance + c2
+}
+
+/// Calculates the SSIM and contrast-structure scores of two constant planes
+/// with the sample values `a` and `b`.
+///
+/// Without any variance, the contrast-structure term is exactly 1, and the
+/// luminance term is the same for every window regardless of its weight.
+fn constant_plane_ssim(a: u32, b: u32, sample_max: u64, config: SsimConfig) -> (f64, f64) {
+    let (a, b) = (a as f64, b as f64);
+    let c1 = (sample_max as f64 * config.k1).powi(2);
+    ((2.0 * a * b + c1) / (a.powi(2) + b.powi(2) + c1), 1.0)
+}
+
+/// Checks whether the scores of a window can be pooled.
+///
+/// With validated stabilization constants, the denominators of the SSIM formula are
+/// always positive, so this only fails because of a bug. Such windows are skipped
+/// in release builds, so that a single window cannot turn the score into `NaN`.
+#[inline]
+fn window_is_finite(ssim: f64, cs: f64) -> bool {
+    let finite = ssim.is_finite() && cs.is_finite();
+    debug_assert!(finite, "SSIM window is not finite");
+    finite
+}
+
+// These come from the original MS-SSIM implementation paper:
+// https://ece.uwaterloo.ca/~z70wang/publications/msssim.pdf
+// They don't add up to 1 due to rounding done in the paper.
+const MS_WEIGHT: [f64; 5] = [0.0448, 0.2856, 0.3001, 0.2363, 0.1333];
+
+pub(crate) fn calculate_plane_msssim<T: Pixel>(
+    plane1: &Plane<T>,
+    plane2: &Plane<T>,
+    bit_depth: usize,
+    scales: Option<usize>,
+    buffers: &mut PlaneBuffers,
+) -> f64 {
+    // Identical planes are a perfect match at every scale
+    if plane1.cfg.width > 0 && plane1.cfg.height > 0 && plane1.is_identical(plane2) {
+        return 1.0;
+    }
+
+    combine_msssim_scales(&calculate_plane_msssim_scales(
+        plane1, plane2, bit_depth, scales, buffers,
+    ))
+}
+
+/// Combines the SSIM and contrast-structure scores of each scale into the MSSSIM score.
+fn combine_msssim_scales(MsSsimScales { ssim, cs }: &MsSsimScales) -> f64 {
+    let scales = ssim.len();
+    // Scale the remaining weights so that they keep the same total as all five.
+    let weight_scale = MS_WEIGHT.iter().sum::<f64>() / MS_WEIGHT[..scales].iter().sum::<f64>();
+    // Negative scores of anti-correlated content have no real fractional power,
+    // so they are clamped to zero, which is the worst possible score.
+    cs.iter()
+        .zip(MS_WEIGHT.iter())
+        .take(scales - 1)
+        .map(|(cs, weight)| cs.max(0.0).powf(*weight * weight_scale))
+        .fold(1.0, |acc, val| acc * val)
+        * ssim[scales - 1]
+            .max(0.0)
+            .powf(MS_WEIGHT[scales - 1] * weight_scale)
+}
+
+/// Calculates the SSIM and contrast-structure scores of a plane at each scale.
+fn calculate_plane_msssim_scales<T: Pixel>(
+    plane1: &Plane<T>,
+    plane2: &Plane<T>,
+    bit_depth: usize,
+    scales: Option<usize>,
+    buffers: &mut PlaneBuffers,
+) -> MsSsimScales {
+    let (width, height) = (plane1.cfg.width, plane1.cfg.height);
+    let kernel = build_msssim_kernel();
+    let scales = scales.unwrap_or_else(|| msssim_scale_count(width, height, kernel.len()));
+    if let (Some(a), Some(b)) = (plane1.constant_sample(), plane2.constant_sample()) {
+        return constant_msssim_scales(a, b, bit_depth, scales);
+    }
+
+    plane_to_vec(plane1, &mut buffers.plane1);
+    plane_to_vec(plane2, &mut buffers.plane2);
+    filter_msssim_scales(None, width, height, bit_depth, scales, &kernel, buffers)
 }
 
-fn calculate_plane_msssim<T: Pixel>(plane1: &Plane<T>, plane2: &Plane<T>, bit_depth: usize) -> f64 {
+/// Builds the kernel used for the SSIM windows at each scale of MSSSIM.
+fn build_msssim_kernel() -> Vec<i64> {
     const KERNEL_SHIFT: usize = 10;
     const KERNEL_WEIGHT: usize = 1 << KERNEL_SHIFT;
-    // These come from the original MS-SSIM implementation paper:
-    // https://ece.uwaterloo.ca/~z70wang/publications/msssim.pdf
-    // They don't add up to 1 due to rounding done in the paper.
-    const MS_WEIGHT: [f64; 5] = [0.0448, 0.2856, 0.3001, 0.2363, 0.1333];
 
+    build_gaussian_kernel(1.5, 5, KERNEL_WEIGHT)
+}
+
+/// Returns the number of MSSSIM scales for a plane, which stops downscaling
+/// before the plane becomes smaller than the kernel.
+fn msssim_scale_count(width: usize, height: usize, kernel_len: usize) -> usize {
+    let min_dim = cmp::min(width, height);
+    (1..MS_WEIGHT.len())
+        .take_while(|&i| min_dim >> i >= kernel_len)
+        .count()
+        + 1
+}
+
+/// Returns the scores of two constant planes with the sample values `a` and `b`.
+///
+/// Downscaling scales the samples of constant planes like the sample range,
+/// so their scores are the same at every scale.
+fn constant_msssim_scales(a: u32, b: u32, bit_depth: usize, scales: usize) -> MsSsimScales {
+    let (ssim, cs) = constant_plane_ssim(a, b, (1 << bit_depth) - 1, SsimConfig::default());
+    MsSsimScales {
+        ssim: vec![ssim; scales],
+        cs: vec![cs; scales],
+    }
+}
+
+/// Filters two tightly packed planes at each scale of MSSSIM.
+///
+/// `planes` holds the planes at full scale, or is `None` if they were already
+/// copied into `buffers.plane1` and `buffers.plane2`.
+fn filter_msssim_scales(
+    planes: Option<(&[u32], &[u32])>,
+    mut width: usize,
+    mut height: usize,
+    bit_depth: usize,
+    scales: usize,
+    kernel: &[i64],
+    buffers: &mut PlaneBuffers,
+) -> MsSsimScales {
     let mut sample_max = (1 << bit_depth) - 1;
-    let mut ssim = [0.0; 5];
-    let mut cs = [0.0; 5];
-    let mut width = plane1.cfg.width;
-    let mut height = plane1.cfg.height;
-    let mut plane1 = plane_to_vec(plane1);
-    let mut plane2 = plane_to_vec(plane2);
-
-    let kernel = build_gaussian_kernel(1.5, 5, KERNEL_WEIGHT);
-    let res = calculate_plane_ssim_internal(
-        &plane1, &plane2, width, height, sample_max, &kernel, &kernel,
-    );
-    ssim[0] = res.0;
-    cs[0] = res.1;
-    for i in 1..5 {
-        plane1 = msssim_downscale(&plane1, width, height);
-        plane2 = msssim_downscale(&plane2, width, height);
-        width /= 2;
-        height /= 2;
-        sample_max *= 4;
-        let res = calculate_plane_ssim_internal(
-            &plane1, &plane2, width, height, sample_max, &kernel, &kernel,
+    let PlaneBuffers {
+        plane1: scaled1,
+        plane2: scaled2,
+        lines,
+        downscaled1,
+        downscaled2,
+    } = buffers;
+    let mut result = MsSsimScales {
+        ssim: Vec::with_capacity(scales),
+        cs: Vec::with_capacity(scales),
+    };
+    for i in 0..scales {
+        if i > 0 {
+            let (source1, source2) = match planes {
+                Some(planes) if i == 1 => planes,
+                _ => (&scaled1[..], &scaled2[..]),
+            };
+            msssim_downscale(source1, width, height, downscaled1);
+            msssim_downscale(source2, width, height, downscaled2);
+            std::mem::swap(scaled1, downscaled1);
+            std::mem::swap(scaled2, downscaled2);
+            width /= 2;
+            height /= 2;
+            sample_max *= 4;
+        }
+        let (current1, current2) = match planes {
+            Some(planes) if i == 0 => planes,
+            _ => (&scaled1[..], &scaled2[..]),
+        };
+        let (ssim, cs) = calculate_plane_ssim_internal(
+            current1,
+            current2,
+            width,
+            height,
+            sample_max,
+            kernel,
+            kernel,
+            None,
+            SsimConfig::default(),
+            None,
+            lines,
         );
-        ssim[i] = res.0;
-        cs[i] = res.1;
+        result.ssim.push(ssim);
+        result.cs.push(cs);
     }
+    result
+}
 
-    cs.iter()
-        .zip(MS_WEIGHT.iter())
-        .take(4)
-        .map(|(cs, weight)| cs.powf(*weight))
-        .fold(1.0, |acc, val| acc * val)
-        * ssim[4].powf(MS_WEIGHT[4])
+/// Builds the kernel used for the SSIM windows of a plane, which scales with
+/// the plane height.
+pub(crate) fn build_plane_kernel(width: usize, height: usize) -> Vec<i64> {
+    const KERNEL_SHIFT: usize = 8;
+    const KERNEL_WEIGHT: usize = 1 << KERNEL_SHIFT;
+
+    build_gaussian_kernel(
+        height as f64 * 1.5 / 256.0,
+        cmp::min(width, height),
+        KERNEL_WEIGHT,
+    )
 }
 
+/// Builds a normalized Gaussian kernel with fewer than `max_len` taps on each side of
+/// the center, but always at least the center tap, even for empty planes.
 fn build_gaussian_kernel(sigma: f64, max_len: usize, kernel_weight: usize) -> Vec<i64> {
     let scale = 1.0 / ((2.0 * PI).sqrt() * sigma);
     let nhisigma2 = -0.5 / sigma.powi(2);
@@ -456,7 +2120,9 @@ fn build_gaussian_kernel(sigma: f64, max_len: usize, kernel_weight: usize) -> Ve
     } else {
         (sigma * (-2.0 * s.log(E)).sqrt()).floor() as usize
     };
-    let kernel_len = if len >= max_len { max_len - 1 } else { len };
+    // Tiny planes, e.g. thumbnails or the last scales of MSSSIM, leave no room for
+    // the neighbors of the center tap.
+    let kernel_len = cmp::min(len, max_len.saturating_sub(1));
     let kernel_size = (kernel_len << 1) | 1;
     let mut kernel = vec![0; kernel_size];
     let mut sum = 0;
@@ -471,18 +2137,35 @@ fn build_gaussian_kernel(sigma: f64, max_len: usize, kernel_weight: usize) -> Ve
     kernel
 }
 
-fn plane_to_vec<T: Pixel>(input: &Plane<T>) -> Vec<u32> {
-    input.data.iter().map(|pix| u32::cast_from(*pix)).collect()
+/// Returns the value of all samples if they are equal, or `None` if they differ
+/// or there are none, like [`PlaneCompare::constant_sample`] for packed samples.
+fn constant_sample(samples: &[u32]) -> Option<u32> {
+    let (&first, rest) = samples.split_first()?;
+    rest.iter().all(|&sample| sample == first).then_some(first)
+}
+
+/// Copies the visible area of a plane into a tightly packed vector,
+/// skipping any padding at the end of each row.
+fn plane_to_vec<T: Pixel>(input: &Plane<T>, output: &mut Vec<u32>) {
+    output.clear();
+    output.extend(input.rows_iter().flatten().map(|pix| u32::cast_from(*pix)));
 }
 
 // This acts differently from downscaling a plane, and is what
 // requires us to pass around slices of bytes, instead of `Plane`s.
 // Instead of averaging the four pixels, it sums them.
 // In effect, this gives us much more precision when we downscale.
-fn msssim_downscale(input: &[u32], input_width: usize, input_height: usize) -> Vec<u32> {
+//
+// Each downscale adds 2 bits to the samples, and there are at most 4 of them,
+// so samples of up to 16 bits grow to at most 24 bits. This leaves plenty of
+// headroom in the `u32` samples. The rows of the SSIM windows are filtered as
+// `i64`, but the squared samples multiplied by both kernels need up to 69 bits,
+// so `filter_windows` sums the windows as `i128`.
+fn msssim_downscale(input: &[u32], input_width: usize, input_height: usize, output: &mut Vec<u32>) {
     let output_width = input_width / 2;
     let output_height = input_height / 2;
-    let mut output = vec![0; output_width * output_height];
+    output.clear();
+    output.resize(output_width * output_height, 0);
     for j in 0..output_height {
         let j0 = 2 * j;
         let j1 = cmp::min(j0 + 1, input_height - 1);
@@ -495,7 +2178,6 @@ fn msssim_downscale(input: &[u32], input_width: usize, input_height: usize) -> V
                 + input[j1 * input_width + i1];
         }
     }
-    output
 }
 
 fn log10_convert(score: f64, weight: f64) -> f64 {
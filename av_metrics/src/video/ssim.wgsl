@@ -0,0 +1,128 @@
+// Filters the SSIM windows of a plane, see `filter_windows` in ssim.rs.
+//
+// The `horizontal` pass filters the moments of each sample along its row into
+// `rows`, and the `vertical` pass filters those along each column into `windows`.
+// Both hold the six moments `[mux, muy, x2, xy, y2, w]` of each position.
+
+struct Params {
+    width: u32,
+    height: u32,
+    // `EDGE_*` below
+    edge: u32,
+    horiz_len: u32,
+    vert_len: u32,
+    _padding1: u32,
+    _padding2: u32,
+    _padding3: u32,
+}
+
+const EDGE_SHRINK: u32 = 0u;
+const EDGE_REPLICATE: u32 = 1u;
+const EDGE_MIRROR: u32 = 2u;
+const EDGE_ZERO: u32 = 3u;
+
+// Returned by `tap` instead of a position
+const TAP_PADDING: i32 = -1;
+const TAP_SKIP: i32 = -2;
+
+@group(0) @binding(0) var<uniform> params: Params;
+// The horizontal kernel, followed by the vertical kernel
+@group(0) @binding(1) var<storage, read> kernels: array<f32>;
+@group(0) @binding(2) var<storage, read> plane1: array<u32>;
+@group(0) @binding(3) var<storage, read> plane2: array<u32>;
+@group(0) @binding(4) var<storage, read_write> rows: array<f32>;
+@group(0) @binding(5) var<storage, read_write> windows: array<f32>;
+
+// Maps position `pos` along a line of `len` samples to the sample to read,
+// like `EdgeHandling::tap`.
+fn tap(pos: i32, len: i32) -> i32 {
+    if pos >= 0 && pos < len {
+        return pos;
+    }
+    switch params.edge {
+        case EDGE_REPLICATE: {
+            return clamp(pos, 0, len - 1);
+        }
+        case EDGE_MIRROR: {
+            // The mirrored line is symmetric around -0.5, which avoids the
+            // remainder of negative numbers, as some backends leave it undefined.
+            let period = 2 * len;
+            let wrapped = select(pos, -1 - pos, pos < 0) % period;
+            if wrapped < len {
+                return wrapped;
+            }
+            return period - 1 - wrapped;
+        }
+        case EDGE_ZERO: {
+            return TAP_PADDING;
+        }
+        default: {
+            return TAP_SKIP;
+        }
+    }
+}
+
+@compute @workgroup_size(16, 16)
+fn horizontal(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.width || id.y >= params.height {
+        return;
+    }
+    let offset = i32(params.horiz_len >> 1u);
+    var sums = array<f32, 6>(0.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+    for (var k = 0u; k < params.horiz_len; k++) {
+        let x = tap(i32(id.x) + i32(k) - offset, i32(params.width));
+        if x == TAP_SKIP {
+            continue;
+        }
+        let weight = kernels[k];
+        sums[5] += weight;
+        if x == TAP_PADDING {
+            continue;
+        }
+        let i = id.y * params.width + u32(x);
+        let pix1 = f32(plane1[i]);
+        let pix2 = f32(plane2[i]);
+        sums[0] += weight * pix1;
+        sums[1] += weight * pix2;
+        sums[2] += weight * pix1 * pix1;
+        sums[3] += weight * pix1 * pix2;
+        sums[4] += weight * pix2 * pix2;
+    }
+    let out = (id.y * params.width + id.x) * 6u;
+    for (var m = 0u; m < 6u; m++) {
+        rows[out + m] = sums[m];
+    }
+}
+
+@compute @workgroup_size(16, 16)
+fn vertical(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.width || id.y >= params.height {
+        return;
+    }
+    // The weight of a horizontally filtered row of padding
+    var padding_weight = 0.0;
+    for (var k = 0u; k < params.horiz_len; k++) {
+        padding_weight += kernels[k];
+    }
+    let offset = i32(params.vert_len >> 1u);
+    var sums = array<f32, 6>(0.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+    for (var k = 0u; k < params.vert_len; k++) {
+        let y = tap(i32(id.y) + i32(k) - offset, i32(params.height));
+        if y == TAP_SKIP {
+            continue;
+        }
+        let weight = kernels[params.horiz_len + k];
+        if y == TAP_PADDING {
+            sums[5] += weight * padding_weight;
+            continue;
+        }
+        let row = (u32(y) * params.width + id.x) * 6u;
+        for (var m = 0u; m < 6u; m++) {
+            sums[m] += weight * rows[row + m];
+        }
+    }
+    let out = (id.y * params.width + id.x) * 6u;
+    for (var m = 0u; m < 6u; m++) {
+        windows[out + m] = sums[m];
+    }
+}
@@ -0,0 +1,103 @@
+//! Temporal consistency metric.
+//!
+//! Per-frame metrics compare each distorted frame to its reference in isolation,
+//! so they cannot detect flicker or pumping, e.g. caused by rate control
+//! alternating between high and low quality frames. This metric compares the
+//! change between consecutive frames of the distorted video to the change
+//! between the same frames of the reference video instead.
+//!
+//! The result is expressed like PSNR, computed from the mean squared difference
+//! between the frame-to-frame deltas of the two videos. Only the luma plane is used.
+
+use crate::video::decode::Decoder;
+use crate::video::pixel::{CastFromPrimitive, Pixel};
+use crate::MetricsError;
+use std::error::Error;
+use v_frame::frame::Frame;
+
+use super::{check_video_details, FrameCompare};
+
+/// Calculates the temporal consistency of two videos. Higher is better.
+///
+/// `decoder1` should be the reference video. A distorted video which changes
+/// between frames in the same way as the reference gets a high score, even if
+/// every individual frame is distorted. At least two frames of each video are
+/// needed, otherwise [`MetricsError::NoFramesCompared`] is returned.
+///
+/// Like PSNR, the score is capped at 100.
+pub fn calculate_video_temporal_consistency<D: Decoder, F: Fn(usize) + Send>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<f64, Box<dyn Error>> {
+    check_video_details(decoder1, decoder2)?;
+
+    if decoder1.get_bit_depth() > 8 {
+        process_video::<D, u16, F>(decoder1, decoder2, frame_limit, progress_callback)
+    } else {
+        process_video::<D, u8, F>(decoder1, decoder2, frame_limit, progress_callback)
+    }
+}
+
+fn process_video<D: Decoder, T: Pixel, F: Fn(usize) + Send>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    frame_limit: Option<usize>,
+    progress_callback: F,
+) -> Result<f64, Box<dyn Error>> {
+    let sample_max = (1u64 << decoder1.get_bit_depth()) - 1;
+    // Frames are compared to their predecessor, so keep the previous pair around.
+    let mut previous: Option<(Frame<T>, Frame<T>)> = None;
+    // Each frame is summed exactly, but the total could overflow a `u64` at high bit depths
+    let mut sq_err = 0f64;
+    let mut n_pixels = 0;
+    let mut decoded = 0;
+    while frame_limit.map(|limit| limit > decoded).unwrap_or(true) {
+        if decoder1.is_cancelled() || decoder2.is_cancelled() {
+            return Err(MetricsError::Cancelled.into());
+        }
+        let (frame1, frame2) = match (
+            decoder1.read_video_frame::<T>(),
+            decoder2.read_video_frame::<T>(),
+        ) {
+            (Some(frame1), Some(frame2)) => (frame1, frame2),
+            _ => match decoder1.take_error().or(decoder2.take_error()) {
+                Some(error) => return Err(error.into()),
+                None => break,
+            },
+        };
+        decoded += 1;
+        progress_callback(decoded);
+        frame1.can_compare(&frame2)?;
+
+        if let Some((prev1, prev2)) = &previous {
+            frame1.can_compare(prev1)?;
+            sq_err += izip!(
+                frame1.planes[0].rows_iter().flatten(),
+                frame2.planes[0].rows_iter().flatten(),
+                prev1.planes[0].rows_iter().flatten(),
+                prev2.planes[0].rows_iter().flatten()
+            )
+            .map(|(&cur1, &cur2, &prev1, &prev2)| {
+                let delta1 = i32::cast_from(cur1) - i32::cast_from(prev1);
+                let delta2 = i32::cast_from(cur2) - i32::cast_from(prev2);
+                (delta2 - delta1).unsigned_abs() as u64
+            })
+            .map(|err| err * err)
+            .sum::<u64>() as f64;
+            n_pixels += frame1.planes[0].cfg.width * frame1.planes[0].cfg.height;
+        }
+        previous = Some((frame1, frame2));
+    }
+    // Mark the end of the decoding process
+    progress_callback(usize::MAX);
+
+    if n_pixels == 0 {
+        return Err(Box::new(MetricsError::NoFramesCompared));
+    }
+    if sq_err == 0.0 {
+        return Ok(100.0);
+    }
+    Ok(10.0 * ((sample_max.pow(2) as f64).log10() + (n_pixels as f64).log10() - sq_err.log10()))
+}
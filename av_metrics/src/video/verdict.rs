@@ -0,0 +1,145 @@
+//! Pass/fail decisions based on metric thresholds.
+//!
+//! Whether an encode is "visually lossless" depends on the content and the viewing
+//! conditions, so no single metric value decides it. For automated checks it is still
+//! useful to apply the same thresholds everywhere, which is what [`LosslessThresholds`]
+//! does. The thresholds are compared to the `avg` score of each metric.
+
+use crate::video::decode::Decoder;
+use crate::video::dynamic::{calculate_video_metrics, from_name, DynMetric, MetricResults};
+use crate::MetricsError;
+use std::error::Error;
+
+/// Minimum scores an encode needs in order to be considered visually lossless.
+///
+/// A threshold of `None` disables that criterion. The defaults require a PSNR of at
+/// least 45 dB and a mean SSIM of at least 0.995. These are conservative rules of
+/// thumb rather than a perceptual guarantee: typical PSNR values for lossy 8-bit
+/// compression lie between 30 and 50 dB (see
+/// https://en.wikipedia.org/wiki/Peak_signal-to-noise_ratio#Quality_estimation_with_PSNR),
+/// so 45 dB is at the top of that range. 0.995 is a similarly strict value for SSIM,
+/// where a lossless encode scores exactly 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LosslessThresholds {
+    /// Minimum PSNR in dB.
+    pub psnr: Option<f64>,
+    /// Minimum mean SSIM, in the range [0, 1].
+    ///
+    /// [`ssim::calculate_video_ssim`](crate::video::ssim::calculate_video_ssim) returns
+    /// a logarithmic score, so the threshold is converted to the same scale before
+    /// comparing.
+    pub ssim: Option<f64>,
+}
+
+impl Default for LosslessThresholds {
+    fn default() -> Self {
+        Self {
+            psnr: Some(45.0),
+            ssim: Some(0.995),
+        }
+    }
+}
+
+/// A metric used by [`LosslessThresholds`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Criterion {
+    /// See [`psnr::calculate_video_psnr`](crate::video::psnr::calculate_video_psnr)
+    Psnr,
+    /// See [`ssim::calculate_video_ssim`](crate::video::ssim::calculate_video_ssim)
+    Ssim,
+}
+
+/// A criterion which was not met.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FailedCriterion {
+    /// The metric which was below its threshold.
+    pub criterion: Criterion,
+    /// The threshold, in the same scale as the threshold in [`LosslessThresholds`].
+    pub threshold: f64,
+    /// The score of the metric, in the same scale as `threshold`.
+    pub value: f64,
+}
+
+/// The outcome of checking metric results against [`LosslessThresholds`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Verdict {
+    /// Every criterion was met.
+    VisuallyLossless,
+    /// At least one criterion was not met. All failed criteria are listed.
+    Lossy(Vec<FailedCriterion>),
+}
+
+impl Verdict {
+    /// Returns `true` if every criterion was met.
+    pub fn is_visually_lossless(&self) -> bool {
+        matches!(self, Verdict::VisuallyLossless)
+    }
+}
+
+impl LosslessThresholds {
+    /// Checks already calculated metrics against the thresholds.
+    ///
+    /// Returns an error if a metric which has a threshold was not calculated.
+    /// A `NaN` score fails its criterion.
+    pub fn check(&self, results: &MetricResults) -> Result<Verdict, MetricsError> {
+        let missing = || MetricsError::UnsupportedInput {
+            reason: "A metric with a threshold was not calculated",
+        };
+        let mut failed = Vec::new();
+        if let Some(threshold) = self.psnr {
+            let value = results.psnr.ok_or_else(missing)?.avg;
+            if value.is_nan() || value < threshold {
+                failed.push(FailedCriterion {
+                    criterion: Criterion::Psnr,
+                    threshold,
+                    value,
+                });
+            }
+        }
+        if let Some(threshold) = self.ssim {
+            // Convert the logarithmic score back to the mean SSIM
+            let value = 1.0 - 10f64.powf(-results.ssim.ok_or_else(missing)?.avg / 10.0);
+            if value.is_nan() || value < threshold {
+                failed.push(FailedCriterion {
+                    criterion: Criterion::Ssim,
+                    threshold,
+                    value,
+                });
+            }
+        }
+        Ok(if failed.is_empty() {
+            Verdict::VisuallyLossless
+        } else {
+            Verdict::Lossy(failed)
+        })
+    }
+
+    /// Calculates the metrics which have a threshold and checks them.
+    ///
+    /// Each decoder can only be read once, so `open_decoders` is called to open a new
+    /// pair of decoders for each metric, like in [`calculate_video_metrics`].
+    pub fn check_video<D, O, F>(
+        &self,
+        open_decoders: O,
+        frame_limit: Option<usize>,
+        progress_callback: F,
+    ) -> Result<Verdict, Box<dyn Error>>
+    where
+        D: Decoder,
+        O: FnMut() -> Result<(D, D), Box<dyn Error>>,
+        F: Fn(usize) + Sync,
+    {
+        let metrics: Vec<Box<dyn DynMetric<D>>> = [(self.psnr, "psnr"), (self.ssim, "ssim")]
+            .iter()
+            .filter(|(threshold, _)| threshold.is_some())
+            .filter_map(|&(_, name)| from_name(name))
+            .collect();
+        let results =
+            calculate_video_metrics(&metrics, open_decoders, frame_limit, progress_callback)?;
+        Ok(self.check(&results)?)
+    }
+}
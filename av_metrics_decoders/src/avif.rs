@@ -0,0 +1,116 @@
+use av_metrics::video::decode::*;
+use av_metrics::video::*;
+use av_metrics::MetricsError;
+use libheif_rs::{Chroma, ColorSpace, HeifContext, LibHeif};
+use std::path::Path;
+
+/// A decoder for AVIF and HEIF still images
+///
+/// The primary image of the file is decoded when the decoder is created and
+/// returned as the only frame, keeping its original chroma sampling and bit
+/// depth. High bit depth images are returned as 10 or 12 bit frames.
+pub struct AvifDecoder {
+    video_details: VideoDetails,
+    planes: Option<Vec<(Vec<u8>, usize)>>,
+}
+
+impl AvifDecoder {
+    /// Initialize a new decoder for a given AVIF or HEIF image
+    pub fn new<P: AsRef<Path>>(input: P) -> Result<Self, MetricsError> {
+        let data = std::fs::read(input)
+            .map_err(|e| MetricsError::decode_error("Could not open the input file", e))?;
+        let decode_error = |e| MetricsError::decode_error("Could not decode the image", e);
+        let ctx = HeifContext::read_from_bytes(&data).map_err(decode_error)?;
+        let handle = ctx.primary_image_handle().map_err(decode_error)?;
+        let image = LibHeif::new()
+            .decode(&handle, ColorSpace::Undefined, None)
+            .map_err(decode_error)?;
+
+        let chroma_sampling = match image.color_space() {
+            Some(ColorSpace::YCbCr(Chroma::C420)) => ChromaSampling::Cs420,
+            Some(ColorSpace::YCbCr(Chroma::C422)) => ChromaSampling::Cs422,
+            Some(ColorSpace::YCbCr(Chroma::C444)) => ChromaSampling::Cs444,
+            Some(ColorSpace::Monochrome) => ChromaSampling::Cs400,
+            color_space => {
+                return Err(MetricsError::decode_error(
+                    "Unsupported color space",
+                    format!("color space {:?}", color_space),
+                ))
+            }
+        };
+        let planes = image.planes();
+        let luma = planes.y.as_ref().ok_or(MetricsError::MalformedInput {
+            reason: "Image has no luma plane",
+        })?;
+        let bit_depth = luma.bits_per_pixel as usize;
+        if !(8..=16).contains(&bit_depth) {
+            return Err(MetricsError::decode_error(
+                "Bit depth must be between 8 and 16",
+                format!("image has {} bits per pixel", bit_depth),
+            ));
+        }
+
+        // libheif stores samples above 8 bits as 16-bit values in native byte order,
+        // while `copy_from_raw_u8` reads little endian values.
+        let to_le = |data: &[u8]| -> Vec<u8> {
+            if bit_depth > 8 {
+                data.chunks_exact(2)
+                    .flat_map(|sample| u16::from_ne_bytes([sample[0], sample[1]]).to_le_bytes())
+                    .collect()
+            } else {
+                data.to_vec()
+            }
+        };
+        let mut stored = vec![(to_le(luma.data), luma.stride)];
+        if chroma_sampling != ChromaSampling::Cs400 {
+            for plane in [&planes.cb, &planes.cr] {
+                let plane = plane.as_ref().ok_or(MetricsError::MalformedInput {
+                    reason: "Image has no chroma planes",
+                })?;
+                stored.push((to_le(plane.data), plane.stride));
+            }
+        }
+
+        Ok(AvifDecoder {
+            video_details: VideoDetails::new(
+                luma.width as usize,
+                luma.height as usize,
+                bit_depth,
+                chroma_sampling,
+            ),
+            planes: Some(stored),
+        })
+    }
+}
+
+impl Decoder for AvifDecoder {
+    fn get_video_details(&self) -> VideoDetails {
+        self.video_details
+    }
+
+    fn read_video_frame<T: Pixel>(&mut self) -> Option<Frame<T>> {
+        let VideoDetails {
+            width,
+            height,
+            bit_depth,
+            chroma_sampling,
+            ..
+        } = self.video_details;
+        let bytes = if bit_depth > 8 { 2 } else { 1 };
+        let planes = self.planes.take()?;
+
+        let mut f: Frame<T> = Frame::new_with_padding(width, height, chroma_sampling, 0);
+        for (plane, (data, stride)) in f.planes.iter_mut().zip(planes.iter()) {
+            plane.copy_from_raw_u8(data, *stride, bytes);
+        }
+        Some(f)
+    }
+
+    fn get_bit_depth(&self) -> usize {
+        self.video_details.bit_depth
+    }
+
+    fn total_frames(&self) -> Option<usize> {
+        Some(1)
+    }
+}
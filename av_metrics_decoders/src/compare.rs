@@ -0,0 +1,109 @@
+use av_metrics::video::dynamic::Metric;
+use av_metrics::video::*;
+use std::error::Error;
+use std::path::Path;
+
+/// Calculates a metric between two video files
+///
+/// `input1` should be the reference video. The decoder is chosen by the file
+/// extension: y4m files are read with the y4m decoder, and all other files are
+/// read with FFmpeg. If only one of the files is a y4m file, both are read with
+/// FFmpeg. An error is returned if the decoder feature needed for the files is
+/// not enabled, or if the videos do not have the same format.
+///
+/// Optionally, `frame_limit` can be set to only compare the first
+/// `frame_limit` frames in each video.
+pub fn compare_files<P1: AsRef<Path>, P2: AsRef<Path>>(
+    input1: P1,
+    input2: P2,
+    metric: Metric,
+    frame_limit: Option<usize>,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    let (input1, input2) = (input1.as_ref(), input2.as_ref());
+    if is_y4m(input1) && is_y4m(input2) {
+        compare_y4m(input1, input2, metric, frame_limit)
+    } else {
+        compare_other(input1, input2, metric, frame_limit)
+    }
+}
+
+fn is_y4m(input: &Path) -> bool {
+    input
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("y4m"))
+}
+
+#[cfg(feature = "y4m")]
+fn compare_y4m(
+    input1: &Path,
+    input2: &Path,
+    metric: Metric,
+    frame_limit: Option<usize>,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    let mut decoder1 = crate::y4m::new_decoder_from_file(input1)?;
+    let mut decoder2 = crate::y4m::new_decoder_from_file(input2)?;
+    compare_decoders(&mut decoder1, &mut decoder2, metric, frame_limit)
+}
+
+#[cfg(not(feature = "y4m"))]
+fn compare_y4m(
+    input1: &Path,
+    input2: &Path,
+    metric: Metric,
+    frame_limit: Option<usize>,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    // FFmpeg can read y4m files as well.
+    if cfg!(any(
+        feature = "ffmpeg",
+        feature = "ffmpeg_static",
+        feature = "ffmpeg_build"
+    )) {
+        return compare_other(input1, input2, metric, frame_limit);
+    }
+    Err("Reading y4m files requires the `y4m` or `ffmpeg` feature of av-metrics-decoders".into())
+}
+
+#[cfg(any(
+    feature = "ffmpeg",
+    feature = "ffmpeg_static",
+    feature = "ffmpeg_build"
+))]
+fn compare_other(
+    input1: &Path,
+    input2: &Path,
+    metric: Metric,
+    frame_limit: Option<usize>,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    let mut decoder1 = crate::FfmpegDecoder::new(input1)?;
+    let mut decoder2 = crate::FfmpegDecoder::new(input2)?;
+    compare_decoders(&mut decoder1, &mut decoder2, metric, frame_limit)
+}
+
+#[cfg(not(any(
+    feature = "ffmpeg",
+    feature = "ffmpeg_static",
+    feature = "ffmpeg_build"
+)))]
+fn compare_other(
+    _input1: &Path,
+    _input2: &Path,
+    _metric: Metric,
+    _frame_limit: Option<usize>,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    Err("Reading files other than y4m requires the `ffmpeg` feature of av-metrics-decoders".into())
+}
+
+#[cfg(any(
+    feature = "y4m",
+    feature = "ffmpeg",
+    feature = "ffmpeg_static",
+    feature = "ffmpeg_build"
+))]
+fn compare_decoders<D: decode::Decoder>(
+    decoder1: &mut D,
+    decoder2: &mut D,
+    metric: Metric,
+    frame_limit: Option<usize>,
+) -> Result<PlanarMetrics, Box<dyn Error>> {
+    dynamic::calculate_video_metric(metric, decoder1, decoder2, frame_limit, |_| ())
+}
@@ -1,14 +1,21 @@
 extern crate ffmpeg_the_third as ffmpeg;
 
+use std::ffi::c_void;
+use std::io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom};
+use std::os::raw::c_int;
 use std::path::Path;
+use std::ptr;
+use std::str::FromStr;
 
 use ffmpeg::codec::{decoder, packet};
 use ffmpeg::format::context;
 use ffmpeg::media::Type;
-use ffmpeg::{format, frame};
+use ffmpeg::software::scaling;
+use ffmpeg::{color, format, frame, picture, Rescale};
 
 use av_metrics::video::decode::*;
 use av_metrics::video::*;
+use av_metrics::MetricsError;
 
 /// An interface that is used for decoding a video stream using FFMpeg
 ///
@@ -18,134 +25,520 @@ pub struct FfmpegDecoder {
     input_ctx: context::Input,
     decoder: decoder::Video,
     video_details: VideoDetails,
+    scaler: Option<Scaler>,
+    total_frames: Option<usize>,
     frameno: usize,
     stream_index: usize,
     end_of_stream: bool,
     eof_sent: bool,
+    /// A frame which was decoded while seeking, but not returned yet.
+    pending: Option<frame::Video>,
+    last_frame_type: FrameType,
+    /// The error which stopped the last call to `read_video_frame`
+    error: Option<MetricsError>,
+    /// The stream the container is read from, if it is not a file.
+    ///
+    /// This has to be dropped after `input_ctx`, which reads through it.
+    _custom_io: Option<CustomIo>,
+}
+
+/// A builder for [`FfmpegDecoder`] which allows configuring the decoder output.
+pub struct FfmpegDecoderBuilder<P: AsRef<Path>> {
+    input: P,
+    pixel_format: Option<String>,
+}
+
+impl<P: AsRef<Path>> FfmpegDecoderBuilder<P> {
+    /// Force the decoder to output frames in the given pixel format, using the
+    /// FFmpeg name of the format, e.g. `yuv420p10le`.
+    ///
+    /// If the video is stored in a different format, every frame is converted
+    /// using the FFmpeg scaler. Only planar YUV formats supported by the metrics
+    /// are accepted.
+    pub fn pixel_format(mut self, pixel_format: &str) -> Self {
+        self.pixel_format = Some(pixel_format.to_string());
+        self
+    }
+
+    /// Initialize the FFMpeg decoder
+    pub fn build(self) -> Result<FfmpegDecoder, MetricsError> {
+        FfmpegDecoder::new_with_format(self.input, self.pixel_format.as_deref())
+    }
+}
+
+/// Wraps the FFmpeg scaler so it can be moved to the thread doing the decoding.
+struct Scaler(scaling::Context);
+
+// SAFETY: `scaling::Context` is only `!Send` because it holds a raw pointer to
+// the `SwsContext`. libswscale keeps no thread-local state and a `SwsContext`
+// has no affinity to the thread which created it, so it may be used from any
+// thread as long as the uses do not overlap. The context is owned by this
+// wrapper, which is only accessed through `&mut FfmpegDecoder`, and it is not
+// `Sync`, so no two threads can run the scaler at the same time.
+unsafe impl Send for Scaler {}
+
+/// A stream FFmpeg can read a container from instead of a file.
+trait IoStream: Read + Send {
+    /// Seeks in the stream, or returns `None` if the stream is not seekable.
+    fn seek_to(&mut self, pos: SeekFrom) -> Option<io::Result<u64>>;
+    /// Returns the length of the stream in bytes, if it is known.
+    fn len(&mut self) -> Option<u64>;
+}
+
+impl IoStream for Cursor<Vec<u8>> {
+    fn seek_to(&mut self, pos: SeekFrom) -> Option<io::Result<u64>> {
+        Some(self.seek(pos))
+    }
+
+    fn len(&mut self) -> Option<u64> {
+        Some(self.get_ref().len() as u64)
+    }
+}
+
+/// A stream which can only be read front to back, e.g. a network socket.
+struct Unseekable<R: Read + Send>(R);
+
+impl<R: Read + Send> Read for Unseekable<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<R: Read + Send> IoStream for Unseekable<R> {
+    fn seek_to(&mut self, _pos: SeekFrom) -> Option<io::Result<u64>> {
+        None
+    }
+
+    fn len(&mut self) -> Option<u64> {
+        None
+    }
+}
+
+/// Size of the buffer FFmpeg reads the stream into.
+const IO_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A custom FFmpeg I/O context, which reads the container from an [`IoStream`].
+struct CustomIo {
+    ctx: *mut ffmpeg::ffi::AVIOContext,
+    /// The I/O context refers to the stream, so it is boxed to keep its address.
+    _stream: Box<Box<dyn IoStream>>,
+}
+
+// SAFETY: The I/O context is owned by a single decoder and is never
+// accessed from more than one thread at a time.
+unsafe impl Send for CustomIo {}
+
+impl CustomIo {
+    fn new(stream: Box<dyn IoStream>, seekable: bool) -> Result<Self, MetricsError> {
+        let mut stream = Box::new(stream);
+        // SAFETY: The buffer is owned by the I/O context from here on, and the
+        // stream outlives the context, which is freed in `drop`.
+        unsafe {
+            let buffer = ffmpeg::ffi::av_malloc(IO_BUFFER_SIZE) as *mut u8;
+            if buffer.is_null() {
+                return Err(out_of_memory("Could not allocate the I/O buffer"));
+            }
+            let ctx = ffmpeg::ffi::avio_alloc_context(
+                buffer,
+                IO_BUFFER_SIZE as c_int,
+                0,
+                &mut *stream as *mut Box<dyn IoStream> as *mut c_void,
+                Some(read_packet),
+                None,
+                if seekable { Some(seek) } else { None },
+            );
+            if ctx.is_null() {
+                ffmpeg::ffi::av_free(buffer as *mut c_void);
+                return Err(out_of_memory("Could not allocate the I/O context"));
+            }
+            Ok(CustomIo {
+                ctx,
+                _stream: stream,
+            })
+        }
+    }
+}
+
+impl Drop for CustomIo {
+    fn drop(&mut self) {
+        // SAFETY: The buffer may have been replaced by FFmpeg, so it is freed
+        // through the context, and the context is not used afterwards.
+        unsafe {
+            ffmpeg::ffi::av_freep(&mut (*self.ctx).buffer as *mut *mut u8 as *mut c_void);
+            ffmpeg::ffi::avio_context_free(&mut self.ctx);
+        }
+    }
+}
+
+unsafe extern fn read_packet(opaque: *mut c_void, buf: *mut u8, buf_size: c_int) -> c_int {
+    let stream = &mut *(opaque as *mut Box<dyn IoStream>);
+    let buf = std::slice::from_raw_parts_mut(buf, buf_size as usize);
+    loop {
+        return match stream.read(buf) {
+            Ok(0) => ffmpeg::Error::Eof.into(),
+            Ok(read) => read as c_int,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => ffmpeg::Error::External.into(),
+        };
+    }
+}
+
+unsafe extern fn seek(opaque: *mut c_void, offset: i64, whence: c_int) -> i64 {
+    let stream = &mut *(opaque as *mut Box<dyn IoStream>);
+    let whence = whence & !(ffmpeg::ffi::AVSEEK_FORCE as c_int);
+    if whence == ffmpeg::ffi::AVSEEK_SIZE as c_int {
+        return stream.len().map_or(-1, |len| len as i64);
+    }
+    // The values of `SEEK_SET`, `SEEK_CUR` and `SEEK_END`
+    let pos = match whence {
+        0 => SeekFrom::Start(offset as u64),
+        1 => SeekFrom::Current(offset),
+        2 => SeekFrom::End(offset),
+        _ => return -1,
+    };
+    match stream.seek_to(pos) {
+        Some(Ok(pos)) => pos as i64,
+        _ => -1,
+    }
+}
+
+/// Returns the error for a failed allocation.
+fn out_of_memory(reason: &'static str) -> MetricsError {
+    MetricsError::decode_error(
+        reason,
+        ffmpeg::Error::Other {
+            errno: ffmpeg::error::ENOMEM,
+        },
+    )
+}
+
+/// Opens a container which is read through a custom I/O context.
+fn open_custom_input(io: &CustomIo) -> Result<context::Input, MetricsError> {
+    // SAFETY: The format context only refers to the I/O context, which the
+    // decoder keeps alive for as long as the format context.
+    unsafe {
+        let mut ps = ffmpeg::ffi::avformat_alloc_context();
+        if ps.is_null() {
+            return Err(out_of_memory("Could not allocate the format context"));
+        }
+        (*ps).pb = io.ctx;
+        (*ps).flags |= ffmpeg::ffi::AVFMT_FLAG_CUSTOM_IO as c_int;
+        // The format context is freed by FFmpeg if opening fails
+        match ffmpeg::ffi::avformat_open_input(&mut ps, ptr::null(), ptr::null(), ptr::null_mut()) {
+            0 => match ffmpeg::ffi::avformat_find_stream_info(ps, ptr::null_mut()) {
+                r if r >= 0 => Ok(context::Input::wrap(ps)),
+                e => {
+                    ffmpeg::ffi::avformat_close_input(&mut ps);
+                    Err(MetricsError::decode_error(
+                        "Could not read the stream information",
+                        ffmpeg::Error::from(e),
+                    ))
+                }
+            },
+            e => Err(MetricsError::decode_error(
+                "Could not open the input",
+                ffmpeg::Error::from(e),
+            )),
+        }
+    }
+}
+
+/// Returns the bit depth, chroma sampling and chroma sample position for
+/// a pixel format, or an error if the format is not supported.
+fn pixel_format_details(
+    pixel_format: format::Pixel,
+) -> Result<(usize, ChromaSampling, ChromaSamplePosition), MetricsError> {
+    use format::pixel::Pixel::*;
+
+    let unsupported = || {
+        MetricsError::decode_error(
+            "Unsupported pixel format",
+            format!("pixel format {:?}", pixel_format),
+        )
+    };
+    let bit_depth = match pixel_format {
+        YUV420P | YUV422P | YUV444P | YUVJ420P | YUVJ422P | YUVJ444P => 8,
+        YUV420P10LE | YUV422P10LE | YUV444P10LE => 10,
+        YUV420P12LE | YUV422P12LE | YUV444P12LE => 12,
+        _ => {
+            return Err(unsupported());
+        }
+    };
+    let chroma_sampling = match pixel_format {
+        YUV420P | YUVJ420P | YUV420P10LE | YUV420P12LE => ChromaSampling::Cs420,
+        YUV422P | YUVJ422P | YUV422P10LE | YUV422P12LE => ChromaSampling::Cs422,
+        YUV444P | YUVJ444P | YUV444P10LE | YUV444P12LE => ChromaSampling::Cs444,
+        _ => {
+            return Err(unsupported());
+        }
+    };
+    let chroma_sample_position = match pixel_format {
+        YUV422P | YUV422P10LE | YUV422P12LE => ChromaSamplePosition::Vertical,
+        _ => ChromaSamplePosition::Colocated,
+    };
+    Ok((bit_depth, chroma_sampling, chroma_sample_position))
+}
+
+/// Returns the pixel format to convert the decoded frames to by default.
+///
+/// 4:1:1 and 4:4:0 video, e.g. from DV sources, cannot be represented by
+/// `ChromaSampling`. Their chroma planes are upsampled along the subsampled
+/// direction to 4:2:2 and 4:4:4 respectively, which keeps the full chroma detail.
+fn supported_format(pixel_format: format::Pixel) -> format::Pixel {
+    use format::pixel::Pixel::*;
+
+    match pixel_format {
+        YUV411P => YUV422P,
+        YUVJ411P => YUVJ422P,
+        YUV440P => YUV444P,
+        YUVJ440P => YUVJ444P,
+        YUV440P10LE => YUV444P10LE,
+        YUV440P12LE => YUV444P12LE,
+        pixel_format => pixel_format,
+    }
 }
 
 impl FfmpegDecoder {
     /// Initialize a new FFMpeg decoder for a given input file
-    pub fn new<P: AsRef<Path>>(input: P) -> Result<Self, String> {
-        ffmpeg::init().map_err(|e| e.to_string())?;
-
-        let input_ctx = format::input(&input).map_err(|e| e.to_string())?;
-        let input = input_ctx
-            .streams()
-            .best(Type::Video)
-            .ok_or_else(|| "Could not find video stream".to_string())?;
+    pub fn new<P: AsRef<Path>>(input: P) -> Result<Self, MetricsError> {
+        Self::new_with_format(input, None)
+    }
+
+    /// Initialize a new FFMpeg decoder which reads the container from memory,
+    /// e.g. a video received over the network.
+    ///
+    /// The container format is detected from the data, and the decoder supports
+    /// seeking like a decoder for a file.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, MetricsError> {
+        let io = CustomIo::new(Box::new(Cursor::new(data)), true)?;
+        Self::from_custom_io(io)
+    }
+
+    /// Initialize a new FFMpeg decoder which reads the container from a stream,
+    /// e.g. a network socket, without writing it to a file first.
+    ///
+    /// The stream is only read front to back, so the decoder does not support
+    /// seeking, and containers which store their index at the end of the file,
+    /// e.g. some MP4 files, cannot be read. Use [`FfmpegDecoder::from_bytes`]
+    /// for those.
+    pub fn from_reader<R: Read + Send + 'static>(reader: R) -> Result<Self, MetricsError> {
+        let io = CustomIo::new(Box::new(Unseekable(reader)), false)?;
+        Self::from_custom_io(io)
+    }
+
+    fn from_custom_io(io: CustomIo) -> Result<Self, MetricsError> {
+        ffmpeg::init().map_err(|e| MetricsError::decode_error("Could not initialize FFmpeg", e))?;
+
+        let input_ctx = open_custom_input(&io)?;
+        // The format context is dropped by `from_input` if it fails, while the I/O
+        // context it reads through is only dropped when this returns.
+        let mut decoder = Self::from_input(input_ctx, None)?;
+        decoder._custom_io = Some(io);
+        Ok(decoder)
+    }
+
+    /// Create a builder to configure a new FFMpeg decoder for a given input file
+    pub fn builder<P: AsRef<Path>>(input: P) -> FfmpegDecoderBuilder<P> {
+        FfmpegDecoderBuilder {
+            input,
+            pixel_format: None,
+        }
+    }
+
+    fn new_with_format<P: AsRef<Path>>(
+        input: P,
+        pixel_format: Option<&str>,
+    ) -> Result<Self, MetricsError> {
+        ffmpeg::init().map_err(|e| MetricsError::decode_error("Could not initialize FFmpeg", e))?;
+
+        let input_ctx = format::input(&input)
+            .map_err(|e| MetricsError::decode_error("Could not open the input file", e))?;
+        Self::from_input(input_ctx, pixel_format)
+    }
+
+    fn from_input(
+        input_ctx: context::Input,
+        pixel_format: Option<&str>,
+    ) -> Result<Self, MetricsError> {
+        let input =
+            input_ctx
+                .streams()
+                .best(Type::Video)
+                .ok_or(MetricsError::UnsupportedInput {
+                    reason: "Input has no video stream",
+                })?;
         let stream_index = input.index();
+        let decoder_error = |e| MetricsError::decode_error("Could not open the video decoder", e);
         let mut decoder = ffmpeg::codec::context::Context::from_parameters(input.parameters())
-            .map_err(|e| e.to_string())?
+            .map_err(decoder_error)?
             .decoder()
             .video()
-            .map_err(|e| e.to_string())?;
+            .map_err(decoder_error)?;
         decoder
             .set_parameters(input.parameters())
-            .map_err(|e| e.to_string())?;
+            .map_err(decoder_error)?;
+
+        let output_format = match pixel_format {
+            Some(name) => format::Pixel::from_str(name).map_err(|_| {
+                MetricsError::decode_error("Unknown pixel format", format!("pixel format {}", name))
+            })?,
+            None => supported_format(decoder.format()),
+        };
+        let (bit_depth, chroma_sampling, chroma_sample_position) =
+            pixel_format_details(output_format)?;
+        let scaler = if output_format != decoder.format() {
+            Some(Scaler(
+                scaling::Context::get(
+                    decoder.format(),
+                    decoder.width(),
+                    decoder.height(),
+                    output_format,
+                    decoder.width(),
+                    decoder.height(),
+                    scaling::Flags::BILINEAR,
+                )
+                .map_err(|e| MetricsError::decode_error("Could not create the scaler", e))?,
+            ))
+        } else {
+            None
+        };
 
-        let frame_rate = input.avg_frame_rate();
+        // The average frame rate is unset for some containers, in which case the
+        // guessed base rate of the stream is used.
+        let frame_rate = [input.avg_frame_rate(), input.rate()]
+            .into_iter()
+            .find(|rate| rate.numerator() > 0 && rate.denominator() > 0)
+            .map(|rate| Rational::new(rate.numerator() as u64, rate.denominator() as u64));
+        let time_base = match frame_rate {
+            Some(frame_rate) => Rational::from_reciprocal(frame_rate),
+            None => {
+                let time_base = input.time_base();
+                Rational::new(time_base.numerator() as u64, time_base.denominator() as u64)
+            }
+        };
+        let transfer_characteristics = match decoder.color_transfer_characteristic() {
+            color::TransferCharacteristic::SMPTE2084 => TransferCharacteristics::Pq,
+            color::TransferCharacteristic::ARIB_STD_B67 => TransferCharacteristics::Hlg,
+            color::TransferCharacteristic::BT709
+            | color::TransferCharacteristic::SMPTE170M
+            | color::TransferCharacteristic::BT2020_10
+            | color::TransferCharacteristic::BT2020_12 => TransferCharacteristics::Sdr,
+            _ => TransferCharacteristics::Unknown,
+        };
+        // An unknown sample aspect ratio is 0:1
+        let sar = decoder.aspect_ratio();
+        let sample_aspect_ratio = Some(Rational::new(
+            sar.numerator() as u64,
+            sar.denominator() as u64,
+        ))
+        .filter(|sar| sar.num > 0 && sar.den > 0);
+        // Not every container stores the number of frames, in which case this is 0.
+        let total_frames = Some(input.frames() as usize).filter(|&frames| frames > 0);
         Ok(Self {
             video_details: VideoDetails {
                 width: decoder.width() as usize,
                 height: decoder.height() as usize,
-                bit_depth: match decoder.format() {
-                    format::pixel::Pixel::YUV420P
-                    | format::pixel::Pixel::YUV422P
-                    | format::pixel::Pixel::YUV444P
-                    | format::pixel::Pixel::YUVJ420P
-                    | format::pixel::Pixel::YUVJ422P
-                    | format::pixel::Pixel::YUVJ444P => 8,
-                    format::pixel::Pixel::YUV420P10LE
-                    | format::pixel::Pixel::YUV422P10LE
-                    | format::pixel::Pixel::YUV444P10LE => 10,
-                    format::pixel::Pixel::YUV420P12LE
-                    | format::pixel::Pixel::YUV422P12LE
-                    | format::pixel::Pixel::YUV444P12LE => 12,
-                    _ => {
-                        return Err(format!("Unsupported pixel format {:?}", decoder.format()));
-                    }
-                },
-                chroma_sampling: match decoder.format() {
-                    format::pixel::Pixel::YUV420P
-                    | format::pixel::Pixel::YUVJ420P
-                    | format::pixel::Pixel::YUV420P10LE
-                    | format::pixel::Pixel::YUV420P12LE => ChromaSampling::Cs420,
-                    format::pixel::Pixel::YUV422P
-                    | format::pixel::Pixel::YUVJ422P
-                    | format::pixel::Pixel::YUV422P10LE
-                    | format::pixel::Pixel::YUV422P12LE => ChromaSampling::Cs422,
-                    format::pixel::Pixel::YUV444P
-                    | format::pixel::Pixel::YUVJ444P
-                    | format::pixel::Pixel::YUV444P10LE
-                    | format::pixel::Pixel::YUV444P12LE => ChromaSampling::Cs444,
-                    _ => {
-                        return Err(format!("Unsupported pixel format {:?}", decoder.format()));
-                    }
-                },
-                chroma_sample_position: match decoder.format() {
-                    format::pixel::Pixel::YUV422P
-                    | format::pixel::Pixel::YUV422P10LE
-                    | format::pixel::Pixel::YUV422P12LE => ChromaSamplePosition::Vertical,
-                    _ => ChromaSamplePosition::Colocated,
-                },
-                time_base: Rational::new(
-                    frame_rate.denominator() as u64,
-                    frame_rate.numerator() as u64,
-                ),
+                bit_depth,
+                chroma_sampling,
+                chroma_sample_position,
+                time_base,
+                frame_rate,
+                transfer_characteristics,
+                field_order: None,
+                sample_aspect_ratio,
                 luma_padding: 0,
             },
+            scaler,
+            total_frames,
             decoder,
             input_ctx,
             frameno: 0,
             stream_index,
             end_of_stream: false,
             eof_sent: false,
+            pending: None,
+            last_frame_type: FrameType::Unknown,
+            error: None,
+            _custom_io: None,
         })
     }
 
-    fn decode_frame<T: Pixel>(&self, decoded: &frame::Video) -> Frame<T> {
-        let mut f: Frame<T> = Frame::new_with_padding(
-            self.video_details.width,
-            self.video_details.height,
-            self.video_details.chroma_sampling,
-            0,
-        );
-        let width = self.video_details.width;
-        let height = self.video_details.height;
-        let bit_depth = self.video_details.bit_depth;
-        let bytes = if bit_depth > 8 { 2 } else { 1 };
-        let (chroma_width, _) = self
-            .video_details
-            .chroma_sampling
-            .get_chroma_dimensions(width, height);
-        f.planes[0].copy_from_raw_u8(decoded.data(0), width * bytes, bytes);
-        convert_chroma_data(
-            &mut f.planes[1],
-            self.video_details.chroma_sample_position,
-            bit_depth,
-            decoded.data(1),
-            chroma_width * bytes,
-            bytes,
-        );
-        convert_chroma_data(
-            &mut f.planes[2],
-            self.video_details.chroma_sample_position,
-            bit_depth,
-            decoded.data(2),
-            chroma_width * bytes,
-            bytes,
-        );
-        f
-    }
-}
+    /// Seeks to frame `n` (counting from 0), so that it is the next frame returned
+    /// by [`Decoder::read_video_frame`].
+    ///
+    /// FFmpeg can only seek to keyframes, so this seeks to the last keyframe at or
+    /// before frame `n` and decodes and discards the frames in between. Frame numbers
+    /// are derived from the frame timestamps and the average frame rate of the
+    /// stream, so the result is only exact for constant frame rate videos. Streams
+    /// without a known frame rate cannot be seeked.
+    pub fn seek_to_frame(&mut self, n: usize) -> Result<(), MetricsError> {
+        let stream =
+            self.input_ctx
+                .stream(self.stream_index)
+                .ok_or(MetricsError::UnsupportedInput {
+                    reason: "Input has no video stream",
+                })?;
+        let time_base = stream.time_base();
+        // Like the frame rate in `VideoDetails`, this falls back to the guessed base rate
+        let frame_duration = [stream.avg_frame_rate(), stream.rate()]
+            .into_iter()
+            .find(|rate| rate.numerator() > 0 && rate.denominator() > 0)
+            .ok_or(MetricsError::UnsupportedInput {
+                reason: "Seeking needs a known frame rate",
+            })?
+            .invert();
+        // The start time is not known for every container.
+        let start_time = Some(stream.start_time())
+            .filter(|&start_time| start_time != ffmpeg::ffi::AV_NOPTS_VALUE)
+            .unwrap_or(0);
 
-impl Decoder for FfmpegDecoder {
-    fn get_video_details(&self) -> VideoDetails {
-        self.video_details
+        let target = start_time
+            .checked_add((n as i64).rescale(frame_duration, time_base))
+            .ok_or_else(|| {
+                MetricsError::decode_error(
+                    "Could not seek",
+                    format!("Frame {} is past the end of the video", n),
+                )
+            })?;
+        let seek_target = target.rescale(time_base, ffmpeg::rescale::TIME_BASE);
+        self.input_ctx
+            .seek(seek_target, ..=seek_target)
+            .map_err(|e| MetricsError::decode_error("Could not seek", e))?;
+        self.decoder.flush();
+        self.end_of_stream = false;
+        self.eof_sent = false;
+        self.pending = None;
+
+        loop {
+            let decoded = self.receive_frame(true).ok_or_else(|| {
+                MetricsError::decode_error(
+                    "Could not seek",
+                    format!("Frame {} is past the end of the video", n),
+                )
+            })?;
+            let timestamp = decoded.timestamp().ok_or(MetricsError::MalformedInput {
+                reason: "Decoded frame has no timestamp",
+            })?;
+            let frameno = (timestamp - start_time).rescale(time_base, frame_duration);
+            if frameno > n as i64 {
+                return Err(MetricsError::decode_error(
+                    "Could not seek",
+                    format!("Frame {} is not at a timestamp of the video", n),
+                ));
+            }
+            if frameno == n as i64 {
+                self.pending = Some(decoded);
+                self.frameno = n;
+                return Ok(());
+            }
+        }
     }
 
-    fn read_video_frame<T: Pixel>(&mut self) -> Option<Frame<T>> {
+    /// Returns the next decoded frame of the video stream.
+    ///
+    /// Unless `keep_timestamps` is set, the packet timestamps are replaced by the
+    /// frame number.
+    fn receive_frame(&mut self, keep_timestamps: bool) -> Option<frame::Video> {
         // For some reason there's a crap ton of work needed to get ffmpeg to do something simple,
         // because each codec has it's own stupid way of doing things and they don't all
         // decode the same way.
@@ -177,17 +570,17 @@ impl Decoder for FfmpegDecoder {
                     self.video_details.width as u32,
                     self.video_details.height as u32,
                 );
-                packet.set_pts(Some(self.frameno as i64));
-                packet.set_dts(Some(self.frameno as i64));
+                if !keep_timestamps {
+                    packet.set_pts(Some(self.frameno as i64));
+                    packet.set_dts(Some(self.frameno as i64));
+                }
 
                 if !self.end_of_stream {
                     let _ = self.decoder.send_packet(&packet);
                 }
 
                 if self.decoder.receive_frame(&mut decoded).is_ok() {
-                    let f = self.decode_frame(&decoded);
-                    self.frameno += 1;
-                    return Some(f);
+                    return Some(decoded);
                 } else if self.end_of_stream {
                     return None;
                 }
@@ -195,7 +588,112 @@ impl Decoder for FfmpegDecoder {
         }
     }
 
+    fn decode_frame<T: Pixel>(&mut self, decoded: &frame::Video) -> Option<Frame<T>> {
+        let mut converted = frame::Video::empty();
+        let decoded = match self.scaler.as_mut() {
+            Some(Scaler(scaler)) => {
+                if let Err(e) = scaler.run(decoded, &mut converted) {
+                    self.error = Some(MetricsError::decode_error("Could not convert the frame", e));
+                    return None;
+                }
+                &converted
+            }
+            None => decoded,
+        };
+
+        let mut f: Frame<T> = Frame::new_with_padding(
+            self.video_details.width,
+            self.video_details.height,
+            self.video_details.chroma_sampling,
+            0,
+        );
+        let bit_depth = self.video_details.bit_depth;
+        let bytes = if bit_depth > 8 { 2 } else { 1 };
+        // The rows of FFmpeg frames are usually padded, so each row starts
+        // `stride` bytes after the previous one rather than after its last sample.
+        f.planes[0].copy_from_raw_u8(decoded.data(0), decoded.stride(0), bytes);
+        convert_chroma_data(
+            &mut f.planes[1],
+            self.video_details.chroma_sample_position,
+            bit_depth,
+            decoded.data(1),
+            decoded.stride(1),
+            bytes,
+        );
+        convert_chroma_data(
+            &mut f.planes[2],
+            self.video_details.chroma_sample_position,
+            bit_depth,
+            decoded.data(2),
+            decoded.stride(2),
+            bytes,
+        );
+        Some(f)
+    }
+}
+
+impl Decoder for FfmpegDecoder {
+    fn get_video_details(&self) -> VideoDetails {
+        self.video_details
+    }
+
+    fn read_video_frame<T: Pixel>(&mut self) -> Option<Frame<T>> {
+        self.error = None;
+        let decoded = match self.pending.take() {
+            Some(decoded) => decoded,
+            None => self.receive_frame(false)?,
+        };
+        self.last_frame_type = match decoded.kind() {
+            picture::Type::I => FrameType::I,
+            picture::Type::P => FrameType::P,
+            picture::Type::B => FrameType::B,
+            _ => FrameType::Unknown,
+        };
+        let f = self.decode_frame(&decoded);
+        self.frameno += 1;
+        f
+    }
+
+    fn last_frame_type(&self) -> FrameType {
+        self.last_frame_type
+    }
+
     fn get_bit_depth(&self) -> usize {
         self.video_details.bit_depth
     }
+
+    fn total_frames(&self) -> Option<usize> {
+        self.total_frames
+    }
+
+    /// Returns `true` if the I/O context of the container supports seeking,
+    /// which is the case for regular files, but not for pipes.
+    fn can_seek(&self) -> bool {
+        // SAFETY: The format context is valid for the lifetime of the decoder,
+        // and its I/O context is only read here.
+        unsafe {
+            let pb = (*self.input_ctx.as_ptr()).pb;
+            !pb.is_null() && (*pb).seekable != 0
+        }
+    }
+
+    /// Rewinds the decoder by seeking to the first frame of the video,
+    /// which requires a seekable input, see [`Decoder::can_seek`].
+    fn reset(&mut self) -> Result<(), MetricsError> {
+        if !self.can_seek() {
+            return Err(MetricsError::UnsupportedInput {
+                reason: "Input stream cannot be rewound",
+            });
+        }
+        self.seek_to_frame(0)?;
+        self.last_frame_type = FrameType::Unknown;
+        self.error = None;
+        Ok(())
+    }
+
+    /// Returns the error if a decoded frame could not be converted to the output
+    /// pixel format, e.g. because its format changed in the middle of the video.
+    fn take_error(&mut self) -> Option<MetricsError> {
+        self.error.take()
+    }
 }
@@ -0,0 +1,77 @@
+use av_metrics::video::decode::*;
+use av_metrics::video::*;
+use av_metrics::MetricsError;
+
+/// A decoder which generates its frames with a closure instead of reading a file
+///
+/// The closure is called with the index of each frame, starting at zero, until
+/// `frame_count` frames have been returned. This makes it possible to test metrics
+/// on deterministic input, e.g. a frame with a known amount of noise added,
+/// without shipping sample files.
+///
+/// The frames are converted to the pixel type requested by the metric, so the
+/// closure should produce samples matching the bit depth in `video_details`.
+pub struct GeneratorDecoder<P: Pixel, F: Fn(usize) -> Frame<P> + Send> {
+    generator: F,
+    video_details: VideoDetails,
+    frame_count: usize,
+    frame_types: Vec<FrameType>,
+    next_frame: usize,
+}
+
+impl<P: Pixel, F: Fn(usize) -> Frame<P> + Send> GeneratorDecoder<P, F> {
+    /// Initialize a new decoder returning `frame_count` frames made by `generator`
+    pub fn new(generator: F, video_details: VideoDetails, frame_count: usize) -> Self {
+        GeneratorDecoder {
+            generator,
+            video_details,
+            frame_count,
+            frame_types: Vec::new(),
+            next_frame: 0,
+        }
+    }
+
+    /// Report `frame_types[i]` as the type of the `i`-th frame, e.g. to test how the
+    /// frames of each type are aggregated. Frames past the end of the list are of
+    /// unknown type, which is also the default for all frames.
+    pub fn frame_types(mut self, frame_types: Vec<FrameType>) -> Self {
+        self.frame_types = frame_types;
+        self
+    }
+}
+
+impl<P: Pixel, F: Fn(usize) -> Frame<P> + Send> Decoder for GeneratorDecoder<P, F> {
+    fn read_video_frame<T: Pixel>(&mut self) -> Option<Frame<T>> {
+        if self.next_frame >= self.frame_count {
+            return None;
+        }
+        let frame = (self.generator)(self.next_frame);
+        self.next_frame += 1;
+        let bit_depth = self.video_details.bit_depth;
+        Some(convert_bit_depth(&frame, bit_depth, bit_depth))
+    }
+
+    fn get_bit_depth(&self) -> usize {
+        self.video_details.bit_depth
+    }
+
+    fn get_video_details(&self) -> VideoDetails {
+        self.video_details
+    }
+
+    fn last_frame_type(&self) -> FrameType {
+        self.next_frame
+            .checked_sub(1)
+            .and_then(|index| self.frame_types.get(index).copied())
+            .unwrap_or_default()
+    }
+
+    fn total_frames(&self) -> Option<usize> {
+        Some(self.frame_count)
+    }
+
+    fn reset(&mut self) -> Result<(), MetricsError> {
+        self.next_frame = 0;
+        Ok(())
+    }
+}
@@ -1,11 +1,20 @@
 //! This crate providers ready-made decoders for use with av-metrics or other tools as needed.
 //!
-//! No decoders are enabled by default. They must be enabled via Cargo features.
+//! Apart from [`GeneratorDecoder`], which synthesizes its frames, no decoders are
+//! enabled by default. They must be enabled via Cargo features.
 //!
-//! Currently supported decoder features: y4m
+//! Currently supported decoder features: y4m, raw, png, avif, tokio
 
 #![deny(missing_docs)]
 
+mod compare;
+mod generator;
+
+pub use crate::compare::compare_files;
+pub use crate::generator::GeneratorDecoder;
+/// The metrics which can be calculated by [`compare_files`]
+pub use av_metrics::video::dynamic::Metric;
+
 #[cfg(feature = "y4m")]
 /// Items related to decoding y4m video
 pub mod y4m;
@@ -13,6 +22,29 @@ pub mod y4m;
 #[cfg(feature = "y4m")]
 pub use crate::y4m::Y4MDecoder;
 
+#[cfg(feature = "raw")]
+/// Items related to decoding headerless raw video
+pub mod raw;
+
+#[cfg(feature = "raw")]
+pub use crate::raw::RawDecoder;
+
+#[cfg(feature = "png")]
+/// Items related to loading PNG images as frames
+pub mod png;
+
+#[cfg(feature = "avif")]
+mod avif;
+
+#[cfg(feature = "avif")]
+pub use crate::avif::AvifDecoder;
+
+#[cfg(feature = "tokio")]
+mod tokio;
+
+#[cfg(feature = "tokio")]
+pub use crate::tokio::AsyncDecoder;
+
 #[cfg(any(
     feature = "ffmpeg",
     feature = "ffmpeg_static",
@@ -25,7 +57,7 @@ mod ffmpeg;
     feature = "ffmpeg_static",
     feature = "ffmpeg_build"
 ))]
-pub use crate::ffmpeg::FfmpegDecoder;
+pub use crate::ffmpeg::{FfmpegDecoder, FfmpegDecoderBuilder};
 
 #[cfg(feature = "vapoursynth")]
 mod vapoursynth;
@@ -0,0 +1,145 @@
+use av_metrics::video::*;
+use av_metrics::MetricsError;
+use std::fs::File;
+use std::mem::size_of;
+use std::path::Path;
+
+/// Coefficients used to convert RGB images to YUV
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum YuvMatrix {
+    /// ITU-R BT.601, as used by JPEG
+    Bt601,
+    /// ITU-R BT.709
+    #[default]
+    Bt709,
+    /// ITU-R BT.2020, non-constant luminance
+    Bt2020,
+}
+
+impl YuvMatrix {
+    /// Returns the red and blue luma coefficients (Kr, Kb).
+    fn coefficients(self) -> (f64, f64) {
+        match self {
+            YuvMatrix::Bt601 => (0.299, 0.114),
+            YuvMatrix::Bt709 => (0.2126, 0.0722),
+            YuvMatrix::Bt2020 => (0.2627, 0.0593),
+        }
+    }
+}
+
+/// Loads a PNG image as a 4:4:4 YUV frame
+///
+/// The samples are converted to full range YUV using `matrix`. Grayscale
+/// images are stored in the luma plane, with neutral chroma planes.
+/// An alpha channel, if present, is discarded, see [`load_png_with_alpha`] to keep it.
+///
+/// The bit depth of the frame is chosen by the pixel type, i.e. `Frame<u8>`
+/// has a bit depth of 8 and `Frame<u16>` has a bit depth of 16. The image
+/// samples are rescaled if the PNG uses a different bit depth.
+pub fn load_png<T: Pixel, P: AsRef<Path>>(
+    input: P,
+    matrix: YuvMatrix,
+) -> Result<Frame<T>, MetricsError> {
+    load_png_with_alpha(input, matrix).map(|(frame, _)| frame)
+}
+
+/// Loads a PNG image as a 4:4:4 YUV frame, along with its alpha channel
+///
+/// The frame is the same as returned by [`load_png`]. The alpha channel is
+/// returned as a separate plane of the same size, rescaled like the other samples,
+/// or `None` if the image is opaque. It can be compared with e.g.
+/// [`psnr::calculate_alpha_psnr`]. A transparent color of images without an
+/// alpha channel is expanded into an alpha plane as well.
+pub fn load_png_with_alpha<T: Pixel, P: AsRef<Path>>(
+    input: P,
+    matrix: YuvMatrix,
+) -> Result<(Frame<T>, Option<Plane<T>>), MetricsError> {
+    let file = File::open(input)
+        .map_err(|e| MetricsError::decode_error("Could not open the input file", e))?;
+    let mut decoder = png::Decoder::new(file);
+    // Expand palettes and grayscale images below 8 bits.
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder
+        .read_info()
+        .map_err(|e| MetricsError::decode_error("Could not read the PNG header", e))?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut buf)
+        .map_err(|e| MetricsError::decode_error("Could not decode the PNG image", e))?;
+
+    let (width, height) = (info.width as usize, info.height as usize);
+    let channels = info.color_type.samples();
+    let png_max = match info.bit_depth {
+        png::BitDepth::Sixteen => u16::MAX as f64,
+        _ => u8::MAX as f64,
+    };
+    let get_sample = |line: &[u8], index: usize| -> f64 {
+        match info.bit_depth {
+            png::BitDepth::Sixteen => u16::from_be_bytes([line[2 * index], line[2 * index + 1]]),
+            _ => line[index] as u16,
+        }
+        .into()
+    };
+    let out_max = ((1u32 << (8 * size_of::<T>())) - 1) as f64;
+    let to_pixel = |value: f64| T::cast_from((value * out_max).round().clamp(0.0, out_max) as u32);
+    let (kr, kb) = matrix.coefficients();
+    let kg = 1.0 - kr - kb;
+
+    let mut frame: Frame<T> = Frame::new_with_padding(width, height, ChromaSampling::Cs444, 0);
+    let alpha_index = match info.color_type {
+        png::ColorType::GrayscaleAlpha => Some(1),
+        png::ColorType::Rgba => Some(3),
+        _ => None,
+    };
+    let mut alpha = alpha_index.map(|_| Plane::new(width, height, 0, 0, 0, 0));
+    for (y, line) in buf.chunks(info.line_size).take(height).enumerate() {
+        for x in 0..width {
+            let (luma, cb, cr) = match info.color_type {
+                png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => {
+                    (get_sample(line, x * channels) / png_max, 0.5, 0.5)
+                }
+                _ => {
+                    let r = get_sample(line, x * channels) / png_max;
+                    let g = get_sample(line, x * channels + 1) / png_max;
+                    let b = get_sample(line, x * channels + 2) / png_max;
+                    let luma = kr * r + kg * g + kb * b;
+                    (
+                        luma,
+                        (b - luma) / (2.0 * (1.0 - kb)) + 0.5,
+                        (r - luma) / (2.0 * (1.0 - kr)) + 0.5,
+                    )
+                }
+            };
+            for (plane, value) in frame.planes.iter_mut().zip([luma, cb, cr]) {
+                let stride = plane.cfg.stride;
+                plane.data[y * stride + x] = to_pixel(value);
+            }
+            if let (Some(plane), Some(index)) = (alpha.as_mut(), alpha_index) {
+                let stride = plane.cfg.stride;
+                plane.data[y * stride + x] =
+                    to_pixel(get_sample(line, x * channels + index) / png_max);
+            }
+        }
+    }
+    Ok((frame, alpha))
+}
+
+/// Loads two PNG images to compare with the frame metrics
+///
+/// See [`load_png`] for details on the conversion. The frames use 4:4:4 chroma
+/// sampling. Returns an error if the images do not have the same dimensions.
+pub fn load_png_pair<T: Pixel, P1: AsRef<Path>, P2: AsRef<Path>>(
+    input1: P1,
+    input2: P2,
+    matrix: YuvMatrix,
+) -> Result<(Frame<T>, Frame<T>), MetricsError> {
+    let frame1 = load_png(input1, matrix)?;
+    let frame2 = load_png(input2, matrix)?;
+    let (cfg1, cfg2) = (&frame1.planes[0].cfg, &frame2.planes[0].cfg);
+    if cfg1.width != cfg2.width || cfg1.height != cfg2.height {
+        return Err(MetricsError::InputMismatch {
+            reason: "Images must have the same dimensions",
+        });
+    }
+    Ok((frame1, frame2))
+}
@@ -0,0 +1,161 @@
+use av_metrics::video::decode::*;
+use av_metrics::video::*;
+use av_metrics::MetricsError;
+use std::fs::File;
+use std::io::{stdin, BufReader, ErrorKind, Read, Stdin};
+use std::path::Path;
+
+/// A decoder for headerless planar YUV input, e.g. as written by `ffmpeg -f rawvideo`
+///
+/// Raw streams do not carry any information about their format,
+/// so the geometry of the video has to be supplied by the caller.
+/// Samples with a bit depth above 8 are expected to be stored as 16-bit little endian.
+/// A stream which ends in the middle of a frame is reported as
+/// [`MetricsError::UnexpectedEof`] by [`Decoder::take_error`].
+pub struct RawDecoder<R: Read + Send> {
+    reader: R,
+    video_details: VideoDetails,
+    frame_buf: Vec<u8>,
+    /// The error which stopped the last call to `read_video_frame`
+    error: Option<MetricsError>,
+}
+
+impl<R: Read + Send> RawDecoder<R> {
+    /// Initialize a new raw decoder reading from `reader`
+    pub fn new(
+        reader: R,
+        width: usize,
+        height: usize,
+        bit_depth: usize,
+        chroma_sampling: ChromaSampling,
+    ) -> Result<Self, MetricsError> {
+        if width == 0 || height == 0 {
+            return Err(MetricsError::UnsupportedInput {
+                reason: "Width and height must be greater than zero",
+            });
+        }
+        if !(8..=16).contains(&bit_depth) {
+            return Err(MetricsError::UnsupportedInput {
+                reason: "Bit depth must be between 8 and 16",
+            });
+        }
+
+        let video_details = VideoDetails::new(width, height, bit_depth, chroma_sampling);
+        let frame_buf = vec![0; frame_size(&video_details)];
+        Ok(RawDecoder {
+            reader,
+            video_details,
+            frame_buf,
+            error: None,
+        })
+    }
+}
+
+/// Initialize a new raw decoder for a given input file
+pub fn new_decoder_from_file<P: AsRef<Path>>(
+    input: P,
+    width: usize,
+    height: usize,
+    bit_depth: usize,
+    chroma_sampling: ChromaSampling,
+) -> Result<RawDecoder<BufReader<File>>, MetricsError> {
+    let file = File::open(input)
+        .map_err(|e| MetricsError::decode_error("Could not open the input file", e))?;
+    RawDecoder::new(
+        BufReader::new(file),
+        width,
+        height,
+        bit_depth,
+        chroma_sampling,
+    )
+}
+
+/// Initialize a new raw decoder from stdin
+pub fn new_decoder_from_stdin(
+    width: usize,
+    height: usize,
+    bit_depth: usize,
+    chroma_sampling: ChromaSampling,
+) -> Result<RawDecoder<BufReader<Stdin>>, MetricsError> {
+    RawDecoder::new(
+        BufReader::new(stdin()),
+        width,
+        height,
+        bit_depth,
+        chroma_sampling,
+    )
+}
+
+/// Size in bytes of a single frame
+fn frame_size(details: &VideoDetails) -> usize {
+    let bytes = if details.bit_depth > 8 { 2 } else { 1 };
+    let (chroma_width, chroma_height) = details
+        .chroma_sampling
+        .get_chroma_dimensions(details.width, details.height);
+    (details.width * details.height + 2 * chroma_width * chroma_height) * bytes
+}
+
+impl<R> Decoder for RawDecoder<R>
+where
+    R: Read + Send,
+{
+    fn get_video_details(&self) -> VideoDetails {
+        self.video_details
+    }
+
+    fn read_video_frame<T: Pixel>(&mut self) -> Option<Frame<T>> {
+        let VideoDetails {
+            width,
+            height,
+            bit_depth,
+            chroma_sampling,
+            ..
+        } = self.video_details;
+        let bytes = if bit_depth > 8 { 2 } else { 1 };
+        // The stream may only end before the first byte of a frame
+        let mut filled = 0;
+        while filled < self.frame_buf.len() {
+            match self.reader.read(&mut self.frame_buf[filled..]) {
+                Ok(0) if filled == 0 => return None,
+                Ok(0) => {
+                    self.error = Some(MetricsError::UnexpectedEof);
+                    return None;
+                }
+                Ok(len) => filled += len,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.error = Some(MetricsError::decode_error("Could not read a frame", e));
+                    return None;
+                }
+            }
+        }
+
+        let mut f: Frame<T> = Frame::new_with_padding(width, height, chroma_sampling, 0);
+        let (chroma_width, chroma_height) = chroma_sampling.get_chroma_dimensions(width, height);
+        let luma_size = width * height * bytes;
+        let chroma_size = chroma_width * chroma_height * bytes;
+        f.planes[0].copy_from_raw_u8(&self.frame_buf[..luma_size], width * bytes, bytes);
+        if chroma_sampling != ChromaSampling::Cs400 {
+            f.planes[1].copy_from_raw_u8(
+                &self.frame_buf[luma_size..][..chroma_size],
+                chroma_width * bytes,
+                bytes,
+            );
+            f.planes[2].copy_from_raw_u8(
+                &self.frame_buf[(luma_size + chroma_size)..],
+                chroma_width * bytes,
+                bytes,
+            );
+        }
+
+        Some(f)
+    }
+
+    fn get_bit_depth(&self) -> usize {
+        self.video_details.bit_depth
+    }
+
+    fn take_error(&mut self) -> Option<MetricsError> {
+        self.error.take()
+    }
+}
@@ -0,0 +1,59 @@
+use av_metrics::video::decode::*;
+use av_metrics::video::*;
+use tokio::task::spawn_blocking;
+
+/// A wrapper for reading frames from any [`Decoder`] in async code using tokio
+///
+/// Decoding is blocking work, so every frame is decoded on the blocking thread
+/// pool of the tokio runtime instead of stalling the async executor.
+/// This must be used from within a tokio runtime.
+///
+/// The video metrics take a [`Decoder`] and are CPU bound as well. To run them
+/// from async code, call them with the plain decoders inside
+/// `tokio::task::spawn_blocking`.
+pub struct AsyncDecoder<D: Decoder + 'static> {
+    inner: Option<D>,
+    video_details: VideoDetails,
+}
+
+impl<D: Decoder + 'static> AsyncDecoder<D> {
+    /// Wraps `decoder` to be used from async code
+    pub fn new(decoder: D) -> Self {
+        AsyncDecoder {
+            video_details: decoder.get_video_details(),
+            inner: Some(decoder),
+        }
+    }
+
+    /// Read the next frame from the input video
+    ///
+    /// Returns `None` at the end of the video. If a previous call was cancelled
+    /// before it completed, the wrapped decoder is lost and `None` is returned
+    /// from then on.
+    pub async fn read_video_frame<T: Pixel>(&mut self) -> Option<Frame<T>> {
+        let mut decoder = self.inner.take()?;
+        let (decoder, frame) = spawn_blocking(move || {
+            let frame = decoder.read_video_frame::<T>();
+            (decoder, frame)
+        })
+        .await
+        .ok()?;
+        self.inner = Some(decoder);
+        frame
+    }
+
+    /// Get the bit depth of the video
+    pub fn get_bit_depth(&self) -> usize {
+        self.video_details.bit_depth
+    }
+
+    /// Get the video details
+    pub fn get_video_details(&self) -> VideoDetails {
+        self.video_details
+    }
+
+    /// Returns the wrapped decoder, unless it was lost by a cancelled read
+    pub fn into_inner(self) -> Option<D> {
+        self.inner
+    }
+}
@@ -1,27 +1,40 @@
-use anyhow::{ensure, Result};
 use av_metrics::video::{
     decode::{Decoder, Rational, VideoDetails},
     ChromaSampling,
 };
+use av_metrics::MetricsError;
 use std::{
+    collections::HashMap,
     mem::{size_of, transmute},
+    ops::Range,
     path::Path,
+    sync::mpsc::{channel, Receiver, Sender},
 };
-use vapoursynth::{
-    format::Format,
-    prelude::*,
-    video_info::{Framerate, Resolution},
-};
+use vapoursynth::{format::Format, prelude::*, video_info::Resolution};
 
 /// A video decoder implementation using Vaopursynth
 pub struct VapoursynthDecoder {
+    // The prefetched frames must be freed before the core which owns them,
+    // so these fields are declared (and dropped) before `env`.
+    /// Prefetched frames which arrived before they were needed.
+    prefetched: HashMap<usize, FrameRef<'static>>,
+    /// Prefetched frames as they are delivered. `None` if rendering a frame failed.
+    prefetch_recv: Receiver<(usize, Option<FrameRef<'static>>)>,
+    prefetch_send: Sender<(usize, Option<FrameRef<'static>>)>,
     env: Environment,
+    num_frames: usize,
     cur_frame: usize,
+    prefetch: usize,
+    /// All frames before this one have already been requested.
+    requested_until: usize,
 }
 
 impl VapoursynthDecoder {
     /// Loads a video file using `LSmashSource`
-    pub fn new_from_video(filename: &Path) -> Result<Self> {
+    pub fn new_from_video(filename: &Path) -> Result<Self, MetricsError> {
+        let filename = filename
+            .canonicalize()
+            .map_err(|e| MetricsError::decode_error("Could not open the input file", e))?;
         let script = format!(
             r#"
 import vapoursynth as vs
@@ -32,67 +45,160 @@ clip = core.lsmas.LWLibavSource(source="{}")
 clip.set_output(0)
         "#,
             filename
-                .canonicalize()
-                .unwrap()
                 .to_string_lossy()
                 .trim_start_matches(r"\\?\")
                 .replace('\\', "\\\\")
                 .replace('"', "\\\"")
         );
-        let env = Environment::from_script(&script)?;
-        let this = Self { env, cur_frame: 0 };
-        this.get_node()?;
-        ensure!(
-            this.get_format()?.sample_type() == SampleType::Integer,
-            "Currently only integer input is supported"
-        );
-        Ok(this)
+        let env = Environment::from_script(&script)
+            .map_err(|e| MetricsError::decode_error("Could not evaluate the script", e))?;
+        Self::new_from_env(env)
     }
 
     /// Loads a `.vpy` script
-    pub fn new_from_script(filename: &Path) -> Result<Self> {
-        let env = Environment::from_file(filename, EvalFlags::SetWorkingDir)?;
-        let this = Self { env, cur_frame: 0 };
-        this.get_node()?;
-        ensure!(
-            this.get_format()?.sample_type() == SampleType::Integer,
-            "Currently only integer input is supported"
-        );
+    pub fn new_from_script(filename: &Path) -> Result<Self, MetricsError> {
+        let env = Environment::from_file(filename, EvalFlags::SetWorkingDir)
+            .map_err(|e| MetricsError::decode_error("Could not evaluate the script", e))?;
+        Self::new_from_env(env)
+    }
+
+    fn new_from_env(env: Environment) -> Result<Self, MetricsError> {
+        let (prefetch_send, prefetch_recv) = channel();
+        let mut this = Self {
+            prefetched: HashMap::new(),
+            prefetch_recv,
+            prefetch_send,
+            env,
+            num_frames: 0,
+            cur_frame: 0,
+            prefetch: 0,
+            requested_until: 0,
+        };
+        let num_frames = this.get_node()?.info().num_frames;
+        this.num_frames = num_frames;
+        this.check_format()?;
         Ok(this)
     }
 
-    fn get_node(&self) -> Result<Node<'_>> {
-        Ok(self.env.get_output(0)?.0)
+    fn check_format(&self) -> Result<(), MetricsError> {
+        let format = self.get_format()?;
+        if format.sample_type() != SampleType::Integer {
+            return Err(MetricsError::decode_error(
+                "Currently only integer input is supported",
+                format!(
+                    "format {} has {}-bit float samples",
+                    format.name(),
+                    format.bits_per_sample()
+                ),
+            ));
+        }
+        // 4:1:1 and 4:4:0 video cannot be represented by `ChromaSampling`
+        if format.color_family() != ColorFamily::Gray
+            && !matches!(
+                (format.sub_sampling_w(), format.sub_sampling_h()),
+                (0, 0) | (1, 0) | (1, 1)
+            )
+        {
+            return Err(MetricsError::decode_error(
+                "Only 4:2:0, 4:2:2 and 4:4:4 chroma subsampling is supported, \
+                 convert other formats to 4:2:2 or 4:4:4 in the script",
+                format!(
+                    "format {} is subsampled by 2^{} horizontally and 2^{} vertically",
+                    format.name(),
+                    format.sub_sampling_w(),
+                    format.sub_sampling_h()
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn get_node(&self) -> Result<Node<'_>, MetricsError> {
+        self.env
+            .get_output(0)
+            .map(|(node, _)| node)
+            .map_err(|e| MetricsError::decode_error("Script has no output clip", e))
     }
 
-    fn get_resolution(&self) -> Result<Resolution> {
+    fn get_resolution(&self) -> Result<Resolution, MetricsError> {
         match self.get_node()?.info().resolution {
             Property::Constant(res) => Ok(res),
-            Property::Variable => Err(anyhow::anyhow!(
-                "Variable resolution videos are not supported"
-            )),
+            Property::Variable => Err(MetricsError::UnsupportedInput {
+                reason: "Variable resolution videos are not supported",
+            }),
         }
     }
 
-    fn get_format(&self) -> Result<Format<'_>> {
+    fn get_format(&self) -> Result<Format<'_>, MetricsError> {
         match self.get_node()?.info().format {
             Property::Constant(format) => Ok(format),
-            Property::Variable => Err(anyhow::anyhow!("Variable format videos are not supported")),
+            Property::Variable => Err(MetricsError::UnsupportedInput {
+                reason: "Variable format videos are not supported",
+            }),
         }
     }
 
-    fn get_frame_rate(&self) -> Result<Framerate> {
-        match self.get_node()?.info().framerate {
-            Property::Constant(fps) => Ok(fps),
-            Property::Variable => Err(anyhow::anyhow!(
-                "Variable frameratevideos are not supported"
-            )),
-        }
+    /// Returns the number of frames in this video
+    pub fn get_frame_count(&self) -> Result<usize, MetricsError> {
+        Ok(self.num_frames)
     }
 
-    /// Returns the number of frames in this video
-    pub fn get_frame_count(&self) -> Result<usize> {
-        Ok(self.get_node()?.info().num_frames)
+    /// Request up to `frames` frames ahead of the one currently being read.
+    ///
+    /// The frames are requested asynchronously, so VapourSynth can render them
+    /// in parallel while the metrics are being computed. This uses more memory,
+    /// but speeds up CPU-heavy scripts considerably. Prefetching is disabled by default.
+    pub fn set_prefetch(&mut self, frames: usize) {
+        self.prefetch = frames;
+    }
+
+    /// Returns the frames which should be requested ahead of the current one
+    /// and have not been requested yet.
+    fn frames_to_prefetch(&mut self) -> Range<usize> {
+        let start = self.requested_until.max(self.cur_frame + 1);
+        let end = (self.cur_frame + 1 + self.prefetch).min(self.num_frames);
+        self.requested_until = self.requested_until.max(end);
+        start..end
+    }
+
+    /// Requests frame `n` asynchronously. The rendered frame is kept until
+    /// [`take_prefetched`](Self::take_prefetched) is called for it.
+    fn request_frame(&self, node: &Node<'_>, n: usize) {
+        let send = self.prefetch_send.clone();
+        node.get_frame_async(n, move |frame, n, _| {
+            // SAFETY: The frame is only extended to `'static` so it can be stored
+            // in the decoder. It is freed before the environment owning the core,
+            // see the field order of `VapoursynthDecoder`.
+            let frame = frame
+                .ok()
+                .map(|frame| unsafe { transmute::<FrameRef<'_>, FrameRef<'static>>(frame) });
+            // The decoder may already be gone, in which case the frame is dropped.
+            let _ = send.send((n, frame));
+        });
+    }
+
+    /// Returns frame `n` if it has been prefetched, waiting for it to be rendered.
+    ///
+    /// Returns `None` if the frame was not prefetched or rendering it failed.
+    fn take_prefetched(&mut self, n: usize) -> Option<FrameRef<'static>> {
+        // The first frame is always read synchronously
+        if n == 0 || n >= self.requested_until {
+            return None;
+        }
+        loop {
+            if let Some(frame) = self.prefetched.remove(&n) {
+                return Some(frame);
+            }
+            // Every request sends exactly one message, and a sender is kept in `self`
+            let (m, frame) = self.prefetch_recv.recv().ok()?;
+            match frame {
+                Some(frame) => {
+                    self.prefetched.insert(m, frame);
+                }
+                None if m == n => return None,
+                None => (),
+            }
+        }
     }
 }
 
@@ -117,11 +223,18 @@ impl Decoder for VapoursynthDecoder {
         );
 
         {
-            let frame = self.get_node().unwrap().get_frame(self.cur_frame);
-            if frame.is_err() {
-                return None;
+            let prefetch = self.frames_to_prefetch();
+            {
+                let node = self.get_node().unwrap();
+                for n in prefetch {
+                    self.request_frame(&node, n);
+                }
             }
-            let frame = frame.unwrap();
+            let frame = match self.take_prefetched(self.cur_frame) {
+                Some(frame) => frame,
+                // Frames which failed to render asynchronously are retried here
+                None => self.get_node().unwrap().get_frame(self.cur_frame).ok()?,
+            };
             match size_of::<T>() {
                 1 => {
                     for (out_row, in_row) in f.planes[0]
@@ -197,14 +310,23 @@ impl Decoder for VapoursynthDecoder {
     fn get_video_details(&self) -> VideoDetails {
         let format = self.get_format().unwrap();
         let res = self.get_resolution().unwrap();
-        let fps = self.get_frame_rate().unwrap();
+        let (time_base, frame_rate) = match self.get_node().unwrap().info().framerate {
+            Property::Constant(fps) => (
+                Rational::new(fps.denominator, fps.numerator),
+                Some(Rational::new(fps.numerator, fps.denominator)),
+            ),
+            // Clips with a variable frame rate only store the duration of each frame
+            // in its properties
+            Property::Variable => (VideoDetails::default().time_base, None),
+        };
+        // Other subsampling is rejected when the decoder is created
         let chroma = match (
             format.color_family(),
-            format.sub_sampling_w() + format.sub_sampling_h(),
+            (format.sub_sampling_w(), format.sub_sampling_h()),
         ) {
             (ColorFamily::Gray, _) => ChromaSampling::Cs400,
-            (_, 0) => ChromaSampling::Cs444,
-            (_, 1) => ChromaSampling::Cs422,
+            (_, (0, 0)) => ChromaSampling::Cs444,
+            (_, (1, 0)) => ChromaSampling::Cs422,
             _ => ChromaSampling::Cs420,
         };
         VideoDetails {
@@ -213,8 +335,17 @@ impl Decoder for VapoursynthDecoder {
             bit_depth: format.bits_per_sample() as usize,
             chroma_sampling: chroma,
             chroma_sample_position: av_metrics::video::ChromaSamplePosition::Unknown,
-            time_base: Rational::new(fps.denominator, fps.numerator),
+            time_base,
+            frame_rate,
+            transfer_characteristics: av_metrics::video::TransferCharacteristics::Unknown,
+            // These are only stored in the properties of each frame
+            field_order: None,
+            sample_aspect_ratio: None,
             luma_padding: 0,
         }
     }
+
+    fn total_frames(&self) -> Option<usize> {
+        self.get_frame_count().ok()
+    }
 }
@@ -1,20 +1,315 @@
 use av_metrics::video::decode::*;
 use av_metrics::video::*;
+use av_metrics::MetricsError;
 use std::fs::File;
-use std::io::{stdin, BufReader, Read, Stdin};
+use std::io::{self, stdin, BufRead, BufReader, ErrorKind, Read, Stdin};
 use std::path::Path;
 
 /// A decoder for a y4m input stream
+///
+/// The input only needs to implement `Read`, so pipes and other
+/// non-seekable streams are supported.
+///
+/// Frame headers may carry parameters after the `FRAME` marker,
+/// which can be read with [`Y4MDecoder::last_frame_params`].
+///
+/// Several y4m streams concatenated into one input are read as a single stream
+/// as long as their resolution and color space match the first one.
+/// Decoding stops cleanly before the first stream which does not match.
 pub struct Y4MDecoder<R: Read + Send> {
-    inner: y4m::Decoder<R>,
+    inner: y4m::Decoder<ConcatRead<RetryRead<R>>>,
+    total_frames: Option<usize>,
+    frame_params: Option<Vec<u8>>,
+    /// Opens the input again, if it is a file.
+    reopen: Option<Box<dyn Fn() -> io::Result<R> + Send>>,
+    /// The error which stopped the last call to `read_video_frame`
+    error: Option<MetricsError>,
 }
 
-/// Function to map y4m color space
-fn map_y4m_color_space(color_space: y4m::Colorspace) -> (ChromaSampling, ChromaSamplePosition) {
+impl<R: Read + Send> Y4MDecoder<R> {
+    /// Returns the raw parameters of the header of the frame returned by the last call
+    /// to [`Decoder::read_video_frame`], e.g. `b"Ip XCOLORRANGE=FULL"`, or `None` if
+    /// the header has no parameters.
+    pub fn last_frame_params(&self) -> Option<&[u8]> {
+        self.frame_params.as_deref()
+    }
+
+    /// Returns the value of the parameter with the given tag in the header of the frame
+    /// returned by the last call to [`Decoder::read_video_frame`].
+    ///
+    /// Each parameter starts with a single tag character, which is followed by its
+    /// value, e.g. the value of the interlacing parameter `Ip` is `b"p"`.
+    /// Vendor extensions use the tag `X`.
+    pub fn last_frame_param(&self, tag: u8) -> Option<&[u8]> {
+        find_param(self.last_frame_params()?, tag)
+    }
+
+    /// Returns the order of the fields if the stream header marks the video as
+    /// interlaced with `It` or `Ib`, or `None` for progressive or mixed video.
+    ///
+    /// This is also reported in [`VideoDetails::field_order`].
+    pub fn field_order(&self) -> Option<FieldOrder> {
+        match find_param(self.inner.get_raw_params(), b'I')? {
+            b"t" => Some(FieldOrder::TopFieldFirst),
+            b"b" => Some(FieldOrder::BottomFieldFirst),
+            _ => None,
+        }
+    }
+
+    /// Returns the ratio of the width to the height of a pixel from the `A` parameter
+    /// of the stream header, or `None` if it is missing or `A0:0` (unknown).
+    ///
+    /// This is also reported in [`VideoDetails::sample_aspect_ratio`].
+    pub fn sample_aspect_ratio(&self) -> Option<Rational> {
+        find_param(self.inner.get_raw_params(), b'A')?;
+        let aspect = self.inner.get_pixel_aspect();
+        (aspect.num != 0 && aspect.den != 0)
+            .then(|| Rational::new(aspect.num as u64, aspect.den as u64))
+    }
+
+    /// Wraps the decoder so that each field of an interlaced stream is returned as a
+    /// separate frame of half the height, in the order given by the stream header.
+    ///
+    /// This compares the videos field by field, instead of mixing the fields into
+    /// one progressive frame. Returns an error if the stream is not marked as
+    /// interlaced, or if the height of a plane is odd.
+    pub fn split_fields(self) -> Result<DeinterlaceDecoder<Self>, MetricsError> {
+        let field_order = self.field_order().ok_or(MetricsError::UnsupportedInput {
+            reason: "The y4m stream is not interlaced",
+        })?;
+        DeinterlaceDecoder::new(self, Deinterlace::SeparateFields, field_order)
+    }
+}
+
+/// Returns the value of the parameter with the given tag in a y4m header.
+fn find_param(params: &[u8], tag: u8) -> Option<&[u8]> {
+    params
+        .split(|&b| b == b' ')
+        .find(|param| param.first() == Some(&tag))
+        .map(|param| &param[1..])
+}
+
+/// Retries reads which were interrupted before any data was read.
+///
+/// The y4m parser reads frame headers one byte at a time and treats
+/// every error as the end of the stream, which could cut off input
+/// coming from a pipe.
+struct RetryRead<R: Read>(R);
+
+impl<R: Read> Read for RetryRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.0.read(buf) {
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                result => return result,
+            }
+        }
+    }
+}
+
+/// The properties which must match for concatenated y4m streams to be read as one.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct StreamFormat {
+    width: usize,
+    height: usize,
+    bit_depth: usize,
+    color_space: (ChromaSampling, ChromaSamplePosition),
+}
+
+impl StreamFormat {
+    /// Parses a stream header line, returning the format and the length of the frame data
+    fn parse(header: &[u8]) -> Option<(Self, usize)> {
+        let decoder = y4m::Decoder::new(header).ok()?;
+        let (width, height) = (decoder.get_width(), decoder.get_height());
+        let color_space = map_y4m_color_space(decoder.get_colorspace())?;
+        let (chroma_width, chroma_height) = color_space.0.get_chroma_dimensions(width, height);
+        let frame_len =
+            (width * height + 2 * chroma_width * chroma_height) * decoder.get_bytes_per_sample();
+        let format = StreamFormat {
+            width,
+            height,
+            bit_depth: decoder.get_bit_depth(),
+            color_space,
+        };
+        Some((format, frame_len))
+    }
+}
+
+/// Returned by [`ConcatRead`] if the input ends within a frame.
+///
+/// The y4m parser treats an [`ErrorKind::UnexpectedEof`] as the regular end of
+/// the stream, so this is wrapped in an [`ErrorKind::InvalidData`] error instead.
+#[derive(Debug)]
+struct TruncatedFrame;
+
+impl std::fmt::Display for TruncatedFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("y4m stream ends in the middle of a frame")
+    }
+}
+
+impl std::error::Error for TruncatedFrame {}
+
+/// Where [`ConcatRead`] is in the y4m stream
+enum ConcatState {
+    /// Reading the header of the first stream
+    StreamHeader(Vec<u8>),
+    /// At the start of the next frame header, or a following stream header
+    FrameStart,
+    /// Within a frame header
+    FrameHeader,
+    /// Within the frame data, with the given number of bytes left
+    FrameData(usize),
+    /// The first stream header could not be parsed, so the y4m parser reports the error
+    Passthrough,
+    /// At a stream which does not match the first one
+    End,
+}
+
+/// Skips the headers of further y4m streams concatenated to the first one,
+/// so that the y4m parser reads all of their frames as one stream.
+///
+/// The y4m parser would fail on such a header, as it expects a frame header.
+/// A stream whose format differs from the first one ends the input instead,
+/// as its frames cannot be returned by the same decoder.
+struct ConcatRead<R: Read> {
+    inner: R,
+    state: ConcatState,
+    /// The format of the first stream, and the length of its frame data
+    format: Option<(StreamFormat, usize)>,
+    /// Bytes read ahead to check for a stream header, starting at `pending_pos`
+    pending: Vec<u8>,
+    pending_pos: usize,
+}
+
+impl<R: Read> ConcatRead<R> {
+    const STREAM_MAGIC: &'static [u8] = b"YUV4MPEG2 ";
+
+    fn new(inner: R) -> Self {
+        ConcatRead {
+            inner,
+            state: ConcatState::StreamHeader(Vec::new()),
+            format: None,
+            pending: Vec::new(),
+            pending_pos: 0,
+        }
+    }
+
+    /// Reads from the bytes read ahead first, then from the inner reader
+    fn read_raw(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending_pos < self.pending.len() {
+            let pending = &self.pending[self.pending_pos..];
+            let len = pending.len().min(buf.len());
+            buf[..len].copy_from_slice(&pending[..len]);
+            self.pending_pos += len;
+            return Ok(len);
+        }
+        self.inner.read(buf)
+    }
+
+    /// Reads ahead far enough to recognize a stream header, and skips it
+    /// if its format matches the first stream.
+    ///
+    /// Returns `false` if a stream with a different format follows.
+    fn skip_stream_headers(&mut self) -> io::Result<bool> {
+        loop {
+            let mut ahead = std::mem::take(&mut self.pending);
+            ahead.drain(..self.pending_pos);
+            self.pending_pos = 0;
+            while ahead.len() < Self::STREAM_MAGIC.len() {
+                let mut byte = [0];
+                if self.inner.read(&mut byte)? == 0 {
+                    break;
+                }
+                ahead.push(byte[0]);
+            }
+            if !ahead.starts_with(Self::STREAM_MAGIC) {
+                self.pending = ahead;
+                return Ok(true);
+            }
+            let mut header = ahead;
+            let mut byte = [0];
+            while header.last() != Some(&b'\n') && self.inner.read(&mut byte)? > 0 {
+                header.push(byte[0]);
+            }
+            let format = StreamFormat::parse(&header).map(|(format, _)| format);
+            if format.is_none() || format != self.format.map(|(format, _)| format) {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for ConcatRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.state {
+            ConcatState::StreamHeader(ref mut header) => {
+                // The y4m parser reads headers one byte at a time
+                let len = self.inner.read(&mut buf[..1])?;
+                header.extend_from_slice(&buf[..len]);
+                if header.last() == Some(&b'\n') {
+                    self.format = StreamFormat::parse(header);
+                    self.state = if self.format.is_some() {
+                        ConcatState::FrameStart
+                    } else {
+                        ConcatState::Passthrough
+                    };
+                }
+                Ok(len)
+            }
+            ConcatState::FrameStart => {
+                if !self.skip_stream_headers()? {
+                    self.state = ConcatState::End;
+                    return Ok(0);
+                }
+                if self.pending.is_empty() {
+                    // The regular end of the input
+                    return Ok(0);
+                }
+                self.state = ConcatState::FrameHeader;
+                self.read(buf)
+            }
+            ConcatState::FrameHeader => {
+                let len = self.read_raw(&mut buf[..1])?;
+                if len == 0 {
+                    return Err(io::Error::new(ErrorKind::InvalidData, TruncatedFrame));
+                }
+                if buf[0] == b'\n' {
+                    let (_, frame_len) = self.format.expect("format is parsed before frames");
+                    self.state = ConcatState::FrameData(frame_len);
+                }
+                Ok(len)
+            }
+            ConcatState::FrameData(left) => {
+                let len = buf.len().min(left);
+                let len = self.read_raw(&mut buf[..len])?;
+                if len == 0 {
+                    return Err(io::Error::new(ErrorKind::InvalidData, TruncatedFrame));
+                }
+                self.state = if left == len {
+                    ConcatState::FrameStart
+                } else {
+                    ConcatState::FrameData(left - len)
+                };
+                Ok(len)
+            }
+            ConcatState::Passthrough => self.read_raw(buf),
+            ConcatState::End => Ok(0),
+        }
+    }
+}
+
+/// Function to map y4m color space, or `None` if it is not supported
+fn map_y4m_color_space(
+    color_space: y4m::Colorspace,
+) -> Option<(ChromaSampling, ChromaSamplePosition)> {
     use av_metrics::video::ChromaSamplePosition::*;
     use av_metrics::video::ChromaSampling::*;
     use y4m::Colorspace::*;
-    match color_space {
+    Some(match color_space {
         Cmono | Cmono12 => (Cs400, Unknown),
         C420jpeg => (Cs420, Bilateral),
         C420paldv => (Cs420, Interpolated),
@@ -22,23 +317,82 @@ fn map_y4m_color_space(color_space: y4m::Colorspace) -> (ChromaSampling, ChromaS
         C420 | C420p10 | C420p12 => (Cs420, Colocated),
         C422 | C422p10 | C422p12 => (Cs422, Vertical),
         C444 | C444p10 | C444p12 => (Cs444, Colocated),
-        _ => unimplemented!(),
+        _ => return None,
+    })
+}
+
+/// Creates the y4m parser, rejecting color spaces which cannot be mapped
+fn new_inner<R: Read>(reader: R) -> Result<y4m::Decoder<ConcatRead<RetryRead<R>>>, MetricsError> {
+    let inner = y4m::Decoder::new(ConcatRead::new(RetryRead(reader)))
+        .map_err(|e| MetricsError::decode_error("Could not read the y4m header", e))?;
+    if map_y4m_color_space(inner.get_colorspace()).is_none() {
+        return Err(MetricsError::UnsupportedInput {
+            reason: "Unsupported y4m color space",
+        });
     }
+    Ok(inner)
 }
 
 /// Initialize a new Y4M decoder for a given input file
 pub fn new_decoder_from_file<P: AsRef<Path>>(
     input: P,
-) -> Result<Y4MDecoder<BufReader<File>>, String> {
-    let file = File::open(input).map_err(|e| e.to_string())?;
-    let inner = y4m::Decoder::new(BufReader::new(file)).map_err(|e| e.to_string())?;
-    Ok(Y4MDecoder { inner })
+) -> Result<Y4MDecoder<BufReader<File>>, MetricsError> {
+    let path = input.as_ref().to_path_buf();
+    let reopen = move || File::open(&path).map(BufReader::new);
+    let inner = new_inner(
+        reopen().map_err(|e| MetricsError::decode_error("Could not open the input file", e))?,
+    )?;
+    let total_frames = estimate_total_frames(input.as_ref(), &inner);
+    Ok(Y4MDecoder {
+        inner,
+        total_frames,
+        frame_params: None,
+        reopen: Some(Box::new(reopen)),
+        error: None,
+    })
+}
+
+/// Estimates the number of frames in a y4m file from its size,
+/// assuming that every frame header has the same length as the first one.
+fn estimate_total_frames<R: Read>(
+    input: &Path,
+    decoder: &y4m::Decoder<ConcatRead<RetryRead<R>>>,
+) -> Option<usize> {
+    let file_len = std::fs::metadata(input).ok()?.len() as usize;
+    let mut reader = BufReader::new(File::open(input).ok()?);
+    let mut header = Vec::new();
+    reader.read_until(b'\n', &mut header).ok()?;
+    // Frame headers may carry parameters after the `FRAME` marker
+    let mut frame_header = Vec::new();
+    reader.read_until(b'\n', &mut frame_header).ok()?;
+
+    let width = decoder.get_width();
+    let height = decoder.get_height();
+    let bytes = decoder.get_bytes_per_sample();
+    let (chroma_sampling, _) = map_y4m_color_space(decoder.get_colorspace())?;
+    let (chroma_width, chroma_height) = chroma_sampling.get_chroma_dimensions(width, height);
+    let frame_len =
+        frame_header.len() + (width * height + 2 * chroma_width * chroma_height) * bytes;
+
+    Some(file_len.saturating_sub(header.len()) / frame_len)
 }
 
 /// Initialize a new Y4M decoder from stdin
-pub fn new_decoder_from_stdin() -> Result<Y4MDecoder<BufReader<Stdin>>, String> {
+pub fn new_decoder_from_stdin() -> Result<Y4MDecoder<BufReader<Stdin>>, MetricsError> {
+    new_decoder_from_reader(BufReader::new(stdin()))
+}
+
+/// Initialize a new Y4M decoder reading from an arbitrary stream, e.g. the
+/// output of a child process
+///
+/// The number of frames is not known for such streams.
+pub fn new_decoder_from_reader<R: Read + Send>(reader: R) -> Result<Y4MDecoder<R>, MetricsError> {
     Ok(Y4MDecoder {
-        inner: y4m::Decoder::new(BufReader::new(stdin())).map_err(|e| e.to_string())?,
+        inner: new_inner(reader)?,
+        total_frames: None,
+        frame_params: None,
+        reopen: None,
+        error: None,
     })
 }
 
@@ -51,9 +405,11 @@ where
         let height = self.inner.get_height();
         let color_space = self.inner.get_colorspace();
         let bit_depth = color_space.get_bit_depth();
-        let (chroma_sampling, chroma_sample_position) = map_y4m_color_space(color_space);
+        let (chroma_sampling, chroma_sample_position) =
+            map_y4m_color_space(color_space).expect("color space is checked on creation");
         let framerate = self.inner.get_framerate();
         let time_base = Rational::new(framerate.den as u64, framerate.num as u64);
+        let frame_rate = Some(Rational::new(framerate.num as u64, framerate.den as u64));
         let luma_padding = 0;
 
         VideoDetails {
@@ -63,6 +419,11 @@ where
             chroma_sampling,
             chroma_sample_position,
             time_base,
+            frame_rate,
+            // y4m has no way to signal the transfer function
+            transfer_characteristics: TransferCharacteristics::Unknown,
+            field_order: self.field_order(),
+            sample_aspect_ratio: self.sample_aspect_ratio(),
             luma_padding,
         }
     }
@@ -70,15 +431,34 @@ where
     fn read_video_frame<T: Pixel>(&mut self) -> Option<Frame<T>> {
         let bit_depth = self.inner.get_bit_depth();
         let color_space = self.inner.get_colorspace();
-        let (chroma_sampling, chroma_sample_pos) = map_y4m_color_space(color_space);
+        let (chroma_sampling, chroma_sample_pos) =
+            map_y4m_color_space(color_space).expect("color space is checked on creation");
         let width = self.inner.get_width();
         let height = self.inner.get_height();
         let bytes = self.inner.get_bytes_per_sample();
-        self.inner.read_frame().ok().map(|frame| {
-            let mut f: Frame<T> = Frame::new_with_padding(width, height, chroma_sampling, 0);
+        self.frame_params = None;
+        self.error = None;
+        let frame = match self.inner.read_frame() {
+            Ok(frame) => frame,
+            Err(y4m::Error::EOF) => return None,
+            Err(y4m::Error::IoError(e))
+                if e.get_ref().is_some_and(|e| e.is::<TruncatedFrame>()) =>
+            {
+                self.error = Some(MetricsError::UnexpectedEof);
+                return None;
+            }
+            Err(e) => {
+                self.error = Some(MetricsError::decode_error("Could not read a y4m frame", e));
+                return None;
+            }
+        };
+        self.frame_params = frame.get_raw_params().map(<[u8]>::to_vec);
+        let mut f: Frame<T> = Frame::new_with_padding(width, height, chroma_sampling, 0);
 
-            let (chroma_width, _) = chroma_sampling.get_chroma_dimensions(width, height);
-            f.planes[0].copy_from_raw_u8(frame.get_y_plane(), width * bytes, bytes);
+        let (chroma_width, _) = chroma_sampling.get_chroma_dimensions(width, height);
+        f.planes[0].copy_from_raw_u8(frame.get_y_plane(), width * bytes, bytes);
+        // Monochrome video has empty chroma planes
+        if chroma_sampling != ChromaSampling::Cs400 {
             convert_chroma_data(
                 &mut f.planes[1],
                 chroma_sample_pos,
@@ -95,12 +475,42 @@ where
                 chroma_width * bytes,
                 bytes,
             );
+        }
 
-            f
-        })
+        Some(f)
     }
 
     fn get_bit_depth(&self) -> usize {
         self.inner.get_bit_depth()
     }
+
+    fn total_frames(&self) -> Option<usize> {
+        self.total_frames
+    }
+
+    /// Returns `true` for decoders created from a file, as the frames of a y4m file
+    /// all have the same size. Other streams, e.g. pipes, are not seekable.
+    fn can_seek(&self) -> bool {
+        self.reopen.is_some()
+    }
+
+    /// Rewinds decoders created from a file by opening the file again.
+    /// Other streams cannot be rewound.
+    fn reset(&mut self) -> Result<(), MetricsError> {
+        let reopen = self.reopen.as_ref().ok_or(MetricsError::UnsupportedInput {
+            reason: "Input stream cannot be rewound",
+        })?;
+        let reader =
+            reopen().map_err(|e| MetricsError::decode_error("Could not open the input file", e))?;
+        self.inner = new_inner(reader)?;
+        self.frame_params = None;
+        self.error = None;
+        Ok(())
+    }
+
+    /// Returns [`MetricsError::UnexpectedEof`] if the input ended within a frame,
+    /// instead of returning the partial frame.
+    fn take_error(&mut self) -> Option<MetricsError> {
+        self.error.take()
+    }
 }
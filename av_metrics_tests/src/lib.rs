@@ -1,22 +1,70 @@
 #[cfg(test)]
 mod tests {
-    use av_metrics::video::ciede::{calculate_video_ciede, calculate_video_ciede_nosimd};
-    use av_metrics::video::psnr::{calculate_video_apsnr, calculate_video_psnr};
-    use av_metrics::video::psnr_hvs::calculate_video_psnr_hvs;
-    use av_metrics::video::ssim::{calculate_video_msssim, calculate_video_ssim};
+    use av_metrics::video::ciede::{
+        calculate_frame_ciede, calculate_video_ciede, calculate_video_ciede_nosimd,
+        calculate_video_ciede_with_statistics,
+    };
+    use av_metrics::video::decode::{
+        BitDepthConvertDecoder, CancellableDecoder, CropDecoder, Decoder, DeinterlaceDecoder,
+        FrameSink, FrameStepDecoder, FrameType, Rational, ResizeDecoder, SkipFramesDecoder,
+        TeeDecoder, TimeRangeDecoder, VideoDetails, Y4mWriter,
+    };
+    use av_metrics::video::dynamic::{
+        calculate_batch, calculate_video_metric, calculate_video_metrics, from_name, BatchJob,
+        Metric, MetricResult, MetricResults,
+    };
+    use av_metrics::video::fusion::FusionMetric;
+    use av_metrics::video::psnr::{
+        calculate_alpha_psnr, calculate_frame_psnr, calculate_frame_psnr_tiled,
+        calculate_video_apsnr, calculate_video_psnr, calculate_video_psnr_by_frame_type,
+        calculate_video_psnr_hdr, calculate_video_psnr_hdr_with_transfer,
+        calculate_video_psnr_with_statistics, PsnrOptions,
+    };
+    use av_metrics::video::psnr_hvs::{
+        calculate_frame_psnr_hvs, calculate_frame_psnr_hvs_many, calculate_frame_psnr_hvs_map,
+        calculate_frame_psnr_hvs_nosimd, calculate_frame_psnr_hvs_with_coverage,
+        calculate_video_psnr_hvs, calculate_video_psnr_hvs_nosimd, PsnrHvsOptions,
+    };
+    use av_metrics::video::ssim::{
+        calculate_alpha_ssim, calculate_frame_msssim, calculate_frame_msssim_scales,
+        calculate_frame_ssim, calculate_frame_ssim_many, calculate_frame_ssim_map,
+        calculate_frame_ssim_rgb, calculate_frame_ssim_tiled, calculate_frame_ssim_weighted,
+        calculate_plane_msssim_u32, calculate_plane_ssim_u32, calculate_video_cs,
+        calculate_video_msssim, calculate_video_ssim, calculate_video_ssim_by_frame_type,
+        calculate_video_ssim_luma, calculate_video_ssim_rgb, calculate_video_ssim_with_statistics,
+        Accumulation, EdgeHandling, MsSsimOptions, Pooling, SsimConfig, SsimOptions,
+    };
+    use av_metrics::video::temporal::calculate_video_temporal_consistency;
+    use av_metrics::video::verdict::{Criterion, LosslessThresholds, Verdict};
+    use av_metrics::video::{
+        calculate_yuv_metrics, crop_frame, deinterlace_frame, frame_from_packed_yuv,
+        frame_from_planes, frame_from_yuv_bytes, frame_hash, frames_identical, plane_from_slice,
+        plane_hash, resize_frame, upsample_chroma, upsample_chroma_plane, write_frames_csv,
+        CastFromPrimitive, ChromaSamplePosition, ChromaSampling, ChromaWeights, Deinterlace,
+        FieldOrder, Frame, FrameLayout, PackedFormat, Pixel, PlanarMetrics, Plane, PlaneSelection,
+        Rect, ResizeFilter, TransferCharacteristics,
+    };
+    use av_metrics::MetricsError;
     #[cfg(feature = "ffmpeg")]
     use av_metrics_decoders::FfmpegDecoder;
+    use av_metrics_decoders::GeneratorDecoder;
     #[cfg(not(feature = "ffmpeg"))]
-    use av_metrics_decoders::Y4MDecoder;
+    use av_metrics_decoders::{y4m::new_decoder_from_file, Y4MDecoder};
+    #[cfg(not(feature = "ffmpeg"))]
+    use std::fs::File;
+    #[cfg(not(feature = "ffmpeg"))]
+    use std::io::BufReader;
     use std::path::Path;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
 
     #[cfg(not(feature = "ffmpeg"))]
-    fn get_decoder<P: AsRef<Path>>(input: P) -> Result<Y4MDecoder, String> {
-        Y4MDecoder::new(input)
+    fn get_decoder<P: AsRef<Path>>(input: P) -> Result<Y4MDecoder<BufReader<File>>, MetricsError> {
+        new_decoder_from_file(input)
     }
 
     #[cfg(feature = "ffmpeg")]
-    fn get_decoder<P: AsRef<Path>>(input: P) -> Result<FfmpegDecoder, String> {
+    fn get_decoder<P: AsRef<Path>>(input: P) -> Result<FfmpegDecoder, MetricsError> {
         FfmpegDecoder::new(input)
     }
 
@@ -97,83 +145,2757 @@ mod tests {
     }
 
     #[test]
-    fn apsnr_yuv420p8() {
-        let mut dec1 = get_decoder(format!(
+    fn psnr_hdr_yuv420p10() {
+        let open = |name: &str| {
+            get_decoder(format!(
+                "{}/../testfiles/yuv420p10_{}.y4m",
+                env!("CARGO_MANIFEST_DIR"),
+                name
+            ))
+            .unwrap()
+        };
+        // y4m does not signal the transfer function
+        assert!(matches!(
+            calculate_video_psnr_hdr(&mut open("input"), &mut open("output"), None, |_| ())
+                .map_err(|err| err.downcast::<MetricsError>().map(|err| *err)),
+            Err(Ok(MetricsError::UnsupportedInput { .. }))
+        ));
+
+        // For PQ, this is the luma PSNR minus 20 * log10(1023 / 876) for the limited range
+        for (transfer, expected) in [
+            (TransferCharacteristics::Pq, 31.1965),
+            (TransferCharacteristics::Hlg, 33.6461),
+            (TransferCharacteristics::Sdr, 35.7333),
+        ] {
+            let result = calculate_video_psnr_hdr_with_transfer(
+                &mut open("input"),
+                &mut open("output"),
+                None,
+                transfer,
+                |_| (),
+            )
+            .unwrap();
+            assert_metric_eq(expected, result.y);
+            assert_metric_eq(expected, result.avg);
+            assert!(result.u.is_nan() && result.v.is_nan());
+        }
+
+        let result = calculate_video_psnr_hdr_with_transfer(
+            &mut open("input"),
+            &mut open("input"),
+            None,
+            TransferCharacteristics::Pq,
+            |_| (),
+        )
+        .unwrap();
+        assert_metric_eq(100.0, result.y);
+    }
+
+    #[test]
+    fn psnr_yuv420p8_frame_step() {
+        let dec1 = get_decoder(format!(
             "{}/../testfiles/yuv420p8_input.y4m",
             env!("CARGO_MANIFEST_DIR")
         ))
         .unwrap();
-        let mut dec2 = get_decoder(format!(
+        let dec2 = get_decoder(format!(
             "{}/../testfiles/yuv420p8_output.y4m",
             env!("CARGO_MANIFEST_DIR")
         ))
         .unwrap();
-        let result = calculate_video_apsnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
-        assert_metric_eq(32.5450, result.y);
-        assert_metric_eq(36.4087, result.u);
-        assert_metric_eq(39.8244, result.v);
-        assert_metric_eq(33.6995, result.avg);
+        let mut dec1 = FrameStepDecoder::new(dec1, 2);
+        let mut dec2 = FrameStepDecoder::new(dec2, 2);
+        let result = calculate_video_psnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(32.5833, result.y);
+        assert_metric_eq(36.4048, result.u);
+        assert_metric_eq(39.8620, result.v);
+        assert_metric_eq(33.7354, result.avg);
     }
 
     #[test]
-    fn apsnr_yuv422p8() {
-        let mut dec1 = get_decoder(format!(
-            "{}/../testfiles/yuv422p8_input.y4m",
+    fn frame_preprocess() {
+        let open = || {
+            let path = |name: &str| {
+                format!(
+                    "{}/../testfiles/yuv420p8_{}.y4m",
+                    env!("CARGO_MANIFEST_DIR"),
+                    name
+                )
+            };
+            (
+                get_decoder(path("input")).unwrap(),
+                get_decoder(path("output")).unwrap(),
+            )
+        };
+
+        // Leaving out every other pair gives the same frames as a frame step of 2
+        let pairs = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&pairs);
+        let options = PsnrOptions::new().preprocess(move |_: &mut Frame<u8>, _: &mut Frame<u8>| {
+            counter.fetch_add(1, Ordering::Relaxed).is_multiple_of(2)
+        });
+        let (mut dec1, mut dec2) = open();
+        let result = options
+            .calculate_video(&mut dec1, &mut dec2, None, |_| ())
+            .unwrap();
+        assert_metric_eq(32.5833, result.y);
+        assert_metric_eq(36.4048, result.u);
+        assert_metric_eq(39.8620, result.v);
+        assert_metric_eq(33.7354, result.avg);
+        assert_eq!(3, pairs.load(Ordering::Relaxed));
+
+        // The frames can be modified before they are compared
+        let (mut dec1, mut dec2) = open();
+        let result = SsimOptions::new()
+            .preprocess(|frame1: &mut Frame<u8>, frame2: &mut Frame<u8>| {
+                *frame2 = frame1.clone();
+                true
+            })
+            .calculate_video(&mut dec1, &mut dec2, None, |_| ())
+            .unwrap();
+        assert!(result.avg.is_infinite());
+
+        // The pixel type of the closure must match the bit depth
+        let (mut dec1, mut dec2) = open();
+        let result = PsnrHvsOptions::new()
+            .preprocess(|_: &mut Frame<u16>, _: &mut Frame<u16>| true)
+            .calculate_video(&mut dec1, &mut dec2, None, |_| ());
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<MetricsError>(),
+            Some(MetricsError::UnsupportedInput { .. })
+        ));
+    }
+
+    #[test]
+    fn skip_frames_decoder() {
+        let path = format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let frames: Vec<_> = get_decoder(&path).unwrap().frames::<u8>().collect();
+        let mut dec = SkipFramesDecoder::new(get_decoder(&path).unwrap(), 1);
+        let skipped: Vec<_> = dec.frames::<u8>().collect();
+        assert_eq!(frames.len() - 1, skipped.len());
+        assert!(frames[1..]
+            .iter()
+            .zip(&skipped)
+            .all(|(frame1, frame2)| frames_identical(frame1, frame2)));
+
+        let mut dec1 = SkipFramesDecoder::new(get_decoder(&path).unwrap(), 10);
+        let mut dec2 = SkipFramesDecoder::new(get_decoder(&path).unwrap(), 10);
+        let result = calculate_video_psnr(&mut dec1, &mut dec2, None, |_| ());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn generator_decoder() {
+        // Each frame is flat, and the second video is brighter by the frame index
+        let flat_frame = |value: u16| {
+            let mut frame = Frame::new_with_padding(64, 48, ChromaSampling::Cs420, 0);
+            for plane in &mut frame.planes {
+                plane.rows_iter_mut().flatten().for_each(|pix| *pix = value);
+            }
+            frame
+        };
+        let details = VideoDetails::new(64, 48, 10, ChromaSampling::Cs420);
+        let generator = |step: u16| move |i: usize| flat_frame(512 + step * i as u16);
+        let mut dec1 = GeneratorDecoder::new(generator(0), details, 3);
+        let mut dec2 = GeneratorDecoder::new(generator(1), details, 3);
+        assert_eq!(dec1.total_frames(), Some(3));
+
+        let psnr = calculate_video_psnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        // The mean squared error over all frames is (0 + 1 + 4) / 3
+        let expected = 10.0 * (1023.0f64 * 1023.0 / (5.0 / 3.0)).log10();
+        assert_metric_eq(expected, psnr.y);
+        assert_metric_eq(expected, psnr.avg);
+
+        dec2.reset().unwrap();
+        let frames: Vec<Frame<u16>> = dec2.frames().collect();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[2].planes[1].p(0, 0), 514);
+    }
+
+    #[test]
+    fn frame_error_stops_decoding() {
+        // The second video switches to a different resolution, which fails every
+        // frame after it, while the decoder could still fill many more frames.
+        let frame = |i: usize, size: usize| {
+            let mut frame = Frame::<u8>::new_with_padding(size, size, ChromaSampling::Cs420, 0);
+            frame.planes[0].data[0] = i as u8;
+            frame
+        };
+        let details = VideoDetails::new(16, 16, 8, ChromaSampling::Cs420);
+        let sized =
+            |changed_at: usize| move |i: usize| frame(i, if i >= changed_at { 32 } else { 16 });
+        let mut dec1 = GeneratorDecoder::new(sized(usize::MAX), details, 1000);
+        let mut dec2 = GeneratorDecoder::new(sized(3), details, 1000);
+        let result = calculate_video_ssim(&mut dec1, &mut dec2, None, |_| ());
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<MetricsError>(),
+            Some(MetricsError::ProcessError { .. })
+        ));
+    }
+
+    #[test]
+    #[cfg(not(feature = "ffmpeg"))]
+    fn fusion_metric() {
+        let open = || {
+            let path =
+                |name: &str| format!("{}/../testfiles/{}.y4m", env!("CARGO_MANIFEST_DIR"), name);
+            (
+                get_decoder(path("yuv420p8_input")).unwrap(),
+                get_decoder(path("yuv420p8_output")).unwrap(),
+            )
+        };
+        let fusion = FusionMetric::new([(Metric::Psnr, 0.5), (Metric::Ssim, 2.0)]).with_bias(-3.0);
+        let (mut dec1, mut dec2) = open();
+        let result = fusion
+            .calculate_video(&mut dec1, &mut dec2, None, |_| ())
+            .unwrap();
+
+        let (mut dec1, mut dec2) = open();
+        let mut frames = 0;
+        while let (Some(frame1), Some(frame2)) =
+            (dec1.read_video_frame::<u8>(), dec2.read_video_frame::<u8>())
+        {
+            let psnr = calculate_frame_psnr(&frame1, &frame2, 8, ChromaSampling::Cs420).unwrap();
+            let ssim = calculate_frame_ssim(&frame1, &frame2, 8, ChromaSampling::Cs420).unwrap();
+            let expected = 0.5 * psnr.avg + 2.0 * ssim.avg - 3.0;
+            assert_metric_eq(expected, result.frames[frames]);
+            frames += 1;
+        }
+        assert_eq!(frames, result.frames.len());
+        let mean = result.frames.iter().sum::<f64>() / frames as f64;
+        assert_metric_eq(mean, result.score);
+    }
+
+    #[test]
+    fn time_range_decoder() {
+        let numbered_frame = |i: usize| {
+            let mut frame = Frame::<u8>::new_with_padding(16, 16, ChromaSampling::Cs420, 0);
+            frame.planes[0].data[0] = i as u8;
+            frame
+        };
+        let details = VideoDetails::new(16, 16, 8, ChromaSampling::Cs420);
+        let decoder = |details| GeneratorDecoder::new(numbered_frame, details, 30);
+        assert!(TimeRangeDecoder::new(decoder(details), 0.5, None).is_err());
+
+        let details = details.with_frame_rate(Rational::new(10, 1));
+        let mut dec = TimeRangeDecoder::new(decoder(details), 0.5, Some(1.5)).unwrap();
+        assert_eq!(dec.total_frames(), Some(10));
+        let frames: Vec<_> = dec
+            .frames::<u8>()
+            .map(|frame| frame.planes[0].p(0, 0))
+            .collect();
+        assert_eq!(frames, (5..15).collect::<Vec<_>>());
+
+        let mut dec = TimeRangeDecoder::new(decoder(details), 2.0, None).unwrap();
+        assert_eq!(dec.frames::<u8>().count(), 10);
+        assert!(TimeRangeDecoder::new(decoder(details), 1.0, Some(1.02)).is_err());
+        assert!(TimeRangeDecoder::new(decoder(details), -1.0, None).is_err());
+    }
+
+    #[test]
+    #[cfg(not(feature = "ffmpeg"))]
+    fn tee_decoder() {
+        for name in ["yuv420p8_input", "yuv420p10_input"] {
+            let path = format!("{}/../testfiles/{}.y4m", env!("CARGO_MANIFEST_DIR"), name);
+            let frames: Vec<_> = get_decoder(&path).unwrap().frames::<u16>().collect();
+            let dec = get_decoder(&path).unwrap();
+            let details = dec.get_video_details();
+            let mut dec = TeeDecoder::new(dec, Y4mWriter::new(Vec::new()));
+            assert!(frames
+                .iter()
+                .zip(dec.frames::<u16>())
+                .all(|(frame1, frame2)| frames_identical(frame1, &frame2)));
+            assert_eq!(details.bit_depth, dec.get_video_details().bit_depth);
+
+            let data = dec.finish().unwrap().into_inner();
+            let mut dec = av_metrics_decoders::y4m::new_decoder_from_reader(&data[..]).unwrap();
+            assert_eq!(details.bit_depth, dec.get_bit_depth());
+            let written: Vec<_> = dec.frames::<u16>().collect();
+            assert_eq!(frames.len(), written.len());
+            assert!(frames
+                .iter()
+                .zip(&written)
+                .all(|(frame1, frame2)| frames_identical(frame1, frame2)));
+        }
+    }
+
+    #[test]
+    fn y4m_writer_formats() {
+        let write = |bit_depth, cs| {
+            let details = VideoDetails::new(64, 48, bit_depth, cs);
+            let mut frame = Frame::<u16>::new_with_padding(64, 48, cs, 0);
+            // Monochrome frames have empty chroma planes
+            for plane in frame.planes.iter_mut().filter(|plane| plane.cfg.width > 0) {
+                let width = plane.cfg.width;
+                for (y, row) in plane.rows_iter_mut().enumerate() {
+                    for (x, pix) in row[..width].iter_mut().enumerate() {
+                        *pix = ((x * 37 + y * 11) % (1 << bit_depth)) as u16;
+                    }
+                }
+            }
+            let mut writer = Y4mWriter::new(Vec::new());
+            writer
+                .write_frame(&frame, &details)
+                .map(|_| (frame, writer.into_inner()))
+        };
+
+        for (bit_depth, cs) in [
+            (10, ChromaSampling::Cs420),
+            (12, ChromaSampling::Cs444),
+            (8, ChromaSampling::Cs400),
+            (12, ChromaSampling::Cs400),
+        ] {
+            let (frame, data) = write(bit_depth, cs).unwrap();
+            let mut dec = av_metrics_decoders::y4m::new_decoder_from_reader(&data[..]).unwrap();
+            assert_eq!(bit_depth, dec.get_bit_depth());
+            assert_eq!(cs, dec.get_video_details().chroma_sampling);
+            assert!(frames_identical(
+                &frame,
+                &dec.read_video_frame::<u16>().unwrap()
+            ));
+        }
+
+        // y4m has no colorspace for these formats
+        for (bit_depth, cs) in [
+            (10, ChromaSampling::Cs400),
+            (16, ChromaSampling::Cs400),
+            (16, ChromaSampling::Cs420),
+            (16, ChromaSampling::Cs444),
+        ] {
+            let error = write(bit_depth, cs).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+            assert!(matches!(
+                error.get_ref().unwrap().downcast_ref::<MetricsError>(),
+                Some(MetricsError::UnsupportedInput { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn y4m_writer_chroma_sample_position() {
+        let input = std::fs::read(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
             env!("CARGO_MANIFEST_DIR")
         ))
         .unwrap();
-        let mut dec2 = get_decoder(format!(
-            "{}/../testfiles/yuv422p8_output.y4m",
+        let mut dec = av_metrics_decoders::y4m::new_decoder_from_reader(&input[..]).unwrap();
+        let frame = dec.read_video_frame::<u8>().unwrap();
+        for position in [
+            ChromaSamplePosition::Colocated,
+            ChromaSamplePosition::Vertical,
+            ChromaSamplePosition::Interpolated,
+            ChromaSamplePosition::Bilateral,
+        ] {
+            let details = VideoDetails {
+                chroma_sample_position: position,
+                ..dec.get_video_details()
+            };
+            let mut writer = Y4mWriter::new(Vec::new());
+            writer.write_frame(&frame, &details).unwrap();
+            let written = writer.into_inner();
+            let dec = av_metrics_decoders::y4m::new_decoder_from_reader(&written[..]).unwrap();
+            assert_eq!(details, dec.get_video_details());
+        }
+    }
+
+    /// Returns the frames of a y4m file without the stream and frame headers.
+    #[cfg(feature = "raw")]
+    fn y4m_to_raw(data: &[u8]) -> Vec<u8> {
+        let dec = av_metrics_decoders::y4m::new_decoder_from_reader(data).unwrap();
+        let details = dec.get_video_details();
+        let header_end = data.iter().position(|&b| b == b'\n').unwrap() + 1;
+        let mut data = &data[header_end..];
+        let bytes = if details.bit_depth > 8 { 2 } else { 1 };
+        let (chroma_width, chroma_height) = details
+            .chroma_sampling
+            .get_chroma_dimensions(details.width, details.height);
+        let frame_size =
+            (details.width * details.height + 2 * chroma_width * chroma_height) * bytes;
+        let mut raw = Vec::new();
+        while !data.is_empty() {
+            let frame_start = data.iter().position(|&b| b == b'\n').unwrap() + 1;
+            raw.extend_from_slice(&data[frame_start..][..frame_size]);
+            data = &data[frame_start + frame_size..];
+        }
+        raw
+    }
+
+    #[test]
+    #[cfg(all(feature = "raw", not(feature = "ffmpeg")))]
+    fn raw_y4m_round_trip() {
+        for name in ["yuv420p8_input", "yuv444p8_output", "yuv420p10_input"] {
+            let path = format!("{}/../testfiles/{}.y4m", env!("CARGO_MANIFEST_DIR"), name);
+            let details = get_decoder(&path).unwrap().get_video_details();
+            let frames: Vec<_> = get_decoder(&path).unwrap().frames::<u16>().collect();
+
+            let raw = y4m_to_raw(&std::fs::read(&path).unwrap());
+            let mut dec = av_metrics_decoders::RawDecoder::new(
+                &raw[..],
+                details.width,
+                details.height,
+                details.bit_depth,
+                details.chroma_sampling,
+            )
+            .unwrap();
+            let decoded: Vec<_> = dec.frames::<u16>().collect();
+            assert_eq!(frames.len(), decoded.len());
+            assert!(frames
+                .iter()
+                .zip(&decoded)
+                .all(|(frame1, frame2)| frames_identical(frame1, frame2)));
+            assert!(dec.take_error().is_none());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "raw")]
+    fn raw_truncated_frame() {
+        let path = format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let raw = y4m_to_raw(&std::fs::read(path).unwrap());
+        let frame_size = 640 * 360 * 3 / 2;
+        let truncated = &raw[..2 * frame_size - 10];
+        let mut dec =
+            av_metrics_decoders::RawDecoder::new(truncated, 640, 360, 8, ChromaSampling::Cs420)
+                .unwrap();
+        assert!(dec.read_video_frame::<u8>().is_some());
+        assert!(dec.read_video_frame::<u8>().is_none());
+        assert!(matches!(
+            dec.take_error(),
+            Some(MetricsError::UnexpectedEof)
+        ));
+
+        // The metrics report the truncated frame instead of a result
+        let mut dec1 =
+            av_metrics_decoders::RawDecoder::new(truncated, 640, 360, 8, ChromaSampling::Cs420)
+                .unwrap();
+        let mut dec2 = av_metrics_decoders::RawDecoder::new(
+            &raw[..2 * frame_size],
+            640,
+            360,
+            8,
+            ChromaSampling::Cs420,
+        )
+        .unwrap();
+        let result = calculate_video_psnr(&mut dec1, &mut dec2, None, |_| ());
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<MetricsError>(),
+            Some(MetricsError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "raw")]
+    fn raw_16bit_little_endian() {
+        let data = [0x01, 0x00, 0xff, 0xff, 0x34, 0x12, 0x00, 0x80];
+        let mut dec =
+            av_metrics_decoders::RawDecoder::new(&data[..], 2, 2, 16, ChromaSampling::Cs400)
+                .unwrap();
+        let frame = dec.read_video_frame::<u16>().unwrap();
+        assert_eq!(&frame.planes[0].row(0)[..2], [0x0001, 0xffff]);
+        assert_eq!(&frame.planes[0].row(1)[..2], [0x1234, 0x8000]);
+        assert!(dec.read_video_frame::<u16>().is_none());
+        assert!(dec.take_error().is_none());
+    }
+
+    #[test]
+    #[cfg(not(feature = "ffmpeg"))]
+    fn y4m_interlaced_fields() {
+        let path = format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let progressive = std::fs::read(&path).unwrap();
+        let dec = av_metrics_decoders::y4m::new_decoder_from_reader(&progressive[..]).unwrap();
+        assert_eq!(dec.get_video_details().field_order, None);
+        assert!(dec.split_fields().is_err());
+
+        // Marking the same frames as interlaced splits each of them into two fields
+        let header_end = progressive.iter().position(|&b| b == b'\n').unwrap();
+        let header = String::from_utf8_lossy(&progressive[..header_end]).replace(" Ip ", " Ib ");
+        let interlaced = [header.as_bytes(), &progressive[header_end..]].concat();
+        let dec = av_metrics_decoders::y4m::new_decoder_from_reader(&interlaced[..]).unwrap();
+        let details = dec.get_video_details();
+        assert_eq!(details.field_order, Some(FieldOrder::BottomFieldFirst));
+
+        let frame = get_decoder(&path)
+            .unwrap()
+            .read_video_frame::<u8>()
+            .unwrap();
+        let fields = deinterlace_frame(
+            &frame,
+            Deinterlace::SeparateFields,
+            FieldOrder::BottomFieldFirst,
+        )
+        .unwrap();
+        let mut dec = dec.split_fields().unwrap();
+        assert_eq!(dec.get_video_details().height, details.height / 2);
+        assert_eq!(dec.get_video_details().field_order, None);
+        for field in &fields {
+            assert!(frames_identical(
+                field,
+                &dec.read_video_frame::<u8>().unwrap()
+            ));
+        }
+
+        // The writer keeps the field order
+        let mut writer = Y4mWriter::new(Vec::new());
+        writer.write_frame(&frame, &details).unwrap();
+        let written = writer.into_inner();
+        let dec = av_metrics_decoders::y4m::new_decoder_from_reader(&written[..]).unwrap();
+        assert_eq!(
+            dec.get_video_details().field_order,
+            Some(FieldOrder::BottomFieldFirst)
+        );
+    }
+
+    #[test]
+    fn y4m_sample_aspect_ratio() {
+        let path = |name: &str| format!("{}/../testfiles/{}.y4m", env!("CARGO_MANIFEST_DIR"), name);
+        // The output file marks the aspect ratio as unknown with `A0:0`
+        let details = get_decoder(path("yuv420p8_output"))
+            .unwrap()
+            .get_video_details();
+        assert_eq!(details.sample_aspect_ratio, None);
+        assert_eq!(details.display_aspect_ratio(), None);
+
+        let square = std::fs::read(path("yuv420p8_input")).unwrap();
+        let dec = av_metrics_decoders::y4m::new_decoder_from_reader(&square[..]).unwrap();
+        let details = dec.get_video_details();
+        assert_eq!(details.sample_aspect_ratio, Some(Rational::new(1, 1)));
+        assert_eq!(details.display_aspect_ratio(), Some(Rational::new(16, 9)));
+
+        let header_end = square.iter().position(|&b| b == b'\n').unwrap();
+        let header = String::from_utf8_lossy(&square[..header_end]).replace(" A1:1 ", " A4:3 ");
+        let anamorphic = [header.as_bytes(), &square[header_end..]].concat();
+        let mut dec = av_metrics_decoders::y4m::new_decoder_from_reader(&anamorphic[..]).unwrap();
+        let details = dec.get_video_details();
+        assert_eq!(details.sample_aspect_ratio, Some(Rational::new(4, 3)));
+        assert_eq!(details.display_aspect_ratio(), Some(Rational::new(64, 27)));
+
+        // The writer keeps the aspect ratio
+        let frame = dec.read_video_frame::<u8>().unwrap();
+        let mut writer = Y4mWriter::new(Vec::new());
+        writer.write_frame(&frame, &details).unwrap();
+        let written = writer.into_inner();
+        let dec = av_metrics_decoders::y4m::new_decoder_from_reader(&written[..]).unwrap();
+        assert_eq!(dec.sample_aspect_ratio(), Some(Rational::new(4, 3)));
+
+        // Resizing keeps the display aspect ratio
+        let dec = ResizeDecoder::new(dec, 320, 360, ResizeFilter::default()).unwrap();
+        let details = dec.get_video_details();
+        assert_eq!(details.sample_aspect_ratio, Some(Rational::new(8, 3)));
+        assert_eq!(details.display_aspect_ratio(), Some(Rational::new(64, 27)));
+    }
+
+    #[test]
+    fn frame_rate() {
+        let dec = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
             env!("CARGO_MANIFEST_DIR")
         ))
         .unwrap();
-        let result = calculate_video_apsnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
-        assert_metric_eq(38.6741, result.y);
-        assert_metric_eq(47.5219, result.u);
-        assert_metric_eq(48.8616, result.v);
-        assert_metric_eq(41.2191, result.avg);
+        let frame_rate = dec.get_video_details().frame_rate.unwrap();
+        assert_eq!((25, 1), (frame_rate.num, frame_rate.den));
+
+        // Only every second frame is returned, which halves the frame rate
+        let dec = FrameStepDecoder::new(dec, 2);
+        let details = dec.get_video_details();
+        let frame_rate = details.frame_rate.unwrap();
+        assert_eq!((25, 2), (frame_rate.num, frame_rate.den));
+        assert_eq!((2, 25), (details.time_base.num, details.time_base.den));
     }
 
     #[test]
-    fn apsnr_yuv444p8() {
-        let mut dec1 = get_decoder(format!(
-            "{}/../testfiles/yuv444p8_input.y4m",
+    #[cfg(not(feature = "ffmpeg"))]
+    fn y4m_frame_params() {
+        let input = format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let data = std::fs::read(&input).unwrap();
+        let header_len = data.iter().position(|&b| b == b'\n').unwrap() + 1;
+        let frame_len = b"FRAME\n".len() + 640 * 360 * 3 / 2;
+        let mut with_params = data[..header_len].to_vec();
+        for frame in data[header_len..].chunks(frame_len) {
+            with_params.extend_from_slice(b"FRAME Ip XTEST=1\n");
+            with_params.extend_from_slice(&frame[b"FRAME\n".len()..]);
+        }
+        let path = std::env::temp_dir().join("av_metrics_y4m_frame_params.y4m");
+        std::fs::write(&path, with_params).unwrap();
+
+        let mut dec1 = get_decoder(&input).unwrap();
+        let mut dec2 = get_decoder(&path).unwrap();
+        assert_eq!(Some(3), dec2.total_frames());
+        assert_eq!(None, dec2.last_frame_params());
+        let mut frames = 0;
+        while let Some(frame2) = dec2.read_video_frame::<u8>() {
+            let frame1 = dec1.read_video_frame::<u8>().unwrap();
+            assert!(frame1.planes == frame2.planes);
+            assert_eq!(Some(&b"Ip XTEST=1"[..]), dec2.last_frame_params());
+            assert_eq!(Some(&b"p"[..]), dec2.last_frame_param(b'I'));
+            assert_eq!(Some(&b"TEST=1"[..]), dec2.last_frame_param(b'X'));
+            assert_eq!(None, dec2.last_frame_param(b'A'));
+            frames += 1;
+        }
+        assert_eq!(3, frames);
+        assert_eq!(None, dec2.last_frame_params());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(not(feature = "ffmpeg"))]
+    fn y4m_concatenated_streams() {
+        let path = |name: &str| format!("{}/../testfiles/{}.y4m", env!("CARGO_MANIFEST_DIR"), name);
+        let read_frames = |data: Vec<u8>| {
+            let mut dec =
+                av_metrics_decoders::y4m::new_decoder_from_reader(std::io::Cursor::new(data))
+                    .unwrap();
+            std::iter::from_fn(|| dec.read_video_frame::<u8>()).collect::<Vec<_>>()
+        };
+        let input = std::fs::read(path("yuv420p8_input")).unwrap();
+        let output = std::fs::read(path("yuv420p8_output")).unwrap();
+
+        // Streams with the same format are read as one
+        let frames = read_frames([input.clone(), output.clone()].concat());
+        let expected = [read_frames(input.clone()), read_frames(output)].concat();
+        assert_eq!(6, frames.len());
+        assert!(frames
+            .iter()
+            .zip(&expected)
+            .all(|(frame1, frame2)| frame1.planes == frame2.planes));
+
+        // Decoding stops before a stream with a different format
+        let other = std::fs::read(path("yuv444p8_input")).unwrap();
+        let frames = read_frames([input.clone(), other.clone(), input].concat());
+        assert_eq!(3, frames.len());
+        assert!(frames
+            .iter()
+            .zip(&expected)
+            .all(|(frame1, frame2)| frame1.planes == frame2.planes));
+    }
+
+    #[test]
+    fn decoder_reset() {
+        let path = format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let mut dec = get_decoder(&path).unwrap();
+        let first = dec.read_video_frame::<u8>().unwrap();
+        while dec.read_video_frame::<u8>().is_some() {}
+        dec.reset().unwrap();
+        assert!(frames_identical(
+            &first,
+            &dec.read_video_frame::<u8>().unwrap()
+        ));
+
+        // Adapters rewind the decoder they wrap
+        let mut dec = FrameStepDecoder::new(get_decoder(&path).unwrap(), 2);
+        let frames: Vec<_> = dec.frames::<u8>().collect();
+        dec.reset().unwrap();
+        let again: Vec<_> = dec.frames::<u8>().collect();
+        assert_eq!(2, frames.len());
+        assert!(frames
+            .iter()
+            .zip(&again)
+            .all(|(frame1, frame2)| frames_identical(frame1, frame2)));
+
+        #[cfg(not(feature = "ffmpeg"))]
+        {
+            let data = std::fs::read(&path).unwrap();
+            let mut dec = av_metrics_decoders::y4m::new_decoder_from_reader(&data[..]).unwrap();
+            assert!(matches!(
+                dec.reset(),
+                Err(MetricsError::UnsupportedInput { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn decoder_errors() {
+        let missing = format!("{}/../testfiles/missing.y4m", env!("CARGO_MANIFEST_DIR"));
+        let err = get_decoder(&missing).err().unwrap();
+        assert!(matches!(err, MetricsError::DecodeError { .. }));
+        assert!(std::error::Error::source(&err).is_some());
+        #[cfg(not(feature = "ffmpeg"))]
+        {
+            let source = std::error::Error::source(&err).unwrap();
+            let io_error = source.downcast_ref::<std::io::Error>().unwrap();
+            assert_eq!(std::io::ErrorKind::NotFound, io_error.kind());
+
+            let err = av_metrics_decoders::y4m::new_decoder_from_reader(&b"FRAME\n"[..])
+                .err()
+                .unwrap();
+            assert!(matches!(err, MetricsError::DecodeError { .. }));
+        }
+
+        // The error is kept when passed on as `Box<dyn Error>`
+        let input = format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let metric = av_metrics_decoders::Metric::Psnr;
+        let err = av_metrics_decoders::compare_files(&missing, &input, metric, None)
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref::<MetricsError>(),
+            Some(MetricsError::DecodeError { .. })
+        ));
+    }
+
+    #[test]
+    #[cfg(not(feature = "ffmpeg"))]
+    fn y4m_truncated_frame() {
+        let data = std::fs::read(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
             env!("CARGO_MANIFEST_DIR")
         ))
         .unwrap();
-        let mut dec2 = get_decoder(format!(
-            "{}/../testfiles/yuv444p8_output.y4m",
+        let decoder =
+            |len: usize| av_metrics_decoders::y4m::new_decoder_from_reader(&data[..len]).unwrap();
+        let mut dec = decoder(data.len());
+        let frames = dec.frames::<u8>().count();
+        assert!(dec.take_error().is_none());
+        let frame_len = (data.len() - data.iter().position(|&b| b == b'\n').unwrap() - 1) / frames;
+
+        // Cut off within the frame data, and within the frame header
+        for len in [data.len() - 100, data.len() - frame_len + 3] {
+            let mut dec = decoder(len);
+            assert_eq!(frames - 1, dec.frames::<u8>().count());
+            assert!(matches!(
+                dec.take_error(),
+                Some(MetricsError::UnexpectedEof)
+            ));
+
+            let err = calculate_video_psnr(&mut decoder(len), &mut decoder(len), None, |_| ())
+                .err()
+                .unwrap();
+            assert!(matches!(
+                err.downcast_ref::<MetricsError>(),
+                Some(MetricsError::UnexpectedEof)
+            ));
+        }
+    }
+
+    #[test]
+    fn decoder_can_seek() {
+        let input = format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let dec = get_decoder(&input).unwrap();
+        assert!(dec.can_seek());
+        // Adapters do not provide seeking
+        assert!(!FrameStepDecoder::new(dec, 2).can_seek());
+
+        #[cfg(not(feature = "ffmpeg"))]
+        {
+            let reader = BufReader::new(File::open(&input).unwrap());
+            let dec = av_metrics_decoders::y4m::new_decoder_from_reader(reader).unwrap();
+            assert!(!dec.can_seek());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "ffmpeg")]
+    fn ffmpeg_in_memory() {
+        let input = format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let output = format!(
+            "{}/../testfiles/yuv420p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let mut dec1 = get_decoder(&input).unwrap();
+        let mut dec2 = get_decoder(&output).unwrap();
+        let expected = calculate_video_psnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+
+        let mut dec1 = FfmpegDecoder::from_bytes(std::fs::read(&input).unwrap()).unwrap();
+        let mut dec2 = FfmpegDecoder::from_reader(std::fs::File::open(&output).unwrap()).unwrap();
+        let details = dec1.get_video_details();
+        assert_eq!((640, 360), (details.width, details.height));
+        assert_eq!(ChromaSampling::Cs420, details.chroma_sampling);
+        assert!(dec1.can_seek());
+        assert!(!dec2.can_seek());
+        let result = calculate_video_psnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    #[cfg(feature = "ffmpeg")]
+    fn ffmpeg_conversion_error() {
+        // A stream of two PNG images, the second of which is grayscale. The scaler
+        // for the forced pixel format is set up for RGB and cannot convert it.
+        let png = |name: &str| {
+            std::fs::read(format!(
+                "{}/../testfiles/png/{}.png",
+                env!("CARGO_MANIFEST_DIR"),
+                name
+            ))
+            .unwrap()
+        };
+        // Without a file extension, FFmpeg detects the format from the data
+        let path = std::env::temp_dir().join("av_metrics_ffmpeg_conversion_error");
+        std::fs::write(&path, [png("rgb8"), png("gray8")].concat()).unwrap();
+        let mut dec = FfmpegDecoder::builder(&path)
+            .pixel_format("yuv444p")
+            .build()
+            .unwrap();
+        assert!(dec.read_video_frame::<u8>().is_some());
+        assert!(dec.take_error().is_none());
+        assert!(dec.read_video_frame::<u8>().is_none());
+        assert!(matches!(
+            dec.take_error(),
+            Some(MetricsError::DecodeError { .. })
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn frame_iterators() {
+        let open = |name: &str| {
+            get_decoder(format!(
+                "{}/../testfiles/{}.y4m",
+                env!("CARGO_MANIFEST_DIR"),
+                name
+            ))
+            .unwrap()
+        };
+        assert_eq!(3, open("yuv420p8_input").frames::<u8>().count());
+
+        let mut dec1 = open("yuv420p8_input");
+        let mut dec2 = open("yuv420p8_output");
+        let scores: Vec<_> = dec1
+            .frames::<u8>()
+            .zip(dec2.frames())
+            .take(2)
+            .map(|(frame1, frame2)| {
+                calculate_frame_psnr(&frame1, &frame2, 8, ChromaSampling::Cs420).unwrap()
+            })
+            .collect();
+        assert_eq!(2, scores.len());
+        // The iterators only read the frames they returned
+        assert!(dec1.read_video_frame::<u8>().is_some());
+        assert!(dec1.read_video_frame::<u8>().is_none());
+
+        let mut dec1 = open("yuv420p8_input");
+        let mut dec2 = open("yuv420p8_output");
+        let frame1 = dec1.read_video_frame::<u8>().unwrap();
+        let frame2 = dec2.read_video_frame::<u8>().unwrap();
+        assert_eq!(
+            calculate_frame_psnr(&frame1, &frame2, 8, ChromaSampling::Cs420).unwrap(),
+            scores[0]
+        );
+    }
+
+    #[test]
+    fn resize_yuv420p8() {
+        let open = |name: &str| {
+            get_decoder(format!(
+                "{}/../testfiles/{}.y4m",
+                env!("CARGO_MANIFEST_DIR"),
+                name
+            ))
+            .unwrap()
+        };
+        let frame = open("yuv420p8_input").read_video_frame::<u8>().unwrap();
+        let cs = ChromaSampling::Cs420;
+
+        for filter in [ResizeFilter::Bilinear, ResizeFilter::Lanczos3] {
+            let same = resize_frame(&frame, 640, 360, 8, filter).unwrap();
+            for (plane, same) in frame.planes.iter().zip(same.planes.iter()) {
+                for (row, same) in plane.rows_iter().zip(same.rows_iter()) {
+                    assert_eq!(&row[..plane.cfg.width], &same[..plane.cfg.width]);
+                }
+            }
+
+            let small = resize_frame(&frame, 320, 179, 8, filter).unwrap();
+            assert_eq!(320, small.planes[0].cfg.width);
+            assert_eq!(179, small.planes[0].cfg.height);
+            assert_eq!(160, small.planes[1].cfg.width);
+            assert_eq!(90, small.planes[1].cfg.height);
+            let restored = resize_frame(&small, 640, 360, 8, filter).unwrap();
+            let psnr = calculate_frame_psnr(&frame, &restored, 8, cs).unwrap();
+            assert!(psnr.avg > 25.0 && psnr.avg.is_finite());
+        }
+        assert!(resize_frame(&frame, 0, 360, 8, ResizeFilter::Bilinear).is_err());
+        assert!(resize_frame(&frame, 640, 360, 17, ResizeFilter::Bilinear).is_err());
+
+        // Compare both videos at a lower resolution
+        let open_scaled =
+            |name: &str| ResizeDecoder::new(open(name), 320, 180, ResizeFilter::default()).unwrap();
+        let mut dec1 = open_scaled("yuv420p8_input");
+        let mut dec2 = open_scaled("yuv420p8_output");
+        assert_eq!(320, dec2.get_video_details().width);
+        assert_eq!(180, dec2.get_video_details().height);
+        let result = calculate_video_psnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        let mut dec1 = open("yuv420p8_input");
+        let mut dec2 = open("yuv420p8_output");
+        let full = calculate_video_psnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        // Downscaling hides some of the distortion
+        assert!(result.avg > full.avg);
+    }
+
+    #[test]
+    fn resize_decoder_error() {
+        // The bit depth of the video is out of range for the resize
+        let details = VideoDetails::new(64, 48, 17, ChromaSampling::Cs420);
+        let frame = |_| Frame::<u16>::new_with_padding(64, 48, ChromaSampling::Cs420, 0);
+        let mut dec = ResizeDecoder::new(
+            GeneratorDecoder::new(frame, details, 3),
+            32,
+            24,
+            ResizeFilter::default(),
+        )
+        .unwrap();
+        assert!(dec.read_video_frame::<u16>().is_none());
+        assert!(matches!(
+            dec.take_error(),
+            Some(MetricsError::UnsupportedInput { .. })
+        ));
+        assert!(dec.take_error().is_none());
+    }
+
+    #[test]
+    fn upsample_chroma_yuv420p8() {
+        let mut dec = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
             env!("CARGO_MANIFEST_DIR")
         ))
         .unwrap();
-        let result = calculate_video_apsnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
-        assert_metric_eq(32.4412, result.y);
-        assert_metric_eq(40.1264, result.u);
-        assert_metric_eq(43.1943, result.v);
-        assert_metric_eq(36.2271, result.avg);
+        let frame = dec.read_video_frame::<u8>().unwrap();
+
+        let upsampled = upsample_chroma(&frame, ChromaSamplePosition::Colocated).unwrap();
+        for plane in &upsampled.planes {
+            assert_eq!((640, 360), (plane.cfg.width, plane.cfg.height));
+            assert_eq!((0, 0), (plane.cfg.xdec, plane.cfg.ydec));
+        }
+        assert!(upsampled.planes[0]
+            .rows_iter()
+            .zip(frame.planes[0].rows_iter())
+            .all(|(a, b)| a[..640] == b[..640]));
+        // Co-sited chroma samples keep their value at the matching luma position
+        for (y, row) in frame.planes[1].rows_iter().enumerate().step_by(7) {
+            for x in (0..320).step_by(11) {
+                assert_eq!(row[x], upsampled.planes[1].p(2 * x, 2 * y));
+            }
+        }
+
+        // Centered samples are interpolated between their neighbours
+        let centered = upsample_chroma(&frame, ChromaSamplePosition::Bilateral).unwrap();
+        let src = |x: usize, y: usize| frame.planes[2].p(x, y) as f64;
+        let expected = 0.75 * (0.75 * src(10, 10) + 0.25 * src(11, 10))
+            + 0.25 * (0.75 * src(10, 9) + 0.25 * src(11, 9));
+        assert!((centered.planes[2].p(21, 20) as f64 - expected).abs() <= 0.5);
+
+        // Constant planes stay constant
+        let flat = Plane::from_slice(&[100u8; 16], 4);
+        let upsampled = upsample_chroma_plane(&flat, 8, 8, ChromaSamplePosition::Vertical);
+        assert!(upsampled.rows_iter().flatten().all(|&sample| sample == 100));
+
+        let gray = Frame::<u8>::new_with_padding(64, 64, ChromaSampling::Cs400, 0);
+        assert!(upsample_chroma(&gray, ChromaSamplePosition::Unknown).is_err());
+    }
+
+    #[test]
+    fn frames_csv() {
+        let frames = [
+            PlanarMetrics {
+                y: 30.5,
+                u: 40.0,
+                v: 41.25,
+                avg: 32.0,
+            },
+            PlanarMetrics {
+                y: f64::INFINITY,
+                u: f64::NAN,
+                v: f64::NAN,
+                avg: f64::INFINITY,
+            },
+        ];
+        let mut csv = Vec::new();
+        write_frames_csv(&frames, &mut csv).unwrap();
+        assert_eq!(
+            "frame,y,u,v,avg\n0,30.5,40,41.25,32\n1,inf,NaN,NaN,inf\n",
+            String::from_utf8(csv).unwrap()
+        );
+    }
+
+    #[test]
+    fn frame_hash_dedup() {
+        let mut dec = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let frame1 = dec.read_video_frame::<u8>().unwrap();
+        let frame2 = dec.read_video_frame::<u8>().unwrap();
+        assert_eq!(frame_hash(&frame1), frame_hash(&frame1.clone()));
+        assert_ne!(frame_hash(&frame1), frame_hash(&frame2));
+        assert!(frames_identical(&frame1, &frame1.clone()));
+        assert!(!frames_identical(&frame1, &frame2));
+
+        // Padding is not part of the content
+        let tight: Vec<u8> = (0..16).collect();
+        let mut padded = vec![255u8; 32];
+        for (row, chunk) in padded.chunks_mut(8).zip(tight.chunks(4)) {
+            row[..4].copy_from_slice(chunk);
+        }
+        let tight = plane_from_slice(&tight, 4, 4, 4, 0, 0).unwrap();
+        let mut padded = plane_from_slice(&padded, 4, 4, 8, 0, 0).unwrap();
+        assert_eq!(plane_hash(&tight), plane_hash(&padded));
+        padded.mut_slice(Default::default())[3][3] = 0;
+        assert_ne!(plane_hash(&tight), plane_hash(&padded));
+    }
+
+    #[test]
+    fn deinterlace_decoder_error() {
+        // The chroma planes have an odd height, unlike in the details of the video
+        let details = VideoDetails::new(64, 48, 8, ChromaSampling::Cs420);
+        let frame = |_| Frame::<u8> {
+            planes: [
+                Plane::new(64, 46, 0, 0, 0, 0),
+                Plane::new(32, 23, 1, 1, 0, 0),
+                Plane::new(32, 23, 1, 1, 0, 0),
+            ],
+        };
+        let mut dec = DeinterlaceDecoder::new(
+            GeneratorDecoder::new(frame, details, 3),
+            Deinterlace::Bob,
+            FieldOrder::TopFieldFirst,
+        )
+        .unwrap();
+        assert!(dec.read_video_frame::<u8>().is_none());
+        assert!(matches!(
+            dec.take_error(),
+            Some(MetricsError::UnsupportedInput { .. })
+        ));
+        assert!(dec.take_error().is_none());
+
+        // Switching the pixel type between the fields of a frame would lose a field
+        let frame = |_| Frame::<u8>::new_with_padding(64, 48, ChromaSampling::Cs420, 0);
+        let mut dec = DeinterlaceDecoder::new(
+            GeneratorDecoder::new(frame, details, 3),
+            Deinterlace::Bob,
+            FieldOrder::TopFieldFirst,
+        )
+        .unwrap();
+        assert!(dec.read_video_frame::<u8>().is_some());
+        assert!(dec.read_video_frame::<u16>().is_none());
+        assert!(matches!(
+            dec.take_error(),
+            Some(MetricsError::UnsupportedInput { .. })
+        ));
+    }
+
+    #[test]
+    fn deinterlace_yuv420p8() {
+        let open = || {
+            get_decoder(format!(
+                "{}/../testfiles/yuv420p8_input.y4m",
+                env!("CARGO_MANIFEST_DIR")
+            ))
+            .unwrap()
+        };
+        let frame = open().read_video_frame::<u8>().unwrap();
+
+        let mut dec = DeinterlaceDecoder::new(
+            open(),
+            Deinterlace::SeparateFields,
+            FieldOrder::TopFieldFirst,
+        )
+        .unwrap();
+        let [top, bottom] = deinterlace_frame(
+            &frame,
+            Deinterlace::SeparateFields,
+            FieldOrder::TopFieldFirst,
+        )
+        .unwrap();
+        assert!(dec.read_video_frame::<u8>().unwrap().planes == top.planes);
+        assert!(dec.read_video_frame::<u8>().unwrap().planes == bottom.planes);
+        for (plane, (top, bottom)) in frame
+            .planes
+            .iter()
+            .zip(top.planes.iter().zip(bottom.planes.iter()))
+        {
+            assert_eq!(plane.cfg.height / 2, top.cfg.height);
+            assert_eq!(plane.cfg.height / 2, bottom.cfg.height);
+            assert_eq!(plane.row(2), top.row(1));
+            assert_eq!(plane.row(3), bottom.row(1));
+        }
+
+        let [bottom, top] =
+            deinterlace_frame(&frame, Deinterlace::Bob, FieldOrder::BottomFieldFirst).unwrap();
+        let luma = &frame.planes[0];
+        assert_eq!(luma.cfg.height, top.planes[0].cfg.height);
+        assert_eq!(luma.row(2), top.planes[0].row(2));
+        assert_eq!(luma.row(3), bottom.planes[0].row(3));
+        let width = luma.cfg.width;
+        let interpolated: Vec<u8> = luma.row(2)[..width]
+            .iter()
+            .zip(&luma.row(4)[..width])
+            .map(|(&a, &b)| ((a as u32 + b as u32 + 1) >> 1) as u8)
+            .collect();
+        assert_eq!(&interpolated[..], &top.planes[0].row(3)[..width]);
+        // The first line of the bottom field is copied from the line below it
+        assert_eq!(&luma.row(1)[..width], &bottom.planes[0].row(0)[..width]);
+
+        // The chroma planes of 4:2:0 video need a luma height divisible by 4
+        let rect = Rect {
+            x: 0,
+            y: 0,
+            width: 640,
+            height: 358,
+        };
+        let cropped = crop_frame(&frame, rect).unwrap();
+        assert!(matches!(
+            deinterlace_frame(&cropped, Deinterlace::Bob, FieldOrder::TopFieldFirst),
+            Err(MetricsError::UnsupportedInput { .. })
+        ));
+        assert!(CropDecoder::new(open(), rect)
+            .and_then(|dec| DeinterlaceDecoder::new(
+                dec,
+                Deinterlace::Bob,
+                FieldOrder::TopFieldFirst
+            ))
+            .is_err());
+
+        let mut dec1 = DeinterlaceDecoder::new(
+            open(),
+            Deinterlace::SeparateFields,
+            FieldOrder::TopFieldFirst,
+        )
+        .unwrap();
+        let details = dec1.get_video_details();
+        assert_eq!(180, details.height);
+        let frame_rate = details.frame_rate.unwrap();
+        assert_eq!((50, 1), (frame_rate.num, frame_rate.den));
+        assert_eq!(Some(6), dec1.total_frames());
+        let dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 =
+            DeinterlaceDecoder::new(dec2, Deinterlace::SeparateFields, FieldOrder::TopFieldFirst)
+                .unwrap();
+        let result = calculate_video_psnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert!(result.avg.is_finite());
+    }
+
+    #[test]
+    fn crop_monochrome() {
+        let mut frame = Frame::<u8>::new_with_padding(64, 48, ChromaSampling::Cs400, 0);
+        for (i, pix) in frame.planes[0].rows_iter_mut().flatten().enumerate() {
+            *pix = i as u8;
+        }
+        let rect = Rect {
+            x: 3,
+            y: 5,
+            width: 32,
+            height: 16,
+        };
+        let cropped = crop_frame(&frame, rect).unwrap();
+        assert_eq!(cropped.luma_dimensions(), (32, 16));
+        assert_eq!(cropped.chroma_dimensions(), (0, 0));
+        assert_eq!(cropped.planes[0].p(0, 0), frame.planes[0].p(3, 5));
+
+        let tiles =
+            calculate_frame_psnr_tiled(&frame, &frame, 8, ChromaSampling::Cs400, 24, 24).unwrap();
+        assert_eq!((tiles.len(), tiles[0].len()), (2, 3));
+    }
+
+    #[test]
+    fn crop_decoder_error() {
+        // The frames are smaller than the details of the video claim
+        let details = VideoDetails::new(64, 48, 8, ChromaSampling::Cs420);
+        let small = |_| Frame::<u8>::new_with_padding(32, 32, ChromaSampling::Cs420, 0);
+        let rect = Rect {
+            x: 0,
+            y: 0,
+            width: 48,
+            height: 48,
+        };
+        let mut dec1 = CropDecoder::new(GeneratorDecoder::new(small, details, 3), rect).unwrap();
+        let mut dec2 = CropDecoder::new(GeneratorDecoder::new(small, details, 3), rect).unwrap();
+        let result = calculate_video_psnr(&mut dec1, &mut dec2, None, |_| ());
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<MetricsError>(),
+            Some(MetricsError::UnsupportedInput { .. })
+        ));
+    }
+
+    #[test]
+    fn psnr_yuv420p8_crop() {
+        let dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let rect = Rect {
+            x: 64,
+            y: 40,
+            width: 320,
+            height: 200,
+        };
+        let mut dec1 = CropDecoder::new(dec1, rect).unwrap();
+        let mut dec2 = CropDecoder::new(dec2, rect).unwrap();
+        assert_eq!(dec1.get_video_details().width, 320);
+        let result = calculate_video_psnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(32.1343, result.y);
+        assert_metric_eq(36.4186, result.u);
+        assert_metric_eq(39.4967, result.v);
+        assert_metric_eq(33.3296, result.avg);
+
+        for rect in [
+            Rect {
+                x: 1,
+                y: 0,
+                width: 320,
+                height: 200,
+            },
+            Rect {
+                x: 0,
+                y: 0,
+                width: 640,
+                height: 400,
+            },
+        ] {
+            let dec = get_decoder(format!(
+                "{}/../testfiles/yuv420p8_input.y4m",
+                env!("CARGO_MANIFEST_DIR")
+            ))
+            .unwrap();
+            assert!(CropDecoder::new(dec, rect).is_err());
+        }
+    }
+
+    #[test]
+    fn psnr_bit_depth_convert() {
+        let dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p10_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        // Both videos have to be wrapped to be of the same type
+        let mut dec1 = BitDepthConvertDecoder::new(dec1, 10).unwrap();
+        let mut dec2 = BitDepthConvertDecoder::new(dec2, 10).unwrap();
+        assert_eq!(10, dec1.get_bit_depth());
+        assert_eq!(10, dec1.get_video_details().bit_depth);
+        let result = calculate_video_psnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(32.5421, result.y);
+        assert_metric_eq(36.4922, result.u);
+        assert_metric_eq(39.8558, result.v);
+        assert_metric_eq(33.7071, result.avg);
+
+        // Converting to a higher bit depth and back is lossless
+        let dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec1 =
+            BitDepthConvertDecoder::new(BitDepthConvertDecoder::new(dec1, 16).unwrap(), 8).unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        while let Some(frame1) = dec1.read_video_frame::<u8>() {
+            let frame2 = dec2.read_video_frame::<u8>().unwrap();
+            for (plane1, plane2) in frame1.planes.iter().zip(frame2.planes.iter()) {
+                assert!(plane1.rows_iter().eq(plane2.rows_iter()));
+            }
+        }
+
+        assert!(matches!(
+            BitDepthConvertDecoder::new(dec2, 17),
+            Err(MetricsError::UnsupportedInput { .. })
+        ));
+    }
+
+    #[test]
+    fn temporal_consistency_yuv420p8() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let result =
+            calculate_video_temporal_consistency(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(29.4245, result);
+
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let result =
+            calculate_video_temporal_consistency(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(100.0, result);
+    }
+
+    #[test]
+    fn psnr_no_frames_compared() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let err = calculate_video_psnr(&mut dec1, &mut dec2, Some(0), |_| ()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MetricsError>(),
+            Some(MetricsError::NoFramesCompared)
+        ));
+    }
+
+    #[test]
+    fn apsnr_yuv420p8() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let result = calculate_video_apsnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(32.5450, result.y);
+        assert_metric_eq(36.4087, result.u);
+        assert_metric_eq(39.8244, result.v);
+        assert_metric_eq(33.6995, result.avg);
+    }
+
+    #[test]
+    fn apsnr_yuv422p8() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv422p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv422p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let result = calculate_video_apsnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(38.6741, result.y);
+        assert_metric_eq(47.5219, result.u);
+        assert_metric_eq(48.8616, result.v);
+        assert_metric_eq(41.2191, result.avg);
+    }
+
+    #[test]
+    fn apsnr_yuv444p8() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv444p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv444p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let result = calculate_video_apsnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(32.4412, result.y);
+        assert_metric_eq(40.1264, result.u);
+        assert_metric_eq(43.1943, result.v);
+        assert_metric_eq(36.2271, result.avg);
+    }
+
+    #[test]
+    fn apsnr_yuv420p10() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p10_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p10_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let result = calculate_video_apsnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(32.5586, result.y);
+        assert_metric_eq(36.4923, result.u);
+        assert_metric_eq(39.8563, result.v);
+        assert_metric_eq(33.7200, result.avg);
+    }
+
+    #[test]
+    fn psnr_hvs_yuv420p8() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let result = calculate_video_psnr_hvs(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(34.2206, result.y);
+        assert_metric_eq(37.6719, result.u);
+        assert_metric_eq(40.4736, result.v);
+        assert_metric_eq(31.7698, result.avg);
+    }
+
+    #[test]
+    fn psnr_hvs_masking_toggle() {
+        let open = || {
+            let dec1 = get_decoder(format!(
+                "{}/../testfiles/yuv420p8_input.y4m",
+                env!("CARGO_MANIFEST_DIR")
+            ))
+            .unwrap();
+            let dec2 = get_decoder(format!(
+                "{}/../testfiles/yuv420p8_output.y4m",
+                env!("CARGO_MANIFEST_DIR")
+            ))
+            .unwrap();
+            (dec1, dec2)
+        };
+        let (mut dec1, mut dec2) = open();
+        let masked = PsnrHvsOptions::new()
+            .masking(true)
+            .calculate_video(&mut dec1, &mut dec2, None, |_| ())
+            .unwrap();
+        let (mut dec1, mut dec2) = open();
+        assert_eq!(
+            calculate_video_psnr_hvs(&mut dec1, &mut dec2, None, |_| ()).unwrap(),
+            masked
+        );
+        // Without the masking, every coefficient error counts fully
+        let (mut dec1, mut dec2) = open();
+        let unmasked = PsnrHvsOptions::new()
+            .masking(false)
+            .calculate_video(&mut dec1, &mut dec2, None, |_| ())
+            .unwrap();
+        assert!(unmasked.y < masked.y);
+        assert!(unmasked.u < masked.u);
+        assert!(unmasked.v < masked.v);
+        assert!(unmasked.avg < masked.avg);
+
+        let (mut dec1, mut dec2) = open();
+        let frame1 = dec1.read_video_frame::<u8>().unwrap();
+        let frame2 = dec2.read_video_frame::<u8>().unwrap();
+        let frame = PsnrHvsOptions::new()
+            .masking(false)
+            .calculate_frame(&frame1, &frame2, 8, ChromaSampling::Cs420)
+            .unwrap();
+        assert!(
+            frame.y
+                < calculate_frame_psnr_hvs(&frame1, &frame2, 8, ChromaSampling::Cs420)
+                    .unwrap()
+                    .y
+        );
+    }
+
+    #[test]
+    fn psnr_hvs_yuv422p8() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv422p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv422p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let result = calculate_video_psnr_hvs(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(45.3710, result.y);
+        assert_metric_eq(46.7724, result.u);
+        assert_metric_eq(45.5575, result.v);
+        assert_metric_eq(39.7099, result.avg);
+    }
+
+    #[test]
+    fn psnr_hvs_yuv444p8() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv444p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv444p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let result = calculate_video_psnr_hvs(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(34.0837, result.y);
+        assert_metric_eq(41.3659, result.u);
+        assert_metric_eq(43.7592, result.v);
+        assert_metric_eq(28.1907, result.avg);
+    }
+
+    #[test]
+    fn psnr_hvs_yuv420p10() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p10_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p10_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let result = calculate_video_psnr_hvs(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(34.3688, result.y);
+        assert_metric_eq(38.1173, result.u);
+        assert_metric_eq(41.0021, result.v);
+        assert_metric_eq(31.9643, result.avg);
+    }
+
+    #[test]
+    fn psnr_hvs_yuv420p12() {
+        let dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p10_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p10_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec1 = BitDepthConvertDecoder::new(dec1, 12).unwrap();
+        let mut dec2 = BitDepthConvertDecoder::new(dec2, 12).unwrap();
+        let result = calculate_video_psnr_hvs(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(34.3886, result.y);
+        assert_metric_eq(38.1457, result.u);
+        assert_metric_eq(41.0550, result.v);
+        assert_metric_eq(31.9864, result.avg);
+    }
+
+    #[test]
+    fn psnr_hvs_map_yuv420p8() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let details = dec1.get_video_details();
+        let frame1 = dec1.read_video_frame::<u8>().unwrap();
+        let frame2 = dec2.read_video_frame::<u8>().unwrap();
+        let result =
+            calculate_frame_psnr_hvs(&frame1, &frame2, 8, details.chroma_sampling).unwrap();
+        let maps =
+            calculate_frame_psnr_hvs_map(&frame1, &frame2, 8, details.chroma_sampling).unwrap();
+        for (map, expected) in maps.iter().zip([result.y, result.u, result.v]) {
+            assert_eq!(map.width * map.height, map.data.len());
+            let mean = map.data.iter().sum::<f64>() / map.data.len() as f64;
+            assert_metric_eq(expected, -10.0 * mean.log10());
+        }
+    }
+
+    #[test]
+    fn psnr_hvs_coverage() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let details = dec1.get_video_details();
+        let frame1 = dec1.read_video_frame::<u8>().unwrap();
+        let frame2 = dec2.read_video_frame::<u8>().unwrap();
+        let expected =
+            calculate_frame_psnr_hvs(&frame1, &frame2, 8, details.chroma_sampling).unwrap();
+        let (result, coverage) =
+            calculate_frame_psnr_hvs_with_coverage(&frame1, &frame2, 8, details.chroma_sampling)
+                .unwrap();
+        assert_eq!(expected, result);
+        for (plane, coverage) in frame1.planes.iter().zip(coverage.iter()) {
+            assert!(coverage.blocks > 0);
+            assert_eq!(plane.cfg.width * plane.cfg.height, coverage.total_pixels);
+            assert_eq!(coverage.total_pixels, coverage.covered_pixels);
+        }
+
+        // The 4x4 chroma planes of an 8x8 frame are too small for a single block
+        let frame1 = Frame::<u8>::new_with_padding(8, 8, ChromaSampling::Cs420, 0);
+        let frame2 = frame1.clone();
+        let (result, coverage) =
+            calculate_frame_psnr_hvs_with_coverage(&frame1, &frame2, 8, ChromaSampling::Cs420)
+                .unwrap();
+        assert_eq!(1, coverage[0].blocks);
+        assert_eq!(64, coverage[0].covered_pixels);
+        assert_eq!(0, coverage[1].blocks);
+        assert_eq!(0, coverage[1].covered_pixels);
+        assert_eq!(16, coverage[1].total_pixels);
+        assert!(result.u.is_nan());
+    }
+
+    #[test]
+    fn psnr_hvs_yuv420p8_nosimd() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let result = calculate_video_psnr_hvs_nosimd(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(34.2206, result.y);
+        assert_metric_eq(37.6719, result.u);
+        assert_metric_eq(40.4736, result.v);
+        assert_metric_eq(31.7698, result.avg);
+    }
+
+    #[test]
+    fn psnr_hvs_simd_matches_scalar() {
+        // Simple LCG, so the test does not need a random number generator
+        let mut state = 0x2545_f491_u32;
+        let mut next = move || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            state >> 8
+        };
+
+        for bit_depth in [10, 12] {
+            let mut frame1 = Frame::<u16>::new_with_padding(64, 48, ChromaSampling::Cs420, 0);
+            let mut frame2 = frame1.clone();
+            for frame in [&mut frame1, &mut frame2] {
+                for plane in frame.planes.iter_mut() {
+                    for px in plane.data_origin_mut() {
+                        *px = (next() & ((1 << bit_depth) - 1)) as u16;
+                    }
+                }
+            }
+            let simd = calculate_frame_psnr_hvs(&frame1, &frame2, bit_depth, ChromaSampling::Cs420)
+                .unwrap();
+            let scalar =
+                calculate_frame_psnr_hvs_nosimd(&frame1, &frame2, bit_depth, ChromaSampling::Cs420)
+                    .unwrap();
+            assert_eq!(scalar, simd);
+        }
+
+        let mut frame1 = Frame::<u8>::new_with_padding(64, 48, ChromaSampling::Cs444, 0);
+        let mut frame2 = frame1.clone();
+        for frame in [&mut frame1, &mut frame2] {
+            for plane in frame.planes.iter_mut() {
+                for px in plane.data_origin_mut() {
+                    *px = next() as u8;
+                }
+            }
+        }
+        let simd = calculate_frame_psnr_hvs(&frame1, &frame2, 8, ChromaSampling::Cs444).unwrap();
+        let scalar =
+            calculate_frame_psnr_hvs_nosimd(&frame1, &frame2, 8, ChromaSampling::Cs444).unwrap();
+        assert_eq!(scalar, simd);
+    }
+
+    #[test]
+    fn single_reference_many_distorted() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let cs = dec1.get_video_details().chroma_sampling;
+        let reference = dec1.read_video_frame::<u8>().unwrap();
+        // Include the reference itself, which is skipped as an identical frame
+        let mut distorted = vec![reference.clone()];
+        while let Some(frame) = dec2.read_video_frame::<u8>() {
+            distorted.push(frame);
+        }
+
+        let results = calculate_frame_ssim_many(&reference, &distorted, 8, cs).unwrap();
+        assert_eq!(distorted.len(), results.len());
+        for (frame, result) in distorted.iter().zip(results) {
+            let expected = calculate_frame_ssim(&reference, frame, 8, cs).unwrap();
+            assert_eq!(expected, result);
+        }
+
+        let results = calculate_frame_psnr_hvs_many(&reference, &distorted, 8, cs).unwrap();
+        assert_eq!(distorted.len(), results.len());
+        for (frame, result) in distorted.iter().zip(results) {
+            let expected = calculate_frame_psnr_hvs(&reference, frame, 8, cs).unwrap();
+            assert_eq!(expected, result);
+        }
+    }
+
+    #[test]
+    fn alpha_plane() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p10_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p10_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let frame1 = dec1.read_video_frame::<u16>().unwrap();
+        let frame2 = dec2.read_video_frame::<u16>().unwrap();
+        // Any plane can stand in for an alpha plane, which is scored like luma
+        let (alpha1, alpha2) = (&frame1.planes[0], &frame2.planes[0]);
+        let cs = ChromaSampling::Cs420;
+        let psnr = calculate_frame_psnr(&frame1, &frame2, 10, cs).unwrap();
+        assert_eq!(psnr.y, calculate_alpha_psnr(alpha1, alpha2, 10).unwrap());
+        let ssim = calculate_frame_ssim(&frame1, &frame2, 10, cs).unwrap();
+        assert_eq!(ssim.y, calculate_alpha_ssim(alpha1, alpha2, 10).unwrap());
+        assert_eq!(100.0, calculate_alpha_psnr(alpha1, alpha1, 10).unwrap());
+
+        assert!(calculate_alpha_psnr(alpha1, &frame2.planes[1], 10).is_err());
+        assert!(calculate_alpha_ssim(alpha1, alpha2, 8).is_err());
+    }
+
+    #[test]
+    fn ssim_yuv420p8() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let result = calculate_video_ssim(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(13.2572, result.y);
+        assert_metric_eq(10.8624, result.u);
+        assert_metric_eq(12.8369, result.v);
+        assert_metric_eq(12.6899, result.avg);
+    }
+
+    #[test]
+    fn ssim_config_yuv420p8() {
+        let decoders = || {
+            (
+                get_decoder(format!(
+                    "{}/../testfiles/yuv420p8_input.y4m",
+                    env!("CARGO_MANIFEST_DIR")
+                ))
+                .unwrap(),
+                get_decoder(format!(
+                    "{}/../testfiles/yuv420p8_output.y4m",
+                    env!("CARGO_MANIFEST_DIR")
+                ))
+                .unwrap(),
+            )
+        };
+        let (mut dec1, mut dec2) = decoders();
+        let result = SsimOptions::new()
+            .config(SsimConfig::default())
+            .calculate_video(&mut dec1, &mut dec2, None, |_| ())
+            .unwrap();
+        assert_metric_eq(13.2572, result.y);
+        assert_metric_eq(10.8624, result.u);
+        assert_metric_eq(12.8369, result.v);
+        assert_metric_eq(12.6899, result.avg);
+
+        let (mut dec1, mut dec2) = decoders();
+        let config = SsimConfig {
+            k1: 0.05,
+            k2: 0.1,
+            ..Default::default()
+        };
+        let result = SsimOptions::new()
+            .config(config)
+            .calculate_video(&mut dec1, &mut dec2, None, |_| ())
+            .unwrap();
+        assert_metric_eq(17.5195, result.y);
+        assert_metric_eq(17.9172, result.u);
+        assert_metric_eq(21.0121, result.v);
+        assert_metric_eq(18.0094, result.avg);
+
+        let (mut dec1, mut dec2) = decoders();
+        let config = SsimConfig {
+            k1: 0.0,
+            ..Default::default()
+        };
+        assert!(SsimOptions::new()
+            .config(config)
+            .calculate_video(&mut dec1, &mut dec2, None, |_| ())
+            .is_err());
+    }
+
+    #[test]
+    fn ssim_edge_handling_yuv420p8() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let cs = dec1.get_video_details().chroma_sampling;
+        let frame1 = dec1.read_video_frame::<u8>().unwrap();
+        let frame2 = dec2.read_video_frame::<u8>().unwrap();
+
+        let default = calculate_frame_ssim(&frame1, &frame2, 8, cs).unwrap();
+        let modes = [
+            EdgeHandling::Shrink,
+            EdgeHandling::Replicate,
+            EdgeHandling::Mirror,
+            EdgeHandling::Zero,
+        ];
+        let results: Vec<PlanarMetrics> = modes
+            .iter()
+            .map(|&edge| {
+                let config = SsimConfig {
+                    edge,
+                    ..Default::default()
+                };
+                SsimOptions::new()
+                    .config(config)
+                    .calculate_frame(&frame1, &frame2, 8, cs)
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(default, results[0]);
+        for (i, result) in results.iter().enumerate() {
+            assert!(result.avg.is_finite());
+            for other in &results[(i + 1)..] {
+                assert_ne!(result.y, other.y);
+            }
+        }
+    }
+
+    #[test]
+    fn ssim_luma_yuv420p8() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let result = calculate_video_ssim_luma(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(13.2572, result.y);
+        assert!(result.u.is_nan());
+        assert!(result.v.is_nan());
+        assert_metric_eq(13.2572, result.avg);
+    }
+
+    #[test]
+    fn plane_selection_yuv420p8() {
+        let open = || {
+            let dec1 = get_decoder(format!(
+                "{}/../testfiles/yuv420p8_input.y4m",
+                env!("CARGO_MANIFEST_DIR")
+            ))
+            .unwrap();
+            let dec2 = get_decoder(format!(
+                "{}/../testfiles/yuv420p8_output.y4m",
+                env!("CARGO_MANIFEST_DIR")
+            ))
+            .unwrap();
+            (dec1, dec2)
+        };
+
+        let (mut dec1, mut dec2) = open();
+        let result = PsnrOptions::new()
+            .planes(PlaneSelection::Y)
+            .calculate_video(&mut dec1, &mut dec2, None, |_| ())
+            .unwrap();
+        assert_metric_eq(32.5281, result.y);
+        assert!(result.u.is_nan());
+        assert!(result.v.is_nan());
+        assert_metric_eq(32.5281, result.avg);
+
+        let (mut dec1, mut dec2) = open();
+        let result = PsnrOptions::new()
+            .planes(PlaneSelection::ALL)
+            .calculate_video_apsnr(&mut dec1, &mut dec2, None, |_| ())
+            .unwrap();
+        let (mut dec1, mut dec2) = open();
+        let expected = calculate_video_apsnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_eq!(expected, result);
+
+        let (mut dec1, mut dec2) = open();
+        let result = PsnrHvsOptions::new()
+            .planes(PlaneSelection::Y | PlaneSelection::U)
+            .calculate_video(&mut dec1, &mut dec2, None, |_| ())
+            .unwrap();
+        assert_metric_eq(34.2206, result.y);
+        assert_metric_eq(37.6719, result.u);
+        assert!(result.v.is_nan());
+        assert_metric_eq(32.7868, result.avg);
+
+        let (mut dec1, mut dec2) = open();
+        let result = SsimOptions::new()
+            .planes(PlaneSelection::CHROMA)
+            .calculate_video(&mut dec1, &mut dec2, None, |_| ())
+            .unwrap();
+        assert!(result.y.is_nan());
+        assert_metric_eq(10.8624, result.u);
+        assert_metric_eq(12.8369, result.v);
+        assert_metric_eq(11.7384, result.avg);
+
+        let (mut dec1, mut dec2) = open();
+        let result = MsSsimOptions::new()
+            .planes(PlaneSelection::Y)
+            .calculate_video(&mut dec1, &mut dec2, None, |_| ())
+            .unwrap();
+        assert_metric_eq(18.8343, result.y);
+        assert!(result.u.is_nan());
+        assert!(result.v.is_nan());
+        assert_metric_eq(18.8343, result.avg);
+
+        let (mut dec1, mut dec2) = open();
+        let empty = PlaneSelection::Y & PlaneSelection::U;
+        assert!(empty.is_empty());
+        let result =
+            SsimOptions::new()
+                .planes(empty)
+                .calculate_video(&mut dec1, &mut dec2, None, |_| ());
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<MetricsError>(),
+            Some(MetricsError::UnsupportedInput { .. })
+        ));
+
+        let (mut dec1, mut dec2) = open();
+        let frame1 = dec1.read_video_frame::<u8>().unwrap();
+        let frame2 = dec2.read_video_frame::<u8>().unwrap();
+        let result = PsnrOptions::new()
+            .planes(PlaneSelection::Y)
+            .calculate_frame(&frame1, &frame2, 8, ChromaSampling::Cs420)
+            .unwrap();
+        let expected = calculate_frame_psnr(&frame1, &frame2, 8, ChromaSampling::Cs420).unwrap();
+        assert_eq!(expected.y, result.y);
+        assert!(result.u.is_nan());
+        assert_eq!(result.y, result.avg);
+        let result = PsnrHvsOptions::new().planes(empty).calculate_frame(
+            &frame1,
+            &frame2,
+            8,
+            ChromaSampling::Cs420,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn per_frame_statistics() {
+        let open = |name: &str| {
+            get_decoder(format!(
+                "{}/../testfiles/{}.y4m",
+                env!("CARGO_MANIFEST_DIR"),
+                name
+            ))
+            .unwrap()
+        };
+        let decoders = || (open("yuv420p8_input"), open("yuv420p8_output"));
+
+        let (mut dec1, mut dec2) = decoders();
+        let stats =
+            calculate_video_psnr_with_statistics(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        let (mut dec1, mut dec2) = decoders();
+        let psnr = calculate_video_psnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_eq!(psnr, stats.all);
+        let (mut dec1, mut dec2) = decoders();
+        let apsnr = calculate_video_apsnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(apsnr.y, stats.mean.y);
+        assert_metric_eq(apsnr.avg, stats.mean.avg);
+
+        let (mut dec1, mut dec2) = decoders();
+        let mut frames = Vec::new();
+        while let (Some(frame1), Some(frame2)) =
+            (dec1.read_video_frame::<u8>(), dec2.read_video_frame::<u8>())
+        {
+            frames.push(calculate_frame_psnr(&frame1, &frame2, 8, ChromaSampling::Cs420).unwrap());
+        }
+        let n = frames.len() as f64;
+        let mean = frames.iter().map(|m| m.y).sum::<f64>() / n;
+        let variance = frames.iter().map(|m| (m.y - mean).powi(2)).sum::<f64>() / n;
+        assert!(variance > 0.0);
+        assert_metric_eq(variance, stats.variance.y);
+        assert_metric_eq(variance.sqrt(), stats.stddev.y);
+        let (worst, min) = frames
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.y.total_cmp(&b.y))
+            .unwrap();
+        let (best, max) = frames
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.y.total_cmp(&b.y))
+            .unwrap();
+        assert_ne!(worst, best);
+        assert_eq!((worst, best), (stats.min_frame.y, stats.max_frame.y));
+        assert_metric_eq(min.y, stats.min.y);
+        assert_metric_eq(max.y, stats.max.y);
+
+        let (mut dec1, mut dec2) = decoders();
+        let stats =
+            calculate_video_ssim_with_statistics(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        let (mut dec1, mut dec2) = decoders();
+        let ssim = calculate_video_ssim(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_eq!(ssim, stats.all);
+        assert!(stats.stddev.avg >= 0.0);
+
+        let (mut dec1, mut dec2) = decoders();
+        let stats =
+            calculate_video_ciede_with_statistics(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(36.2820, stats.all);
+        assert_metric_eq(stats.variance.sqrt(), stats.stddev);
+        assert!(stats.min <= stats.all && stats.all <= stats.max);
+    }
+
+    #[test]
+    fn frame_type_subtotals() {
+        let open = || {
+            let dec1 = get_decoder(format!(
+                "{}/../testfiles/yuv420p8_input.y4m",
+                env!("CARGO_MANIFEST_DIR")
+            ))
+            .unwrap();
+            let dec2 = get_decoder(format!(
+                "{}/../testfiles/yuv420p8_output.y4m",
+                env!("CARGO_MANIFEST_DIR")
+            ))
+            .unwrap();
+            (dec1, dec2)
+        };
+
+        let (mut dec1, mut dec2) = open();
+        let expected = calculate_video_psnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        let (mut dec1, mut dec2) = open();
+        let result =
+            calculate_video_psnr_by_frame_type(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(expected.y, result.all.y);
+        assert_metric_eq(expected.avg, result.all.avg);
+        // Y4M has no frame types, so every frame ends up in a single group
+        assert_eq!(result.by_type.len(), 1);
+        let subtotal = &result.by_type[0];
+        if cfg!(not(feature = "ffmpeg")) {
+            assert_eq!(subtotal.frame_type, FrameType::Unknown);
+        }
+        assert_eq!(subtotal.frames, 3);
+        assert_metric_eq(result.all.avg, subtotal.result.avg);
+
+        let (mut dec1, mut dec2) = open();
+        let expected = calculate_video_ssim(&mut dec1, &mut dec2, Some(2), |_| ()).unwrap();
+        let (mut dec1, mut dec2) = open();
+        let result =
+            calculate_video_ssim_by_frame_type(&mut dec1, &mut dec2, Some(2), |_| ()).unwrap();
+        assert_metric_eq(expected.avg, result.all.avg);
+        assert_eq!(result.by_type[0].frames, 2);
+        assert_metric_eq(result.all.avg, result.by_type[0].result.avg);
+    }
+
+    #[test]
+    fn frame_type_groups() {
+        // Flat frames, where the second video is off by a different amount per type
+        let flat_frame = |value: u8| {
+            let mut frame = Frame::new_with_padding(64, 48, ChromaSampling::Cs420, 0);
+            for plane in &mut frame.planes {
+                plane.rows_iter_mut().flatten().for_each(|pix| *pix = value);
+            }
+            frame
+        };
+        let details = VideoDetails::new(64, 48, 8, ChromaSampling::Cs420);
+        let errors = [1u8, 2, 2, 3, 2, 3];
+        // The last frame is left out, so its type is unknown
+        let types = vec![
+            FrameType::I,
+            FrameType::B,
+            FrameType::B,
+            FrameType::P,
+            FrameType::B,
+        ];
+        let generator = |errors: [u8; 6]| move |i: usize| flat_frame(100 + errors[i]);
+        let open = |types1: Vec<FrameType>, types2: Vec<FrameType>| {
+            let dec1 = GeneratorDecoder::new(generator([0; 6]), details, 6).frame_types(types1);
+            let dec2 = GeneratorDecoder::new(generator(errors), details, 6).frame_types(types2);
+            (dec1, dec2)
+        };
+        let psnr = |error: f64| 10.0 * (255.0f64 * 255.0 / (error * error)).log10();
+
+        let (mut dec1, mut dec2) = open(Vec::new(), types.clone());
+        let result =
+            calculate_video_psnr_by_frame_type(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(psnr((31.0f64 / 6.0).sqrt()), result.all.avg);
+        let groups: Vec<_> = result
+            .by_type
+            .iter()
+            .map(|subtotal| (subtotal.frame_type, subtotal.frames))
+            .collect();
+        assert_eq!(
+            groups,
+            [
+                (FrameType::I, 1),
+                (FrameType::P, 1),
+                (FrameType::B, 3),
+                (FrameType::Unknown, 1)
+            ]
+        );
+        for (subtotal, error) in result.by_type.iter().zip([1.0, 3.0, 2.0, 3.0]) {
+            assert_metric_eq(psnr(error), subtotal.result.y);
+            assert_metric_eq(psnr(error), subtotal.result.avg);
+        }
+
+        // The types of the first video are used where the second one does not know them
+        let (mut dec1, mut dec2) = open(types.clone(), Vec::new());
+        let fallback =
+            calculate_video_psnr_by_frame_type(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_eq!(result, fallback);
+
+        // A group gives the same score as a video of only the frames of that type
+        let (mut dec1, mut dec2) = open(Vec::new(), types);
+        let result =
+            calculate_video_ssim_by_frame_type(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        let [i_frames, p_frames, b_frames, _] = &result.by_type[..] else {
+            panic!("Expected four groups, got {:?}", result.by_type);
+        };
+        let mut dec1 = GeneratorDecoder::new(generator([0; 6]), details, 3);
+        let mut dec2 = GeneratorDecoder::new(generator([2; 6]), details, 3);
+        let expected = calculate_video_ssim(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_eq!(b_frames.frames, 3);
+        assert_metric_eq(expected.avg, b_frames.result.avg);
+        // The smaller the error of a group, the higher its score
+        assert!(i_frames.result.avg > b_frames.result.avg);
+        assert!(b_frames.result.avg > p_frames.result.avg);
+    }
+
+    #[test]
+    fn ssim_raw_yuv420p8() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let result = SsimOptions::new()
+            .raw(true)
+            .calculate_video(&mut dec1, &mut dec2, None, |_| ())
+            .unwrap();
+        assert_metric_eq(0.9528, result.y);
+        assert_metric_eq(0.9180, result.u);
+        assert_metric_eq(0.9480, result.v);
+        assert_metric_eq(0.9462, result.avg);
+        // Consistent with the logarithmic scale used by `calculate_video_ssim`
+        assert_metric_eq(13.2572, -10.0 * (1.0 - result.y).log10());
+    }
+
+    #[test]
+    fn chroma_weights_yuv420p8() {
+        let decoders = || {
+            (
+                get_decoder(format!(
+                    "{}/../testfiles/yuv420p8_input.y4m",
+                    env!("CARGO_MANIFEST_DIR")
+                ))
+                .unwrap(),
+                get_decoder(format!(
+                    "{}/../testfiles/yuv420p8_output.y4m",
+                    env!("CARGO_MANIFEST_DIR")
+                ))
+                .unwrap(),
+            )
+        };
+        // The default weights for 4:2:0 are 0.25 for each chroma plane
+        let (mut dec1, mut dec2) = decoders();
+        let result = SsimOptions::new()
+            .chroma_weights(ChromaWeights::uniform(0.25))
+            .calculate_video(&mut dec1, &mut dec2, None, |_| ())
+            .unwrap();
+        assert_metric_eq(12.6899, result.avg);
+
+        let luma_only = ChromaWeights { u: 0.0, v: 0.0 };
+        let (mut dec1, mut dec2) = decoders();
+        let result = SsimOptions::new()
+            .chroma_weights(luma_only)
+            .calculate_video(&mut dec1, &mut dec2, None, |_| ())
+            .unwrap();
+        assert_metric_eq(result.y, result.avg);
+        let (mut dec1, mut dec2) = decoders();
+        let result = MsSsimOptions::new()
+            .chroma_weights(luma_only)
+            .calculate_video(&mut dec1, &mut dec2, None, |_| ())
+            .unwrap();
+        assert_metric_eq(result.y, result.avg);
+        let (mut dec1, mut dec2) = decoders();
+        let result = PsnrHvsOptions::new()
+            .chroma_weights(luma_only)
+            .calculate_video(&mut dec1, &mut dec2, None, |_| ())
+            .unwrap();
+        assert_metric_eq(result.y, result.avg);
+
+        let (mut dec1, mut dec2) = decoders();
+        let result = SsimOptions::new()
+            .chroma_weights(ChromaWeights { u: 0.5, v: 0.0 })
+            .calculate_video(&mut dec1, &mut dec2, None, |_| ())
+            .unwrap();
+        assert_metric_eq(12.3047, result.avg);
+
+        // The weights apply to single frames as well
+        let (mut dec1, mut dec2) = decoders();
+        let frame1 = dec1.read_video_frame::<u8>().unwrap();
+        let frame2 = dec2.read_video_frame::<u8>().unwrap();
+        let result = SsimOptions::new()
+            .chroma_weights(luma_only)
+            .calculate_frame(&frame1, &frame2, 8, ChromaSampling::Cs420)
+            .unwrap();
+        assert_eq!(result.y, result.avg);
+    }
+
+    #[test]
+    fn cs_yuv420p8() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let result = calculate_video_cs(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(13.2715, result.y);
+        assert_metric_eq(10.8650, result.u);
+        assert_metric_eq(12.8400, result.v);
+        assert_metric_eq(12.6994, result.avg);
+    }
+
+    #[test]
+    fn ssim_weighted_uniform_yuv420p8() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let details = dec1.get_video_details();
+        let frame1 = dec1.read_video_frame::<u8>().unwrap();
+        let frame2 = dec2.read_video_frame::<u8>().unwrap();
+        let expected = calculate_frame_ssim(&frame1, &frame2, 8, details.chroma_sampling).unwrap();
+        let luma = &frame1.planes[0].cfg;
+        let weights = vec![2.0; luma.width * luma.height];
+        let result =
+            calculate_frame_ssim_weighted(&frame1, &frame2, 8, details.chroma_sampling, &weights)
+                .unwrap();
+        assert_metric_eq(expected.y, result.y);
+        assert_metric_eq(expected.u, result.u);
+        assert_metric_eq(expected.v, result.v);
+        assert_metric_eq(expected.avg, result.avg);
+        assert!(calculate_frame_ssim_weighted(
+            &frame1,
+            &frame2,
+            8,
+            details.chroma_sampling,
+            &weights[1..]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn ssim_non_finite_guards() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let cs = dec1.get_video_details().chroma_sampling;
+        let frame1 = dec1.read_video_frame::<u8>().unwrap();
+
+        // Inverted content has negative contrast-structure scores at every scale
+        let mut frame2 = frame1.clone();
+        for plane in frame2.planes.iter_mut() {
+            for pix in plane.data.iter_mut() {
+                *pix = 255 - *pix;
+            }
+        }
+        let result = calculate_frame_msssim(&frame1, &frame2, 8, cs).unwrap();
+        assert!(result.y.is_finite());
+        assert!(result.avg.is_finite());
+        let result = calculate_frame_ssim(&frame1, &frame2, 8, cs).unwrap();
+        assert!(result.avg.is_finite());
+
+        let luma = &frame1.planes[0].cfg;
+        let mut weights = vec![0.0; luma.width * luma.height];
+        assert!(calculate_frame_ssim_weighted(&frame1, &frame2, 8, cs, &weights).is_err());
+        weights[0] = f32::NAN;
+        assert!(calculate_frame_ssim_weighted(&frame1, &frame2, 8, cs, &weights).is_err());
+        weights[0] = -1.0;
+        weights[1] = 2.0;
+        assert!(calculate_frame_ssim_weighted(&frame1, &frame2, 8, cs, &weights).is_err());
+
+        let config = SsimConfig {
+            k2: 2.0,
+            ..Default::default()
+        };
+        assert!(SsimOptions::new()
+            .config(config)
+            .calculate_frame(&frame1, &frame2, 8, cs)
+            .is_err());
+    }
+
+    #[test]
+    fn ssim_variance_pooling() {
+        let config = SsimConfig {
+            pooling: Pooling::Variance,
+            ..Default::default()
+        };
+        let cs = ChromaSampling::Cs420;
+        // Textured on the left half, flat on the right half
+        let mut frame1 = Frame::<u8>::new_with_padding(128, 64, cs, 0);
+        for plane in &mut frame1.planes {
+            let width = plane.cfg.width;
+            for (i, pix) in plane.data.iter_mut().enumerate() {
+                *pix = if i % width < width / 2 {
+                    (i * 7919 % 200) as u8
+                } else {
+                    100
+                };
+            }
+        }
+        // Only the flat half is distorted
+        let mut frame2 = frame1.clone();
+        for plane in &mut frame2.planes {
+            let width = plane.cfg.width;
+            for (i, pix) in plane.data.iter_mut().enumerate() {
+                if i % width >= width / 2 {
+                    *pix = 130;
+                }
+            }
+        }
+
+        let mean = calculate_frame_ssim(&frame1, &frame2, 8, cs).unwrap();
+        let weighted = SsimOptions::new()
+            .config(config)
+            .calculate_frame(&frame1, &frame2, 8, cs)
+            .unwrap();
+        assert!(weighted.y.is_finite());
+        assert!(weighted.y > mean.y);
+        assert!(weighted.avg > mean.avg);
+    }
+
+    #[test]
+    fn plane_ssim_u32() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p10_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p10_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let frame1 = dec1.read_video_frame::<u16>().unwrap();
+        let frame2 = dec2.read_video_frame::<u16>().unwrap();
+        let ssim = calculate_frame_ssim(&frame1, &frame2, 10, ChromaSampling::Cs420).unwrap();
+        let msssim = calculate_frame_msssim(&frame1, &frame2, 10, ChromaSampling::Cs420).unwrap();
+
+        for (i, plane) in [ssim.y, ssim.u, ssim.v].into_iter().enumerate() {
+            let to_u32 = |frame: &Frame<u16>| -> Vec<u32> {
+                let plane = &frame.planes[i];
+                plane
+                    .rows_iter()
+                    .flat_map(|row| &row[..plane.cfg.width])
+                    .map(|&pix| pix as u32)
+                    .collect()
+            };
+            let (plane1, plane2) = (to_u32(&frame1), to_u32(&frame2));
+            let cfg = &frame1.planes[i].cfg;
+            assert_eq!(
+                plane,
+                calculate_plane_ssim_u32(&plane1, &plane2, cfg.width, cfg.height, 10).unwrap()
+            );
+            let msssim_plane = [msssim.y, msssim.u, msssim.v][i];
+            assert_eq!(
+                msssim_plane,
+                calculate_plane_msssim_u32(&plane1, &plane2, cfg.width, cfg.height, 10).unwrap()
+            );
+        }
+
+        assert!(calculate_plane_ssim_u32(&[0; 15], &[0; 16], 4, 4, 8).is_err());
+        assert!(calculate_plane_ssim_u32(&[0; 16], &[0; 16], 4, 4, 17).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "gpu")]
+    fn ssim_gpu_accumulation() {
+        if !Accumulation::gpu_available() {
+            eprintln!("skipping ssim_gpu_accumulation: no GPU adapter available");
+            return;
+        }
+        fn compare<T: av_metrics::video::Pixel>(name: &str, bit_depth: usize) {
+            let mut dec1 = get_decoder(format!(
+                "{}/../testfiles/{}_input.y4m",
+                env!("CARGO_MANIFEST_DIR"),
+                name
+            ))
+            .unwrap();
+            let mut dec2 = get_decoder(format!(
+                "{}/../testfiles/{}_output.y4m",
+                env!("CARGO_MANIFEST_DIR"),
+                name
+            ))
+            .unwrap();
+            let cs = dec1.get_video_details().chroma_sampling;
+            let frame1 = dec1.read_video_frame::<T>().unwrap();
+            let frame2 = dec2.read_video_frame::<T>().unwrap();
+            for edge in [
+                EdgeHandling::Shrink,
+                EdgeHandling::Replicate,
+                EdgeHandling::Mirror,
+                EdgeHandling::Zero,
+            ] {
+                let cpu = SsimConfig {
+                    edge,
+                    ..Default::default()
+                };
+                let gpu = SsimConfig {
+                    accumulation: Accumulation::Gpu,
+                    ..cpu
+                };
+                let cpu = SsimOptions::new()
+                    .config(cpu)
+                    .calculate_frame(&frame1, &frame2, bit_depth, cs)
+                    .unwrap();
+                let gpu = SsimOptions::new()
+                    .config(gpu)
+                    .calculate_frame(&frame1, &frame2, bit_depth, cs)
+                    .unwrap();
+                cpu.check_approx_eq(&gpu, 0.001).unwrap();
+            }
+        }
+
+        compare::<u8>("yuv420p8", 8);
+        compare::<u16>("yuv420p10", 10);
+    }
+
+    #[test]
+    fn ssim_gpu_fallback() {
+        if Accumulation::gpu_available() {
+            return;
+        }
+        // Without a GPU, the windows are filtered like with `Accumulation::Float`
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p10_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p10_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let frame1 = dec1.read_video_frame::<u16>().unwrap();
+        let frame2 = dec2.read_video_frame::<u16>().unwrap();
+        let calculate = |accumulation| {
+            let config = SsimConfig {
+                accumulation,
+                ..Default::default()
+            };
+            SsimOptions::new()
+                .config(config)
+                .calculate_frame(&frame1, &frame2, 10, ChromaSampling::Cs420)
+                .unwrap()
+        };
+        let float = calculate(Accumulation::Float);
+        let gpu = calculate(Accumulation::Gpu);
+        for (float, gpu) in [(float.y, gpu.y), (float.u, gpu.u), (float.v, gpu.v)] {
+            assert_eq!(float.to_bits(), gpu.to_bits());
+        }
+    }
+
+    #[test]
+    fn ssim_float_accumulation() {
+        let config = SsimConfig {
+            accumulation: Accumulation::Float,
+            ..Default::default()
+        };
+        for (name, bit_depth) in [("yuv420p8", 8), ("yuv444p8", 8), ("yuv420p10", 10)] {
+            let mut dec1 = get_decoder(format!(
+                "{}/../testfiles/{}_input.y4m",
+                env!("CARGO_MANIFEST_DIR"),
+                name
+            ))
+            .unwrap();
+            let mut dec2 = get_decoder(format!(
+                "{}/../testfiles/{}_output.y4m",
+                env!("CARGO_MANIFEST_DIR"),
+                name
+            ))
+            .unwrap();
+            let cs = dec1.get_video_details().chroma_sampling;
+            let (integer, float) = if bit_depth > 8 {
+                let frame1 = dec1.read_video_frame::<u16>().unwrap();
+                let frame2 = dec2.read_video_frame::<u16>().unwrap();
+                (
+                    calculate_frame_ssim(&frame1, &frame2, bit_depth, cs).unwrap(),
+                    SsimOptions::new()
+                        .config(config)
+                        .calculate_frame(&frame1, &frame2, bit_depth, cs)
+                        .unwrap(),
+                )
+            } else {
+                let frame1 = dec1.read_video_frame::<u8>().unwrap();
+                let frame2 = dec2.read_video_frame::<u8>().unwrap();
+                (
+                    calculate_frame_ssim(&frame1, &frame2, bit_depth, cs).unwrap(),
+                    SsimOptions::new()
+                        .config(config)
+                        .calculate_frame(&frame1, &frame2, bit_depth, cs)
+                        .unwrap(),
+                )
+            };
+            integer.check_approx_eq(&float, 0.001).unwrap();
+        }
+    }
+
+    #[test]
+    fn tiled_yuv420p8() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let cs = dec1.get_video_details().chroma_sampling;
+        let frame1 = dec1.read_video_frame::<u8>().unwrap();
+        let frame2 = dec2.read_video_frame::<u8>().unwrap();
+
+        let tiles = calculate_frame_ssim_tiled(&frame1, &frame2, 8, cs, 256, 128).unwrap();
+        assert_eq!(3, tiles.len());
+        assert!(tiles.iter().all(|row| row.len() == 3));
+        // Edge tiles are smaller
+        let rect = Rect {
+            x: 512,
+            y: 256,
+            width: 128,
+            height: 104,
+        };
+        let tile1 = crop_frame(&frame1, rect).unwrap();
+        let tile2 = crop_frame(&frame2, rect).unwrap();
+        assert_eq!(
+            calculate_frame_ssim(&tile1, &tile2, 8, cs).unwrap(),
+            tiles[2][2]
+        );
+        let tiles = calculate_frame_psnr_tiled(&frame1, &frame2, 8, cs, 256, 128).unwrap();
+        assert_eq!(
+            calculate_frame_psnr(&tile1, &tile2, 8, cs).unwrap(),
+            tiles[2][2]
+        );
+
+        // A single tile covers the whole frame
+        let tiles = calculate_frame_psnr_tiled(&frame1, &frame2, 8, cs, 1024, 1024).unwrap();
+        assert_eq!(
+            vec![vec![calculate_frame_psnr(&frame1, &frame2, 8, cs).unwrap()]],
+            tiles
+        );
+
+        assert!(calculate_frame_ssim_tiled(&frame1, &frame2, 8, cs, 0, 64).is_err());
+        assert!(calculate_frame_ssim_tiled(&frame1, &frame2, 8, cs, 63, 64).is_err());
+    }
+
+    #[test]
+    fn ssim_map_yuv420p8() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let cs = dec1.get_video_details().chroma_sampling;
+        let frame1 = dec1.read_video_frame::<u8>().unwrap();
+        let frame2 = dec2.read_video_frame::<u8>().unwrap();
+        let result = SsimOptions::new()
+            .raw(true)
+            .calculate_frame(&frame1, &frame2, 8, cs)
+            .unwrap();
+        let maps = calculate_frame_ssim_map(&frame1, &frame2, 8, cs).unwrap();
+        for ((map, plane), expected) in maps
+            .iter()
+            .zip(frame1.planes.iter())
+            .zip([result.y, result.u, result.v])
+        {
+            assert_eq!(plane.cfg.width, map.width);
+            assert_eq!(plane.cfg.height, map.height);
+            assert_eq!(map.width * map.height, map.data.len());
+            assert!(map.data.iter().all(|&v| v <= 1.0));
+            // Windows near the edges have a lower weight in the score
+            let mean = map.data.iter().map(|&v| v as f64).sum::<f64>() / map.data.len() as f64;
+            assert!((expected - mean).abs() < 0.001);
+        }
+
+        let maps = calculate_frame_ssim_map(&frame1, &frame1, 8, cs).unwrap();
+        assert!(maps[0].data.iter().all(|&v| v == 1.0));
+    }
+
+    #[test]
+    fn msssim_yuv420p8() {
+        let mut dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let result = calculate_video_msssim(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(18.8343, result.y);
+        assert_metric_eq(16.6943, result.u);
+        assert_metric_eq(18.7662, result.v);
+        assert_metric_eq(18.3859, result.avg);
     }
 
     #[test]
-    fn apsnr_yuv420p10() {
+    fn msssim_scales_yuv420p8() {
         let mut dec1 = get_decoder(format!(
-            "{}/../testfiles/yuv420p10_input.y4m",
+            "{}/../testfiles/yuv420p8_input.y4m",
             env!("CARGO_MANIFEST_DIR")
         ))
         .unwrap();
         let mut dec2 = get_decoder(format!(
-            "{}/../testfiles/yuv420p10_output.y4m",
+            "{}/../testfiles/yuv420p8_output.y4m",
             env!("CARGO_MANIFEST_DIR")
         ))
         .unwrap();
-        let result = calculate_video_apsnr(&mut dec1, &mut dec2, None, |_| ()).unwrap();
-        assert_metric_eq(32.5586, result.y);
-        assert_metric_eq(36.4923, result.u);
-        assert_metric_eq(39.8563, result.v);
-        assert_metric_eq(33.7200, result.avg);
+        let frame1 = dec1.read_video_frame::<u8>().unwrap();
+        let frame2 = dec2.read_video_frame::<u8>().unwrap();
+        let cs = ChromaSampling::Cs420;
+        let expected = calculate_frame_msssim(&frame1, &frame2, 8, cs).unwrap();
+        let scales = calculate_frame_msssim_scales(&frame1, &frame2, 8, cs).unwrap();
+
+        // Combining the scales with the MSSSIM weights gives the regular score
+        const WEIGHTS: [f64; 5] = [0.0448, 0.2856, 0.3001, 0.2363, 0.1333];
+        for (plane, expected) in scales.iter().zip([expected.y, expected.u, expected.v]) {
+            let n = plane.ssim.len();
+            assert_eq!(n, plane.cs.len());
+            assert!((1..=5).contains(&n));
+            let weight_scale = WEIGHTS.iter().sum::<f64>() / WEIGHTS[..n].iter().sum::<f64>();
+            let msssim = plane.cs[..n - 1]
+                .iter()
+                .zip(WEIGHTS)
+                .map(|(cs, weight)| cs.max(0.0).powf(weight * weight_scale))
+                .product::<f64>()
+                * plane.ssim[n - 1].powf(WEIGHTS[n - 1] * weight_scale);
+            assert_metric_eq(expected, -10.0 * (1.0 - msssim).log10());
+        }
+        // The luma plane is large enough for all five scales
+        assert_eq!(5, scales[0].ssim.len());
+        let fixed = MsSsimOptions::new()
+            .scales(5)
+            .calculate_frame(&frame1, &frame2, 8, cs);
+        assert_metric_eq(expected.y, fixed.unwrap().y);
+
+        let identical = calculate_frame_msssim_scales(&frame1, &frame1, 8, cs).unwrap();
+        for score in identical[0].ssim.iter().chain(&identical[0].cs) {
+            assert!((score - 1.0).abs() < 1e-9);
+        }
     }
 
     #[test]
-    fn psnr_hvs_yuv420p8() {
+    fn msssim_16bit_8k_white() {
+        // Full-white 16-bit frames give the largest possible SSIM moments.
+        // With samples scaled by 257, 16-bit scores must match the 8-bit ones.
+        let (width, height) = (7680, 4320);
+        let mut frame1 = Frame::<u8>::new_with_padding(width, height, ChromaSampling::Cs420, 0);
+        let mut frame2 = frame1.clone();
+        let mut frame1_16 = Frame::<u16>::new_with_padding(width, height, ChromaSampling::Cs420, 0);
+        let mut frame2_16 = frame1_16.clone();
+        for plane in 0..3 {
+            for (i, p2) in frame2.planes[plane].data.iter_mut().enumerate() {
+                *p2 = if i % 7 == 0 { 0 } else { 255 };
+            }
+            frame1.planes[plane].data.fill(255);
+            for (src, dst) in frame2.planes[plane]
+                .data
+                .iter()
+                .zip(frame2_16.planes[plane].data.iter_mut())
+            {
+                *dst = *src as u16 * 257;
+            }
+            frame1_16.planes[plane].data.fill(u16::MAX);
+        }
+
+        let expected = calculate_frame_msssim(&frame1, &frame2, 8, ChromaSampling::Cs420).unwrap();
+        let result =
+            calculate_frame_msssim(&frame1_16, &frame2_16, 16, ChromaSampling::Cs420).unwrap();
+        assert_metric_eq(expected.y, result.y);
+        assert_metric_eq(expected.u, result.u);
+        assert_metric_eq(expected.v, result.v);
+        assert_metric_eq(expected.avg, result.avg);
+    }
+
+    #[test]
+    fn padded_planes_yuv420p8() {
         let mut dec1 = get_decoder(format!(
             "{}/../testfiles/yuv420p8_input.y4m",
             env!("CARGO_MANIFEST_DIR")
@@ -184,34 +2906,426 @@ mod tests {
             env!("CARGO_MANIFEST_DIR")
         ))
         .unwrap();
-        let result = calculate_video_psnr_hvs(&mut dec1, &mut dec2, None, |_| ()).unwrap();
-        assert_metric_eq(34.3227, result.y);
-        assert_metric_eq(37.7400, result.u);
-        assert_metric_eq(40.5570, result.v);
-        assert_metric_eq(31.8676, result.avg);
+        let details = dec1.get_video_details();
+        let cs = details.chroma_sampling;
+        let frame1 = dec1.read_video_frame::<u8>().unwrap();
+        let frame2 = dec2.read_video_frame::<u8>().unwrap();
+
+        // Copy the frames into planes with padding around them, which is filled
+        // with different values so that it affects the scores if it is read.
+        let pad = |frame: &Frame<u8>, fill: u8| {
+            let mut padded = Frame::new_with_padding(details.width, details.height, cs, 32);
+            for (src, dst) in frame.planes.iter().zip(padded.planes.iter_mut()) {
+                dst.data.fill(fill);
+                for (src_row, dst_row) in src.rows_iter().zip(dst.rows_iter_mut()) {
+                    dst_row[..src_row.len()].copy_from_slice(src_row);
+                }
+            }
+            padded
+        };
+        let padded1 = pad(&frame1, 0);
+        let padded2 = pad(&frame2, 255);
+        assert_ne!(padded1.planes[0].cfg.stride, padded1.planes[0].cfg.width);
+
+        let expected = calculate_frame_psnr(&frame1, &frame2, 8, cs).unwrap();
+        let result = calculate_frame_psnr(&padded1, &padded2, 8, cs).unwrap();
+        assert_eq!(expected, result);
+        let expected = calculate_frame_ssim(&frame1, &frame2, 8, cs).unwrap();
+        let result = calculate_frame_ssim(&padded1, &padded2, 8, cs).unwrap();
+        assert_eq!(expected, result);
+        let expected = calculate_frame_msssim(&frame1, &frame2, 8, cs).unwrap();
+        let result = calculate_frame_msssim(&padded1, &padded2, 8, cs).unwrap();
+        assert_eq!(expected, result);
+        let expected = calculate_frame_psnr_hvs(&frame1, &frame2, 8, cs).unwrap();
+        let result = calculate_frame_psnr_hvs(&padded1, &padded2, 8, cs).unwrap();
+        assert_eq!(expected, result);
+        let expected = calculate_frame_ciede(&frame1, &frame2, 8, cs).unwrap();
+        let result = calculate_frame_ciede(&padded1, &padded2, 8, cs).unwrap();
+        assert_eq!(expected, result);
     }
 
     #[test]
-    fn psnr_hvs_yuv422p8() {
+    fn dyn_metrics() {
+        let get_decoders = || {
+            let dec1 = get_decoder(format!(
+                "{}/../testfiles/yuv420p8_input.y4m",
+                env!("CARGO_MANIFEST_DIR")
+            ))
+            .unwrap();
+            let dec2 = get_decoder(format!(
+                "{}/../testfiles/yuv420p8_output.y4m",
+                env!("CARGO_MANIFEST_DIR")
+            ))
+            .unwrap();
+            (dec1, dec2)
+        };
+        // Unknown metrics are skipped
+        let metrics: Vec<_> = ["psnr", "ssim", "vmaf", "ciede2000"]
+            .iter()
+            .filter_map(|name| from_name(name))
+            .collect();
+        assert_eq!(3, metrics.len());
+        assert_eq!("ssim", metrics[1].name());
+
+        let (mut dec1, mut dec2) = get_decoders();
+        let expected = calculate_video_psnr(&mut dec1, &mut dec2, Some(2), |_| ()).unwrap();
+        let (mut dec1, mut dec2) = get_decoders();
+        let result = metrics[0].calculate_video(&mut dec1, &mut dec2, Some(2), &|_| ());
+        assert_eq!(MetricResult::Planar(expected), result.unwrap());
+
+        let (mut dec1, mut dec2) = get_decoders();
+        let frame1 = dec1.read_video_frame::<u8>().unwrap();
+        let frame2 = dec2.read_video_frame::<u8>().unwrap();
+        let cs = ChromaSampling::Cs420;
+        let expected = calculate_frame_ssim(&frame1, &frame2, 8, cs).unwrap();
+        let result = metrics[1].calculate_frame_u8(&frame1, &frame2, 8, cs);
+        assert_eq!(MetricResult::Planar(expected), result.unwrap());
+        let expected = calculate_frame_ciede(&frame1, &frame2, 8, cs).unwrap();
+        let result = metrics[2].calculate_frame_u8(&frame1, &frame2, 8, cs);
+        assert_eq!(MetricResult::Single(expected), result.unwrap());
+    }
+
+    #[test]
+    fn metric_enum() {
+        assert_eq!(Metric::MsSsim, "msssim".parse().unwrap());
+        assert!(matches!(
+            "vmaf".parse::<Metric>(),
+            Err(MetricsError::UnsupportedInput { .. })
+        ));
+        // Accepted by `from_name`, but not a per-plane metric
+        assert!(matches!(
+            "ciede2000".parse::<Metric>(),
+            Err(MetricsError::UnsupportedInput { .. })
+        ));
+        for metric in Metric::ALL {
+            assert_eq!(metric, metric.to_string().parse().unwrap());
+        }
+
+        let open = |name: &str| {
+            get_decoder(format!(
+                "{}/../testfiles/{}.y4m",
+                env!("CARGO_MANIFEST_DIR"),
+                name
+            ))
+            .unwrap()
+        };
+        let mut dec1 = open("yuv420p8_input");
+        let mut dec2 = open("yuv420p8_output");
+        let expected = calculate_video_ssim(&mut dec1, &mut dec2, Some(2), |_| ()).unwrap();
+        let mut dec1 = open("yuv420p8_input");
+        let mut dec2 = open("yuv420p8_output");
+        let result =
+            calculate_video_metric(Metric::Ssim, &mut dec1, &mut dec2, Some(2), |_| ()).unwrap();
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn dyn_metric_results() {
+        let open_decoders = || {
+            let dec1 = get_decoder(format!(
+                "{}/../testfiles/yuv420p8_input.y4m",
+                env!("CARGO_MANIFEST_DIR")
+            ))?;
+            let dec2 = get_decoder(format!(
+                "{}/../testfiles/yuv420p8_output.y4m",
+                env!("CARGO_MANIFEST_DIR")
+            ))?;
+            Ok((dec1, dec2))
+        };
+        let metrics: Vec<_> = ["psnr", "ciede2000"]
+            .iter()
+            .filter_map(|name| from_name(name))
+            .collect();
+        let results = calculate_video_metrics(&metrics, open_decoders, Some(2), |_| ()).unwrap();
+        assert_eq!(2, results.frames);
+        assert_eq!(640, results.video_details.width);
+        assert!(results.psnr.is_some());
+        assert!(results.ciede2000.is_some());
+        assert!(results.ssim.is_none());
+
+        let (mut dec1, mut dec2) = open_decoders().unwrap();
+        let expected = calculate_video_psnr(&mut dec1, &mut dec2, Some(2), |_| ()).unwrap();
+        assert_eq!(Some(expected), results.psnr);
+    }
+
+    #[test]
+    fn batch_jobs() {
+        let path = |name: &str| format!("{}/../testfiles/{}.y4m", env!("CARGO_MANIFEST_DIR"), name);
+        let job = |reference: &str, distorted: &str, metric: &str| BatchJob {
+            reference: path(reference),
+            distorted: path(distorted),
+            metric: from_name(metric).unwrap(),
+        };
+        let jobs = vec![
+            job("yuv420p8_input", "yuv420p8_output", "psnr"),
+            job("yuv420p8_input", "yuv444p8_output", "psnr"),
+            job("yuv420p8_input", "yuv420p8_output", "ciede2000"),
+            job("yuv420p8_input", "missing", "ssim"),
+        ];
+        let finished = Mutex::new(Vec::new());
+        let results = calculate_batch(
+            &jobs,
+            |path| Ok(get_decoder(path)?),
+            2,
+            Some(2),
+            |index, frameno| {
+                if frameno == usize::MAX {
+                    finished.lock().unwrap().push(index);
+                }
+            },
+        )
+        .unwrap();
+        assert_eq!(4, results.len());
+
+        let mut dec1 = get_decoder(path("yuv420p8_input")).unwrap();
+        let mut dec2 = get_decoder(path("yuv420p8_output")).unwrap();
+        let expected = calculate_video_psnr(&mut dec1, &mut dec2, Some(2), |_| ()).unwrap();
+        assert_eq!(
+            MetricResult::Planar(expected),
+            *results[0].as_ref().unwrap()
+        );
+        assert!(matches!(
+            results[1],
+            Err(MetricsError::VideoDetailsMismatch { .. })
+        ));
+        assert!(matches!(results[2], Ok(MetricResult::Single(_))));
+        assert!(results[3].is_err());
+
+        let mut finished = finished.into_inner().unwrap();
+        finished.sort_unstable();
+        assert_eq!(vec![0, 2], finished);
+
+        assert!(calculate_batch(&jobs, |path| Ok(get_decoder(path)?), 0, None, |_, _| ()).is_err());
+    }
+
+    #[test]
+    fn visually_lossless_verdict() {
+        let open_decoders = || {
+            let dec1 = get_decoder(format!(
+                "{}/../testfiles/yuv420p8_input.y4m",
+                env!("CARGO_MANIFEST_DIR")
+            ))?;
+            let dec2 = get_decoder(format!(
+                "{}/../testfiles/yuv420p8_output.y4m",
+                env!("CARGO_MANIFEST_DIR")
+            ))?;
+            Ok((dec1, dec2))
+        };
+        let verdict = LosslessThresholds::default()
+            .check_video(open_decoders, None, |_| ())
+            .unwrap();
+        let failed = match verdict {
+            Verdict::Lossy(failed) => failed,
+            Verdict::VisuallyLossless => panic!("Expected a lossy verdict"),
+        };
+        assert_eq!(2, failed.len());
+        assert_eq!(Criterion::Psnr, failed[0].criterion);
+        assert_metric_eq(45.0, failed[0].threshold);
+        assert_metric_eq(33.6861, failed[0].value);
+        assert_eq!(Criterion::Ssim, failed[1].criterion);
+        assert_metric_eq(0.995, failed[1].threshold);
+        assert!(failed[1].value < 0.995);
+
+        let thresholds = LosslessThresholds {
+            psnr: Some(30.0),
+            ssim: None,
+        };
+        let verdict = thresholds.check_video(open_decoders, None, |_| ()).unwrap();
+        assert!(verdict.is_visually_lossless());
+
+        // A mean SSIM of 0.999 is 30 dB on the logarithmic scale
+        let results = MetricResults {
+            psnr: Some(PlanarMetrics {
+                avg: 50.0,
+                ..Default::default()
+            }),
+            ssim: Some(PlanarMetrics {
+                avg: 30.0,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let verdict = LosslessThresholds::default().check(&results).unwrap();
+        assert_eq!(Verdict::VisuallyLossless, verdict);
+
+        let results = MetricResults {
+            ssim: None,
+            ..results
+        };
+        assert!(matches!(
+            LosslessThresholds::default().check(&results),
+            Err(MetricsError::UnsupportedInput { .. })
+        ));
+    }
+
+    #[test]
+    fn frame_from_raw_planes() {
         let mut dec1 = get_decoder(format!(
-            "{}/../testfiles/yuv422p8_input.y4m",
+            "{}/../testfiles/yuv420p8_input.y4m",
             env!("CARGO_MANIFEST_DIR")
         ))
         .unwrap();
         let mut dec2 = get_decoder(format!(
-            "{}/../testfiles/yuv422p8_output.y4m",
+            "{}/../testfiles/yuv420p8_output.y4m",
             env!("CARGO_MANIFEST_DIR")
         ))
         .unwrap();
-        let result = calculate_video_psnr_hvs(&mut dec1, &mut dec2, None, |_| ()).unwrap();
-        assert_metric_eq(45.3473, result.y);
-        assert_metric_eq(46.3951, result.u);
-        assert_metric_eq(45.1177, result.v);
-        assert_metric_eq(39.5041, result.avg);
+        let cs = dec1.get_video_details().chroma_sampling;
+        let frame1 = dec1.read_video_frame::<u8>().unwrap();
+        let frame2 = dec2.read_video_frame::<u8>().unwrap();
+
+        // Copy the planes into buffers with 16 samples of padding per row
+        let rebuild = |frame: &Frame<u8>| {
+            let planes = frame.planes.clone().map(|plane| {
+                let (width, height) = (plane.cfg.width, plane.cfg.height);
+                let stride = width + 16;
+                let mut data = vec![0u8; stride * height];
+                for (src_row, dst_row) in plane.rows_iter().zip(data.chunks_mut(stride)) {
+                    dst_row[..width].copy_from_slice(&src_row[..width]);
+                }
+                plane_from_slice(&data, width, height, stride, plane.cfg.xdec, plane.cfg.ydec)
+                    .unwrap()
+            });
+            frame_from_planes(planes).unwrap()
+        };
+        let expected = calculate_frame_ssim(&frame1, &frame2, 8, cs).unwrap();
+        let result = calculate_frame_ssim(&rebuild(&frame1), &rebuild(&frame2), 8, cs).unwrap();
+        assert_eq!(expected, result);
+
+        let data = vec![0u8; 64 * 64];
+        assert!(plane_from_slice(&data, 64, 65, 64, 0, 0).is_err());
+        assert!(plane_from_slice(&data, 64, 32, 32, 0, 0).is_err());
+        let luma = plane_from_slice(&data, 64, 64, 64, 0, 0).unwrap();
+        let chroma = plane_from_slice(&data, 32, 16, 32, 1, 1).unwrap();
+        assert!(frame_from_planes([luma, chroma.clone(), chroma]).is_err());
     }
 
     #[test]
-    fn psnr_hvs_yuv444p8() {
+    fn yuv_bytes_metrics() {
+        let open = |name: &str| {
+            get_decoder(format!(
+                "{}/../testfiles/{}.y4m",
+                env!("CARGO_MANIFEST_DIR"),
+                name
+            ))
+            .unwrap()
+        };
+
+        let mut dec1 = open("yuv420p8_input");
+        let mut dec2 = open("yuv420p8_output");
+        let details = dec1.get_video_details();
+        let (width, height, cs) = (details.width, details.height, details.chroma_sampling);
+        let frame1 = dec1.read_video_frame::<u8>().unwrap();
+        let frame2 = dec2.read_video_frame::<u8>().unwrap();
+        let to_bytes = |frame: &Frame<u8>| -> Vec<u8> {
+            frame
+                .planes
+                .iter()
+                .flat_map(|plane| plane.rows_iter().flat_map(|row| &row[..plane.cfg.width]))
+                .copied()
+                .collect()
+        };
+        let (data1, data2) = (to_bytes(&frame1), to_bytes(&frame2));
+        let result = calculate_yuv_metrics(&data1, &data2, width, height, 8, cs).unwrap();
+        assert_eq!(
+            calculate_frame_psnr(&frame1, &frame2, 8, cs).unwrap(),
+            result.psnr
+        );
+        assert_eq!(
+            calculate_frame_ssim(&frame1, &frame2, 8, cs).unwrap(),
+            result.ssim
+        );
+        assert!(calculate_yuv_metrics(&data1, &data2[1..], width, height, 8, cs).is_err());
+        assert!(calculate_yuv_metrics(&data1, &data2, width, height - 2, 8, cs).is_err());
+        assert!(frame_from_yuv_bytes::<u16>(&data1, width, height, 8, cs).is_err());
+
+        let mut dec1 = open("yuv420p10_input");
+        let mut dec2 = open("yuv420p10_output");
+        let frame1 = dec1.read_video_frame::<u16>().unwrap();
+        let frame2 = dec2.read_video_frame::<u16>().unwrap();
+        let to_bytes = |frame: &Frame<u16>| -> Vec<u8> {
+            frame
+                .planes
+                .iter()
+                .flat_map(|plane| plane.rows_iter().flat_map(|row| &row[..plane.cfg.width]))
+                .flat_map(|sample| sample.to_le_bytes())
+                .collect()
+        };
+        let (data1, data2) = (to_bytes(&frame1), to_bytes(&frame2));
+        let result = calculate_yuv_metrics(&data1, &data2, width, height, 10, cs).unwrap();
+        assert_eq!(
+            calculate_frame_psnr(&frame1, &frame2, 10, cs).unwrap(),
+            result.psnr
+        );
+        assert_eq!(
+            calculate_frame_ssim(&frame1, &frame2, 10, cs).unwrap(),
+            result.ssim
+        );
+    }
+
+    #[test]
+    fn frame_layout() {
+        for cs in [
+            ChromaSampling::Cs420,
+            ChromaSampling::Cs422,
+            ChromaSampling::Cs444,
+            ChromaSampling::Cs400,
+        ] {
+            let frame = Frame::<u8>::new_with_padding(64, 48, cs, 0);
+            assert_eq!(frame.luma_dimensions(), (64, 48));
+            assert_eq!(frame.chroma_dimensions(), cs.get_chroma_dimensions(64, 48));
+            assert_eq!(
+                frame.chroma_decimation(),
+                cs.get_decimation().unwrap_or((0, 0))
+            );
+            assert_eq!(frame.chroma_sampling(), Some(cs));
+        }
+
+        let mut frame = Frame::<u8>::new_with_padding(64, 48, ChromaSampling::Cs420, 0);
+        for plane in &mut frame.planes[1..] {
+            plane.cfg.xdec = 2;
+            plane.cfg.ydec = 0;
+        }
+        assert_eq!(frame.chroma_decimation(), (2, 0));
+        assert_eq!(frame.chroma_sampling(), None);
+    }
+
+    #[test]
+    fn packed_yuv_frame() {
+        let mut dec = get_decoder(format!(
+            "{}/../testfiles/yuv422p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let details = dec.get_video_details();
+        let (width, height) = (details.width, details.height);
+        let frame = dec.read_video_frame::<u8>().unwrap();
+        let pack = |format: PackedFormat| -> Vec<u8> {
+            let [y, u, v] = &frame.planes;
+            (0..height)
+                .flat_map(|row| (0..width / 2).map(move |col| (row, col)))
+                .flat_map(|(row, col)| {
+                    let (y0, y1) = (y.p(col * 2, row), y.p(col * 2 + 1, row));
+                    let (u, v) = (u.p(col, row), v.p(col, row));
+                    match format {
+                        PackedFormat::Uyvy => [u, y0, v, y1],
+                        PackedFormat::Yuyv => [y0, u, y1, v],
+                    }
+                })
+                .collect()
+        };
+        for format in [PackedFormat::Uyvy, PackedFormat::Yuyv] {
+            let data = pack(format);
+            let unpacked = frame_from_packed_yuv::<u8>(&data, format, width, height).unwrap();
+            assert!(frames_identical(&frame, &unpacked));
+            assert!(frame_from_packed_yuv::<u8>(&data[1..], format, width, height).is_err());
+            assert!(frame_from_packed_yuv::<u16>(&data, format, width, height).is_err());
+        }
+        assert!(frame_from_packed_yuv::<u8>(&[0; 6], PackedFormat::Uyvy, 3, 1).is_err());
+    }
+
+    #[test]
+    fn legacy_chroma_subsampling() {
         let mut dec1 = get_decoder(format!(
             "{}/../testfiles/yuv444p8_input.y4m",
             env!("CARGO_MANIFEST_DIR")
@@ -222,34 +3336,249 @@ mod tests {
             env!("CARGO_MANIFEST_DIR")
         ))
         .unwrap();
-        let result = calculate_video_psnr_hvs(&mut dec1, &mut dec2, None, |_| ()).unwrap();
-        assert_metric_eq(34.1887, result.y);
-        assert_metric_eq(38.0190, result.u);
-        assert_metric_eq(40.4087, result.v);
-        assert_metric_eq(27.2354, result.avg);
+        let frame1 = dec1.read_video_frame::<u8>().unwrap();
+        let frame2 = dec2.read_video_frame::<u8>().unwrap();
+
+        // Subsample the 4:4:4 chroma planes by picking every n-th sample
+        let subsample = |frame: &Frame<u8>, xdec: usize, ydec: usize| {
+            let [luma, u, v] = &frame.planes;
+            let chroma = |plane: &Plane<u8>| {
+                let (width, height) = (plane.cfg.width, plane.cfg.height);
+                let data: Vec<u8> = plane
+                    .rows_iter()
+                    .take(height)
+                    .step_by(1 << ydec)
+                    .flat_map(|row| row[..width].iter().step_by(1 << xdec).copied())
+                    .collect();
+                let width = (width + (1 << xdec) - 1) >> xdec;
+                let height = (height + (1 << ydec) - 1) >> ydec;
+                plane_from_slice(&data, width, height, width, xdec, ydec).unwrap()
+            };
+            frame_from_planes([luma.clone(), chroma(u), chroma(v)]).unwrap()
+        };
+
+        // 4:1:1 and 4:4:0
+        for (xdec, ydec) in [(2, 0), (0, 1)] {
+            let frame1 = subsample(&frame1, xdec, ydec);
+            let frame2 = subsample(&frame2, xdec, ydec);
+            let cs = ChromaSampling::Cs444;
+            for result in [
+                calculate_frame_psnr(&frame1, &frame2, 8, cs).unwrap(),
+                calculate_frame_psnr_hvs(&frame1, &frame2, 8, cs).unwrap(),
+                calculate_frame_ssim(&frame1, &frame2, 8, cs).unwrap(),
+                calculate_frame_msssim(&frame1, &frame2, 8, cs).unwrap(),
+            ] {
+                assert!(result.y.is_finite());
+                assert!(result.u.is_finite());
+                assert!(result.v.is_finite());
+            }
+
+            // The chroma planes are weighted by their actual size, not by `cs`
+            let weight = 1.0 / (1 << (xdec + ydec)) as f64;
+            let ssim_avg = |result: &PlanarMetrics| {
+                let raw = |score: f64| 1.0 - 10f64.powf(-score / 10.0);
+                let sum = raw(result.y) + weight * (raw(result.u) + raw(result.v));
+                -10.0 * (1.0 - sum / (1.0 + 2.0 * weight)).log10()
+            };
+            // PSNR-HVS scales the weighted error by the total weight
+            let psnr_hvs_avg = |result: &PlanarMetrics| {
+                let error = |score: f64| 10f64.powf(-score / 10.0);
+                let sum = error(result.y) + weight * (error(result.u) + error(result.v));
+                -10.0 * (sum * (1.0 + 2.0 * weight)).log10()
+            };
+            let ssim = calculate_frame_ssim(&frame1, &frame2, 8, cs).unwrap();
+            assert_metric_eq(ssim_avg(&ssim), ssim.avg);
+            let msssim = calculate_frame_msssim(&frame1, &frame2, 8, cs).unwrap();
+            assert_metric_eq(ssim_avg(&msssim), msssim.avg);
+            let psnr_hvs = calculate_frame_psnr_hvs(&frame1, &frame2, 8, cs).unwrap();
+            assert_metric_eq(psnr_hvs_avg(&psnr_hvs), psnr_hvs.avg);
+
+            // The same weights are used for videos
+            let (width, height) = frame1.luma_dimensions();
+            let details = VideoDetails::new(width, height, 8, cs);
+            let decoder = |frame: &Frame<u8>| {
+                let frame = frame.clone();
+                GeneratorDecoder::new(move |_| frame.clone(), details, 2)
+            };
+            let decoders = || (decoder(&frame1), decoder(&frame2));
+            let (mut dec1, mut dec2) = decoders();
+            let ssim = calculate_video_ssim(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+            assert_metric_eq(ssim_avg(&ssim), ssim.avg);
+            let (mut dec1, mut dec2) = decoders();
+            let msssim = calculate_video_msssim(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+            assert_metric_eq(ssim_avg(&msssim), msssim.avg);
+            let (mut dec1, mut dec2) = decoders();
+            let psnr_hvs = calculate_video_psnr_hvs(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+            assert_metric_eq(psnr_hvs_avg(&psnr_hvs), psnr_hvs.avg);
+
+            let ciede = calculate_frame_ciede(&frame1, &frame2, 8, cs);
+            if xdec > 1 {
+                assert!(ciede.is_err());
+            } else {
+                assert!(ciede.unwrap().is_finite());
+            }
+        }
+
+        assert_eq!(
+            ChromaWeights::uniform(0.25),
+            ChromaWeights::from_decimation(2, 0)
+        );
+        assert_eq!(
+            ChromaWeights::uniform(0.5),
+            ChromaWeights::from_decimation(0, 1)
+        );
+        assert_eq!(
+            ChromaWeights::from(ChromaSampling::Cs420),
+            ChromaWeights::from_decimation(1, 1)
+        );
+        assert_eq!(
+            ChromaWeights::from(ChromaSampling::Cs422),
+            ChromaWeights::from_override(None, ChromaSampling::Cs422)
+        );
+        assert_eq!(
+            ChromaWeights::uniform(2.0),
+            ChromaWeights::from_override(Some(2.0), ChromaSampling::Cs422)
+        );
     }
 
     #[test]
-    fn psnr_hvs_yuv420p10() {
-        let mut dec1 = get_decoder(format!(
-            "{}/../testfiles/yuv420p10_input.y4m",
-            env!("CARGO_MANIFEST_DIR")
-        ))
-        .unwrap();
-        let mut dec2 = get_decoder(format!(
-            "{}/../testfiles/yuv420p10_output.y4m",
+    fn planar_metrics_comparison() {
+        let golden = PlanarMetrics {
+            y: 13.2989,
+            u: 14.0089,
+            v: 15.7419,
+            avg: 14.2338,
+        };
+        let current = PlanarMetrics {
+            y: 13.3089,
+            ..golden
+        };
+        let diff = current.difference(&golden);
+        assert!((diff.y - 0.01).abs() < 1e-9);
+        assert_eq!(0.0, diff.u);
+        assert!(current.approx_eq(&golden, 0.02));
+        assert!(!current.approx_eq(&golden, 0.001));
+        let message = current.check_approx_eq(&golden, 0.001).unwrap_err();
+        assert!(message.contains("y: 13.3089 vs 13.2989"), "{}", message);
+        assert!(!message.contains("avg"), "{}", message);
+
+        // Monochrome results have no chroma scores
+        let mono = PlanarMetrics {
+            u: f64::NAN,
+            v: f64::NAN,
+            ..golden
+        };
+        assert!(mono.approx_eq(&mono, 0.0));
+        assert!(mono.difference(&golden).u.is_nan());
+        assert!(!mono.approx_eq(&golden, 1.0));
+
+        let identical = PlanarMetrics {
+            y: f64::INFINITY,
+            u: f64::INFINITY,
+            v: f64::INFINITY,
+            avg: f64::INFINITY,
+        };
+        assert!(identical.approx_eq(&identical, 0.0));
+        assert!(!identical.approx_eq(&golden, 1.0));
+
+        assert_eq!(golden.to_array(), [13.2989, 14.0089, 15.7419, 14.2338]);
+        assert_eq!(PlanarMetrics::from_array(golden.to_array()), golden);
+    }
+
+    #[test]
+    fn identical_frames() {
+        let mut dec = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
             env!("CARGO_MANIFEST_DIR")
         ))
         .unwrap();
-        let result = calculate_video_psnr_hvs(&mut dec1, &mut dec2, None, |_| ()).unwrap();
-        assert_metric_eq(34.4843, result.y);
-        assert_metric_eq(38.1651, result.u);
-        assert_metric_eq(41.0645, result.v);
-        assert_metric_eq(32.0711, result.avg);
+        let cs = dec.get_video_details().chroma_sampling;
+        let frame = dec.read_video_frame::<u8>().unwrap();
+        let copy = frame.clone();
+
+        let raw = SsimOptions::new().raw(true);
+        let result = raw.calculate_frame(&frame, &copy, 8, cs).unwrap();
+        assert_eq!(1.0, result.y);
+        assert_eq!(1.0, result.avg);
+        let result = calculate_frame_ssim(&frame, &copy, 8, cs).unwrap();
+        assert_eq!(f64::INFINITY, result.avg);
+        let result = calculate_frame_msssim(&frame, &copy, 8, cs).unwrap();
+        assert_eq!(f64::INFINITY, result.avg);
+        let result = calculate_frame_psnr_hvs(&frame, &copy, 8, cs).unwrap();
+        assert_eq!(f64::INFINITY, result.avg);
+        assert_eq!(100.0, calculate_frame_ciede(&frame, &copy, 8, cs).unwrap());
+
+        // Planes which are too small to be scored are not treated as a perfect match
+        let frame = Frame::<u8>::new_with_padding(8, 8, ChromaSampling::Cs420, 0);
+        let result = calculate_frame_psnr_hvs(&frame, &frame.clone(), 8, cs).unwrap();
+        assert_eq!(f64::INFINITY, result.y);
+        assert!(result.u.is_nan());
     }
 
     #[test]
-    fn ssim_yuv420p8() {
+    fn constant_frames() {
+        let constant_frame = |value: u8| {
+            let mut frame = Frame::<u8>::new_with_padding(64, 48, ChromaSampling::Cs420, 0);
+            for plane in &mut frame.planes {
+                plane.data.fill(value);
+            }
+            frame
+        };
+        let cs = ChromaSampling::Cs420;
+        let black = constant_frame(16);
+        let raw = SsimOptions::new().raw(true);
+
+        // Equal constant frames are a perfect match
+        let result = raw
+            .calculate_frame(&black, &constant_frame(16), 8, cs)
+            .unwrap();
+        assert_eq!(1.0, result.avg);
+        let result = calculate_frame_msssim(&black, &constant_frame(16), 8, cs).unwrap();
+        assert_eq!(f64::INFINITY, result.avg);
+        let result = calculate_frame_psnr(&black, &constant_frame(16), 8, cs).unwrap();
+        assert_eq!(100.0, result.avg);
+
+        // Otherwise, only the luminance term remains
+        let gray = constant_frame(128);
+        let c1 = (0.01f64 * 255.0).powi(2);
+        let expected = (2.0 * 16.0 * 128.0 + c1) / (16.0f64.powi(2) + 128.0f64.powi(2) + c1);
+        let result = raw.calculate_frame(&black, &gray, 8, cs).unwrap();
+        assert!((result.y - expected).abs() < 1e-12);
+        assert!((result.avg - expected).abs() < 1e-12);
+        assert_eq!(result, raw.calculate_frame(&gray, &black, 8, cs).unwrap());
+        let scales = calculate_frame_msssim_scales(&black, &gray, 8, cs).unwrap();
+        assert!(scales[0].cs.iter().all(|&cs| cs == 1.0));
+        assert!(scales[0].ssim.iter().all(|&ssim| ssim == expected));
+        let result = calculate_frame_psnr(&black, &gray, 8, cs).unwrap();
+        assert_metric_eq(
+            10.0 * (255.0f64.powi(2) / 112.0f64.powi(2)).log10(),
+            result.y,
+        );
+
+        // The shortcut matches filtering the planes
+        let config = SsimConfig {
+            edge: EdgeHandling::Replicate,
+            ..Default::default()
+        };
+        let filtered = SsimOptions::new()
+            .config(config)
+            .calculate_frame(&black, &gray, 8, cs)
+            .unwrap();
+        let weights = vec![1.0; 64 * 48];
+        let weighted = calculate_frame_ssim_weighted(&black, &gray, 8, cs, &weights).unwrap();
+        filtered.check_approx_eq(&weighted, 1e-6).unwrap();
+
+        // A constant reference against varying content is scored as usual
+        let mut noisy = gray.clone();
+        for (i, pix) in noisy.planes[0].data.iter_mut().enumerate() {
+            *pix = (i * 37 % 256) as u8;
+        }
+        let result = raw.calculate_frame(&black, &noisy, 8, cs).unwrap();
+        assert!(result.y.is_finite() && result.y < expected);
+    }
+
+    #[test]
+    fn monochrome_frames() {
         let mut dec1 = get_decoder(format!(
             "{}/../testfiles/yuv420p8_input.y4m",
             env!("CARGO_MANIFEST_DIR")
@@ -260,30 +3589,345 @@ mod tests {
             env!("CARGO_MANIFEST_DIR")
         ))
         .unwrap();
-        let result = calculate_video_ssim(&mut dec1, &mut dec2, None, |_| ()).unwrap();
-        assert_metric_eq(13.2572, result.y);
-        assert_metric_eq(10.8624, result.u);
-        assert_metric_eq(12.8369, result.v);
-        assert_metric_eq(12.6899, result.avg);
+        let details = dec1.get_video_details();
+        let frame1 = dec1.read_video_frame::<u8>().unwrap();
+        let frame2 = dec2.read_video_frame::<u8>().unwrap();
+
+        // Only keep the luma plane of the frames
+        let gray = |frame: &Frame<u8>| {
+            let mut gray =
+                Frame::new_with_padding(details.width, details.height, ChromaSampling::Cs400, 0);
+            for (src_row, dst_row) in frame.planes[0]
+                .rows_iter()
+                .zip(gray.planes[0].rows_iter_mut())
+            {
+                dst_row[..src_row.len()].copy_from_slice(src_row);
+            }
+            gray
+        };
+        let (gray1, gray2) = (gray(&frame1), gray(&frame2));
+        let cs = ChromaSampling::Cs400;
+
+        let expected = calculate_frame_psnr(&frame1, &frame2, 8, details.chroma_sampling).unwrap();
+        let result = calculate_frame_psnr(&gray1, &gray2, 8, cs).unwrap();
+        assert_eq!(expected.y, result.y);
+        assert_eq!(result.y, result.avg);
+        assert!(result.u.is_nan() && result.v.is_nan());
+        assert!(calculate_frame_ciede(&gray1, &gray2, 8, cs).is_err());
+        let expected = calculate_frame_ssim(&frame1, &frame2, 8, details.chroma_sampling).unwrap();
+        let result = calculate_frame_ssim(&gray1, &gray2, 8, cs).unwrap();
+        assert_eq!(expected.y, result.y);
+        assert_eq!(result.y, result.avg);
+        let expected =
+            calculate_frame_msssim(&frame1, &frame2, 8, details.chroma_sampling).unwrap();
+        let result = calculate_frame_msssim(&gray1, &gray2, 8, cs).unwrap();
+        assert_eq!(expected.y, result.y);
+        assert_eq!(result.y, result.avg);
+        let expected =
+            calculate_frame_psnr_hvs(&frame1, &frame2, 8, details.chroma_sampling).unwrap();
+        let result = calculate_frame_psnr_hvs(&gray1, &gray2, 8, cs).unwrap();
+        assert_eq!(expected.y, result.y);
+        assert_eq!(result.y, result.avg);
     }
 
     #[test]
-    fn msssim_yuv420p8() {
+    fn video_details_mismatch() {
         let mut dec1 = get_decoder(format!(
             "{}/../testfiles/yuv420p8_input.y4m",
             env!("CARGO_MANIFEST_DIR")
         ))
         .unwrap();
         let mut dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv444p8_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let err = calculate_video_ssim(&mut dec1, &mut dec2, None, |_| ()).unwrap_err();
+        assert_eq!(
+            "Input videos must have matching formats: mismatched chroma sampling",
+            err.to_string()
+        );
+
+        let dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p8_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let dec2 = get_decoder(format!(
             "{}/../testfiles/yuv420p8_output.y4m",
             env!("CARGO_MANIFEST_DIR")
         ))
         .unwrap();
-        let result = calculate_video_msssim(&mut dec1, &mut dec2, None, |_| ()).unwrap();
-        assert_metric_eq(18.8343, result.y);
-        assert_metric_eq(16.6943, result.u);
-        assert_metric_eq(18.7662, result.v);
-        assert_metric_eq(18.3859, result.avg);
+        let full = Rect {
+            x: 0,
+            y: 0,
+            width: 640,
+            height: 360,
+        };
+        let mut dec1 = CropDecoder::new(dec1, full).unwrap();
+        let mut dec2 = CropDecoder::new(dec2, Rect { width: 320, ..full }).unwrap();
+        let err = calculate_video_psnr(&mut dec1, &mut dec2, None, |_| ()).unwrap_err();
+        match err.downcast_ref::<MetricsError>() {
+            Some(MetricsError::VideoDetailsMismatch { fields }) => {
+                assert_eq!(&["width"], fields.as_slice())
+            }
+            _ => panic!("Unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn video_details_builder() {
+        let details = VideoDetails::new(1920, 1080, 10, ChromaSampling::Cs422)
+            .with_frame_rate(Rational::new(24, 1))
+            .with_transfer_characteristics(TransferCharacteristics::Pq);
+        assert_eq!(1920, details.width());
+        assert_eq!(1080, details.height());
+        assert_eq!(10, details.bit_depth());
+        assert_eq!(ChromaSampling::Cs422, details.chroma_sampling());
+        assert_eq!(Some(24), details.frame_rate.map(|rate| rate.num));
+        assert_eq!(
+            TransferCharacteristics::Pq,
+            details.transfer_characteristics
+        );
+
+        let details = details.with_size(640, 360).with_bit_depth(8);
+        assert_eq!(
+            (640, 360, 8),
+            (details.width, details.height, details.bit_depth)
+        );
+        assert_eq!(ChromaSampling::Cs422, details.chroma_sampling);
+    }
+
+    #[test]
+    fn cancel_calculation() {
+        let open = |token: &Arc<AtomicBool>| {
+            let open = |name: &str| {
+                let dec = get_decoder(format!(
+                    "{}/../testfiles/yuv420p8_{}.y4m",
+                    env!("CARGO_MANIFEST_DIR"),
+                    name
+                ))
+                .unwrap();
+                CancellableDecoder::new(dec, Arc::clone(token))
+            };
+            (open("input"), open("output"))
+        };
+        fn is_cancelled<T>(result: Result<T, Box<dyn std::error::Error>>) -> bool {
+            matches!(
+                result.map_err(|err| err.downcast::<MetricsError>().map(|err| *err)),
+                Err(Ok(MetricsError::Cancelled))
+            )
+        }
+
+        let token = Arc::new(AtomicBool::new(false));
+        let (mut dec1, mut dec2) = open(&token);
+        assert!(calculate_video_psnr(&mut dec1, &mut dec2, None, |_| ()).is_ok());
+
+        // Cancelled from the progress callback after the first frame
+        let (mut dec1, mut dec2) = open(&token);
+        let result = calculate_video_psnr(&mut dec1, &mut dec2, None, |frame| {
+            if frame == 1 {
+                token.store(true, Ordering::Relaxed);
+            }
+        });
+        assert!(is_cancelled(result));
+
+        let (mut dec1, mut dec2) = open(&token);
+        assert!(is_cancelled(calculate_video_ssim(
+            &mut dec1,
+            &mut dec2,
+            None,
+            |_| ()
+        )));
+
+        // The temporal metric decodes the frames itself
+        let (mut dec1, mut dec2) = open(&token);
+        assert!(is_cancelled(calculate_video_temporal_consistency(
+            &mut dec1,
+            &mut dec2,
+            None,
+            |_| ()
+        )));
+    }
+
+    /// A port of the window loop of Daala's `dump_ssim`, filtering each window in two
+    /// dimensions at once.
+    ///
+    /// This only checks that the separable filtering of the metric computes the same
+    /// windows. It is not a substitute for scores produced by `dump_ssim` itself.
+    fn daala_plane_ssim<T: Pixel>(plane1: &Plane<T>, plane2: &Plane<T>, bit_depth: usize) -> f64 {
+        let (width, height) = (plane1.cfg.width, plane1.cfg.height);
+        let sigma = height as f64 * 1.5 / 256.0;
+        let s = (0.5 * std::f64::consts::PI).sqrt() * sigma / 256.0;
+        let len = if s >= 1.0 {
+            0
+        } else {
+            (sigma * (-2.0 * s.ln()).sqrt()).floor() as usize
+        };
+        let len = len.min(width.min(height).saturating_sub(1));
+        let scale = 1.0 / ((2.0 * std::f64::consts::PI).sqrt() * sigma);
+        let mut kernel = vec![0i64; 2 * len + 1];
+        for ci in 1..=len {
+            let val =
+                (256.0 * scale * std::f64::consts::E.powf(-0.5 / sigma.powi(2) * ci.pow(2) as f64)
+                    + 0.5) as i64;
+            kernel[len - ci] = val;
+            kernel[len + ci] = val;
+        }
+        kernel[len] = 256 - kernel.iter().sum::<i64>();
+
+        let sample_max = ((1u64 << bit_depth) - 1) as f64;
+        let (c1, c2) = (
+            sample_max.powi(2) * 0.01f64.powi(2),
+            sample_max.powi(2) * 0.03f64.powi(2),
+        );
+        let mut ssim = 0.0;
+        let mut ssimw = 0.0;
+        for y in 0..height {
+            for x in 0..width {
+                let mut moments = [0i128; 6];
+                for (ky, &vweight) in kernel.iter().enumerate() {
+                    let Some(sy) = (y + ky).checked_sub(len).filter(|&sy| sy < height) else {
+                        continue;
+                    };
+                    for (kx, &hweight) in kernel.iter().enumerate() {
+                        let Some(sx) = (x + kx).checked_sub(len).filter(|&sx| sx < width) else {
+                            continue;
+                        };
+                        let pix1 = u32::cast_from(plane1.p(sx, sy)) as i128;
+                        let pix2 = u32::cast_from(plane2.p(sx, sy)) as i128;
+                        let weight = (vweight * hweight) as i128;
+                        let terms = [pix1, pix2, pix1 * pix1, pix1 * pix2, pix2 * pix2, 1];
+                        for (moment, term) in moments.iter_mut().zip(terms) {
+                            *moment += weight * term;
+                        }
+                    }
+                }
+                let [mux, muy, x2, xy, y2, w] = moments.map(|moment| moment as f64);
+                let (c1, c2) = (c1 * w.powi(2), c2 * w.powi(2));
+                let mx2 = mux.powi(2);
+                let mxy = mux * muy;
+                let my2 = muy.powi(2);
+                let cs = w * (c2 + 2.0 * (xy * w - mxy)) / (x2 * w - mx2 + y2 * w - my2 + c2);
+                ssim += cs * (2.0 * mxy + c1) / (mx2 + my2 + c1);
+                ssimw += w;
+            }
+        }
+        10.0 * (1f64.log10() - (1.0 - ssim / ssimw).log10())
+    }
+
+    fn assert_daala_ssim<T: Pixel>(frame1: &Frame<T>, frame2: &Frame<T>, bit_depth: usize) {
+        let cs = ChromaSampling::Cs420;
+        let result = SsimOptions::new()
+            .config(SsimConfig::daala())
+            .calculate_frame(frame1, frame2, bit_depth, cs)
+            .unwrap();
+        for (i, score) in [result.y, result.u, result.v].into_iter().enumerate() {
+            let expected = daala_plane_ssim(&frame1.planes[i], &frame2.planes[i], bit_depth);
+            assert_eq!(expected.to_bits(), score.to_bits(), "plane {}", i);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "ffmpeg"))]
+    fn ssim_matches_daala() {
+        for depth in [8, 10] {
+            let path = |name: &str| {
+                format!(
+                    "{}/../testfiles/yuv420p{}_{}.y4m",
+                    env!("CARGO_MANIFEST_DIR"),
+                    depth,
+                    name
+                )
+            };
+            let mut dec1 = get_decoder(path("input")).unwrap();
+            let mut dec2 = get_decoder(path("output")).unwrap();
+            if depth > 8 {
+                let frame1 = dec1.read_video_frame::<u16>().unwrap();
+                let frame2 = dec2.read_video_frame::<u16>().unwrap();
+                assert_daala_ssim(&frame1, &frame2, depth);
+            } else {
+                let frame1 = dec1.read_video_frame::<u8>().unwrap();
+                let frame2 = dec2.read_video_frame::<u8>().unwrap();
+                assert_daala_ssim(&frame1, &frame2, depth);
+            }
+        }
+
+        // Constant planes are only filtered in the exact mode, which can change
+        // the last bits of their scores.
+        let mut dark = Frame::<u8>::new_with_padding(64, 48, ChromaSampling::Cs420, 0);
+        let mut light = dark.clone();
+        for (plane1, plane2) in dark.planes.iter_mut().zip(&mut light.planes) {
+            plane1.rows_iter_mut().flatten().for_each(|pix| *pix = 40);
+            plane2.rows_iter_mut().flatten().for_each(|pix| *pix = 45);
+        }
+        assert_daala_ssim(&dark, &light, 8);
+        let shortcut = calculate_frame_ssim(&dark, &light, 8, ChromaSampling::Cs420).unwrap();
+        assert_metric_eq(
+            daala_plane_ssim(&dark.planes[0], &light.planes[0], 8),
+            shortcut.y,
+        );
+    }
+
+    #[test]
+    fn ssim_tiny_frames() {
+        for size in 1..=3 {
+            for cs in [ChromaSampling::Cs420, ChromaSampling::Cs444] {
+                let mut frame1 = Frame::<u8>::new_with_padding(size, size, cs, 0);
+                let mut frame2 = frame1.clone();
+                for (plane1, plane2) in frame1.planes.iter_mut().zip(&mut frame2.planes) {
+                    for (i, (p1, p2)) in plane1
+                        .rows_iter_mut()
+                        .flatten()
+                        .zip(plane2.rows_iter_mut().flatten())
+                        .enumerate()
+                    {
+                        *p1 = (i * 40) as u8;
+                        *p2 = (i * 40 + 7) as u8;
+                    }
+                }
+                let ssim = calculate_frame_ssim(&frame1, &frame2, 8, cs).unwrap();
+                let msssim = calculate_frame_msssim(&frame1, &frame2, 8, cs).unwrap();
+                for result in [ssim, msssim] {
+                    assert!(result.y.is_finite(), "{}x{}: {:?}", size, size, result);
+                    assert!(result.u.is_finite(), "{}x{}: {:?}", size, size, result);
+                    assert!(result.avg.is_finite(), "{}x{}: {:?}", size, size, result);
+                }
+            }
+
+            let plane1: Vec<u32> = (0..size * size).map(|i| i as u32 * 40).collect();
+            let plane2: Vec<u32> = plane1.iter().map(|sample| sample + 7).collect();
+            assert!(calculate_plane_ssim_u32(&plane1, &plane2, size, size, 8)
+                .unwrap()
+                .is_finite());
+        }
+    }
+
+    #[test]
+    fn msssim_small_frame() {
+        let mut frame1 = Frame::<u8>::new_with_padding(24, 24, ChromaSampling::Cs420, 0);
+        let mut frame2 = frame1.clone();
+        for plane in 0..3 {
+            let width = frame1.planes[plane].cfg.width;
+            for (i, (p1, p2)) in frame1.planes[plane]
+                .data
+                .iter_mut()
+                .zip(frame2.planes[plane].data.iter_mut())
+                .enumerate()
+            {
+                *p1 = ((i % width) * 8) as u8;
+                *p2 = ((i % width) * 8 + i % 3) as u8;
+            }
+        }
+        let result = calculate_frame_msssim(&frame1, &frame2, 8, ChromaSampling::Cs420).unwrap();
+        assert!(result.y.is_finite());
+        assert!(result.u.is_finite());
+        assert!(result.v.is_finite());
+        assert!(result.avg.is_finite());
+        assert!(MsSsimOptions::new()
+            .scales(0)
+            .calculate_frame(&frame1, &frame2, 8, ChromaSampling::Cs420)
+            .is_err());
     }
 
     #[test]
@@ -343,6 +3987,78 @@ mod tests {
         assert_metric_eq(14.2338, result.avg);
     }
 
+    #[test]
+    fn ssim_rgb() {
+        let open = |name: &str| {
+            get_decoder(format!(
+                "{}/../testfiles/{}.y4m",
+                env!("CARGO_MANIFEST_DIR"),
+                name
+            ))
+            .unwrap()
+        };
+        // The planes of 4:4:4 video are weighted equally either way
+        let mut dec1 = open("yuv444p8_input");
+        let mut dec2 = open("yuv444p8_output");
+        let result = calculate_video_ssim_rgb(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(13.2989, result.y);
+        assert_metric_eq(14.0089, result.u);
+        assert_metric_eq(15.7419, result.v);
+        assert_metric_eq(14.2338, result.avg);
+
+        let mut dec1 = open("yuv444p8_input");
+        let mut dec2 = open("yuv444p8_output");
+        let frame1 = dec1.read_video_frame::<u8>().unwrap();
+        let frame2 = dec2.read_video_frame::<u8>().unwrap();
+        let expected = calculate_frame_ssim(&frame1, &frame2, 8, ChromaSampling::Cs444).unwrap();
+        let result = calculate_frame_ssim_rgb(&frame1, &frame2, 8).unwrap();
+        assert_metric_eq(expected.y, result.y);
+        assert_metric_eq(expected.u, result.u);
+        assert_metric_eq(expected.v, result.v);
+        assert_metric_eq(expected.avg, result.avg);
+
+        let mut dec1 = open("yuv420p8_input");
+        let mut dec2 = open("yuv420p8_output");
+        let frame1 = dec1.read_video_frame::<u8>().unwrap();
+        let frame2 = dec2.read_video_frame::<u8>().unwrap();
+        assert!(calculate_frame_ssim_rgb(&frame1, &frame2, 8).is_err());
+        assert!(calculate_video_ssim_rgb(&mut dec1, &mut dec2, None, |_| ()).is_err());
+
+        // Upsampled 4:2:0 video, whose chroma planes differ enough from the luma plane
+        // that the default 4:2:0 weights would change `avg`
+        let upsample = |frame: &Frame<u8>| {
+            let mut frame = upsample_chroma(frame, ChromaSamplePosition::Colocated).unwrap();
+            // The planes only need the same geometry, not the same padding
+            let green = &frame.planes[1];
+            let (width, height) = (green.cfg.width, green.cfg.height);
+            let mut padded = Plane::new(width, height, 0, 0, 16, 16);
+            for (dst, src) in padded.rows_iter_mut().zip(green.rows_iter()) {
+                dst[..width].copy_from_slice(&src[..width]);
+            }
+            frame.planes[1] = padded;
+            frame
+        };
+        let (rgb1, rgb2) = (upsample(&frame1), upsample(&frame2));
+        let result = calculate_frame_ssim_rgb(&rgb1, &rgb2, 8).unwrap();
+        let avg = |weight: f64| {
+            let raw = |score: f64| 1.0 - 10f64.powf(-score / 10.0);
+            let sum = raw(result.y) + weight * (raw(result.u) + raw(result.v));
+            -10.0 * (1.0 - sum / (1.0 + 2.0 * weight)).log10()
+        };
+        assert_metric_eq(avg(1.0), result.avg);
+        assert!((avg(0.25) - result.avg).abs() > 0.1);
+
+        let (width, height) = rgb1.luma_dimensions();
+        let details = VideoDetails::new(width, height, 8, ChromaSampling::Cs444);
+        let decoder = |frame: &Frame<u8>| {
+            let frame = frame.clone();
+            GeneratorDecoder::new(move |_| frame.clone(), details, 2)
+        };
+        let (mut dec1, mut dec2) = (decoder(&rgb1), decoder(&rgb2));
+        let video = calculate_video_ssim_rgb(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(result.avg, video.avg);
+    }
+
     #[test]
     fn msssim_yuv444p8() {
         let mut dec1 = get_decoder(format!(
@@ -381,6 +4097,27 @@ mod tests {
         assert_metric_eq(12.7729, result.avg);
     }
 
+    #[test]
+    fn ssim_yuv420p12() {
+        let dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p10_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p10_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec1 = BitDepthConvertDecoder::new(dec1, 12).unwrap();
+        let mut dec2 = BitDepthConvertDecoder::new(dec2, 12).unwrap();
+        let result = calculate_video_ssim(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(13.3624, result.y);
+        assert_metric_eq(10.9352, result.u);
+        assert_metric_eq(12.8724, result.v);
+        assert_metric_eq(12.7755, result.avg);
+    }
+
     #[test]
     fn msssim_yuv420p10() {
         let mut dec1 = get_decoder(format!(
@@ -400,6 +4137,87 @@ mod tests {
         assert_metric_eq(18.5631, result.avg);
     }
 
+    #[test]
+    fn msssim_yuv420p12() {
+        let dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p10_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p10_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec1 = BitDepthConvertDecoder::new(dec1, 12).unwrap();
+        let mut dec2 = BitDepthConvertDecoder::new(dec2, 12).unwrap();
+        let result = calculate_video_msssim(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(19.0415, result.y);
+        assert_metric_eq(16.8571, result.u);
+        assert_metric_eq(18.8688, result.v);
+        assert_metric_eq(18.5660, result.avg);
+    }
+
+    #[test]
+    fn msssim_yuv420p16() {
+        let dec1 = get_decoder(format!(
+            "{}/../testfiles/yuv420p10_input.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let dec2 = get_decoder(format!(
+            "{}/../testfiles/yuv420p10_output.y4m",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut dec1 = BitDepthConvertDecoder::new(dec1, 16).unwrap();
+        let mut dec2 = BitDepthConvertDecoder::new(dec2, 16).unwrap();
+        let result = calculate_video_msssim(&mut dec1, &mut dec2, None, |_| ()).unwrap();
+        assert_metric_eq(19.0422, result.y);
+        assert_metric_eq(16.8581, result.u);
+        assert_metric_eq(18.8701, result.v);
+        assert_metric_eq(18.5669, result.avg);
+    }
+
+    #[test]
+    fn msssim_bright_high_bit_depth() {
+        for bit_depth in [12, 16] {
+            let sample_max = (1u32 << bit_depth) - 1;
+            let bright_frame = |value: u32| {
+                let mut frame = Frame::<u16>::new_with_padding(512, 512, ChromaSampling::Cs420, 0);
+                for plane in frame.planes.iter_mut() {
+                    plane.data_origin_mut().fill(value as u16);
+                }
+                frame
+            };
+            let frame1 = bright_frame(sample_max);
+            let frame2 = bright_frame(sample_max - 1);
+            let result =
+                calculate_frame_msssim(&frame1, &frame2, bit_depth, ChromaSampling::Cs420).unwrap();
+
+            // Flat planes have perfect contrast and structure. The luminance term is
+            // the same at every scale, as the samples and constants scale together.
+            let (a, b) = (sample_max as f64, (sample_max - 1) as f64);
+            let c1 = (0.01 * a).powi(2);
+            let luminance = (2.0 * a * b + c1) / (a * a + b * b + c1);
+            let expected = -10.0 * (1.0 - luminance.powf(0.1333)).log10();
+            assert_metric_eq(expected, result.y);
+        }
+    }
+
+    #[test]
+    fn unsupported_bit_depths() {
+        let frame1 = Frame::<u16>::new_with_padding(64, 64, ChromaSampling::Cs420, 0);
+        let frame2 = frame1.clone();
+        let cs = ChromaSampling::Cs420;
+        assert!(calculate_frame_psnr_hvs(&frame1, &frame2, 14, cs).is_ok());
+        assert!(calculate_frame_psnr_hvs(&frame1, &frame2, 16, cs).is_err());
+        assert!(calculate_frame_ciede(&frame1, &frame2, 12, cs).is_ok());
+        assert!(calculate_frame_ciede(&frame1, &frame2, 14, cs).is_err());
+        assert!(calculate_frame_ssim(&frame1, &frame2, 16, cs).is_ok());
+        assert!(calculate_frame_msssim(&frame1, &frame2, 16, cs).is_ok());
+    }
+
     #[test]
     fn ciede2000_yuv420p8_nosimd() {
         let mut dec1 = get_decoder(format!(
@@ -528,6 +4346,150 @@ mod tests {
         assert_metric_eq(36.3691, result);
     }
 
+    #[test]
+    fn ciede2000_yuv420p8_deterministic() {
+        let run = || {
+            let mut dec1 = get_decoder(format!(
+                "{}/../testfiles/yuv420p8_input.y4m",
+                env!("CARGO_MANIFEST_DIR")
+            ))
+            .unwrap();
+            let mut dec2 = get_decoder(format!(
+                "{}/../testfiles/yuv420p8_output.y4m",
+                env!("CARGO_MANIFEST_DIR")
+            ))
+            .unwrap();
+            calculate_video_ciede(&mut dec1, &mut dec2, None, |_| ()).unwrap()
+        };
+        let first = run();
+        for _ in 0..3 {
+            assert_eq!(first.to_bits(), run().to_bits());
+        }
+    }
+
+    #[cfg(feature = "png")]
+    fn load_test_png<T: Pixel>(name: &str) -> (Frame<T>, Option<Plane<T>>) {
+        av_metrics_decoders::png::load_png_with_alpha(
+            format!(
+                "{}/../testfiles/png/{}.png",
+                env!("CARGO_MANIFEST_DIR"),
+                name
+            ),
+            av_metrics_decoders::png::YuvMatrix::Bt709,
+        )
+        .unwrap()
+    }
+
+    #[cfg(feature = "png")]
+    fn plane_samples<T: Pixel>(plane: &Plane<T>) -> Vec<T> {
+        plane
+            .rows_iter()
+            .flat_map(|row| row[..plane.cfg.width].to_vec())
+            .collect()
+    }
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn png_grayscale() {
+        let (frame, alpha) = load_test_png::<u8>("gray8");
+        assert!(alpha.is_none());
+        assert_eq!(plane_samples(&frame.planes[0]), [0, 128, 255, 64, 192, 32]);
+        assert_eq!(plane_samples(&frame.planes[1]), [128; 6]);
+        assert_eq!(plane_samples(&frame.planes[2]), [128; 6]);
+        // 8-bit samples are rescaled to the full 16-bit range
+        let (frame, _) = load_test_png::<u16>("gray8");
+        assert_eq!(
+            plane_samples(&frame.planes[0]),
+            [0, 128 * 257, 65535, 64 * 257, 192 * 257, 32 * 257]
+        );
+
+        let (frame, alpha) = load_test_png::<u16>("gray16");
+        assert!(alpha.is_none());
+        assert_eq!(
+            plane_samples(&frame.planes[0]),
+            [0, 32768, 65535, 1000, 50000, 12345]
+        );
+        let (frame, _) = load_test_png::<u8>("gray16");
+        assert_eq!(plane_samples(&frame.planes[0]), [0, 128, 255, 4, 195, 48]);
+    }
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn png_rgb() {
+        // Red, green, blue, white, black and gray in BT.709 full range
+        let (frame, alpha) = load_test_png::<u8>("rgb8");
+        assert!(alpha.is_none());
+        assert_eq!(plane_samples(&frame.planes[0]), [54, 182, 18, 255, 0, 128]);
+        assert_eq!(
+            plane_samples(&frame.planes[1]),
+            [98, 29, 255, 128, 128, 128]
+        );
+        assert_eq!(
+            plane_samples(&frame.planes[2]),
+            [255, 12, 116, 128, 128, 128]
+        );
+
+        // The same colors with 16-bit samples
+        let (frame16, alpha) = load_test_png::<u8>("rgb16");
+        assert!(alpha.is_none());
+        assert!(frames_identical(&frame, &frame16));
+    }
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn png_alpha() {
+        let (rgb, _) = load_test_png::<u8>("rgb8");
+        let (frame, alpha) = load_test_png::<u8>("rgba8");
+        assert!(frames_identical(&rgb, &frame));
+        assert_eq!(plane_samples(&alpha.unwrap()), [255, 128, 0, 64, 255, 1]);
+        // `load_png` discards the alpha channel
+        let frame: Frame<u8> = av_metrics_decoders::png::load_png(
+            format!("{}/../testfiles/png/rgba8.png", env!("CARGO_MANIFEST_DIR")),
+            av_metrics_decoders::png::YuvMatrix::Bt709,
+        )
+        .unwrap();
+        assert!(frames_identical(&rgb, &frame));
+
+        // A transparent color is expanded into an alpha plane
+        let (gray, _) = load_test_png::<u8>("gray8");
+        let (frame, alpha) = load_test_png::<u8>("gray8_trns");
+        assert!(frames_identical(&gray, &frame));
+        assert_eq!(plane_samples(&alpha.unwrap()), [255, 255, 255, 0, 255, 255]);
+        let (frame, alpha) = load_test_png::<u16>("rgb8_trns");
+        assert!(frames_identical(&load_test_png::<u16>("rgb8").0, &frame));
+        assert_eq!(
+            plane_samples(&alpha.unwrap()),
+            [65535, 65535, 65535, 65535, 0, 65535]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn png_alpha_metrics() {
+        // The alpha planes are [255, 128, 0, 64, 255, 1] and [255, 255, 255, 255, 0, 255]
+        let alpha1 = load_test_png::<u8>("rgba8").1.unwrap();
+        let alpha2 = load_test_png::<u8>("rgb8_trns").1.unwrap();
+        let mse =
+            (127.0f64.powi(2) + 255.0f64.powi(2) * 2.0 + 191.0f64.powi(2) + 254.0f64.powi(2)) / 6.0;
+        let expected = 10.0 * (255.0f64.powi(2) / mse).log10();
+        assert_metric_eq(expected, calculate_alpha_psnr(&alpha1, &alpha2, 8).unwrap());
+        assert_eq!(100.0, calculate_alpha_psnr(&alpha1, &alpha1, 8).unwrap());
+
+        // 8-bit alpha is rescaled to the full 16-bit range, which keeps the PSNR
+        let alpha1_16 = load_test_png::<u16>("rgba8").1.unwrap();
+        let alpha2_16 = load_test_png::<u16>("rgb8_trns").1.unwrap();
+        assert_metric_eq(
+            expected,
+            calculate_alpha_psnr(&alpha1_16, &alpha2_16, 16).unwrap(),
+        );
+
+        let ssim = calculate_alpha_ssim(&alpha1, &alpha2, 8).unwrap();
+        assert!(ssim.is_finite());
+        assert!(calculate_alpha_ssim(&alpha1, &alpha1, 8)
+            .unwrap()
+            .is_infinite());
+    }
+
     fn assert_metric_eq(expected: f64, value: f64) {
         assert!(
             (expected - value).abs() < 0.01,
@@ -1,17 +1,20 @@
 #![allow(clippy::upper_case_acronyms)]
 
-use av_metrics::video::decode::Decoder;
+use av_metrics::video::decode::{Decoder, TimeRangeDecoder};
 use av_metrics::video::*;
+use av_metrics::MetricsError;
 #[cfg(feature = "ffmpeg")]
 use av_metrics_decoders::FfmpegDecoder;
 #[cfg(not(feature = "ffmpeg"))]
-use av_metrics_decoders::Y4MDecoder;
+use av_metrics_decoders::{y4m::new_decoder_from_file, Y4MDecoder};
 use clap::{Arg, Command};
 use console::style;
 use indicatif::{ProgressBar, ProgressStyle};
 use serde::Serialize;
 use std::error::Error;
 use std::fs::File;
+#[cfg(not(feature = "ffmpeg"))]
+use std::io::BufReader;
 use std::io::{BufWriter, Stdout, Write};
 use std::path::Path;
 
@@ -45,6 +48,31 @@ fn main() -> Result<(), String> {
                 .possible_value("msssim")
                 .possible_value("ciede2000"),
         )
+        .arg(
+            Arg::new("CHROMA_WEIGHT")
+                .help(
+                    "Weight of each chroma plane relative to the luma plane in the average \
+                     score of PSNR-HVS, SSIM and MSSSIM, instead of the relative size of the \
+                     chroma planes",
+                )
+                .long("chroma-weight")
+                .takes_value(true)
+                .value_name("WEIGHT"),
+        )
+        .arg(
+            Arg::new("START_TIME")
+                .help("Only score the frames from this time onwards, in seconds")
+                .long("start-time")
+                .takes_value(true)
+                .value_name("SECONDS"),
+        )
+        .arg(
+            Arg::new("END_TIME")
+                .help("Only score the frames before this time, in seconds")
+                .long("end-time")
+                .takes_value(true)
+                .value_name("SECONDS"),
+        )
         .arg(
             Arg::new("JSON")
                 .help("Output results as JSON--useful for piping to other programs")
@@ -116,6 +144,33 @@ fn main() -> Result<(), String> {
     let base_type = InputType::detect(base);
 
     let metrics = cli.value_of("METRIC");
+    let chroma_weight = cli
+        .value_of("CHROMA_WEIGHT")
+        .map(|weight| match weight.parse::<f64>() {
+            Ok(weight) if weight >= 0.0 => Ok(weight),
+            _ => Err(format!("Invalid chroma weight: {weight}")),
+        })
+        .transpose()?;
+    let parse_time = |name| {
+        cli.value_of(name)
+            .map(|time| match time.parse::<f64>() {
+                Ok(time) if time >= 0.0 && time.is_finite() => Ok(time),
+                _ => Err(format!("Invalid time: {time}")),
+            })
+            .transpose()
+    };
+    let time_range = match (parse_time("START_TIME")?, parse_time("END_TIME")?) {
+        (None, None) => None,
+        (start, end) => Some(TimeRange {
+            start: start.unwrap_or(0.0),
+            end,
+        }),
+    };
+    if let Some(range) = time_range {
+        for input in std::iter::once(base).chain(inputs.clone()) {
+            range.validate(input)?;
+        }
+    }
 
     let mut report = Report {
         base,
@@ -131,6 +186,8 @@ fn main() -> Result<(), String> {
                     base,
                     input,
                     metrics,
+                    chroma_weight,
+                    time_range,
                     cli.is_present("QUIET"),
                     cli.is_present("FRAMES"),
                 ));
@@ -171,12 +228,12 @@ impl InputType {
 }
 
 #[cfg(not(feature = "ffmpeg"))]
-pub fn get_decoder<P: AsRef<Path>>(input: P) -> Result<Y4MDecoder, String> {
-    Y4MDecoder::new(input)
+pub fn get_decoder<P: AsRef<Path>>(input: P) -> Result<Y4MDecoder<BufReader<File>>, MetricsError> {
+    new_decoder_from_file(input)
 }
 
 #[cfg(feature = "ffmpeg")]
-pub fn get_decoder<P: AsRef<Path>>(input: P) -> Result<FfmpegDecoder, String> {
+pub fn get_decoder<P: AsRef<Path>>(input: P) -> Result<FfmpegDecoder, MetricsError> {
     FfmpegDecoder::new(input)
 }
 
@@ -201,6 +258,8 @@ fn run_video_metrics(
     input1: &str,
     input2: &str,
     metric: Option<&str>,
+    chroma_weight: Option<f64>,
+    time_range: Option<TimeRange>,
     quiet: bool,
     all_frames: bool,
 ) -> MetricsResults {
@@ -212,7 +271,7 @@ fn run_video_metrics(
     let (progress, total_frames) = if quiet || !console::user_attended() {
         (ProgressBar::hidden(), 0)
     } else if all_frames {
-        let total_frames = total_frames(&input1, &input2);
+        let total_frames = total_frames(&input1, &input2, time_range);
         (
             ProgressBar::new(total_frames).with_style(
                 ProgressStyle::default_spinner()
@@ -245,37 +304,37 @@ fn run_video_metrics(
     if metric.is_none() || metric == Some("psnr") {
         progress.set_prefix("Computing PSNR");
         progress.reset();
-        results.psnr = Psnr::run(input1, input2, progress_fn);
+        results.psnr = Psnr::run(input1, input2, chroma_weight, time_range, progress_fn);
     }
 
     if metric.is_none() || metric == Some("apsnr") {
         progress.set_prefix("Computing APSNR");
         progress.reset();
-        results.apsnr = APsnr::run(input1, input2, progress_fn);
+        results.apsnr = APsnr::run(input1, input2, chroma_weight, time_range, progress_fn);
     }
 
     if metric.is_none() || metric == Some("psnrhvs") {
         progress.set_prefix("Computing PSNR-HVS");
         progress.reset();
-        results.psnr_hvs = PsnrHvs::run(input1, input2, progress_fn);
+        results.psnr_hvs = PsnrHvs::run(input1, input2, chroma_weight, time_range, progress_fn);
     }
 
     if metric.is_none() || metric == Some("ssim") {
         progress.set_prefix("Computing SSIM");
         progress.reset();
-        results.ssim = Ssim::run(input1, input2, progress_fn);
+        results.ssim = Ssim::run(input1, input2, chroma_weight, time_range, progress_fn);
     }
 
     if metric.is_none() || metric == Some("msssim") {
         progress.set_prefix("Computing MSSSIM");
         progress.reset();
-        results.msssim = MsSsim::run(input1, input2, progress_fn);
+        results.msssim = MsSsim::run(input1, input2, chroma_weight, time_range, progress_fn);
     }
 
     if metric.is_none() || metric == Some("ciede2000") {
         progress.set_prefix("Computing CIEDE2000");
         progress.reset();
-        results.ciede2000 = Ciede2000::run(input1, input2, progress_fn);
+        results.ciede2000 = Ciede2000::run(input1, input2, chroma_weight, time_range, progress_fn);
     }
 
     results
@@ -291,15 +350,45 @@ fn count_frames<D: Decoder, P: Pixel>(dec1: &mut D, dec2: &mut D) -> u64 {
     frame_number
 }
 
-fn total_frames<P: AsRef<Path>>(input1: P, input2: P) -> u64 {
+fn total_frames<P: AsRef<Path>>(input1: P, input2: P, time_range: Option<TimeRange>) -> u64 {
     let mut decoder1 =
         get_decoder(input1).expect("Failed to open input file 1 for counting frames");
     let mut decoder2 =
         get_decoder(input2).expect("Failed to open input file 2 for counting frames");
+    match time_range {
+        Some(range) => count_frames_of(&mut range.apply(decoder1), &mut range.apply(decoder2)),
+        None => count_frames_of(&mut decoder1, &mut decoder2),
+    }
+}
+
+fn count_frames_of<D: Decoder>(decoder1: &mut D, decoder2: &mut D) -> u64 {
     if decoder1.get_bit_depth() > 8 {
-        count_frames::<_, u16>(&mut decoder1, &mut decoder2)
+        count_frames::<_, u16>(decoder1, decoder2)
     } else {
-        count_frames::<_, u8>(&mut decoder1, &mut decoder2)
+        count_frames::<_, u8>(decoder1, decoder2)
+    }
+}
+
+/// The part of the inputs to score, in seconds.
+#[derive(Debug, Clone, Copy)]
+struct TimeRange {
+    start: f64,
+    end: Option<f64>,
+}
+
+impl TimeRange {
+    /// Checks that the range selects at least one frame of `input`, which has to
+    /// have a known frame rate.
+    fn validate<P: AsRef<Path>>(self, input: P) -> Result<(), String> {
+        let decoder = get_decoder(&input).map_err(|err| err.to_string())?;
+        TimeRangeDecoder::new(decoder, self.start, self.end)
+            .map(|_| ())
+            .map_err(|err| format!("Invalid time range for {}: {err}", input.as_ref().display()))
+    }
+
+    /// Applies the range to a decoder of an input which passed [`TimeRange::validate`].
+    fn apply<D: Decoder>(self, decoder: D) -> TimeRangeDecoder<D> {
+        TimeRangeDecoder::new(decoder, self.start, self.end).expect("time range is validated")
     }
 }
 
@@ -417,16 +506,32 @@ trait CliMetric {
     fn run<P: AsRef<Path>, F: Fn(usize) + Send>(
         input1: P,
         input2: P,
+        chroma_weight: Option<f64>,
+        time_range: Option<TimeRange>,
         progress_callback: F,
     ) -> Option<Self::VideoResult> {
         let mut dec1 = get_decoder(input1).expect("Failed to open input file 1");
         let mut dec2 = get_decoder(input2).expect("Failed to open input file 2");
-        Self::calculate_video_metric(&mut dec1, &mut dec2, progress_callback).ok()
+        match time_range {
+            Some(range) => Self::calculate_video_metric(
+                &mut range.apply(dec1),
+                &mut range.apply(dec2),
+                chroma_weight,
+                progress_callback,
+            ),
+            None => {
+                Self::calculate_video_metric(&mut dec1, &mut dec2, chroma_weight, progress_callback)
+            }
+        }
+        .ok()
     }
 
+    /// `chroma_weight` overrides the weights of the chroma planes, for the metrics
+    /// which weight the planes in their average score.
     fn calculate_video_metric<D: Decoder, F: Fn(usize) + Send>(
         dec1: &mut D,
         dec2: &mut D,
+        chroma_weight: Option<f64>,
         progress_callback: F,
     ) -> Result<Self::VideoResult, Box<dyn Error>>;
 }
@@ -439,6 +544,7 @@ impl CliMetric for Psnr {
     fn calculate_video_metric<D: Decoder, F: Fn(usize) + Send>(
         dec1: &mut D,
         dec2: &mut D,
+        _chroma_weight: Option<f64>,
         progress_callback: F,
     ) -> Result<Self::VideoResult, Box<dyn Error>> {
         psnr::calculate_video_psnr(dec1, dec2, None, progress_callback)
@@ -453,6 +559,7 @@ impl CliMetric for APsnr {
     fn calculate_video_metric<D: Decoder, F: Fn(usize) + Send>(
         dec1: &mut D,
         dec2: &mut D,
+        _chroma_weight: Option<f64>,
         progress_callback: F,
     ) -> Result<Self::VideoResult, Box<dyn Error>> {
         psnr::calculate_video_apsnr(dec1, dec2, None, progress_callback)
@@ -467,9 +574,14 @@ impl CliMetric for PsnrHvs {
     fn calculate_video_metric<D: Decoder, F: Fn(usize) + Send>(
         dec1: &mut D,
         dec2: &mut D,
+        chroma_weight: Option<f64>,
         progress_callback: F,
     ) -> Result<Self::VideoResult, Box<dyn Error>> {
-        psnr_hvs::calculate_video_psnr_hvs(dec1, dec2, None, progress_callback)
+        let mut options = psnr_hvs::PsnrHvsOptions::new();
+        if let Some(weight) = chroma_weight {
+            options = options.chroma_weights(ChromaWeights::uniform(weight));
+        }
+        options.calculate_video(dec1, dec2, None, progress_callback)
     }
 }
 
@@ -481,9 +593,14 @@ impl CliMetric for Ssim {
     fn calculate_video_metric<D: Decoder, F: Fn(usize) + Send>(
         dec1: &mut D,
         dec2: &mut D,
+        chroma_weight: Option<f64>,
         progress_callback: F,
     ) -> Result<Self::VideoResult, Box<dyn Error>> {
-        ssim::calculate_video_ssim(dec1, dec2, None, progress_callback)
+        let mut options = ssim::SsimOptions::new();
+        if let Some(weight) = chroma_weight {
+            options = options.chroma_weights(ChromaWeights::uniform(weight));
+        }
+        options.calculate_video(dec1, dec2, None, progress_callback)
     }
 }
 
@@ -495,9 +612,14 @@ impl CliMetric for MsSsim {
     fn calculate_video_metric<D: Decoder, F: Fn(usize) + Send>(
         dec1: &mut D,
         dec2: &mut D,
+        chroma_weight: Option<f64>,
         progress_callback: F,
     ) -> Result<Self::VideoResult, Box<dyn Error>> {
-        ssim::calculate_video_msssim(dec1, dec2, None, progress_callback)
+        let mut options = ssim::MsSsimOptions::new();
+        if let Some(weight) = chroma_weight {
+            options = options.chroma_weights(ChromaWeights::uniform(weight));
+        }
+        options.calculate_video(dec1, dec2, None, progress_callback)
     }
 }
 
@@ -509,6 +631,7 @@ impl CliMetric for Ciede2000 {
     fn calculate_video_metric<D: Decoder, F: Fn(usize) + Send>(
         dec1: &mut D,
         dec2: &mut D,
+        _chroma_weight: Option<f64>,
         progress_callback: F,
     ) -> Result<Self::VideoResult, Box<dyn Error>> {
         ciede::calculate_video_ciede(dec1, dec2, None, progress_callback)